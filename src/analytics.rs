@@ -0,0 +1,370 @@
+//! Aggregate usage numbers for the BI pipeline. The [Analytics] actor listens on the [EventBus],
+//! sums up what happened over every interval into a [Snapshot] and POSTs the snapshots to the
+//! analytics sink in batches. Only aggregates leave the server, never who the users are.
+
+use actix::prelude::*;
+use ahash::{HashMap, HashMapExt, HashSet};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::events::{EventBus, ServerEvent, Subscribe};
+use crate::game::GameMode;
+use crate::room::invite::unix_time;
+use crate::session::UserId;
+use crate::webhook::{Outbound, PostJson, JSON, KAFKA_REST_JSON};
+
+/// Bumped whenever a field of [Snapshot] changes meaning or goes away, new fields are added
+/// without bumping it
+const SCHEMA_VERSION: u32 = 1;
+const DEFAULT_INTERVAL: u64 = 300;
+const DEFAULT_BATCH_SIZE: usize = 6;
+/// Snapshots kept while the sink is unreachable, the oldest are dropped past this
+const MAX_PENDING: usize = 288;
+/// Users count as active for this long after they signed in
+const ACTIVE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How the snapshots are wrapped for the sink
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SinkFormat {
+    /// A single JSON object holding the schema version and the snapshots
+    Batch,
+    /// Records in the format of the Kafka REST proxy, one per snapshot
+    KafkaRest,
+}
+
+pub struct AnalyticsConfig {
+    /// `http://` or `https://` endpoint the batches are POSTed to
+    pub sink: String,
+    pub format: SinkFormat,
+    /// Time covered by every snapshot
+    pub interval: Duration,
+    /// Snapshots sent together
+    pub batch_size: usize,
+}
+
+impl AnalyticsConfig {
+    /// Reads `ANALYTICS_SINK`, `ANALYTICS_FORMAT` (`batch` or `kafka-rest`),
+    /// `ANALYTICS_INTERVAL` (in seconds) and `ANALYTICS_BATCH_SIZE`. Analytics stay off without a
+    /// sink.
+    pub fn from_env() -> Option<Self> {
+        let sink = std::env::var("ANALYTICS_SINK")
+            .ok()
+            .filter(|x| !x.is_empty())?;
+        let format = match std::env::var("ANALYTICS_FORMAT").as_deref() {
+            Ok("kafka-rest") => SinkFormat::KafkaRest,
+            Ok("batch") | Err(_) => SinkFormat::Batch,
+            Ok(format) => {
+                log::error!("unknown analytics format {format}, sending plain batches");
+                SinkFormat::Batch
+            }
+        };
+        let read = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(default)
+        };
+        Some(Self {
+            sink,
+            format,
+            interval: Duration::from_secs(read("ANALYTICS_INTERVAL", DEFAULT_INTERVAL).max(1)),
+            batch_size: read("ANALYTICS_BATCH_SIZE", DEFAULT_BATCH_SIZE as u64).max(1) as usize,
+        })
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct ModeCount {
+    pub mode: GameMode,
+    pub games: u64,
+}
+
+/// What happened on the server over one interval
+#[derive(Serialize, Clone)]
+pub struct Snapshot {
+    /// Seconds since the unix epoch the interval started at
+    pub from: u64,
+    /// Seconds since the unix epoch the interval ended at
+    pub to: u64,
+    /// Distinct users who signed in over the last day, as far as this process has seen. Bots
+    /// are left out.
+    pub daily_active_users: usize,
+    pub sign_ins: u64,
+    /// Distinct users signing in during the interval who had already signed in earlier in the
+    /// day
+    pub returning_users: usize,
+    /// Distinct users signing in for the first time in a day
+    pub new_users: usize,
+    pub games_started: u64,
+    pub games_finished: u64,
+    /// Games started, extrapolated to an hour
+    pub games_per_hour: f64,
+    /// Games started in every mode played during the interval, most popular first
+    pub modes: Vec<ModeCount>,
+}
+
+#[derive(Serialize)]
+struct Batch<'a> {
+    schema: u32,
+    snapshots: &'a [Snapshot],
+}
+
+/// Record produced through a Kafka REST proxy, also used by [crate::bridge]
+#[derive(Serialize)]
+pub(crate) struct KafkaRecord<T> {
+    pub value: T,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    schema: u32,
+    #[serde(flatten)]
+    snapshot: &'a Snapshot,
+}
+
+/// Body of a request producing records to a topic through a Kafka REST proxy
+#[derive(Serialize)]
+pub(crate) struct KafkaBatch<T> {
+    pub records: Vec<KafkaRecord<T>>,
+}
+
+/// Counts of the interval in progress
+#[derive(Default)]
+struct Window {
+    sign_ins: u64,
+    returning: HashSet<UserId>,
+    new: HashSet<UserId>,
+    games_started: u64,
+    games_finished: u64,
+    modes: Vec<ModeCount>,
+}
+
+pub struct Analytics {
+    config: AnalyticsConfig,
+    events: Addr<EventBus>,
+    outbound: Outbound,
+    window: Window,
+    window_start: (u64, Instant),
+    /// When every user active over the last day last signed in
+    seen: HashMap<UserId, Instant>,
+    /// Snapshots not delivered yet, oldest first
+    pending: VecDeque<Snapshot>,
+    /// A batch is on its way to the sink
+    sending: bool,
+}
+
+impl Analytics {
+    pub fn new(config: AnalyticsConfig, events: Addr<EventBus>, outbound: Outbound) -> Self {
+        Self {
+            config,
+            events,
+            outbound,
+            window: Window::default(),
+            window_start: (unix_time(), Instant::now()),
+            seen: HashMap::new(),
+            pending: VecDeque::new(),
+            sending: false,
+        }
+    }
+    fn signed_in(&mut self, user: UserId) {
+        let now = Instant::now();
+        self.window.sign_ins += 1;
+        match self.seen.insert(user.clone(), now) {
+            Some(at) if now.duration_since(at) >= ACTIVE_WINDOW => {
+                self.window.new.insert(user);
+            }
+            Some(at) if at < self.window_start.1 => {
+                self.window.returning.insert(user);
+            }
+            Some(_) => {}
+            None => {
+                self.window.new.insert(user);
+            }
+        }
+    }
+    fn game_started(&mut self, mode: GameMode) {
+        self.window.games_started += 1;
+        match self.window.modes.iter_mut().find(|x| x.mode == mode) {
+            Some(count) => count.games += 1,
+            None => self.window.modes.push(ModeCount { mode, games: 1 }),
+        }
+    }
+    /// Closes the interval in progress and sends the pending snapshots once there are enough
+    fn snapshot(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        self.seen
+            .retain(|_, at| now.duration_since(*at) < ACTIVE_WINDOW);
+        let (from, started) = std::mem::replace(&mut self.window_start, (unix_time(), now));
+        let mut window = std::mem::take(&mut self.window);
+        window.modes.sort_unstable_by_key(|x| std::cmp::Reverse(x.games));
+        let hours = now.duration_since(started).as_secs_f64() / 3600.0;
+        let snapshot = Snapshot {
+            from,
+            to: self.window_start.0,
+            daily_active_users: self.seen.len(),
+            sign_ins: window.sign_ins,
+            returning_users: window.returning.len(),
+            new_users: window.new.len(),
+            games_started: window.games_started,
+            games_finished: window.games_finished,
+            games_per_hour: window.games_started as f64 / hours.max(f64::EPSILON),
+            modes: window.modes,
+        };
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(snapshot);
+        if self.pending.len() >= self.config.batch_size {
+            self.flush(ctx);
+        }
+    }
+    /// Sends every pending snapshot, keeping them for the next attempt if the sink cannot be
+    /// reached
+    fn flush(&mut self, ctx: &mut Context<Self>) {
+        if self.sending {
+            return;
+        }
+        let batch = Vec::from(std::mem::take(&mut self.pending));
+        let snapshots = batch.as_slice();
+        let (body, content_type) = match self.config.format {
+            SinkFormat::Batch => (
+                serde_json::to_string(&Batch {
+                    schema: SCHEMA_VERSION,
+                    snapshots,
+                }),
+                JSON,
+            ),
+            SinkFormat::KafkaRest => (
+                serde_json::to_string(&KafkaBatch {
+                    records: snapshots
+                        .iter()
+                        .map(|snapshot| KafkaRecord {
+                            value: Versioned {
+                                schema: SCHEMA_VERSION,
+                                snapshot,
+                            },
+                        })
+                        .collect(),
+                }),
+                KAFKA_REST_JSON,
+            ),
+        };
+        let body = match body {
+            Ok(body) => body,
+            Err(err) => {
+                log::error!("cannot serialize analytics snapshots: {err}");
+                return;
+            }
+        };
+        self.sending = true;
+        self.outbound
+            .post(PostJson {
+                url: self.config.sink.clone(),
+                content_type,
+                body,
+            })
+            .into_actor(self)
+            .map(move |res, act, _| {
+                act.sending = false;
+                match res {
+                    Ok(status) if (200..300).contains(&status) => return,
+                    Ok(status) => log::error!("analytics sink answered {status}"),
+                    Err(err) => log::error!("cannot reach analytics sink: {err}"),
+                }
+                // Retried along with the next batch, ahead of the snapshots taken meanwhile
+                for snapshot in batch.into_iter().rev() {
+                    act.pending.push_front(snapshot);
+                }
+                while act.pending.len() > MAX_PENDING {
+                    act.pending.pop_front();
+                }
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Actor for Analytics {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.events.do_send(Subscribe(ctx.address().recipient()));
+        ctx.run_interval(self.config.interval, Self::snapshot);
+    }
+}
+
+impl Handler<ServerEvent> for Analytics {
+    type Result = ();
+    fn handle(&mut self, msg: ServerEvent, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            ServerEvent::SignedIn { user, bot: false } => self.signed_in(user),
+            ServerEvent::GameStarted { mode, .. } => self.game_started(mode),
+            ServerEvent::GameFinished { .. } => self.window.games_finished += 1,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{http_endpoint, signed_in};
+    use serde_json::Value;
+
+    #[actix::test]
+    async fn snapshots_go_out_together_once_a_batch_fills_up() {
+        let (sink, mut requests) = http_endpoint().await;
+        let config = AnalyticsConfig {
+            sink,
+            format: SinkFormat::KafkaRest,
+            interval: Duration::from_millis(50),
+            batch_size: 3,
+        };
+        let analytics =
+            Analytics::new(config, EventBus::default().start(), Outbound::start()).start();
+        analytics.send(signed_in("ann")).await.unwrap();
+        analytics.send(signed_in("bob")).await.unwrap();
+        analytics
+            .send(ServerEvent::GameStarted {
+                room: "ABCD".into(),
+                mode: GameMode::Standard,
+                players: 2,
+            })
+            .await
+            .unwrap();
+        let request = actix::clock::timeout(Duration::from_secs(2), requests.recv())
+            .await
+            .expect("no batch within 2s")
+            .unwrap();
+        assert!(request.head.contains(KAFKA_REST_JSON));
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        let records = body["records"].as_array().unwrap();
+        assert_eq!(records.len(), 3);
+        let first = &records[0]["value"];
+        assert_eq!(first["schema"], SCHEMA_VERSION);
+        assert_eq!(first["sign_ins"], 2);
+        assert_eq!(first["new_users"], 2);
+        assert_eq!(first["modes"][0]["games"], 1);
+        assert_eq!(records[1]["value"]["sign_ins"], 0);
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[actix::test]
+    async fn users_seen_before_the_interval_count_as_returning() {
+        let config = AnalyticsConfig {
+            sink: "http://127.0.0.1:9".into(),
+            format: SinkFormat::Batch,
+            interval: Duration::from_secs(60),
+            batch_size: 1,
+        };
+        let mut analytics = Analytics::new(config, EventBus::default().start(), Outbound::start());
+        let earlier = analytics.window_start.1 - Duration::from_secs(60);
+        analytics.seen.insert("ann".into(), earlier);
+        analytics.seen.insert("bob".into(), earlier - ACTIVE_WINDOW);
+        for user in ["ann", "bob", "cid", "cid"] {
+            analytics.signed_in(user.into());
+        }
+        assert_eq!(analytics.window.sign_ins, 4);
+        assert_eq!(analytics.window.returning.len(), 1);
+        assert_eq!(analytics.window.new.len(), 2);
+    }
+}
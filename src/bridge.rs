@@ -0,0 +1,279 @@
+//! Optional bridge forwarding every [ServerEvent] to a message broker, so that notification,
+//! analytics or anti-cheat services can follow what happens on the server as it happens instead
+//! of polling the HTTP API. Every kind of event goes to its own topic, named after the kind with
+//! a configurable prefix, e.g. `zgm.game_started`.
+//!
+//! Kafka records are sent in batches, every topic's events together once a second or as soon as
+//! [MAX_BATCH] of them are waiting. Events are published on a best effort basis: whatever comes
+//! up while the broker cannot be reached is dropped.
+
+use actix::prelude::*;
+use ahash::{HashMap, HashMapExt};
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::analytics::{KafkaBatch, KafkaRecord};
+use crate::events::{EventBus, ServerEvent, Subscribe};
+use crate::webhook::{is_http_url, Outbound, PostJson, KAFKA_REST_JSON};
+
+const DEFAULT_PREFIX: &str = "zgm";
+/// Events waiting to be handed to the broker, newer ones are dropped past this
+const MAX_QUEUED_EVENTS: usize = 1024;
+/// How often the events waiting for the Kafka proxy are sent
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// Events of a topic sent to the Kafka proxy in a single request
+const MAX_BATCH: usize = 100;
+
+pub enum Broker {
+    /// Address of a NATS server, events are published on subjects named after their topic
+    Nats(String),
+    /// Base URL of a Kafka REST proxy, events are produced to topics of the same name
+    KafkaRest(String),
+}
+
+pub struct BridgeConfig {
+    pub broker: Broker,
+    /// Put in front of every topic
+    pub prefix: String,
+}
+
+impl BridgeConfig {
+    /// Reads `EVENT_BRIDGE`, either a `nats://host:port` address or the `http://` or `https://` URL
+    /// of a Kafka REST proxy, and `EVENT_BRIDGE_PREFIX`. There is no bridge without the former.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("EVENT_BRIDGE")
+            .ok()
+            .filter(|x| !x.is_empty())?;
+        let broker = if url.starts_with("nats://") {
+            Broker::Nats(url.trim_end_matches('/').into())
+        } else if is_http_url(&url) {
+            Broker::KafkaRest(url.trim_end_matches('/').into())
+        } else {
+            log::error!("unsupported event bridge {url}, events stay in the process");
+            return None;
+        };
+        let prefix = std::env::var("EVENT_BRIDGE_PREFIX")
+            .ok()
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| DEFAULT_PREFIX.into());
+        Some(Self { broker, prefix })
+    }
+}
+
+/// Subscribes to the [EventBus] and hands every event to the broker
+pub struct EventBridge {
+    config: BridgeConfig,
+    events: Addr<EventBus>,
+    outbound: Outbound,
+    /// Feeds the connection to the NATS server, see [nats]
+    nats: Option<Sender<(String, Vec<u8>)>>,
+    /// Events waiting for the Kafka proxy by topic
+    batches: HashMap<String, Vec<ServerEvent>>,
+    /// Number of events in [EventBridge::batches]
+    queued: usize,
+}
+
+impl EventBridge {
+    pub fn new(config: BridgeConfig, events: Addr<EventBus>, outbound: Outbound) -> Self {
+        Self {
+            config,
+            events,
+            outbound,
+            nats: None,
+            batches: HashMap::new(),
+            queued: 0,
+        }
+    }
+    /// Sends the events of the topic waiting for the Kafka proxy
+    fn produce(&mut self, topic: String) {
+        let Broker::KafkaRest(url) = &self.config.broker else {
+            return;
+        };
+        let Some(events) = self.batches.remove(&topic) else {
+            return;
+        };
+        self.queued -= events.len();
+        let batch = KafkaBatch {
+            records: events.iter().map(|value| KafkaRecord { value }).collect(),
+        };
+        let body = match serde_json::to_string(&batch) {
+            Ok(body) => body,
+            Err(err) => return log::error!("cannot serialize {topic} events: {err}"),
+        };
+        let sent = self.outbound.post(PostJson {
+            url: format!("{url}/topics/{topic}"),
+            content_type: KAFKA_REST_JSON,
+            body,
+        });
+        actix::spawn(async move {
+            match sent.await {
+                Ok(status) if (200..300).contains(&status) => {}
+                Ok(status) => log::error!("kafka proxy answered {status} for {topic}"),
+                Err(err) => log::error!("cannot reach kafka proxy: {err}"),
+            }
+        });
+    }
+}
+
+impl Actor for EventBridge {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        match &self.config.broker {
+            Broker::Nats(url) => {
+                let (sender, receiver) = channel(MAX_QUEUED_EVENTS);
+                actix::spawn(nats(url.clone(), receiver));
+                self.nats = Some(sender);
+            }
+            Broker::KafkaRest(_) => {
+                ctx.run_interval(BATCH_INTERVAL, |act, _| {
+                    let topics: Vec<_> = act.batches.keys().cloned().collect();
+                    for topic in topics {
+                        act.produce(topic);
+                    }
+                });
+            }
+        }
+        self.events.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<ServerEvent> for EventBridge {
+    type Result = ();
+    fn handle(&mut self, msg: ServerEvent, _: &mut Self::Context) -> Self::Result {
+        let topic = format!("{}.{}", self.config.prefix, msg.topic());
+        if let Some(nats) = &self.nats {
+            let payload = match serde_json::to_vec(&msg) {
+                Ok(payload) => payload,
+                Err(err) => return log::error!("cannot serialize {topic} event: {err}"),
+            };
+            if let Err(err) = nats.try_send((topic, payload)) {
+                let (topic, _) = err.into_inner();
+                log::warn!("nats is falling behind, dropping {topic} event");
+            }
+        } else if self.queued >= MAX_QUEUED_EVENTS {
+            log::warn!("kafka proxy is falling behind, dropping {topic} event");
+        } else {
+            self.queued += 1;
+            let batch = self.batches.entry(topic.clone()).or_default();
+            batch.push(msg);
+            if batch.len() >= MAX_BATCH {
+                self.produce(topic);
+            }
+        }
+    }
+}
+
+/// Publishes the events queued on `events` to the NATS server at `url`. The client keeps
+/// reconnecting to the server whenever the connection drops, the queue fills up meanwhile.
+async fn nats(url: String, mut events: Receiver<(String, Vec<u8>)>) {
+    let client = match async_nats::ConnectOptions::new()
+        .name("zgm-srv")
+        .retry_on_initial_connect()
+        .connect(&url)
+        .await
+    {
+        Ok(client) => client,
+        Err(err) => return log::error!("cannot connect to nats at {url}: {err}"),
+    };
+    while let Some((subject, payload)) = events.recv().await {
+        if let Err(err) = client.publish(subject, payload.into()).await {
+            log::error!("cannot publish to nats at {url}: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameMode;
+    use crate::testing::{http_endpoint, signed_in};
+    use serde_json::Value;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    fn game_started() -> ServerEvent {
+        ServerEvent::GameStarted {
+            room: "ABCD".into(),
+            mode: GameMode::Standard,
+            players: 2,
+        }
+    }
+
+    #[actix::test]
+    async fn kafka_records_of_a_topic_go_out_together() {
+        let (url, mut requests) = http_endpoint().await;
+        let config = BridgeConfig {
+            broker: Broker::KafkaRest(url),
+            prefix: "test".into(),
+        };
+        let bridge =
+            EventBridge::new(config, EventBus::default().start(), Outbound::start()).start();
+        bridge.send(signed_in("ann")).await.unwrap();
+        bridge.send(game_started()).await.unwrap();
+        bridge.send(signed_in("bob")).await.unwrap();
+        let mut batches = HashMap::new();
+        for _ in 0..2 {
+            let request = actix::clock::timeout(TIMEOUT, requests.recv())
+                .await
+                .expect("no batch in time")
+                .unwrap();
+            assert!(request.head.contains(KAFKA_REST_JSON));
+            let path = request.head.split_whitespace().nth(1).unwrap().to_owned();
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            batches.insert(path, body["records"].as_array().unwrap().clone());
+        }
+        let signed_in = &batches["/topics/test.signed_in"];
+        assert_eq!(signed_in.len(), 2);
+        assert_eq!(signed_in[1]["value"]["data"]["user"], "bob");
+        assert_eq!(batches["/topics/test.game_started"].len(), 1);
+    }
+
+    /// Plays a NATS server just far enough for a client to publish to it, handing out whatever
+    /// is published
+    async fn nats_server() -> (String, Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let (sender, published) = channel(16);
+        actix::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let info = r#"{"server_id":"test","version":"2.10.0","proto":1,"max_payload":1048576}"#;
+            writer
+                .write_all(format!("INFO {info}\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.starts_with("PING") {
+                    writer.write_all(b"PONG\r\n").await.unwrap();
+                } else if let Some(subject) = line.strip_prefix("PUB ") {
+                    let subject = subject.split_whitespace().next().unwrap().to_owned();
+                    let payload = lines.next_line().await.unwrap().unwrap();
+                    sender.send((subject, payload)).await.unwrap();
+                }
+            }
+        });
+        (url, published)
+    }
+
+    #[actix::test]
+    async fn events_are_published_on_prefixed_nats_subjects() {
+        let (url, mut published) = nats_server().await;
+        let config = BridgeConfig {
+            broker: Broker::Nats(url),
+            prefix: "test".into(),
+        };
+        let bridge =
+            EventBridge::new(config, EventBus::default().start(), Outbound::start()).start();
+        bridge.send(game_started()).await.unwrap();
+        let (subject, payload) = actix::clock::timeout(TIMEOUT, published.recv())
+            .await
+            .expect("nothing published in time")
+            .unwrap();
+        assert_eq!(subject, "test.game_started");
+        let event: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(event["data"]["room"], "ABCD");
+    }
+}
@@ -0,0 +1,209 @@
+//! Capacity classes. Part of the session and room capacity of the server is held back for tagged
+//! traffic, such as tournaments or partners, so that a spike of public players cannot starve
+//! scheduled events. Clients show the key of their tag in the [KEY_HEADER] header when
+//! connecting, and everyone else gets by with the public share. Every session is admitted before
+//! it starts, whichever transport the client connects over.
+
+use actix_web::{http::StatusCode, ResponseError};
+use ahash::{HashMap, HashMapExt};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const DEFAULT_RESERVED_SHARE: f64 = 0.1;
+/// Header clients show the key of their tag in
+pub const KEY_HEADER: &str = "x-capacity-key";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CapacityClass {
+    #[default]
+    Public,
+    /// Traffic with a tag, which can also use the reserved share
+    Reserved,
+}
+
+pub struct CapacityConfig {
+    /// Sessions connected at once over any transport, unlimited if unset
+    pub max_sessions: Option<usize>,
+    /// Rooms open at once, unlimited if unset
+    pub max_rooms: Option<usize>,
+    /// Fraction of both limits only tagged traffic can use
+    pub reserved_share: f64,
+    /// Tag of the traffic every key stands for
+    pub keys: HashMap<Box<str>, Box<str>>,
+}
+
+impl CapacityConfig {
+    /// Reads `MAX_SESSIONS`, `MAX_ROOMS`, `RESERVED_CAPACITY` (a fraction, a tenth by default)
+    /// and `CAPACITY_KEYS`, comma separated `tag=key` pairs such as `tournament=s3cret`
+    pub fn from_env() -> Self {
+        let read = |name| {
+            std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .filter(|x| *x > 0)
+        };
+        let mut keys = HashMap::new();
+        for entry in std::env::var("CAPACITY_KEYS")
+            .unwrap_or_default()
+            .split(',')
+        {
+            match entry.trim().split_once('=') {
+                Some((tag, key)) if !tag.is_empty() && !key.is_empty() => {
+                    keys.insert(key.into(), tag.into());
+                }
+                None if entry.trim().is_empty() => {}
+                _ => log::error!("ignoring malformed capacity key for {entry}"),
+            }
+        }
+        Self {
+            max_sessions: read("MAX_SESSIONS"),
+            max_rooms: read("MAX_ROOMS"),
+            reserved_share: std::env::var("RESERVED_CAPACITY")
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(DEFAULT_RESERVED_SHARE)
+                .clamp(0.0, 1.0),
+            keys,
+        }
+    }
+}
+
+/// Why a client was turned away on connecting
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refusal {
+    UnknownKey,
+    /// The share of the client's class is used up, the client can retry later
+    Full,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::UnknownKey => write!(f, "unknown capacity key"),
+            Refusal::Full => write!(f, "server full"),
+        }
+    }
+}
+
+impl ResponseError for Refusal {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Refusal::UnknownKey => StatusCode::FORBIDDEN,
+            Refusal::Full => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Limits shared by the session admission check and the room manager
+pub struct Capacity {
+    config: CapacityConfig,
+    sessions: AtomicUsize,
+}
+
+impl Capacity {
+    pub fn new(config: CapacityConfig) -> Self {
+        Self {
+            config,
+            sessions: AtomicUsize::new(0),
+        }
+    }
+    /// Class of the traffic the key belongs to, [None] if the key is unknown
+    pub fn classify(&self, key: Option<&str>) -> Option<CapacityClass> {
+        let Some(key) = key else {
+            return Some(CapacityClass::Public);
+        };
+        let tag = self.config.keys.get(key)?;
+        log::debug!("admitting {tag} traffic");
+        Some(CapacityClass::Reserved)
+    }
+    /// Lets a session of the class in unless its share is used up. The session counts against
+    /// the limit for as long as the permit is kept.
+    pub fn admit(self: &Arc<Self>, class: CapacityClass) -> Option<SessionPermit> {
+        if let Some(max) = self.config.max_sessions {
+            let limit = self.limit(max, class);
+            self.sessions
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    (n < limit).then_some(n + 1)
+                })
+                .ok()?;
+        }
+        Some(SessionPermit {
+            capacity: self.clone(),
+            class,
+        })
+    }
+    /// Lets in a client showing the key, if any, see [Capacity::classify]
+    pub fn admit_key(self: &Arc<Self>, key: Option<&str>) -> Result<SessionPermit, Refusal> {
+        let class = self.classify(key).ok_or(Refusal::UnknownKey)?;
+        self.admit(class).ok_or(Refusal::Full)
+    }
+    /// Whether another room can be opened for the class while `live` rooms are open
+    pub fn room_available(&self, live: usize, class: CapacityClass) -> bool {
+        self.config
+            .max_rooms
+            .is_none_or(|max| live < self.limit(max, class))
+    }
+    fn limit(&self, max: usize, class: CapacityClass) -> usize {
+        match class {
+            CapacityClass::Public => {
+                max - (max as f64 * self.config.reserved_share).ceil() as usize
+            }
+            CapacityClass::Reserved => max,
+        }
+    }
+}
+
+/// A session let in by [Capacity::admit], handed back when dropped
+pub struct SessionPermit {
+    capacity: Arc<Capacity>,
+    pub class: CapacityClass,
+}
+
+impl Drop for SessionPermit {
+    fn drop(&mut self) {
+        if self.capacity.config.max_sessions.is_some() {
+            self.capacity.sessions.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_traffic_leaves_the_reserved_share_alone() {
+        let capacity = Arc::new(Capacity::new(CapacityConfig {
+            max_sessions: Some(10),
+            max_rooms: Some(10),
+            reserved_share: 0.25,
+            keys: [("s3cret".into(), "tournament".into())]
+                .into_iter()
+                .collect(),
+        }));
+        assert_eq!(capacity.classify(Some("nope")), None);
+        assert_eq!(
+            capacity.admit_key(Some("nope")).err(),
+            Some(Refusal::UnknownKey)
+        );
+        let reserved = capacity.classify(Some("s3cret")).unwrap();
+        let public = capacity.classify(None).unwrap();
+        let mut permits = Vec::new();
+        while let Some(permit) = capacity.admit(public) {
+            permits.push(permit);
+        }
+        assert_eq!(permits.len(), 7);
+        while let Some(permit) = capacity.admit(reserved) {
+            permits.push(permit);
+        }
+        assert_eq!(permits.len(), 10);
+        permits.pop();
+        assert!(capacity.admit(public).is_none());
+        assert_eq!(capacity.admit_key(None).err(), Some(Refusal::Full));
+        assert!(capacity.admit(reserved).is_some());
+        assert!(capacity.room_available(6, public));
+        assert!(!capacity.room_available(7, public));
+        assert!(capacity.room_available(9, reserved));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::room::RoomCode;
+use crate::session::TransientId;
+use actix::{Actor, Context, Handler, Message, MessageResult};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of dead letters kept around for inspection, older ones are evicted first
+const CAPACITY: usize = 1024;
+/// Frames are cut down to this many bytes before being stored
+const MAX_FRAME_LENGTH: usize = 512;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    /// The message could not be turned into a frame
+    Serialization,
+    /// The recipient's session had already stopped
+    SessionGone,
+}
+
+/// An outgoing message that never reached its client
+#[derive(Message, Serialize, Clone)]
+#[rtype(result = "()")]
+pub struct DeadLetter {
+    /// Seconds since the unix epoch
+    pub at: u64,
+    pub reason: DeadLetterReason,
+    /// Transient id of the intended recipient, if known
+    pub target: Option<TransientId>,
+    /// Code of the room the message was sent from, if any
+    pub room: Option<String>,
+    /// The (possibly truncated) frame, if the message could be serialized at all
+    pub frame: Option<String>,
+    /// Extra information such as the serialization error
+    pub detail: Option<String>,
+}
+
+impl DeadLetter {
+    pub fn new(reason: DeadLetterReason, target: Option<TransientId>) -> Self {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        Self {
+            at,
+            reason,
+            target,
+            room: None,
+            frame: None,
+            detail: None,
+        }
+    }
+    pub fn room(mut self, code: &RoomCode) -> Self {
+        self.room = Some(String::from_utf8_lossy(code).into_owned());
+        self
+    }
+    pub fn frame(mut self, frame: &str) -> Self {
+        let mut end = frame.len().min(MAX_FRAME_LENGTH);
+        while !frame.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.frame = Some(frame[..end].to_string());
+        self
+    }
+    pub fn detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct DeadLetterMetrics {
+    /// Every dead letter ever recorded, including evicted ones
+    pub total: u64,
+    pub serialization: u64,
+    pub session_gone: u64,
+    /// Dead letters pushed out of the buffer to make room for newer ones
+    pub evicted: u64,
+}
+
+/// Bounded sink for undeliverable client messages, kept for incident investigations. Every dead
+/// letter is also appended to the file named by `DEAD_LETTER_LOG` as a JSON line, if set.
+pub struct DeadLetters {
+    letters: VecDeque<DeadLetter>,
+    metrics: DeadLetterMetrics,
+    log: Option<File>,
+}
+
+impl DeadLetters {
+    pub fn from_env() -> Self {
+        let log = std::env::var("DEAD_LETTER_LOG").ok().and_then(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|err| log::error!("cannot open dead letter log {path}: {err}"))
+                .ok()
+        });
+        Self {
+            letters: VecDeque::with_capacity(CAPACITY),
+            metrics: Default::default(),
+            log,
+        }
+    }
+}
+
+impl Actor for DeadLetters {
+    type Context = Context<Self>;
+}
+
+impl Handler<DeadLetter> for DeadLetters {
+    type Result = ();
+    fn handle(&mut self, msg: DeadLetter, _: &mut Self::Context) -> Self::Result {
+        self.metrics.total += 1;
+        match msg.reason {
+            DeadLetterReason::Serialization => self.metrics.serialization += 1,
+            DeadLetterReason::SessionGone => self.metrics.session_gone += 1,
+        }
+        if let Some(log) = &mut self.log {
+            let line = serde_json::to_string(&msg).unwrap_or_default();
+            if let Err(err) = writeln!(log, "{line}") {
+                log::error!("failed to persist dead letter: {err}");
+            }
+        }
+        if self.letters.len() == CAPACITY {
+            self.letters.pop_front();
+            self.metrics.evicted += 1;
+        }
+        self.letters.push_back(msg);
+    }
+}
+
+#[derive(Serialize)]
+pub struct DeadLetterReport {
+    pub metrics: DeadLetterMetrics,
+    /// Most recent first
+    pub letters: Vec<DeadLetter>,
+}
+
+/// Snapshot of the sink for the admin API, optionally narrowed down to a single recipient
+#[derive(Message)]
+#[rtype(result = "DeadLetterReport")]
+pub struct GetDeadLetters {
+    pub target: Option<TransientId>,
+    pub limit: usize,
+}
+
+impl Handler<GetDeadLetters> for DeadLetters {
+    type Result = MessageResult<GetDeadLetters>;
+    fn handle(&mut self, msg: GetDeadLetters, _: &mut Self::Context) -> Self::Result {
+        let letters = self
+            .letters
+            .iter()
+            .rev()
+            .filter(|x| msg.target.is_none_or(|target| x.target == Some(target)))
+            .take(msg.limit)
+            .cloned()
+            .collect();
+        MessageResult(DeadLetterReport {
+            metrics: self.metrics.clone(),
+            letters,
+        })
+    }
+}
@@ -0,0 +1,274 @@
+//! Red button diagnostics. `/admin/diagnostics` takes a snapshot of the server as a single JSON
+//! document to attach to bug reports: how many actors are running, how the pools are used, the
+//! busiest rooms, the latest panics and dead letters, and a hash of the configuration so that
+//! snapshots of differently configured nodes can be told apart without revealing any secrets.
+
+use actix::{Addr, MailboxError};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::deadletter::{DeadLetterReport, DeadLetters, GetDeadLetters};
+use crate::load::Load;
+use crate::room::actor::GetTraffic;
+use crate::room::invite::unix_time;
+use crate::room::placement::PlacementMetrics;
+use crate::room::traffic::RoomTraffic;
+use crate::room::{ask_rooms, GetPlacementMetrics, GetRoomStats, RoomManager};
+use crate::session::features::FeatureFlags;
+use crate::session::{CountSessions, SessionManager};
+use crate::version::BuildInfo;
+
+/// Panics kept for snapshots, older ones are dropped first
+const MAX_PANICS: usize = 16;
+/// Rooms listed in a snapshot, busiest first
+const MAX_BUSIEST_ROOMS: usize = 10;
+/// Dead letters listed in a snapshot, most recent first
+const MAX_DEAD_LETTERS: usize = 20;
+/// Environment variables that make up the configuration, see [config_hash]. Secrets and what
+/// tells nodes of the same deployment apart are left out, as is the environment the server
+/// happens to run in.
+const CONFIG_VARS: &[&str] = &[
+    "ADMIN_AUDIT_LOG",
+    "ALERT_WEBHOOK",
+    "ANALYTICS_BATCH_SIZE",
+    "ANALYTICS_FORMAT",
+    "ANALYTICS_INTERVAL",
+    "ANALYTICS_SINK",
+    "DEAD_LETTER_LOG",
+    "DISABLED_FEATURES",
+    "EVENT_BRIDGE",
+    "EVENT_BRIDGE_PREFIX",
+    "GAME_SUMMARY_LOG",
+    "HANDOVER_SOCKET",
+    "HEARTBEAT_INTERVAL",
+    "HEARTBEAT_TIMEOUT",
+    "IDLE_TIMEOUT",
+    "INVITE_TTL",
+    "LOAD_MAX_LAG",
+    "LOAD_MAX_MAILBOX_DELAY",
+    "MATCH_RATING_BAND",
+    "MAX_ROOMS",
+    "MAX_SESSIONS",
+    "MODE_LIMITS",
+    "MULTI_CONNECTION",
+    "OUTGOING_DISCONNECT_THRESHOLD",
+    "OUTGOING_SHED_THRESHOLD",
+    "PING_INTERVAL",
+    "PROFANITY_ACTION",
+    "PROFANITY_WORD_LIST",
+    "RECONNECTION_TIME_LIMIT",
+    "REGION_HOSTS",
+    "RESERVED_CAPACITY",
+    "ROOM_CODE_DENYLIST",
+    "ROOM_CODE_LENGTH",
+    "ROOM_INACTIVITY_TIMEOUT",
+    "ROOM_INACTIVITY_WARNING",
+    "ROOM_LIFETIME",
+    "ROOM_LIFETIME_WARNINGS",
+    "ROOM_PLACEMENT",
+    "ROOM_WEBHOOKS",
+    "ROOM_WORKERS",
+    "TCP_GATEWAY",
+    "WATCHDOG_CODE_USAGE",
+    "WATCHDOG_INTERVAL",
+    "WATCHDOG_JOIN_FAILURE_RATIO",
+    "WATCHDOG_ROOMS_PER_MINUTE",
+    "WS_DEFLATE",
+    "WS_DEFLATE_THRESHOLD",
+];
+
+static PANICS: Mutex<VecDeque<PanicRecord>> = Mutex::new(VecDeque::new());
+
+#[derive(Serialize, Clone)]
+pub struct PanicRecord {
+    /// Seconds since the unix epoch
+    pub at: u64,
+    pub thread: Option<String>,
+    pub message: String,
+    /// File and line the panic was raised at
+    pub location: Option<String>,
+}
+
+/// Keeps the latest panics around for [Diagnostics::panics], on top of whatever the previous
+/// hook did with them
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|x| x.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let record = PanicRecord {
+            at: unix_time(),
+            thread: std::thread::current().name().map(Into::into),
+            message,
+            location: info
+                .location()
+                .map(|x| format!("{}:{}", x.file(), x.line())),
+        };
+        let mut panics = PANICS.lock().unwrap_or_else(PoisonError::into_inner);
+        if panics.len() >= MAX_PANICS {
+            panics.pop_front();
+        }
+        panics.push_back(record);
+        drop(panics);
+        previous(info);
+    }));
+}
+
+/// SHA-1 digest of the [CONFIG_VARS] among the variables of the environment
+fn config_hash(vars: impl Iterator<Item = (OsString, OsString)>) -> String {
+    let mut vars = vars
+        .filter(|(name, _)| name.to_str().is_some_and(|x| CONFIG_VARS.contains(&x)))
+        .collect::<Vec<_>>();
+    vars.sort();
+    let mut hasher = Sha1::new();
+    for (name, value) in vars {
+        hasher.update(name.as_encoded_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_encoded_bytes());
+        hasher.update(b"\n");
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct ActorCounts {
+    pub sessions: usize,
+    pub live_rooms: usize,
+    /// Stopped rooms kept around for reuse
+    pub pooled_rooms: usize,
+}
+
+#[derive(Serialize)]
+pub struct PoolSizes {
+    /// Threads of the [crate::jobs::JobPool] and broadcasters of the
+    /// [crate::room::fanout::FanoutPool], one of each per worker
+    pub workers: usize,
+    pub placement: PlacementMetrics,
+}
+
+/// A point in time snapshot of the server
+#[derive(Serialize)]
+pub struct Diagnostics {
+    /// Seconds since the unix epoch
+    pub at: u64,
+    pub server: BuildInfo,
+    pub config_hash: String,
+    pub actors: ActorCounts,
+    pub pools: PoolSizes,
+    /// Whether the server is shedding load, see [crate::load]
+    pub degraded: bool,
+    /// Rooms sending out the most frames, see [crate::room::traffic]
+    pub busiest_rooms: Vec<RoomTraffic>,
+    /// Oldest first
+    pub panics: Vec<PanicRecord>,
+    pub dead_letters: DeadLetterReport,
+}
+
+/// Everything a snapshot is taken from, kept by the HTTP server
+pub struct Sources {
+    pub session_manager: Addr<SessionManager>,
+    pub room_manager: Addr<RoomManager>,
+    pub dead_letters: Addr<DeadLetters>,
+    pub load: Arc<Load>,
+    pub features: Arc<FeatureFlags>,
+    pub workers: usize,
+}
+
+impl Sources {
+    pub async fn capture(&self) -> Result<Diagnostics, MailboxError> {
+        let sessions = self.session_manager.send(CountSessions).await?;
+        let stats = self.room_manager.send(GetRoomStats).await?;
+        let placement = self.room_manager.send(GetPlacementMetrics).await?;
+        let mut busiest_rooms = ask_rooms(&self.room_manager, GetTraffic).await?;
+        busiest_rooms.sort_by(|a, b| b.rate.total_cmp(&a.rate));
+        busiest_rooms.truncate(MAX_BUSIEST_ROOMS);
+        let dead_letters = self
+            .dead_letters
+            .send(GetDeadLetters {
+                target: None,
+                limit: MAX_DEAD_LETTERS,
+            })
+            .await?;
+        let panics = PANICS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect();
+        Ok(Diagnostics {
+            at: unix_time(),
+            server: BuildInfo::new(&self.features),
+            config_hash: config_hash(std::env::vars_os()),
+            actors: ActorCounts {
+                sessions,
+                live_rooms: stats.live_rooms,
+                pooled_rooms: stats.pooled_rooms,
+            },
+            pools: PoolSizes {
+                workers: self.workers,
+                placement,
+            },
+            degraded: self.load.degraded(),
+            busiest_rooms,
+            panics,
+            dead_letters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Server;
+    use serde_json::json;
+
+    #[test]
+    fn only_the_configuration_is_hashed() {
+        let env = |vars: &[(&str, &str)]| {
+            let vars = vars.iter().map(|(name, value)| (name.into(), value.into()));
+            config_hash(vars.collect::<Vec<_>>().into_iter())
+        };
+        let hash = env(&[("MAX_ROOMS", "10"), ("PATH", "/bin")]);
+        let elsewhere = [
+            ("PATH", "/usr/bin"),
+            ("INVITE_SECRET", "x"),
+            ("MAX_ROOMS", "10"),
+        ];
+        assert_eq!(env(&elsewhere), hash);
+        assert_ne!(env(&[("MAX_ROOMS", "20")]), hash);
+    }
+
+    #[actix::test]
+    async fn snapshots_count_sessions_and_rooms() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        ann.expect("Result").await;
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        let sources = Sources {
+            session_manager: server.session_manager.clone(),
+            room_manager: server.room_manager.clone(),
+            dead_letters: server.services.dead_letters.clone(),
+            load: Arc::clone(&server.services.load),
+            features: Arc::default(),
+            workers: 1,
+        };
+        let snapshot = sources.capture().await.unwrap();
+        assert_eq!(snapshot.actors.sessions, 2);
+        assert_eq!(snapshot.actors.live_rooms, 1);
+        assert_eq!(snapshot.busiest_rooms.len(), 1);
+    }
+}
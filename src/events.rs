@@ -0,0 +1,105 @@
+use actix::{Actor, Context, Handler, Message, Recipient};
+use serde::Serialize;
+
+use crate::game::GameMode;
+use crate::load::LoadReport;
+use crate::room::RoomKind;
+use crate::session::message::RemoveReason;
+use crate::session::UserId;
+use crate::watchdog::CapacityWarning;
+
+/// Something noteworthy that happened on the server, published on the [EventBus] for whoever
+/// wants to know about it
+#[derive(Message, Serialize, Clone)]
+#[rtype(result = "()")]
+#[serde(tag = "kind", content = "data")]
+pub enum ServerEvent {
+    /// The server is getting close to one of its limits, see [crate::watchdog::Watchdog]
+    CapacityWarning(CapacityWarning),
+    /// A previously raised capacity warning no longer applies
+    CapacityRecovered(CapacityWarning),
+    /// The server is overloaded and sheds load, see [crate::load::LoadMonitor]
+    LoadShedding(LoadReport),
+    /// The server is back to normal after shedding load
+    LoadRecovered(LoadReport),
+    /// A user signed in, clients reconnecting to a session the user still has are left out
+    SignedIn {
+        user: UserId,
+        bot: bool,
+    },
+    /// A room opened, either freshly started or handed to a new leader from the pool
+    RoomCreated {
+        room: String,
+        kind: RoomKind,
+        public: bool,
+        mode: GameMode,
+    },
+    RoomClosed {
+        room: String,
+        reason: RemoveReason,
+    },
+    GameStarted {
+        room: String,
+        mode: GameMode,
+        players: usize,
+    },
+    /// A game ran until its end, see [crate::game::GameOver]
+    GameFinished {
+        room: String,
+        mode: GameMode,
+        players: usize,
+    },
+}
+
+impl ServerEvent {
+    /// Name of the kind of event, for subscribers that sort events by their kind
+    pub fn topic(&self) -> &'static str {
+        match self {
+            Self::CapacityWarning(_) => "capacity_warning",
+            Self::CapacityRecovered(_) => "capacity_recovered",
+            Self::LoadShedding(_) => "load_shedding",
+            Self::LoadRecovered(_) => "load_recovered",
+            Self::SignedIn { .. } => "signed_in",
+            Self::RoomCreated { .. } => "room_created",
+            Self::RoomClosed { .. } => "room_closed",
+            Self::GameStarted { .. } => "game_started",
+            Self::GameFinished { .. } => "game_finished",
+        }
+    }
+}
+
+/// Fans server events out to every subscriber. Subscribers that stopped are dropped the next
+/// time something is published.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Recipient<ServerEvent>>,
+}
+
+impl Actor for EventBus {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Subscribe(pub Recipient<ServerEvent>);
+
+impl Handler<Subscribe> for EventBus {
+    type Result = ();
+    fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) -> Self::Result {
+        self.subscribers.push(msg.0);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Publish(pub ServerEvent);
+
+impl Handler<Publish> for EventBus {
+    type Result = ();
+    fn handle(&mut self, msg: Publish, _: &mut Self::Context) -> Self::Result {
+        self.subscribers.retain(|subscriber| subscriber.connected());
+        for subscriber in &self.subscribers {
+            subscriber.do_send(msg.0.clone());
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use super::{GameHost, GameTimer, Input, TurnTimeout};
+use crate::load::Load;
+use crate::room::actor::{Broadcast, PlayerInRoom};
+use crate::session::{
+    message::{Deadline, OutgoingMessage},
+    TransientId,
+};
+use actix::{AsyncContext, Context, SpawnHandle};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long (in seconds) the turn holder gets to submit an input before their turn is skipped,
+/// unless the room's leader picked a different duration
+pub const TURN_DURATION: u64 = 30;
+/// Number of consecutive turns a player can miss before they are marked as AFK
+const AFK_THRESHOLD: u8 = 2;
+/// Most turns a player gets made up for over a game, the ones they let run out past this are
+/// excused instead, see [SkippedTurn::Averaged]
+const MAX_AVERAGED_TURNS: u8 = 2;
+/// Most players announced ahead of their turn, see [Engine::upcoming_turns]
+pub const MAX_UPCOMING_TURNS: u8 = 2;
+
+/// Whether a player is still connected to the room, see
+/// [crate::room::RoomConfig::reconnect_grace_secs]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Connection {
+    Connected,
+    /// The player lost connection and their seat is held for them until they reconnect
+    Reconnecting,
+}
+
+/// How a game mode scores a turn that ran out without the turn holder playing, see
+/// [super::GameRules::skipped_turn]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SkippedTurn {
+    /// Counts towards the player being marked AFK, nothing is awarded
+    Missed,
+    /// Doesn't count against the player, nothing is awarded
+    Excused,
+    /// Doesn't count against the player, who is awarded the average of the points they earned
+    /// on the turns they did play. Only the first [MAX_AVERAGED_TURNS] of a game are made up for,
+    /// later ones are [SkippedTurn::Excused].
+    Averaged,
+}
+
+/// State tied to individual players such as their score
+pub struct PlayerState {
+    pub score: usize,
+    pub id: TransientId,
+    pub alive: bool,
+    pub connection: Connection,
+    /// Number of consecutive turns this player let run out without submitting anything
+    missed_turns: u8,
+    /// AFK players have their turns skipped until they submit an input again
+    afk: bool,
+    /// Turns the player was made up for, see [MAX_AVERAGED_TURNS]
+    averaged_turns: u8,
+}
+
+impl PlayerState {
+    fn new(id: TransientId) -> Self {
+        Self {
+            score: Default::default(),
+            id,
+            alive: true,
+            connection: Connection::Connected,
+            missed_turns: 0,
+            afk: false,
+            averaged_turns: 0,
+        }
+    }
+}
+
+impl From<&PlayerInRoom> for PlayerState {
+    fn from(value: &PlayerInRoom) -> Self {
+        Self::new(value.transient_id)
+    }
+}
+
+/// Why a player was awarded points
+#[derive(Serialize, Clone, Copy)]
+pub enum ScoreReason {
+    /// Guessed the secret word, worth a point per letter
+    WordLength,
+    /// Made up for a turn missed while reconnecting, see [SkippedTurn::Averaged]
+    Averaged,
+}
+
+/// Part of the points an input earned, see [Engine::award]
+#[derive(Serialize, Clone)]
+pub struct ScoreComponent {
+    pub reason: ScoreReason,
+    pub points: usize,
+}
+
+/// How a single turn played out, kept so that disputed results can be explained to the players
+#[derive(Serialize, Clone)]
+pub struct TurnRecord {
+    pub player: TransientId,
+    /// What the player submitted, nothing if their turn ran out
+    pub input: Option<Input>,
+    /// Milliseconds from the turn starting to the input, or to the turn running out
+    pub took: u64,
+    pub points: usize,
+    pub breakdown: Vec<ScoreComponent>,
+}
+
+/// The part of the game state that restored clients need regardless of the game mode
+#[derive(Serialize)]
+pub struct EngineState {
+    /// When the current turn runs out, if the turn timer is running
+    deadline: Option<Deadline>,
+    /// When the turn is handed to the next player, if it is being handed over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handoff: Option<Deadline>,
+    turn: Option<TransientId>,
+    score: usize,
+    /// The leader paused the game, see [Engine::pause]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    paused: bool,
+}
+
+/// What was left on the clock when the game was paused, see [Engine::pause]
+#[derive(Clone, Copy)]
+enum Paused {
+    Turn(Duration),
+    Handoff(Duration),
+}
+
+/// Mode agnostic game engine that keeps track of the players, whose turn it is, the turn timer and
+/// the scores. Game modes drive the engine from their own rules (see [super::GameRules]).
+pub struct Engine {
+    players: Vec<Option<PlayerState>>,
+    turn: usize,
+    /// When the current turn was handed to the turn holder
+    turn_started: Instant,
+    timer: Option<(SpawnHandle, Instant)>,
+    turn_duration: Duration,
+    /// How long the current turn lasts, which is longer than [Engine::turn_duration] if the
+    /// server was shedding load when it started, see [Load::stretch]
+    turn_length: Duration,
+    load: Arc<Load>,
+    /// Pause between two turns, see [Engine::next_turn]
+    handoff: Duration,
+    /// Pending end of the pause between two turns and when it fires
+    handoff_timer: Option<(SpawnHandle, Instant)>,
+    /// Set while the game is paused
+    paused: Option<Paused>,
+    /// How many of the following turn holders to announce on every turn change, zero to not
+    /// announce any
+    upcoming_turns: u8,
+    /// Every turn played so far, see [Engine::record_turn]
+    turn_log: Vec<TurnRecord>,
+    /// Points awarded for the input being handled, recorded along with it
+    awarded: Vec<ScoreComponent>,
+}
+
+impl Engine {
+    /// Seats the players at the same positions they have in the room
+    pub fn new(
+        players: &[Option<TransientId>],
+        turn_duration: Duration,
+        handoff: Duration,
+        upcoming_turns: u8,
+        load: Arc<Load>,
+    ) -> Self {
+        let players = players
+            .iter()
+            .map(|x| x.map(PlayerState::new))
+            .collect::<Vec<_>>();
+        Self {
+            players,
+            turn: 0,
+            turn_started: Instant::now(),
+            timer: None,
+            turn_duration,
+            turn_length: turn_duration,
+            load,
+            handoff,
+            handoff_timer: None,
+            paused: None,
+            upcoming_turns: upcoming_turns.min(MAX_UPCOMING_TURNS),
+            turn_log: Vec::new(),
+            awarded: Vec::new(),
+        }
+    }
+    /// Hands the first turn to the first alive player, if there is any.
+    pub fn begin<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        // The turn is advanced from the last slot so that the first alive player gets to go first
+        self.turn = self.players.len().saturating_sub(1);
+        self.hand_over(ctx);
+    }
+    pub fn player(&self, idx: usize) -> Option<&PlayerState> {
+        self.players.get(idx).and_then(|x| x.as_ref())
+    }
+    pub fn turn(&self) -> usize {
+        self.turn
+    }
+    pub fn turn_started(&self) -> Instant {
+        self.turn_started
+    }
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+    /// Final score of every player that took part in the game
+    pub fn scores(&self) -> Vec<(TransientId, usize)> {
+        self.players
+            .iter()
+            .flatten()
+            .map(|x| (x.id, x.score))
+            .collect()
+    }
+    /// Seats a player that joined while the game was already running. They start from scratch
+    /// and get their first turn once the rotation comes around to them.
+    pub fn add_player(&mut self, idx: usize, player: &PlayerInRoom) {
+        if idx >= self.players.len() {
+            self.players.resize_with(idx + 1, || None);
+        }
+        self.players[idx] = Some(PlayerState::from(player));
+    }
+    /// Adds to the player's score, the reason ends up in the turn log with the input that
+    /// earned the points
+    pub fn award(&mut self, idx: usize, reason: ScoreReason, points: usize) {
+        if let Some(Some(player)) = self.players.get_mut(idx) {
+            player.score += points;
+            self.awarded.push(ScoreComponent { reason, points });
+        }
+    }
+    pub fn set_connection(&mut self, idx: usize, connection: Connection) {
+        if let Some(Some(player)) = self.players.get_mut(idx) {
+            player.connection = connection;
+        }
+    }
+    /// Hands the seat over to the new transient id of a player who reconnected, along with the
+    /// turns they played so far
+    pub fn set_id(&mut self, idx: usize, id: TransientId) {
+        let Some(Some(player)) = self.players.get_mut(idx) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut player.id, id);
+        for turn in self.turn_log.iter_mut().filter(|x| x.player == previous) {
+            turn.player = id;
+        }
+    }
+    /// Points the player earned per turn they played, [None] if they haven't played any yet
+    fn average_points(&self, idx: usize) -> Option<usize> {
+        let id = self.player(idx)?.id;
+        let (turns, points) = self
+            .turn_log
+            .iter()
+            .filter(|turn| turn.player == id && turn.input.is_some())
+            .fold((0, 0), |(turns, points), turn| {
+                (turns + 1, points + turn.points)
+            });
+        (turns > 0).then(|| points / turns)
+    }
+    /// Logs the player's input, or their turn running out, with whatever was awarded for it
+    pub fn record_turn(&mut self, idx: usize, input: Option<&Input>, took: Duration) {
+        let breakdown = std::mem::take(&mut self.awarded);
+        let Some(player) = self.player(idx) else {
+            return;
+        };
+        let record = TurnRecord {
+            player: player.id,
+            input: input.cloned(),
+            took: took.as_millis() as u64,
+            points: breakdown.iter().map(|x| x.points).sum(),
+            breakdown,
+        };
+        self.turn_log.push(record);
+    }
+    /// Every turn played so far, oldest first
+    pub fn take_turn_log(&mut self) -> Vec<TurnRecord> {
+        std::mem::take(&mut self.turn_log)
+    }
+    /// (Re)starts the turn timer, cancelling the previous one if it is still pending.
+    pub fn start_turn_timer<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        self.run_turn_timer(ctx, self.turn_length);
+    }
+    fn run_turn_timer<H: GameHost>(&mut self, ctx: &mut Context<H>, duration: Duration) {
+        self.stop_turn_timer(ctx);
+        let handle = ctx.notify_later(TurnTimeout, duration);
+        self.timer = Some((handle, Instant::now() + duration));
+    }
+    pub fn stop_turn_timer<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        if let Some((handle, _)) = self.timer.take() {
+            ctx.cancel_future(handle);
+        }
+    }
+    /// Stops every timer of the engine for good, once the game is over
+    pub fn stop<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        self.stop_turn_timer(ctx);
+        if let Some((handle, _)) = self.handoff_timer.take() {
+            ctx.cancel_future(handle);
+        }
+        self.paused = None;
+    }
+    /// Stops the clock, keeping whatever was left of the turn or of the pause between two turns.
+    /// Nobody gets to play until the game is resumed.
+    pub fn pause<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        if self.paused.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        let paused = if let Some((handle, at)) = self.handoff_timer.take() {
+            ctx.cancel_future(handle);
+            Paused::Handoff(at.saturating_duration_since(now))
+        } else {
+            let left = self.timer.map_or(self.turn_length, |(_, at)| {
+                at.saturating_duration_since(now)
+            });
+            Paused::Turn(left)
+        };
+        self.stop_turn_timer(ctx);
+        self.paused = Some(paused);
+    }
+    /// Starts the clock again where [Engine::pause] stopped it, letting the players know when
+    /// the turn runs out now
+    pub fn resume<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        match self.paused.take() {
+            Some(Paused::Handoff(left)) => self.schedule_handoff(ctx, left),
+            Some(Paused::Turn(left)) => {
+                // The time spent paused doesn't count towards how long the turn took
+                let spent = self.turn_length.saturating_sub(left);
+                self.turn_started = Instant::now().checked_sub(spent).unwrap_or(self.turn_started);
+                self.run_turn_timer(ctx, left);
+                if let Some(player) = self.player(self.turn) {
+                    ctx.notify(Broadcast(OutgoingMessage::TurnUpdate {
+                        player: player.id,
+                        deadline: Deadline::after(left),
+                    }));
+                }
+            }
+            None => {}
+        }
+    }
+    pub fn paused(&self) -> bool {
+        self.paused.is_some()
+    }
+    /// Whether the turn is between two players, when nobody gets to play
+    pub fn handing_off(&self) -> bool {
+        self.handoff_timer.is_some() || matches!(self.paused, Some(Paused::Handoff(_)))
+    }
+    /// Ends the current turn. The next player gets the turn once the pause between two turns is
+    /// over, which clients are told about with [OutgoingMessage::TurnEnding] so that they can
+    /// all play the transition at the same time.
+    pub fn next_turn<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        if self.handoff.is_zero() {
+            self.hand_over(ctx);
+            return;
+        }
+        self.stop_turn_timer(ctx);
+        ctx.notify(Broadcast(OutgoingMessage::TurnEnding(Deadline::after(
+            self.handoff,
+        ))));
+        self.schedule_handoff(ctx, self.handoff);
+    }
+    fn schedule_handoff<H: GameHost>(&mut self, ctx: &mut Context<H>, delay: Duration) {
+        let handle = ctx.notify_later(GameTimer::Handoff, delay);
+        self.handoff_timer = Some((handle, Instant::now() + delay));
+    }
+    /// Hands the turn over to the next player who is still alive, skipping anyone marked as AFK.
+    /// If every remaining player is AFK, the turn simply goes to the next alive player. Nothing
+    /// happens if nobody is alive, the game is over by then.
+    pub fn hand_over<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        self.handoff_timer = None;
+        let Some(next) = self.next_after(self.turn) else {
+            log::warn!("no player is alive to hand the turn over to");
+            return;
+        };
+        self.turn = next;
+        self.turn_started = Instant::now();
+        self.turn_length = self.load.stretch(self.turn_duration);
+        let id = self.players[next].as_ref().unwrap().id;
+        ctx.notify(Broadcast(OutgoingMessage::TurnUpdate {
+            player: id,
+            deadline: Deadline::after(self.turn_length),
+        }));
+        if self.upcoming_turns > 0 {
+            ctx.notify(Broadcast(OutgoingMessage::UpcomingTurns(
+                self.upcoming_turns(),
+            )));
+        }
+        self.start_turn_timer(ctx);
+    }
+    /// Players who get the turn after the current turn holder, in order, assuming nobody
+    /// changes their AFK status in the meantime. Lets clients get ready ahead of time.
+    pub fn upcoming_turns(&self) -> Vec<TransientId> {
+        let mut upcoming = Vec::with_capacity(self.upcoming_turns as usize);
+        let mut idx = self.turn;
+        for _ in 0..self.upcoming_turns {
+            match self.next_after(idx) {
+                Some(next) if next != self.turn => idx = next,
+                _ => break,
+            }
+            upcoming.extend(self.player(idx).map(|x| x.id));
+        }
+        upcoming
+    }
+    /// Seat of the player who gets the turn after the one in seat `idx`, [None] if nobody is
+    /// alive
+    fn next_after(&self, idx: usize) -> Option<usize> {
+        let len = self.players.len();
+        let candidates = (1..=len).map(|offset| (idx + offset) % len);
+        candidates
+            .clone()
+            .find(|&idx| {
+                self.players[idx]
+                    .as_ref()
+                    .is_some_and(|state| state.alive && !state.afk)
+            })
+            .or_else(|| {
+                candidates.clone().find(|&idx| {
+                    self.players[idx]
+                        .as_ref()
+                        .is_some_and(|state| state.alive)
+                })
+            })
+    }
+    /// Records that the player submitted an input, bringing them back from AFK if necessary.
+    pub fn mark_active<H: GameHost>(&mut self, ctx: &mut Context<H>, idx: usize) {
+        if let Some(Some(player)) = self.players.get_mut(idx) {
+            if player.afk {
+                player.afk = false;
+                ctx.notify(Broadcast(OutgoingMessage::PlayerReturned(player.id)));
+            }
+            player.missed_turns = 0;
+        }
+    }
+    /// Scores the turn that ran out as the game mode wants it to, and moves on to the next
+    /// player. Turn holders charged with a missed turn are marked as AFK once they have missed
+    /// [AFK_THRESHOLD] turns in a row.
+    pub fn on_turn_timeout<H: GameHost>(&mut self, ctx: &mut Context<H>, skipped: SkippedTurn) {
+        self.timer = None;
+        let player = self.players.get_mut(self.turn).and_then(Option::as_mut);
+        if let (Some(player), SkippedTurn::Averaged) = (player, skipped) {
+            if player.averaged_turns < MAX_AVERAGED_TURNS {
+                player.averaged_turns += 1;
+                if let Some(points) = self.average_points(self.turn) {
+                    self.award(self.turn, ScoreReason::Averaged, points);
+                }
+            }
+        }
+        self.record_turn(self.turn, None, self.turn_started.elapsed());
+        let player = self.players.get_mut(self.turn).and_then(Option::as_mut);
+        if let (Some(player), SkippedTurn::Missed) = (player, skipped) {
+            player.missed_turns = player.missed_turns.saturating_add(1);
+            if !player.afk && player.missed_turns >= AFK_THRESHOLD {
+                player.afk = true;
+                ctx.notify(Broadcast(OutgoingMessage::PlayerAfk(player.id)));
+            }
+        }
+        self.next_turn(ctx);
+    }
+    pub fn get_state(&self, player: usize) -> EngineState {
+        let deadline = self
+            .timer
+            .map(|(_, trigger_time)| Deadline::at(trigger_time));
+        let score = self
+            .player(player)
+            .expect("player data cannot be empty!")
+            .score;
+        EngineState {
+            deadline,
+            handoff: self.handoff_timer.map(|(_, at)| Deadline::at(at)),
+            turn: self.player(self.turn).map(|x| x.id),
+            score,
+            paused: self.paused(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_go_nowhere_without_anyone_alive() {
+        let second = Duration::from_secs(1);
+        let empty = Engine::new(&[], second, second, MAX_UPCOMING_TURNS, Arc::default());
+        assert_eq!(empty.next_after(0), None);
+        assert!(empty.upcoming_turns().is_empty());
+
+        let players = [Some(TransientId::from(1)), None, Some(TransientId::from(3))];
+        let mut engine = Engine::new(&players, second, second, MAX_UPCOMING_TURNS, Arc::default());
+        assert_eq!(engine.next_after(2), Some(0));
+        assert_eq!(engine.next_after(0), Some(2));
+        for player in engine.players.iter_mut().flatten() {
+            player.alive = false;
+        }
+        assert_eq!(engine.next_after(0), None);
+    }
+}
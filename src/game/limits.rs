@@ -0,0 +1,140 @@
+//! Caps on the games running at once in every mode, so that operators can try out a new mode on
+//! part of the traffic without it taking over the server. Rooms take a slot of their mode when
+//! a game starts and give it back once the game is over.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::GameMode;
+
+struct ModeCounter {
+    mode: GameMode,
+    limit: Option<usize>,
+    running: AtomicUsize,
+}
+
+pub struct ModeLimits {
+    /// One counter for every mode, see [GameMode::ALL]
+    modes: Vec<ModeCounter>,
+}
+
+/// Whether a mode can be played right now, for clients to grey out the modes that can't
+#[derive(Serialize, Clone)]
+pub struct ModeAvailability {
+    pub mode: GameMode,
+    /// Games of the mode running on the server
+    pub running: usize,
+    /// Most games of the mode the server runs at once, unlimited if unset
+    pub limit: Option<usize>,
+    pub available: bool,
+}
+
+impl ModeLimits {
+    /// Reads `MODE_LIMITS`, comma separated `Mode=limit` pairs such as `Standard=100`. Modes
+    /// left out are unlimited.
+    pub fn from_env() -> Self {
+        Self::parse(&std::env::var("MODE_LIMITS").unwrap_or_default())
+    }
+    fn parse(limits: &str) -> Self {
+        let mut modes = GameMode::ALL
+            .iter()
+            .map(|&mode| ModeCounter {
+                mode,
+                limit: None,
+                running: AtomicUsize::new(0),
+            })
+            .collect::<Vec<_>>();
+        for entry in limits.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(mode, limit)| {
+                let mode = serde_json::from_value::<GameMode>(mode.trim().into()).ok()?;
+                Some((mode, limit.trim().parse().ok()?))
+            });
+            match parsed {
+                Some((mode, limit)) => {
+                    if let Some(counter) = modes.iter_mut().find(|x| x.mode == mode) {
+                        counter.limit = Some(limit);
+                    }
+                }
+                None => log::error!("ignoring malformed mode limit {entry}"),
+            }
+        }
+        Self { modes }
+    }
+    fn counter(&self, mode: GameMode) -> &ModeCounter {
+        self.modes
+            .iter()
+            .find(|x| x.mode == mode)
+            .expect("every mode has a counter")
+    }
+    pub fn at_capacity(&self, mode: GameMode) -> bool {
+        let counter = self.counter(mode);
+        counter
+            .limit
+            .is_some_and(|limit| counter.running.load(Ordering::Relaxed) >= limit)
+    }
+    /// Takes a slot for a game of the mode, unless the mode is at capacity
+    pub fn start(self: &Arc<Self>, mode: GameMode) -> Option<ModeSlot> {
+        let counter = self.counter(mode);
+        let limit = counter.limit.unwrap_or(usize::MAX);
+        counter
+            .running
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < limit).then_some(n + 1)
+            })
+            .ok()?;
+        Some(ModeSlot {
+            limits: self.clone(),
+            mode,
+        })
+    }
+    pub fn availability(&self) -> Vec<ModeAvailability> {
+        self.modes
+            .iter()
+            .map(|counter| {
+                let running = counter.running.load(Ordering::Relaxed);
+                ModeAvailability {
+                    mode: counter.mode,
+                    running,
+                    limit: counter.limit,
+                    available: counter.limit.is_none_or(|limit| running < limit),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A running game of the mode, given back when dropped
+pub struct ModeSlot {
+    limits: Arc<ModeLimits>,
+    mode: GameMode,
+}
+
+impl Drop for ModeSlot {
+    fn drop(&mut self) {
+        self.limits
+            .counter(self.mode)
+            .running
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_beyond_the_limit_are_refused() {
+        let limits = Arc::new(ModeLimits::parse("Standard=2, Unknown=5"));
+        let first = limits.start(GameMode::Standard).unwrap();
+        let _second = limits.start(GameMode::Standard).unwrap();
+        assert!(limits.at_capacity(GameMode::Standard));
+        assert!(limits.start(GameMode::Standard).is_none());
+        assert!(!limits.availability()[0].available);
+        drop(first);
+        assert!(!limits.at_capacity(GameMode::Standard));
+        assert_eq!(limits.availability()[0].running, 1);
+        let unlimited = Arc::new(ModeLimits::parse(""));
+        assert!(unlimited.start(GameMode::Standard).is_some());
+    }
+}
@@ -1,118 +1,220 @@
-use crate::room::actor::{PlayerInRoom, Room};
+use crate::load::Load;
+use crate::room::actor::{Broadcast, GameConfigOptions, PlayerInRoom, Room};
 use crate::session::TransientId;
-use actix::Context;
-use serde::Serialize;
+use actix::{Actor, Context, Handler, Message};
+use engine::{Connection, Engine, EngineState, SkippedTurn, TurnRecord};
+use serde::{Deserialize, Serialize};
+use standard::StandardGame;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use validation::{InputError, InputValidator};
+use words::WordList;
 
-/// Game state for client side state restoration upon reconnection */
+pub mod engine;
+pub mod limits;
+#[cfg(test)]
+mod replay;
+pub mod standard;
+pub mod summary;
+pub mod validation;
+pub mod words;
+
+/// Game state for client side state restoration upon reconnection
 #[derive(Serialize)]
-pub struct SerializedState {
-    word: String,
-    time_remaining: Option<u64>,
-    turn: TransientId,
-    score: usize,
+pub struct SerializedState<T> {
+    #[serde(flatten)]
+    engine: EngineState,
+    #[serde(flatten)]
+    mode: T,
 }
 
-/// State tied to individual players such as their score
-struct PlayerState {
-    score: usize,
-    id: TransientId,
-    alive: bool,
+/// Fired on the [Room] by the turn timer when the turn holder fails to submit an input in time.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct TurnTimeout;
+
+/// Fired on the [Room] by the timers game modes set on top of the turn timer, handed back to
+/// the mode through [GameRules::on_timer]
+#[derive(Message, Deserialize, Clone, Copy, Debug)]
+#[rtype(result = "()")]
+pub enum GameTimer {
+    /// The round ran out of time, see [GameConfigOptions::round_duration]
+    RoundOver,
+    /// Time to give the players another hint, see [GameConfigOptions::hint_interval]
+    Hint,
+    /// The pause between two turns is over, see [GameConfigOptions::turn_handoff]. Set by the
+    /// [Engine] and handled by it before modes get to see any timer.
+    Handoff,
 }
 
-/// Common game state, that applies to all game modes
-pub struct GameState {
-    player_data: Vec<Option<PlayerState>>,
-    word: String,
-    turn: usize,
-    timer: Option<(actix::SpawnHandle, std::time::Instant)>,
+/// Sent to the [Room] by the game mode once the game has reached its end.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct GameOver;
+
+/// Sent to the [Room] by the game mode when something happened that is worth watching again,
+/// which plays the last moments back to everyone, see [crate::room::instant_replay]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Highlight;
+
+/// Actor a game runs on and reports back to. This is always the [Room] except in tests, which
+/// run games on their own host to record what the game does.
+pub trait GameHost:
+    Actor<Context = Context<Self>>
+    + Handler<Broadcast>
+    + Handler<TurnTimeout>
+    + Handler<GameTimer>
+    + Handler<GameOver>
+    + Handler<Highlight>
+{
 }
 
-pub struct Game {
-    /// Common game state, that applies to all game modes
-    state: GameState,
+impl<T> GameHost for T where
+    T: Actor<Context = Context<T>>
+        + Handler<Broadcast>
+        + Handler<TurnTimeout>
+        + Handler<GameTimer>
+        + Handler<GameOver>
+        + Handler<Highlight>
+{
 }
 
-impl From<&PlayerInRoom> for PlayerState {
-    fn from(value: &PlayerInRoom) -> Self {
-        Self {
-            score: Default::default(),
-            id: value.transient_id,
-            alive: true,
+/// Rules of a specific game mode. Modes only hold their own state and drive the mode agnostic
+/// [Engine] (players, turns, timers, scores) through the hooks below.
+pub trait GameRules {
+    /// Mode specific part of the state sent to restoring clients
+    type State: Serialize;
+    fn on_begin<H: GameHost>(&mut self, engine: &mut Engine, ctx: &mut Context<H>);
+    fn on_input<H: GameHost>(
+        &mut self,
+        engine: &mut Engine,
+        ctx: &mut Context<H>,
+        player: usize,
+        input: &Input,
+    );
+    fn get_state(&self, engine: &Engine, player: usize) -> Self::State;
+    /// How to score a turn that ran out, depending on whether the turn holder was connected.
+    /// Turns missed while reconnecting are excused unless the mode says otherwise.
+    fn skipped_turn(&self, connection: Connection) -> SkippedTurn {
+        match connection {
+            Connection::Connected => SkippedTurn::Missed,
+            Connection::Reconnecting => SkippedTurn::Excused,
         }
     }
+    /// Called when one of the timers the mode set goes off
+    fn on_timer<H: GameHost>(&mut self, _: &mut Engine, _: &mut Context<H>, _: GameTimer) {}
+    /// Called once the game is over, for the mode to cancel the timers it set
+    fn on_end<H: GameHost>(&mut self, _: &mut Context<H>) {}
+    /// Called when the game is paused, for the mode to stop the timers it set
+    fn on_pause<H: GameHost>(&mut self, _: &mut Context<H>) {}
+    /// Called when the game is resumed, for the mode to start its timers again
+    fn on_resume<H: GameHost>(&mut self, _: &mut Context<H>) {}
+    /// Whether the text gives away something that must stay hidden from players, such as the
+    /// secret word. Used to suppress chat messages while a game is running.
+    fn is_secret(&self, _text: &str) -> bool {
+        false
+    }
+    /// Player count below which the mode lets new players join a running game, [None] if it
+    /// doesn't support mid-game joins at all.
+    fn backfill_threshold(&self) -> Option<usize> {
+        None
+    }
+    /// Whether turns are handed over right away whatever pause the room set between them, for
+    /// fast paced (blitz) modes
+    fn instant_handoff(&self) -> bool {
+        false
+    }
+}
+
+/// A running game: the shared [Engine] paired with the rules of the selected game mode
+pub struct Game<R, H = Room> {
+    engine: Engine,
+    validator: InputValidator,
+    rules: R,
+    host: PhantomData<H>,
 }
 
-impl Game {
-    pub fn new(players: &Vec<Option<PlayerInRoom>>, game_mode: GameMode) -> Self {
-        let player_data = players
-            .iter()
-            .map(|x| x.as_ref().map(|x| PlayerState::from(x)))
-            .collect::<Vec<_>>();
-        let state = GameState {
-            player_data,
-            word: "".to_string(),
-            turn: 0,
-            timer: None,
+impl<R: GameRules, H: GameHost> Game<R, H> {
+    /// `players` holds the transient id of the player in every seat of the room, turns last
+    /// longer while `load` is degraded
+    pub fn new(
+        players: &[Option<TransientId>],
+        config: &GameConfigOptions,
+        rules: R,
+        load: Arc<Load>,
+    ) -> Self {
+        let handoff = match rules.instant_handoff() {
+            true => Duration::ZERO,
+            false => config.turn_handoff,
         };
-        Self { state }
-    }
-    pub fn get_state(&self, player: usize) -> SerializedState {
-        let state = &self.state;
-        let word = state.word.clone();
-        let time_remaining = state.timer.map(|(_, trigger_time)| {
-            trigger_time
-                .duration_since(std::time::Instant::now())
-                .as_secs()
-        });
-        let turn = state.turn;
-        let score = state
-            .player_data
-            .get(player)
-            .expect("data doesnt exist for player!")
-            .as_ref()
-            .expect("player data cannot be empty!")
-            .score;
-        SerializedState {
-            word,
-            time_remaining,
-            turn: turn.try_into().unwrap(),
-            score,
+        let engine = Engine::new(
+            players,
+            config.turn_duration,
+            handoff,
+            config.upcoming_turns,
+            load,
+        );
+        let validator = InputValidator::new(config.validation.clone(), engine.player_count());
+        Self {
+            engine,
+            validator,
+            rules,
+            host: PhantomData,
         }
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum GameMode {
-    Standard,
-}
+/// Type erased game as held by the [Room]. Each mode's typed state is converted into a
+/// [serde_json::Value] at this boundary so that rooms don't have to know about specific modes.
+pub type Controller =
+    dyn GameController<Ctx = Context<Room>, GameInput = Input, SerializedState = serde_json::Value>;
 
-impl Default for GameMode {
-    fn default() -> Self {
-        GameMode::Standard
+/// Creates a new game for the given game mode, in the room's language unless the room has words
+/// of its own
+pub fn new_game(
+    players: &[Option<PlayerInRoom>],
+    config: &GameConfigOptions,
+    language: Option<&str>,
+    load: Arc<Load>,
+) -> Box<Controller> {
+    let players = players
+        .iter()
+        .map(|x| x.as_ref().map(|x| x.transient_id))
+        .collect::<Vec<_>>();
+    let players = players.as_slice();
+    match config.mode {
+        GameMode::Standard => {
+            let words = match &config.words {
+                Some(words) => WordList::Custom(Arc::clone(words)),
+                None => WordList::Builtin(words::words(language)),
+            };
+            let rules = match config.seed {
+                Some(seed) => StandardGame::with_seed(words, seed.0),
+                None => StandardGame::new(words),
+            };
+            let rules = rules.with_timers(config.round_duration, config.hint_interval);
+            Box::new(Game::new(players, config, rules, load))
+        }
     }
 }
 
-/// Example implementation of a game mode
-struct StandardGame {}
-
-impl StandardGame {
-    fn next_turn(&self, state: &mut GameState, room: &Room) {
-        state.turn = if let Some(next) = state.player_data.as_slice()[state.turn..]
-            .iter()
-            .position(|x| x.as_ref().map_or(false, |state| state.alive))
-        {
-            next
-        } else {
-            state
-                .player_data
-                .iter()
-                .position(|x| x.as_ref().map_or(false, |state| state.alive))
-                .expect("everyone cannot be dead!")
-        };
-        room.notify_clients(
-            crate::session::message::OutgoingMessage::TurnUpdate(room.get_id(state.turn).unwrap()),
-            None,
-        );
+#[derive(Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum GameMode {
+    #[default]
+    Standard,
+}
+
+impl GameMode {
+    /// Every mode the server can run
+    pub const ALL: &[GameMode] = &[GameMode::Standard];
+    /// Whether games of the mode can limit their rounds and give hints, see
+    /// [GameConfigOptions::round_duration] and [GameConfigOptions::hint_interval]
+    pub fn timed(self) -> bool {
+        match self {
+            GameMode::Standard => true,
+        }
     }
 }
 
@@ -124,30 +226,126 @@ pub trait GameController {
     fn on_end(&mut self, ctx: &mut Self::Ctx);
     fn on_pause(&mut self, ctx: &mut Self::Ctx);
     fn on_resume(&mut self, ctx: &mut Self::Ctx);
-    fn on_input(&mut self, ctx: &mut Self::Ctx, input: &Self::GameInput);
+    fn paused(&self) -> bool;
+    fn on_input(
+        &mut self,
+        ctx: &mut Self::Ctx,
+        player: usize,
+        input: &Self::GameInput,
+    ) -> Result<(), InputError>;
+    /// Called when the turn holder runs out of time without submitting an input
+    fn on_turn_timeout(&mut self, ctx: &mut Self::Ctx);
+    fn on_timer(&mut self, ctx: &mut Self::Ctx, timer: GameTimer);
+    /// Called when a player loses connection or comes back, see [engine::Connection]
+    fn set_connection(&mut self, player: usize, connection: Connection);
+    /// Called when a player reconnects under a new transient id, see [engine::Engine::set_id]
+    fn set_id(&mut self, player: usize, id: TransientId);
     fn get_state(&self, player: usize) -> Self::SerializedState;
+    fn is_secret(&self, text: &str) -> bool;
+    fn scores(&self) -> Vec<(TransientId, usize)>;
+    /// Every turn of the game, see [engine::Engine::record_turn]
+    fn take_turn_log(&mut self) -> Vec<TurnRecord>;
+    /// Whether the game would take in another player given how many are currently in the room
+    fn wants_players(&self, player_count: usize) -> bool;
+    fn on_player_joined(&mut self, ctx: &mut Self::Ctx, player: usize, info: &PlayerInRoom);
 }
 
+/// Gameplay input submitted by a client, routed to the running game through its room
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "data")]
 pub enum Input {
-    Word(String)
+    Word(String),
 }
 
-impl GameController for Game {
-    type Ctx = Context<Room>;
+impl<R: GameRules, H: GameHost> GameController for Game<R, H> {
+    type Ctx = Context<H>;
     type GameInput = Input;
-    type SerializedState = String;
+    type SerializedState = serde_json::Value;
     fn on_begin(&mut self, ctx: &mut Self::Ctx) {
+        self.rules.on_begin(&mut self.engine, ctx);
+        self.engine.begin(ctx);
+    }
+    fn on_input(
+        &mut self,
+        ctx: &mut Self::Ctx,
+        player: usize,
+        input: &Self::GameInput,
+    ) -> Result<(), InputError> {
+        // Any input at all is proof of presence, even if it ends up being rejected
+        self.engine.mark_active(ctx, player);
+        if let Err(err) = self.validator.validate(&self.engine, player, input) {
+            let id = self.engine.player(player).map(|x| x.id);
+            log::warn!("rejected input from player {id:?}: {err:?}");
+            return Err(err);
+        }
+        // Taken before the rules get to hand the turn to the next player
+        let took = self.engine.turn_started().elapsed();
+        self.rules.on_input(&mut self.engine, ctx, player, input);
+        self.engine.record_turn(player, Some(input), took);
+        Ok(())
     }
-    fn on_input(&mut self, ctx: &mut Self::Ctx, input: &Self::GameInput) {
+    fn on_turn_timeout(&mut self, ctx: &mut Self::Ctx) {
+        let connection = self
+            .engine
+            .player(self.engine.turn())
+            .map_or(Connection::Connected, |x| x.connection);
+        let skipped = self.rules.skipped_turn(connection);
+        self.engine.on_turn_timeout(ctx, skipped);
+    }
+    fn on_timer(&mut self, ctx: &mut Self::Ctx, timer: GameTimer) {
+        match timer {
+            GameTimer::Handoff => self.engine.hand_over(ctx),
+            timer => self.rules.on_timer(&mut self.engine, ctx, timer),
+        }
+    }
+    fn set_connection(&mut self, player: usize, connection: Connection) {
+        self.engine.set_connection(player, connection);
+    }
+    fn set_id(&mut self, player: usize, id: TransientId) {
+        self.engine.set_id(player, id);
     }
     fn on_pause(&mut self, ctx: &mut Self::Ctx) {
+        if !self.engine.paused() {
+            self.engine.pause(ctx);
+            self.rules.on_pause(ctx);
+        }
     }
     fn on_resume(&mut self, ctx: &mut Self::Ctx) {
+        if self.engine.paused() {
+            self.rules.on_resume(ctx);
+            self.engine.resume(ctx);
+        }
+    }
+    fn paused(&self) -> bool {
+        self.engine.paused()
     }
     fn on_end(&mut self, ctx: &mut Self::Ctx) {
+        self.engine.stop(ctx);
+        self.rules.on_end(ctx);
+    }
+    fn get_state(&self, player: usize) -> Self::SerializedState {
+        let state = SerializedState {
+            engine: self.engine.get_state(player),
+            mode: self.rules.get_state(&self.engine, player),
+        };
+        serde_json::to_value(&state).expect("game state must be serializable")
+    }
+    fn is_secret(&self, text: &str) -> bool {
+        self.rules.is_secret(text)
     }
-    fn get_state<'a>(&'a self, player: usize) -> Self::SerializedState {
-        serde_json::to_string(&self.get_state(player)).unwrap()
+    fn scores(&self) -> Vec<(TransientId, usize)> {
+        self.engine.scores()
+    }
+    fn take_turn_log(&mut self) -> Vec<TurnRecord> {
+        self.engine.take_turn_log()
+    }
+    fn wants_players(&self, player_count: usize) -> bool {
+        self.rules
+            .backfill_threshold()
+            .is_some_and(|threshold| player_count < threshold)
+    }
+    fn on_player_joined(&mut self, _: &mut Self::Ctx, player: usize, info: &PlayerInRoom) {
+        self.engine.add_player(player, info);
+        self.validator.add_player(player);
     }
 }
-
@@ -0,0 +1,303 @@
+//! Regression tests that replay recorded games through the engine. Every file in `replays/` at
+//! the root of the repository holds the seed and the players of a game, the inputs, timeouts and
+//! timers that happened during it, and the scores and events the game is expected to produce.
+//! Any rule change that makes a replay diverge fails the tests. Intended changes are recorded by
+//! running the tests with `UPDATE_REPLAYS` set, which rewrites the expectations in place.
+
+use super::engine::Connection;
+use super::standard::StandardGame;
+use super::validation::ValidationConfig;
+use super::words::{self, WordList};
+use super::{Game, GameController, GameOver, GameTimer, Highlight, Input, TurnTimeout};
+use crate::room::actor::{Broadcast, GameConfigOptions, GameInputError};
+use crate::session::message::{OutgoingMessage, Result};
+use crate::session::TransientId;
+use actix::{Actor, Context, Handler, Message, MessageResult};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct Replay {
+    seed: u64,
+    /// Transient id of the player in every seat
+    players: Vec<Option<TransientId>>,
+    steps: Vec<Step>,
+    /// See [GameConfigOptions::upcoming_turns]
+    #[serde(default)]
+    upcoming_turns: u8,
+    /// Language of the room, English if unset
+    #[serde(default)]
+    language: Option<String>,
+    /// Seconds, see [GameConfigOptions::round_duration]
+    #[serde(default)]
+    round_duration: Option<u64>,
+    /// Seconds, see [GameConfigOptions::hint_interval]
+    #[serde(default)]
+    hint_interval: Option<u64>,
+    /// Seconds, see [GameConfigOptions::turn_handoff]
+    #[serde(default)]
+    turn_handoff: u64,
+    expected: Option<Outcome>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    Input {
+        player: usize,
+        input: Input,
+    },
+    /// The turn holder ran out of time
+    Timeout,
+    /// One of the timers the game set went off
+    Timer(GameTimer),
+    /// The player in the seat lost connection or came back
+    Connection {
+        player: usize,
+        connection: Connection,
+    },
+    /// The leader paused the game
+    Pause,
+    Resume,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Outcome {
+    scores: Vec<(TransientId, usize)>,
+    /// Everything the game broadcast or answered, in order
+    events: Vec<Value>,
+}
+
+/// Stands in for the room, running the game and recording everything it sends out
+struct ReplayHost {
+    game: Game<StandardGame, ReplayHost>,
+    events: Vec<Value>,
+}
+
+impl ReplayHost {
+    fn record(&mut self, msg: OutgoingMessage) {
+        let mut event = serde_json::to_value(msg).expect("events must be serializable");
+        // Deadlines are on the wall clock, only the time left on them is the same every run
+        for pointer in ["/data/deadline", "/data"] {
+            if let Some(Value::Object(deadline)) = event.pointer_mut(pointer) {
+                deadline.remove("at");
+            }
+        }
+        self.events.push(event);
+    }
+}
+
+impl Actor for ReplayHost {
+    type Context = Context<Self>;
+}
+
+/// The game and every step of it are driven through messages, awaited one after the other, so
+/// that whatever the game queued up on the host is handled before the next step is played
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Begin;
+
+impl Handler<Begin> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, _: Begin, ctx: &mut Self::Context) -> Self::Result {
+        self.game.on_begin(ctx);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Play(Step);
+
+impl Handler<Play> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, msg: Play, ctx: &mut Self::Context) -> Self::Result {
+        match msg.0 {
+            Step::Input { player, input } => {
+                let result = match self.game.on_input(ctx, player, &input) {
+                    Ok(()) => Result::Success(()),
+                    Err(err) => Result::Error(GameInputError::Rejected(err)),
+                };
+                self.record(OutgoingMessage::GameInputResult(result));
+            }
+            Step::Timeout => self.game.on_turn_timeout(ctx),
+            Step::Timer(timer) => self.game.on_timer(ctx, timer),
+            Step::Connection { player, connection } => self.game.set_connection(player, connection),
+            Step::Pause => self.game.on_pause(ctx),
+            Step::Resume => self.game.on_resume(ctx),
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Outcome")]
+struct Finish;
+
+impl Handler<Finish> for ReplayHost {
+    type Result = MessageResult<Finish>;
+    fn handle(&mut self, _: Finish, ctx: &mut Self::Context) -> Self::Result {
+        self.game.on_end(ctx);
+        MessageResult(Outcome {
+            scores: self.game.scores(),
+            events: std::mem::take(&mut self.events),
+        })
+    }
+}
+
+impl Handler<Broadcast> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, msg: Broadcast, _: &mut Self::Context) -> Self::Result {
+        self.record(msg.0);
+    }
+}
+
+impl Handler<Highlight> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, _: Highlight, _: &mut Self::Context) -> Self::Result {
+        self.events.push(Value::from("Highlight"));
+    }
+}
+
+impl Handler<GameOver> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, _: GameOver, _: &mut Self::Context) -> Self::Result {
+        self.events.push(Value::from("GameOver"));
+    }
+}
+
+/// Replays play their timeouts as steps, the ones going off on the wall clock are left alone
+impl Handler<TurnTimeout> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, _: TurnTimeout, _: &mut Self::Context) -> Self::Result {
+        log::warn!("ignoring a turn timeout outside of the replay's steps");
+    }
+}
+
+/// Same as for [TurnTimeout]
+impl Handler<GameTimer> for ReplayHost {
+    type Result = ();
+    fn handle(&mut self, timer: GameTimer, _: &mut Self::Context) -> Self::Result {
+        log::warn!("ignoring {timer:?} outside of the replay's steps");
+    }
+}
+
+async fn play(replay: Replay) -> Outcome {
+    let config = GameConfigOptions {
+        // Replays are played back as fast as possible
+        validation: ValidationConfig {
+            min_input_delay: Duration::ZERO,
+        },
+        upcoming_turns: replay.upcoming_turns,
+        turn_handoff: Duration::from_secs(replay.turn_handoff),
+        ..Default::default()
+    };
+    let words = WordList::Builtin(words::words(replay.language.as_deref()));
+    let rules = StandardGame::with_seed(words, replay.seed).with_timers(
+        replay.round_duration.map(Duration::from_secs),
+        replay.hint_interval.map(Duration::from_secs),
+    );
+    let host = ReplayHost {
+        game: Game::new(&replay.players, &config, rules, Arc::default()),
+        events: Vec::new(),
+    }
+    .start();
+    host.send(Begin).await.unwrap();
+    for step in replay.steps {
+        host.send(Play(step)).await.unwrap();
+    }
+    host.send(Finish).await.unwrap()
+}
+
+#[actix::test]
+async fn replays_match_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("replays");
+    let update = std::env::var_os("UPDATE_REPLAYS").is_some();
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+    assert!(!files.is_empty(), "no replays to check");
+    for path in files {
+        let text = std::fs::read_to_string(&path).unwrap();
+        let replay: Replay = serde_json::from_str(&text).unwrap();
+        let expected = serde_json::to_value(&replay.expected).unwrap();
+        let outcome = play(replay).await;
+        if update {
+            let mut file: Value = serde_json::from_str(&text).unwrap();
+            file["expected"] = serde_json::to_value(&outcome).unwrap();
+            let text = serde_json::to_string_pretty(&file).unwrap() + "\n";
+            std::fs::write(&path, text).unwrap();
+        } else {
+            assert_eq!(
+                expected,
+                serde_json::to_value(&outcome).unwrap(),
+                "{} diverged from its recording",
+                path.display()
+            );
+        }
+    }
+}
+
+/// Resuming hands out whatever was left on the clock, which differs from run to run by the time
+/// it takes to play the steps, so pauses are checked here rather than in a recording
+#[actix::test]
+async fn pauses_hold_the_turn_and_the_handoff() {
+    let guess = |player, word: &str| Step::Input {
+        player,
+        input: Input::Word(word.to_owned()),
+    };
+    let replay = Replay {
+        seed: 7,
+        players: vec![Some(10.into()), Some(12.into())],
+        steps: vec![
+            Step::Pause,
+            guess(0, "zzzz"),
+            Step::Resume,
+            guess(0, "zzzz"),
+            Step::Pause,
+            Step::Resume,
+            guess(1, "zzzz"),
+        ],
+        upcoming_turns: 0,
+        language: None,
+        round_duration: Some(60),
+        hint_interval: None,
+        turn_handoff: 5,
+        expected: None,
+    };
+    let events = play(replay).await.events;
+    let results = events
+        .iter()
+        .filter(|x| x["kind"] == "GameInputResult")
+        .map(|x| x["data"]["status"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, ["Error", "Success", "Error"]);
+    let kinds = events
+        .iter()
+        .map(|x| x["kind"].as_str().unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            "WordUpdate",
+            "RoundDeadline",
+            "TurnUpdate",
+            // Paused
+            "GameInputResult",
+            // Resumed
+            "RoundDeadline",
+            "TurnUpdate",
+            "GameInputResult",
+            "TurnEnding",
+            // Paused and resumed while handing off, the turn stays between the players
+            "RoundDeadline",
+            "GameInputResult",
+        ]
+    );
+    let resumed = &events[5]["data"]["deadline"]["remaining"];
+    assert!((25_000..30_000).contains(&resumed.as_u64().unwrap()));
+}
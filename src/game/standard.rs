@@ -0,0 +1,236 @@
+use super::engine::{Connection, Engine, ScoreReason, SkippedTurn};
+use super::words::WordList;
+use super::{GameHost, GameOver, GameRules, GameTimer, Highlight, Input};
+use crate::room::actor::Broadcast;
+use crate::session::message::{Deadline, OutgoingMessage};
+use actix::{AsyncContext, Context, SpawnHandle};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Number of words that have to be guessed before the game ends
+const ROUNDS: usize = 5;
+/// Running games with fewer players than this are topped up with random joiners
+const BACKFILL_BELOW: usize = 4;
+/// Words guessed with less time than this left in the round are played back to the room
+const CLOSE_CALL: Duration = Duration::from_secs(3);
+
+/// Word game specific part of the state sent to restoring clients
+#[derive(Serialize)]
+pub struct StandardState {
+    /// The secret word with every letter masked out
+    word: String,
+    round: usize,
+    /// When the round runs out of time, if the room set a limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    round_deadline: Option<Deadline>,
+}
+
+/// The standard word guessing mode: a secret word is picked every round and players take turns
+/// guessing it. A correct guess is awarded as many points as the word has letters. Rooms can put
+/// a time limit on every round, after which the word is given away and the next one dealt, and
+/// have the letters of the word revealed one by one as hints.
+pub struct StandardGame {
+    /// Words of the room's language or the ones its leader gave
+    words: WordList,
+    word: String,
+    round: usize,
+    /// Picks the secret words, seeded for reproducible games
+    rng: fastrand::Rng,
+    round_duration: Option<Duration>,
+    hint_interval: Option<Duration>,
+    /// Pending end of the current round and when it fires
+    round_timer: Option<(SpawnHandle, Instant)>,
+    hint_timer: Option<SpawnHandle>,
+    /// Time that was left in the round when the game was paused
+    paused_round: Option<Duration>,
+    /// Letters of the word given away as hints so far, from the first one on
+    revealed: usize,
+}
+
+impl StandardGame {
+    pub fn new(words: WordList) -> Self {
+        Self::with_rng(words, fastrand::Rng::new())
+    }
+    /// Same as [StandardGame::new] but always picks the same sequence of words for a given seed
+    pub fn with_seed(words: WordList, seed: u64) -> Self {
+        Self::with_rng(words, fastrand::Rng::with_seed(seed))
+    }
+    fn with_rng(words: WordList, rng: fastrand::Rng) -> Self {
+        Self {
+            words,
+            word: String::new(),
+            round: 0,
+            rng,
+            round_duration: None,
+            hint_interval: None,
+            round_timer: None,
+            hint_timer: None,
+            paused_round: None,
+            revealed: 0,
+        }
+    }
+    /// Limits every round to `round_duration` and reveals a letter every `hint_interval`
+    pub fn with_timers(
+        mut self,
+        round_duration: Option<Duration>,
+        hint_interval: Option<Duration>,
+    ) -> Self {
+        self.round_duration = round_duration;
+        self.hint_interval = hint_interval;
+        self
+    }
+    fn new_word<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        self.stop_timers(ctx);
+        self.word = self.words.pick(&mut self.rng).to_string();
+        self.revealed = 0;
+        ctx.notify(Broadcast(OutgoingMessage::WordUpdate(self.masked())));
+        if let Some(duration) = self.round_duration {
+            self.start_round_timer(ctx, duration);
+        }
+        self.schedule_hint(ctx);
+    }
+    fn start_round_timer<H: GameHost>(&mut self, ctx: &mut Context<H>, duration: Duration) {
+        let handle = ctx.notify_later(GameTimer::RoundOver, duration);
+        self.round_timer = Some((handle, Instant::now() + duration));
+        ctx.notify(Broadcast(OutgoingMessage::RoundDeadline(Deadline::after(
+            duration,
+        ))));
+    }
+    /// Sets the timer for the next hint, unless every letter that can be revealed already is.
+    /// At least half of the word is always left to guess.
+    fn schedule_hint<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        if let Some(interval) = self.hint_interval {
+            if self.revealed < self.word.chars().count() / 2 {
+                self.hint_timer = Some(ctx.notify_later(GameTimer::Hint, interval));
+            }
+        }
+    }
+    fn stop_timers<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        self.paused_round = None;
+        if let Some((handle, _)) = self.round_timer.take() {
+            ctx.cancel_future(handle);
+        }
+        if let Some(handle) = self.hint_timer.take() {
+            ctx.cancel_future(handle);
+        }
+    }
+    /// The word with the letters that haven't been revealed masked out
+    fn masked(&self) -> String {
+        self.word
+            .chars()
+            .enumerate()
+            .map(|(idx, x)| if idx < self.revealed { x } else { '_' })
+            .collect()
+    }
+    /// Moves on to the next word, or ends the game after the last round
+    fn end_round<H: GameHost>(&mut self, engine: &mut Engine, ctx: &mut Context<H>) -> bool {
+        self.round += 1;
+        if self.round >= ROUNDS {
+            self.stop_timers(ctx);
+            engine.stop_turn_timer(ctx);
+            ctx.notify(GameOver);
+            return false;
+        }
+        self.new_word(ctx);
+        true
+    }
+}
+
+impl GameRules for StandardGame {
+    type State = StandardState;
+    fn on_begin<H: GameHost>(&mut self, _: &mut Engine, ctx: &mut Context<H>) {
+        self.new_word(ctx);
+    }
+    fn on_input<H: GameHost>(
+        &mut self,
+        engine: &mut Engine,
+        ctx: &mut Context<H>,
+        player: usize,
+        input: &Input,
+    ) {
+        match input {
+            Input::Word(guess) => {
+                if guess.eq_ignore_ascii_case(&self.word) {
+                    engine.award(player, ScoreReason::WordLength, self.word.len());
+                    let id = engine.player(player).expect("turn holder must exist").id;
+                    ctx.notify(Broadcast(OutgoingMessage::WordGuessed {
+                        player: id,
+                        word: self.word.clone(),
+                    }));
+                    let now = Instant::now();
+                    let left = self
+                        .round_timer
+                        .map(|(_, at)| at.saturating_duration_since(now));
+                    if left.is_some_and(|left| left < CLOSE_CALL) {
+                        ctx.notify(Highlight);
+                    }
+                    if !self.end_round(engine, ctx) {
+                        return;
+                    }
+                }
+                engine.next_turn(ctx);
+            }
+        }
+    }
+    fn get_state(&self, _: &Engine, _: usize) -> Self::State {
+        StandardState {
+            word: self.masked(),
+            round: self.round,
+            round_deadline: self.round_timer.map(|(_, at)| Deadline::at(at)),
+        }
+    }
+    fn on_timer<H: GameHost>(
+        &mut self,
+        engine: &mut Engine,
+        ctx: &mut Context<H>,
+        timer: GameTimer,
+    ) {
+        match timer {
+            GameTimer::RoundOver => {
+                self.round_timer = None;
+                ctx.notify(Broadcast(OutgoingMessage::WordExpired(self.word.clone())));
+                self.end_round(engine, ctx);
+            }
+            GameTimer::Hint => {
+                self.hint_timer = None;
+                self.revealed += 1;
+                ctx.notify(Broadcast(OutgoingMessage::WordUpdate(self.masked())));
+                self.schedule_hint(ctx);
+            }
+            // Handled by the engine, see [GameRules::instant_handoff]
+            GameTimer::Handoff => {}
+        }
+    }
+    /// Players who drop out of a long game shouldn't fall hopelessly behind, so the first few
+    /// turns missed while reconnecting are worth what the player usually scores
+    fn skipped_turn(&self, connection: Connection) -> SkippedTurn {
+        match connection {
+            Connection::Connected => SkippedTurn::Missed,
+            Connection::Reconnecting => SkippedTurn::Averaged,
+        }
+    }
+    fn on_end<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        self.stop_timers(ctx);
+    }
+    /// Keeps what was left of the round. The next hint comes a whole interval after the game
+    /// is resumed.
+    fn on_pause<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        let left = self
+            .round_timer
+            .map(|(_, at)| at.saturating_duration_since(Instant::now()));
+        self.stop_timers(ctx);
+        self.paused_round = left;
+    }
+    fn on_resume<H: GameHost>(&mut self, ctx: &mut Context<H>) {
+        if let Some(left) = self.paused_round.take() {
+            self.start_round_timer(ctx, left);
+        }
+        self.schedule_hint(ctx);
+    }
+    fn is_secret(&self, text: &str) -> bool {
+        text.trim().eq_ignore_ascii_case(&self.word)
+    }
+    fn backfill_threshold(&self) -> Option<usize> {
+        Some(BACKFILL_BELOW)
+    }
+}
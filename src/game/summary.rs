@@ -0,0 +1,245 @@
+use super::engine::TurnRecord;
+use crate::jobs::Job;
+use crate::room::metadata::RoomMetadata;
+use crate::room::vault::Vault;
+use crate::session::{message::PlayerResult, TransientId};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Standings sent to every member of the room once a game ends
+#[derive(Serialize, Clone)]
+pub struct GameSummary {
+    /// Sorted from the highest to the lowest score
+    pub standings: Vec<PlayerResult>,
+    /// Everyone tied for the highest score
+    pub winners: Vec<TransientId>,
+    /// Every turn of the game, only sent to the players if the room shares its turn log
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub turns: Vec<TurnRecord>,
+    /// Details the leader attached to the room the game was played in
+    #[serde(skip_serializing_if = "RoomMetadata::is_empty")]
+    pub metadata: RoomMetadata,
+}
+
+/// Keeps the summaries of finished games, turn log included, so that disputed results can be
+/// looked into later. Every summary is appended to the file named by `GAME_SUMMARY_LOG` as a JSON
+/// line, or logged under the `game_summary` target if unset. Summaries of games played in privacy
+/// mode are sealed with the key of their room, see [Vault], as are the trails of such rooms.
+#[derive(Default)]
+pub struct GameArchive {
+    file: Option<Mutex<File>>,
+    /// Where the summaries are appended to, read back by [GameArchive::export]
+    path: Option<PathBuf>,
+    vault: Vault,
+}
+
+impl GameArchive {
+    pub fn from_env() -> Self {
+        let vault = Vault::from_env();
+        let Ok(path) = std::env::var("GAME_SUMMARY_LOG") else {
+            return Self {
+                vault,
+                ..Default::default()
+            };
+        };
+        Self::open(Path::new(&path), vault)
+            .map_err(|err| log::error!("cannot open game summary log {path}: {err}"))
+            .unwrap_or_default()
+    }
+    fn open(path: &Path, vault: Vault) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+            path: Some(path.into()),
+            vault,
+        })
+    }
+    /// Seals data the room leaves behind with its key
+    pub fn seal(&self, room: &[u8], data: &[u8]) -> String {
+        self.vault.seal(room, data)
+    }
+    /// Opens what [GameArchive::seal] sealed, only meant for the admin archive export
+    pub fn open_sealed(&self, room: &[u8], sealed: &str) -> Option<Vec<u8>> {
+        self.vault.open(room, sealed)
+    }
+    /// Every summary archived for the room, oldest first, with the sealed ones opened. Summaries
+    /// that no longer open, sealed with another key, are left sealed. Only meant for the admin
+    /// archive export, nothing if summaries are only logged.
+    pub fn export(&self, room: &str) -> std::io::Result<Vec<Value>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let text = std::fs::read_to_string(path)?;
+        let entries = text
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter(|entry| entry["room"] == room)
+            .map(|mut entry| {
+                let summary = entry["sealed"]
+                    .as_str()
+                    .and_then(|sealed| self.vault.open(room.as_bytes(), sealed))
+                    .and_then(|summary| serde_json::from_slice::<Value>(&summary).ok());
+                if let (Some(summary), Some(fields)) = (summary, entry.as_object_mut()) {
+                    fields.remove("sealed");
+                    fields.insert("summary".into(), summary);
+                }
+                entry
+            });
+        Ok(entries.collect())
+    }
+    fn record(&self, room: &str, summary: &GameSummary, sealed: bool) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let summary = match serde_json::to_value(summary) {
+            Ok(summary) => summary,
+            Err(err) => return log::error!("cannot serialize summary: {err}"),
+        };
+        let line = if sealed {
+            let sealed = self
+                .vault
+                .seal(room.as_bytes(), summary.to_string().as_bytes());
+            json!({ "at": at, "room": room, "sealed": sealed }).to_string()
+        } else {
+            json!({ "at": at, "room": room, "summary": summary }).to_string()
+        };
+        match &self.file {
+            Some(file) => {
+                let mut file = file.lock().unwrap_or_else(|err| err.into_inner());
+                if let Err(err) = writeln!(file, "{line}") {
+                    log::error!("failed to persist game summary: {err}");
+                }
+            }
+            None => log::info!(target: "game_summary", "{line}"),
+        }
+    }
+}
+
+/// Ranks the players of a finished game and archives the results. Runs on the job pool since
+/// rooms can be very large.
+pub struct Summarize {
+    pub room: String,
+    pub results: Vec<PlayerResult>,
+    pub turns: Vec<TurnRecord>,
+    pub metadata: RoomMetadata,
+    pub archive: Arc<GameArchive>,
+    /// The room is in privacy mode, see [GameArchive]
+    pub sealed: bool,
+}
+
+impl Job for Summarize {
+    type Output = GameSummary;
+    fn run(self) -> GameSummary {
+        let mut standings = self.results;
+        standings.sort_by_key(|x| std::cmp::Reverse(x.score));
+        for idx in 0..standings.len() {
+            // Players with the same score share a rank
+            standings[idx].rank = if idx > 0 && standings[idx - 1].score == standings[idx].score {
+                standings[idx - 1].rank
+            } else {
+                idx + 1
+            };
+        }
+        let winners = standings
+            .iter()
+            .take_while(|x| x.rank == 1)
+            .map(|x| x.id)
+            .collect();
+        let summary = GameSummary {
+            standings,
+            winners,
+            turns: self.turns,
+            metadata: self.metadata,
+        };
+        self.archive.record(&self.room, &summary, self.sealed);
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn result(id: u64, score: usize) -> PlayerResult {
+        PlayerResult {
+            id: id.into(),
+            name: None,
+            score,
+            rank: 0,
+        }
+    }
+
+    fn turn() -> TurnRecord {
+        TurnRecord {
+            player: 2.into(),
+            input: None,
+            took: 1500,
+            points: 0,
+            breakdown: Vec::new(),
+        }
+    }
+
+    fn summarize(room: &str, archive: &Arc<GameArchive>, sealed: bool) -> GameSummary {
+        Summarize {
+            room: room.into(),
+            results: vec![result(1, 3), result(2, 5), result(3, 5)],
+            turns: vec![turn()],
+            metadata: RoomMetadata::default(),
+            archive: Arc::clone(archive),
+            sealed,
+        }
+        .run()
+    }
+
+    #[test]
+    fn ties_share_a_rank_and_the_archive_gets_every_turn() {
+        let path = std::env::temp_dir().join(format!("summaries-{}", std::process::id()));
+        let archive = Arc::new(GameArchive::open(&path, Vault::default()).unwrap());
+        let summary = summarize("ABCD", &archive, false);
+        let ranks: Vec<_> = summary.standings.iter().map(|x| x.rank).collect();
+        assert_eq!(ranks, [1, 1, 3]);
+        assert_eq!(summary.winners.len(), 2);
+        let mut text = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(line["room"], "ABCD");
+        assert_eq!(line["summary"]["turns"][0]["took"], 1500);
+    }
+
+    #[test]
+    fn summaries_of_private_games_only_open_in_the_export() {
+        let path = std::env::temp_dir().join(format!("sealed-summaries-{}", std::process::id()));
+        let archive = Arc::new(GameArchive::open(&path, Vault::new(b"secret")).unwrap());
+        summarize("ABCD", &archive, true);
+        summarize("EFGH", &archive, false);
+        let text = std::fs::read_to_string(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        assert_eq!(line["room"], "ABCD");
+        assert!(line.get("summary").is_none());
+        assert!(!text.lines().next().unwrap().contains("took"));
+        let exported = archive.export("ABCD").unwrap();
+        // Another key leaves them sealed
+        let other = GameArchive::open(&path, Vault::new(b"other secret")).unwrap();
+        let foreign = other.export("ABCD").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert!(exported[0].get("sealed").is_none());
+        assert_eq!(exported[0]["summary"]["turns"][0]["took"], 1500);
+        assert_eq!(
+            exported[0]["summary"]["winners"].as_array().unwrap().len(),
+            2
+        );
+        assert_eq!(foreign.len(), 1);
+        assert!(foreign[0]["sealed"].is_string());
+    }
+}
@@ -0,0 +1,81 @@
+use super::engine::Engine;
+use super::Input;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Default lower bound on how quickly a turn holder can possibly respond after their turn starts
+const MIN_INPUT_DELAY_MS: u64 = 250;
+
+/// Tunables for the [InputValidator]
+#[derive(Clone)]
+pub struct ValidationConfig {
+    /// Inputs arriving sooner than this after the turn started are considered automated
+    pub min_input_delay: Duration,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            min_input_delay: Duration::from_millis(MIN_INPUT_DELAY_MS),
+        }
+    }
+}
+
+/// Reasons for which an input is refused before it ever reaches the game mode
+#[derive(Serialize, Clone, Debug)]
+pub enum InputError {
+    /// The player submitted an input while it was someone else's turn, or while the turn was
+    /// being handed over
+    OutOfTurn,
+    /// The input arrived faster than a human could have produced it
+    TooFast,
+    /// The player submitted the exact same input as their previous one
+    Duplicate,
+}
+
+/// Sits between the room and the game mode, rejecting inputs that could not have been produced by
+/// a well behaved client.
+pub struct InputValidator {
+    config: ValidationConfig,
+    /// Last accepted input of every player, indexed by their position in the room
+    last_inputs: Vec<Option<Input>>,
+}
+
+impl InputValidator {
+    pub fn new(config: ValidationConfig, player_count: usize) -> Self {
+        Self {
+            config,
+            last_inputs: (0..player_count).map(|_| None).collect(),
+        }
+    }
+    /// Forgets the previous occupant's inputs when a player takes over a seat mid-game
+    pub fn add_player(&mut self, player: usize) {
+        if player >= self.last_inputs.len() {
+            self.last_inputs.resize(player + 1, None);
+        } else {
+            self.last_inputs[player] = None;
+        }
+    }
+    pub fn validate(
+        &mut self,
+        engine: &Engine,
+        player: usize,
+        input: &Input,
+    ) -> Result<(), InputError> {
+        if engine.turn() != player || engine.handing_off() || engine.paused() {
+            return Err(InputError::OutOfTurn);
+        }
+        if Instant::now().duration_since(engine.turn_started()) < self.config.min_input_delay {
+            return Err(InputError::TooFast);
+        }
+        // Nobody outside the seats the validator knows of can hold the turn
+        let Some(last) = self.last_inputs.get_mut(player) else {
+            return Err(InputError::OutOfTurn);
+        };
+        if last.as_ref() == Some(input) {
+            return Err(InputError::Duplicate);
+        }
+        *last = Some(input.clone());
+        Ok(())
+    }
+}
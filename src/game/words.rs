@@ -0,0 +1,129 @@
+//! Word lists the game modes pick their secret words from, one per supported language
+
+use std::sync::Arc;
+
+/// Language of rooms that have none set or asked for one there is no word list for
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+const ENGLISH: &[&str] = &[
+    "apple", "bridge", "candle", "dragon", "engine", "forest", "guitar", "harbor", "island",
+    "jacket", "kettle", "ladder", "mirror", "needle", "orange", "pencil", "rocket", "saddle",
+    "tunnel", "window",
+];
+const SPANISH: &[&str] = &[
+    "manzana", "puente", "vela", "dragon", "motor", "bosque", "guitarra", "puerto", "isla",
+    "chaqueta", "escalera", "espejo", "aguja", "naranja", "lapiz", "cohete", "tunel", "ventana",
+    "camisa", "raton",
+];
+const GERMAN: &[&str] = &[
+    "apfel",
+    "kerze",
+    "drache",
+    "motor",
+    "wald",
+    "gitarre",
+    "hafen",
+    "insel",
+    "jacke",
+    "kessel",
+    "leiter",
+    "spiegel",
+    "nadel",
+    "orange",
+    "bleistift",
+    "rakete",
+    "sattel",
+    "tunnel",
+    "fenster",
+    "brot",
+];
+const FRENCH: &[&str] = &[
+    "pomme", "pont", "bougie", "dragon", "moteur", "foret", "guitare", "port", "veste", "echelle",
+    "miroir", "aiguille", "orange", "crayon", "fusee", "selle", "tunnel", "fenetre", "chemise",
+    "bateau",
+];
+
+const WORD_LISTS: &[(&str, &[&str])] = &[
+    ("en", ENGLISH),
+    ("es", SPANISH),
+    ("de", GERMAN),
+    ("fr", FRENCH),
+];
+
+/// Primary subtag of a language tag, lowercased: `en-US` and `EN_gb` both become `en`
+pub fn primary_language(tag: &str) -> Box<str> {
+    tag.trim()
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .into()
+}
+
+/// Code of the word list for the language tag, if there is one
+pub fn supported_language(tag: &str) -> Option<&'static str> {
+    let language = primary_language(tag);
+    WORD_LISTS
+        .iter()
+        .map(|(code, _)| *code)
+        .find(|code| **code == *language)
+}
+
+/// Word list of the language, falling back to the one of [DEFAULT_LANGUAGE]
+pub fn words(language: Option<&str>) -> &'static [&'static str] {
+    let language = language
+        .and_then(supported_language)
+        .unwrap_or(DEFAULT_LANGUAGE);
+    WORD_LISTS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .expect("the default language must have a word list")
+        .1
+}
+
+/// Most words a leader can give a room to play with instead of its language's list
+pub const MAX_CUSTOM_WORDS: usize = 200;
+/// Longest word a leader can give a room
+pub const MAX_CUSTOM_WORD_LENGTH: usize = 24;
+
+/// Words a game picks its secret words from
+#[derive(Clone)]
+pub enum WordList {
+    /// The list of a language, see [words]
+    Builtin(&'static [&'static str]),
+    /// Words the room's leader gave, see [custom_words]
+    Custom(Arc<[Box<str>]>),
+}
+
+impl WordList {
+    /// One of the words, at random
+    pub fn pick(&self, rng: &mut fastrand::Rng) -> &str {
+        match self {
+            WordList::Builtin(words) => words[rng.usize(..words.len())],
+            WordList::Custom(words) => &words[rng.usize(..words.len())],
+        }
+    }
+}
+
+/// The words lowercased, if there are at most [MAX_CUSTOM_WORDS] of them and every one is made of
+/// letters only and at most [MAX_CUSTOM_WORD_LENGTH] long. Words given twice are kept once.
+pub fn custom_words(words: &[String]) -> Option<Arc<[Box<str>]>> {
+    if words.len() > MAX_CUSTOM_WORDS {
+        return None;
+    }
+    let mut list: Vec<Box<str>> = Vec::with_capacity(words.len());
+    for word in words {
+        let word = word.trim();
+        if word.is_empty()
+            || word.len() > MAX_CUSTOM_WORD_LENGTH
+            || !word.chars().all(|x| x.is_ascii_alphabetic())
+        {
+            return None;
+        }
+        let word = word.to_ascii_lowercase();
+        if !list.iter().any(|x| **x == word) {
+            list.push(word.into());
+        }
+    }
+    Some(list.into())
+}
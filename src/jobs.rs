@@ -0,0 +1,166 @@
+use actix::dev::Request;
+use actix::{Actor, Addr, Handler, Message, MessageResult, SyncArbiter, SyncContext};
+
+/// CPU heavy piece of work that has no business running on a room or session actor, since it
+/// would hold up every message queued behind it.
+pub trait Job: Send + 'static {
+    type Output: Send + 'static;
+    fn run(self) -> Self::Output;
+}
+
+/// Envelope used to hand a [Job] to one of the pool's workers
+pub struct Run<J: Job>(pub J);
+
+impl<J: Job> Message for Run<J> {
+    type Result = J::Output;
+}
+
+/// Worker running on one of the [JobPool]'s dedicated threads
+pub struct JobWorker;
+
+impl Actor for JobWorker {
+    type Context = SyncContext<Self>;
+}
+
+impl<J: Job> Handler<Run<J>> for JobWorker {
+    type Result = MessageResult<Run<J>>;
+    fn handle(&mut self, msg: Run<J>, _: &mut Self::Context) -> Self::Result {
+        MessageResult(msg.0.run())
+    }
+}
+
+/// Pool of [JobWorker]s backed by a [SyncArbiter], shared by every room. Jobs are picked up by
+/// whichever worker is free, so a long job only ever ties up a single thread.
+#[derive(Clone)]
+pub struct JobPool {
+    workers: Addr<JobWorker>,
+}
+
+impl JobPool {
+    pub fn new(threads: usize) -> Self {
+        let workers = SyncArbiter::start(threads.max(1), || JobWorker);
+        Self { workers }
+    }
+    /// Queues the job, the returned future resolves with its output once a worker has run it
+    pub fn run<J: Job>(&self, job: J) -> Request<JobWorker, Run<J>> {
+        self.workers.send(Run(job))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::{ActorFutureExt, AsyncContext, Context, WrapFuture};
+    use std::time::{Duration, Instant};
+
+    /// Stands in for an expensive job by blocking its worker thread
+    struct Spin(Duration);
+
+    impl Job for Spin {
+        type Output = ();
+        fn run(self) {
+            std::thread::sleep(self.0);
+        }
+    }
+
+    /// Behaves like a room: offloads expensive work to the pool while still answering messages
+    struct Host {
+        jobs: JobPool,
+        done: usize,
+    }
+
+    impl Actor for Host {
+        type Context = Context<Self>;
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Offload(Duration);
+
+    impl Handler<Offload> for Host {
+        type Result = ();
+        fn handle(&mut self, msg: Offload, ctx: &mut Self::Context) -> Self::Result {
+            ctx.spawn(
+                self.jobs
+                    .run(Spin(msg.0))
+                    .into_actor(self)
+                    .map(|_, act, _| act.done += 1),
+            );
+        }
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "usize")]
+    struct Ping;
+
+    impl Handler<Ping> for Host {
+        type Result = usize;
+        fn handle(&mut self, _: Ping, _: &mut Self::Context) -> Self::Result {
+            self.done
+        }
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Inline(Duration);
+
+    impl Handler<Inline> for Host {
+        type Result = ();
+        fn handle(&mut self, msg: Inline, _: &mut Self::Context) -> Self::Result {
+            Spin(msg.0).run();
+        }
+    }
+
+    async fn ping(host: &Addr<Host>) -> Duration {
+        let start = Instant::now();
+        host.send(Ping).await.unwrap();
+        start.elapsed()
+    }
+
+    #[actix::test]
+    async fn jobs_return_their_output() {
+        struct Add(u32, u32);
+        impl Job for Add {
+            type Output = u32;
+            fn run(self) -> u32 {
+                self.0 + self.1
+            }
+        }
+        let pool = JobPool::new(1);
+        assert_eq!(pool.run(Add(2, 3)).await.unwrap(), 5);
+    }
+
+    #[actix::test]
+    async fn latency_stays_flat_while_jobs_run() {
+        let host = Host {
+            jobs: JobPool::new(2),
+            done: 0,
+        }
+        .start();
+        let idle = ping(&host).await;
+        for _ in 0..8 {
+            host.do_send(Offload(Duration::from_millis(100)));
+        }
+        for _ in 0..10 {
+            let busy = ping(&host).await;
+            assert!(
+                busy < idle + Duration::from_millis(20),
+                "ping took {busy:?} while jobs were running"
+            );
+            actix::clock::sleep(Duration::from_millis(20)).await;
+        }
+        // Jobs are still being worked through, so every ping above really overlapped with them
+        assert!(host.send(Ping).await.unwrap() < 8);
+    }
+
+    #[actix::test]
+    async fn inline_work_blocks_the_actor() {
+        let host = Host {
+            jobs: JobPool::new(1),
+            done: 0,
+        }
+        .start();
+        host.do_send(Inline(Duration::from_millis(100)));
+        assert!(ping(&host).await >= Duration::from_millis(90));
+    }
+}
@@ -0,0 +1,172 @@
+use actix::prelude::*;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::events::{EventBus, Publish, ServerEvent};
+use crate::room::{GetRoomStats, RoomManager};
+
+/// How often the event loop and the room manager are probed
+const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_LAG: u64 = 100;
+const DEFAULT_MAX_MAILBOX_DELAY: u64 = 250;
+/// Probes in a row that have to come in under the thresholds before the server leaves degraded
+/// mode, so that it does not flap in and out of it
+const CALM_PROBES: u32 = 10;
+/// Periodic work runs this many times less often in degraded mode, see [Load::skips]
+const DEGRADED_TICK_FACTOR: u64 = 4;
+/// Turns starting in degraded mode last this many times longer, see [Load::stretch]
+const DEGRADED_TURN_FACTOR: u32 = 2;
+
+/// Thresholds the [LoadMonitor] puts the server in degraded mode above
+pub struct LoadConfig {
+    /// How late a timer on the event loop can fire
+    pub max_lag: Duration,
+    /// How long a message can wait in the room manager's mailbox
+    pub max_mailbox_delay: Duration,
+}
+
+impl LoadConfig {
+    /// Reads `LOAD_MAX_LAG` and `LOAD_MAX_MAILBOX_DELAY`, both in milliseconds
+    pub fn from_env() -> Self {
+        let read = |name, default| {
+            let millis = std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(default);
+            Duration::from_millis(millis)
+        };
+        Self {
+            max_lag: read("LOAD_MAX_LAG", DEFAULT_MAX_LAG),
+            max_mailbox_delay: read("LOAD_MAX_MAILBOX_DELAY", DEFAULT_MAX_MAILBOX_DELAY),
+        }
+    }
+}
+
+/// Whether the server is shedding load, shared by everything that has work it can put off.
+/// While degraded, chat and reactions are held back, periodic work slows down, turns last longer
+/// and no new rooms are opened.
+#[derive(Default)]
+pub struct Load {
+    degraded: AtomicBool,
+}
+
+impl Load {
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+    /// Whether periodic work skips its `tick`th run, which makes it run [DEGRADED_TICK_FACTOR]
+    /// times less often while degraded
+    pub fn skips(&self, tick: u64) -> bool {
+        self.degraded() && !tick.is_multiple_of(DEGRADED_TICK_FACTOR)
+    }
+    /// How long a turn of the duration lasts if it starts now, [DEGRADED_TURN_FACTOR] times as
+    /// long while degraded so that players don't run out of time on account of the server
+    pub fn stretch(&self, turn: Duration) -> Duration {
+        match self.degraded() {
+            true => turn * DEGRADED_TURN_FACTOR,
+            false => turn,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct LoadReport {
+    /// How late the last probe fired, in milliseconds
+    pub lag: u64,
+    /// How long the last probe waited for the room manager, in milliseconds
+    pub mailbox_delay: u64,
+}
+
+/// Probes how late timers fire on the event loop and how long the room manager takes to get to
+/// a message, and switches [Load] to degraded mode when either goes over its threshold
+pub struct LoadMonitor {
+    config: LoadConfig,
+    load: Arc<Load>,
+    room_manager: Addr<RoomManager>,
+    events: Addr<EventBus>,
+    last_probe: Instant,
+    /// Whether the room manager has yet to answer the last probe, no new one is sent until it
+    /// does so as not to add to its backlog
+    probing: bool,
+    /// Probes under the thresholds since the last one over them
+    calm: u32,
+}
+
+impl LoadMonitor {
+    pub fn new(
+        config: LoadConfig,
+        load: Arc<Load>,
+        room_manager: Addr<RoomManager>,
+        events: Addr<EventBus>,
+    ) -> Self {
+        Self {
+            config,
+            load,
+            room_manager,
+            events,
+            last_probe: Instant::now(),
+            probing: false,
+            calm: 0,
+        }
+    }
+    fn probe(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let lag = now
+            .duration_since(self.last_probe)
+            .saturating_sub(PROBE_INTERVAL);
+        self.last_probe = now;
+        if self.probing {
+            return;
+        }
+        self.probing = true;
+        self.room_manager
+            .send(GetRoomStats)
+            .into_actor(self)
+            .then(move |res, act, _| {
+                act.probing = false;
+                if let Err(err) = res {
+                    log::error!("cannot probe the room manager: {err}");
+                }
+                act.update(LoadReport {
+                    lag: lag.as_millis() as u64,
+                    mailbox_delay: now.elapsed().as_millis() as u64,
+                });
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn update(&mut self, report: LoadReport) {
+        let overloaded = report.lag > self.config.max_lag.as_millis() as u64
+            || report.mailbox_delay > self.config.max_mailbox_delay.as_millis() as u64;
+        if overloaded {
+            self.calm = 0;
+            if !self.load.degraded.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "shedding load, event loop {}ms late, room manager {}ms behind",
+                    report.lag,
+                    report.mailbox_delay
+                );
+                self.events
+                    .do_send(Publish(ServerEvent::LoadShedding(report)));
+            }
+        } else if self.load.degraded() {
+            self.calm += 1;
+            if self.calm >= CALM_PROBES {
+                self.load.degraded.store(false, Ordering::Relaxed);
+                log::info!("load back to normal");
+                self.events
+                    .do_send(Publish(ServerEvent::LoadRecovered(report)));
+            }
+        }
+    }
+}
+
+impl Actor for LoadMonitor {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.last_probe = Instant::now();
+        ctx.run_interval(PROBE_INTERVAL, |act, ctx| act.probe(ctx));
+    }
+}
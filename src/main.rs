@@ -1,9 +1,32 @@
+mod analytics;
+mod bridge;
+mod capacity;
+mod deadletter;
+mod diagnostics;
+mod events;
 mod game;
+mod jobs;
+mod load;
+mod profanity;
+mod rating;
 mod room;
 mod server;
 mod session;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "soak")]
+mod soak;
+mod version;
+mod watchdog;
+mod webhook;
 
 #[actix::main]
 async fn main() -> std::io::Result<()> {
+    // Only errors are logged by default, along with the frames of traced sessions
+    let filter = format!("error,{}=info", session::actor::TRACE_TARGET);
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(filter))
+        .format_timestamp_millis()
+        .init();
+    crate::diagnostics::install_panic_hook();
     crate::server::http::start().await
 }
@@ -0,0 +1,126 @@
+use ahash::HashSet;
+
+/// Environment variable pointing to a newline separated word list replacing the built-in one
+const WORD_LIST_VAR: &str = "PROFANITY_WORD_LIST";
+/// Environment variable selecting what to do with offending text (`mask`, `reject`, `escalate`)
+const ACTION_VAR: &str = "PROFANITY_ACTION";
+
+/// Used when no word list is configured
+const DEFAULT_WORDS: &[&str] = &[
+    "arse",
+    "asshole",
+    "bastard",
+    "bitch",
+    "bollocks",
+    "cock",
+    "cunt",
+    "dick",
+    "fuck",
+    "fucker",
+    "fucking",
+    "motherfucker",
+    "piss",
+    "prick",
+    "pussy",
+    "shit",
+    "slut",
+    "twat",
+    "wanker",
+    "whore",
+];
+
+/// What happens to text containing a listed word
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterAction {
+    /// Offending words are replaced with asterisks
+    Mask,
+    /// The whole text is refused
+    Reject,
+    /// The text is masked and the incident is reported to moderation
+    Escalate,
+}
+
+/// Outcome of running a piece of text through the [ProfanityFilter]
+pub enum Verdict {
+    Clean,
+    /// The text with every offending word masked out
+    Masked(String),
+    /// Same as [Verdict::Masked], but the caller is expected to report the incident
+    Escalated(String),
+    Rejected,
+}
+
+/// Profanity filter applied to any player supplied text that other players get to see
+pub struct ProfanityFilter {
+    words: HashSet<String>,
+    action: FilterAction,
+}
+
+impl ProfanityFilter {
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>, action: FilterAction) -> Self {
+        let words = words
+            .into_iter()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        Self { words, action }
+    }
+    /// Loads the word list and action from the environment, falling back to the built-in word
+    /// list and masking.
+    pub fn load() -> Self {
+        let action = match std::env::var(ACTION_VAR).as_deref() {
+            Ok("reject") => FilterAction::Reject,
+            Ok("escalate") => FilterAction::Escalate,
+            Ok("mask") | Err(_) => FilterAction::Mask,
+            Ok(other) => {
+                log::warn!("unknown {ACTION_VAR} `{other}`, masking profanity instead");
+                FilterAction::Mask
+            }
+        };
+        match std::env::var(WORD_LIST_VAR) {
+            Ok(path) => match std::fs::read_to_string(&path) {
+                Ok(list) => Self::new(list.lines(), action),
+                Err(err) => {
+                    log::error!("failed to read profanity word list {path}: {err}");
+                    Self::new(DEFAULT_WORDS.iter().copied(), action)
+                }
+            },
+            Err(_) => Self::new(DEFAULT_WORDS.iter().copied(), action),
+        }
+    }
+    /// Checks the text word by word, so that innocent words merely containing a listed word
+    /// are left alone.
+    pub fn check(&self, text: &str) -> Verdict {
+        let mut masked = String::with_capacity(text.len());
+        let mut found = false;
+        let mut rest = text;
+        while !rest.is_empty() {
+            let end = rest
+                .find(|x: char| x.is_alphanumeric() != rest.starts_with(char::is_alphanumeric))
+                .unwrap_or(rest.len());
+            let (segment, tail) = rest.split_at(end);
+            if self.words.contains(&segment.to_lowercase()) {
+                found = true;
+                masked.extend(segment.chars().map(|_| '*'));
+            } else {
+                masked.push_str(segment);
+            }
+            rest = tail;
+        }
+        if !found {
+            return Verdict::Clean;
+        }
+        match self.action {
+            FilterAction::Mask => Verdict::Masked(masked),
+            FilterAction::Reject => Verdict::Rejected,
+            FilterAction::Escalate => Verdict::Escalated(masked),
+        }
+    }
+}
+
+impl Default for ProfanityFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_WORDS.iter().copied(), FilterAction::Mask)
+    }
+}
@@ -0,0 +1,165 @@
+use ahash::{HashMap, HashMapExt};
+use std::sync::RwLock;
+
+use crate::session::UserId;
+
+/// Rating of players who have not finished a game yet
+pub const INITIAL_RATING: f64 = 1500.0;
+/// Most a rating can move in a single game
+const K_FACTOR: f64 = 32.0;
+const DEFAULT_RATING_BAND: f64 = 200.0;
+
+/// Elo ratings of the players, updated from the results of every game and kept for as long as
+/// the server runs
+pub struct Ratings {
+    ratings: RwLock<HashMap<UserId, f64>>,
+    /// How far the average rating of a room can be from a player's for random joins to prefer it
+    band: f64,
+}
+
+impl Ratings {
+    pub fn new(band: f64) -> Self {
+        Self {
+            ratings: RwLock::new(HashMap::new()),
+            band,
+        }
+    }
+    /// Reads `MATCH_RATING_BAND`
+    pub fn from_env() -> Self {
+        let band = match std::env::var("MATCH_RATING_BAND").map(|x| x.parse()) {
+            Ok(Ok(band)) => band,
+            Ok(Err(_)) => {
+                log::error!("ignoring malformed MATCH_RATING_BAND");
+                DEFAULT_RATING_BAND
+            }
+            Err(_) => DEFAULT_RATING_BAND,
+        };
+        Self::new(band)
+    }
+    pub fn get(&self, user: &UserId) -> f64 {
+        self.ratings
+            .read()
+            .unwrap()
+            .get(user)
+            .copied()
+            .unwrap_or(INITIAL_RATING)
+    }
+    /// Average rating of the users, [INITIAL_RATING] if there are none
+    pub fn average<'a>(&self, users: impl IntoIterator<Item = &'a UserId>) -> f64 {
+        let ratings = self.ratings.read().unwrap();
+        let (sum, count) = users.into_iter().fold((0.0, 0), |(sum, count), user| {
+            let rating = ratings.get(user).copied().unwrap_or(INITIAL_RATING);
+            (sum + rating, count + 1)
+        });
+        if count == 0 {
+            INITIAL_RATING
+        } else {
+            sum / count as f64
+        }
+    }
+    /// Whether two ratings are close enough for the players to be matched together
+    pub fn within_band(&self, a: f64, b: f64) -> bool {
+        (a - b).abs() <= self.band
+    }
+    /// Updates the ratings of the players of a finished game from their ranks, where players
+    /// sharing a rank tied. Players who left before the end rank below everyone who finished,
+    /// unless they came back and finished the game after all.
+    pub fn record(&self, ranks: &[(UserId, usize)], deserters: &[UserId]) {
+        let last = ranks.iter().map(|(_, rank)| rank + 1).max().unwrap_or(1);
+        let mut ranks = ranks.to_vec();
+        for user in deserters {
+            if !ranks.iter().any(|(x, _)| x == user) {
+                ranks.push((user.clone(), last));
+            }
+        }
+        let mut ratings = self.ratings.write().unwrap();
+        let current = ranks
+            .iter()
+            .map(|(user, rank)| {
+                let rating = ratings.get(user).copied().unwrap_or(INITIAL_RATING);
+                (rating, *rank)
+            })
+            .collect::<Vec<_>>();
+        for ((user, _), rating) in ranks.iter().zip(updated(&current)) {
+            ratings.insert(user.clone(), rating);
+        }
+    }
+}
+
+/// New ratings for the players of a game, given their ratings and ranks going in. Every player
+/// is scored as if they played a match against each of the others, and the change is scaled down
+/// so that a game moves a rating by [K_FACTOR] at most however many played.
+fn updated(players: &[(f64, usize)]) -> Vec<f64> {
+    let opponents = players.len().saturating_sub(1).max(1) as f64;
+    players
+        .iter()
+        .enumerate()
+        .map(|(i, &(rating, rank))| {
+            let delta = players
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| i != *j)
+                .map(|(_, &(other, other_rank))| {
+                    let expected = 1.0 / (1.0 + 10f64.powf((other - rating) / 400.0));
+                    let actual = match rank.cmp(&other_rank) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    actual - expected
+                })
+                .sum::<f64>();
+            rating + K_FACTOR * delta / opponents
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winners_gain_what_losers_lose() {
+        let ratings = updated(&[(1500.0, 1), (1500.0, 2), (1500.0, 3)]);
+        assert!(ratings[0] > 1500.0 && ratings[2] < 1500.0);
+        assert!((ratings[1] - 1500.0).abs() < 1e-9);
+        assert!((ratings.iter().sum::<f64>() - 4500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn upsets_move_ratings_further() {
+        let expected = updated(&[(1800.0, 1), (1500.0, 2)]);
+        let upset = updated(&[(1800.0, 2), (1500.0, 1)]);
+        assert!(upset[1] - 1500.0 > expected[0] - 1800.0);
+        assert!(upset[1] - 1500.0 <= K_FACTOR);
+    }
+
+    #[test]
+    fn ties_between_equals_change_nothing() {
+        assert_eq!(updated(&[(1600.0, 1), (1600.0, 1)]), vec![1600.0, 1600.0]);
+    }
+
+    #[test]
+    fn recorded_results_are_kept_per_user() {
+        let ratings = Ratings::new(DEFAULT_RATING_BAND);
+        let (a, b): (UserId, UserId) = ("a".into(), "b".into());
+        ratings.record(&[(a.clone(), 1), (b.clone(), 2)], &[]);
+        assert!(ratings.get(&a) > INITIAL_RATING);
+        assert!(ratings.get(&b) < INITIAL_RATING);
+        assert_eq!(ratings.average([&a, &b]), INITIAL_RATING);
+    }
+
+    #[test]
+    fn leaving_early_counts_as_coming_last() {
+        let ratings = Ratings::new(DEFAULT_RATING_BAND);
+        let (a, b, c): (UserId, UserId, UserId) = ("a".into(), "b".into(), "c".into());
+        // `b` left and came back, only `c` is missing from the standings
+        ratings.record(
+            &[(a.clone(), 1), (b.clone(), 2)],
+            &[b.clone(), c.clone(), c.clone()],
+        );
+        assert!(ratings.get(&a) > ratings.get(&b));
+        assert!(ratings.get(&b) > ratings.get(&c));
+        assert!(ratings.get(&c) < INITIAL_RATING);
+    }
+}
@@ -1,21 +1,93 @@
+use super::audit::{AuditEvent, AuditRecord, AuditTrail};
+use super::chat::{ChatError, ChatLimiter, FLUSH_INTERVAL, MAX_CHAT_LENGTH, MAX_DEFERRED};
+use super::fanout::{Partition, FANOUT_THRESHOLD};
+use super::history::History;
+use super::instant_replay::{ReplayBuffer, ReplayClip, ReplayError};
+use super::invite::{unix_time, Invite};
+use super::lobby::{ClosePoll, CloseRematchVote, Lobby, LobbyAction, Reaction, POLL_INTERVAL};
+use super::metadata::MetadataError;
+use super::observer::{Observation, ObserveError, Observed, Observers};
+use super::practice::{PracticeBoard, PracticeSeed};
+use super::settings::{RoomSettings, SettingsError, SettingsUpdate};
+use super::state::RoomState;
+use super::traffic::{RoomTraffic, Traffic};
 use super::RoomCode;
 use super::*;
-use crate::game::{Game, GameController, GameMode, Input};
-use crate::session::TransientId;
+use crate::capacity::CapacityClass;
+use crate::deadletter::{DeadLetter, DeadLetterReason};
+use crate::events::{Publish, ServerEvent};
+use crate::game::engine::{Connection, TURN_DURATION};
+use crate::game::limits::ModeSlot;
+use crate::game::summary::{GameSummary, Summarize};
+use crate::game::validation::{InputError, ValidationConfig};
+use crate::game::{
+    new_game, Controller, GameMode, GameOver, GameTimer, Highlight, Input, TurnTimeout,
+};
+use crate::profanity::{ProfanityFilter, Verdict};
+use crate::session::bot::is_bot;
+use crate::session::catalog::Countdown;
+use crate::session::latency;
+use crate::session::profile::Profile;
 use crate::session::{
-    actor::{ClearRoom, RestoreState, SerializedMessage, Session},
-    message::{OutgoingMessage, RemoveReason},
+    actor::{
+        ClearRoom, Frame, MoveToRoom, QueueOutcome, Queued, ReplayEvents, RestoreState,
+        SerializedMessage, Session, Warn,
+    },
+    message::{
+        self, BannedPlayer, Deadline, OutgoingMessage, PlayerResult, RemoveReason, RosterEntry,
+    },
+    sink::SharedFrame,
+};
+use crate::session::{TransientId, UserId};
+use actix::dev::SendError;
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message,
+    MessageResult, Recipient, SpawnHandle, WrapFuture,
 };
-use actix::{Actor, ActorContext, Addr, AsyncContext, Context, Handler, Message};
 use ahash::{HashMap, HashMapExt};
+use serde_json::value::RawValue;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Seconds between a room gathering enough players and the game starting
+pub const START_COUNTDOWN: u64 = 10;
+/// Seconds between two checks for inactivity, see [InactivityConfig]
+const INACTIVITY_CHECK_INTERVAL: u64 = 5;
+/// Most players that can wait in line for a seat in a full room
+const MAX_QUEUE_LENGTH: usize = 32;
+/// Seconds between two updates of their position sent to every queued player
+const QUEUE_UPDATE_INTERVAL: u64 = 5;
 
 pub struct PlayerInRoom {
     pub addr: Addr<Session>,
     pub transient_id: TransientId, // extra_info: Info
+    pub user: UserId,
+    pub profile: Profile,
+    /// Round trip time of the player's connection when they joined, if it was measured
+    pub rtt: Option<Duration>,
 }
 
 pub struct GameConfigOptions {
-    mode: GameMode,
+    pub mode: GameMode,
+    pub validation: ValidationConfig,
+    pub turn_duration: Duration,
+    /// Pause between two turns for clients to animate the turn changing hands, none by default
+    pub turn_handoff: Duration,
+    /// How long every round lasts at most before it is skipped, rounds are only over once
+    /// someone gets them right if unset
+    pub round_duration: Option<Duration>,
+    /// How often the game gives the players a hint, if at all
+    pub hint_interval: Option<Duration>,
+    /// Number of upcoming turn holders announced on every turn change, see
+    /// [crate::game::engine::Engine::upcoming_turns]
+    pub upcoming_turns: u8,
+    /// Whether the players get the turn by turn log of the game along with its results
+    pub share_turn_log: bool,
+    /// Deals the same puzzle every time, only set for practice rooms
+    pub seed: Option<PracticeSeed>,
+    /// Words the leader gave the room to play with instead of its language's list
+    pub words: Option<Arc<[Box<str>]>>,
     // Add extra options
 }
 
@@ -23,100 +95,841 @@ impl Default for GameConfigOptions {
     fn default() -> Self {
         Self {
             mode: Default::default(),
+            validation: Default::default(),
+            turn_duration: Duration::from_secs(TURN_DURATION),
+            turn_handoff: Duration::ZERO,
+            round_duration: None,
+            hint_interval: None,
+            upcoming_turns: 0,
+            share_turn_log: false,
+            seed: None,
+            words: None,
         }
     }
 }
 
-type Controller =
-    dyn GameController<Ctx = Context<Room>, GameInput = Input, SerializedState = String>;
-
 pub struct Room {
     players: Vec<Option<PlayerInRoom>>,
     id_map: HashMap<TransientId, usize>,
+    /// People watching the room without a seat. They hear everything said to the whole room but
+    /// take no part in games, see [RoomConfig::max_spectators].
+    spectators: HashMap<TransientId, Addr<Session>>,
     game: Option<Box<Controller>>,
+    /// Counts the running game against the limit of its mode, see [ModeLimits]
+    mode_slot: Option<ModeSlot>,
     code: RoomCode,
     room_manager: Addr<RoomManager>, // further configuration / extra state
     game_config: GameConfigOptions,
     room_config: RoomConfig,
     leader: TransientId,
     player_count: usize,
+    /// Where the room is between games, only ever changed through [Room::set_state]
+    state: RoomState,
+    /// Pending start of the next game and when it fires, see [Room::update_countdown]
+    countdown: Option<(SpawnHandle, Instant)>,
+    /// Last time a message was received from one of the players or a game was running
+    last_activity: Instant,
+    /// Whether the players have been told that the room is about to expire
+    expiry_warned: bool,
+    /// When the room was opened, which its [RoomConfig::lifetime] counts from
+    opened: Instant,
+    /// The room reached the end of its [RoomConfig::lifetime] and closes as soon as the running
+    /// game is wrapped up
+    lifetime_over: bool,
+    /// The finished game is being summarized on the job pool, see [GameOver]. The room stays
+    /// in [RoomState::PostGame] without a rematch vote until the results are out.
+    summarizing: Option<SpawnHandle>,
+    /// Players who left the running game, who are rated as if they came last once it ends
+    deserters: Vec<UserId>,
+    /// Reason given to the players still in the room once it stops
+    close_reason: RemoveReason,
+    /// Lobby mini-interactions for players waiting for the game to start
+    lobby: Lobby,
+    chat_limiter: ChatLimiter,
+    /// Chat and reactions held back while the server sheds load, relayed in batches instead of
+    /// right away
+    deferred: Vec<OutgoingMessage>,
+    /// Runs of the flush timer, which slows down while the server sheds load
+    flush_ticks: u64,
+    /// Periodic work of the room, cancelled once it closes
+    timers: Vec<SpawnHandle>,
+    /// The room closed and the actor sits idle in the room manager's pool until it is handed
+    /// to a new leader, see [ResetRoom]
+    closed: bool,
+    /// Counts the leaders the actor was handed to from the pool. Futures started for one of
+    /// them check it before they touch the room, so that they cannot reach into the next one's.
+    generation: u64,
+    /// Locked rooms turn away new players and stay out of matchmaking
+    locked: bool,
+    /// Users the leader kicked, they cannot join again while the room exists unless the leader
+    /// unbans them
+    banned: HashMap<UserId, BannedPlayer>,
+    /// Invites the leader handed out that are still good, by nonce
+    invites: HashMap<u64, Invitation>,
+    /// Players waiting for a seat in the full room, in the order they get seated in
+    queue: VecDeque<Joiner>,
+    services: RoomServices,
+    /// Members split up between the [FanoutPool]'s broadcasters. Built lazily on the first large
+    /// broadcast and thrown away whenever the members of the room change.
+    partitions: RefCell<Option<Vec<Partition>>>,
+    /// Latest messages sent to the whole room, for members catching up after a reconnect
+    history: RefCell<History>,
+    /// Recent events of the running game, see [super::instant_replay]
+    replay: ReplayBuffer,
+    /// Frames sent out recently, see [super::traffic]
+    traffic: RefCell<Traffic>,
+    /// Admins watching the room without being part of it, see [super::observer]
+    observers: Observers,
+    /// Players who lost connection, along with the timer that removes them unless they
+    /// reconnect first, see [RoomConfig::reconnect_grace_secs]
+    disconnected: HashMap<TransientId, SpawnHandle>,
+    /// Comings and goings in the room for moderators to look into, see [super::audit]
+    audit: AuditTrail,
 }
 
 impl Room {
     pub fn new(
         code: RoomCode,
         room_manager: Addr<RoomManager>,
-        leader: (TransientId, Addr<Session>),
+        leader: Joiner,
         room_config: RoomConfig,
+        game_config: GameConfigOptions,
+        services: RoomServices,
     ) -> Self {
-        let (transient_id, addr) = leader;
-        let leader = PlayerInRoom { addr, transient_id };
+        let Joiner {
+            session: (transient_id, addr),
+            user,
+            profile,
+            rtt,
+            ..
+        } = leader;
         let mut id_map = HashMap::with_capacity(room_config.max_player_count as usize);
         id_map.insert(transient_id, 0usize);
         let mut players = Vec::with_capacity(room_config.max_player_count as usize);
-        players.push(Some(leader));
+        // The leader has nobody to clash with, so only an inappropriate name can be refused
+        let profile = profile
+            .and_then(|profile| filter_name(&services.profanity, profile))
+            .unwrap_or_else(|| Profile::placeholder(transient_id));
+        let mut audit = AuditTrail::default();
+        audit.record(AuditEvent::Joined {
+            player: transient_id,
+            user: user.clone(),
+            name: profile.name.as_str().into(),
+        });
+        players.push(Some(PlayerInRoom {
+            addr,
+            transient_id,
+            user,
+            profile,
+            rtt,
+        }));
         Self {
             players,
             game: None,
             id_map,
+            spectators: HashMap::default(),
             leader: transient_id,
             code,
             room_manager,
-            game_config: Default::default(),
+            game_config,
             room_config,
             player_count: 1,
+            state: RoomState::Lobby,
+            countdown: None,
+            mode_slot: None,
+            last_activity: Instant::now(),
+            expiry_warned: false,
+            opened: Instant::now(),
+            lifetime_over: false,
+            summarizing: None,
+            deserters: Vec::new(),
+            close_reason: RemoveReason::RoomClosed,
+            lobby: Default::default(),
+            chat_limiter: Default::default(),
+            deferred: Vec::new(),
+            flush_ticks: 0,
+            timers: Vec::new(),
+            closed: false,
+            generation: 0,
+            locked: false,
+            banned: HashMap::default(),
+            invites: HashMap::default(),
+            queue: VecDeque::new(),
+            services,
+            partitions: RefCell::new(None),
+            history: RefCell::new(History::default()),
+            replay: ReplayBuffer::default(),
+            traffic: RefCell::new(Traffic::default()),
+            observers: Observers::default(),
+            disconnected: HashMap::new(),
+            audit,
+        }
+    }
+    /// Moves the room on to the next state and lets the members know, unless the room cannot go
+    /// there from where it is
+    fn set_state(&mut self, next: RoomState) -> bool {
+        if self.state == next {
+            return true;
+        }
+        if !self.state.can_become(next) {
+            log::error!(
+                "room {} cannot go from {:?} to {next:?}",
+                String::from_utf8_lossy(&self.code),
+                self.state
+            );
+            return false;
         }
+        self.state = next;
+        self.notify_clients(OutgoingMessage::RoomState(next), None);
+        true
     }
     fn start_game(&mut self, ctx: &mut <Self as Actor>::Context) {
-        let mut game = Box::new(Game::new(&self.players, self.game_config.mode));
+        let Some(slot) = self.services.modes.start(self.game_config.mode) else {
+            // Other rooms took the last slots of the mode while the room was counting down or
+            // voting for a rematch
+            self.countdown = None;
+            self.notify_clients(
+                OutgoingMessage::StartFailed(StartGameError::ModeAtCapacity),
+                None,
+            );
+            if self.state == RoomState::PostGame {
+                self.reopen(ctx);
+            } else {
+                self.set_state(RoomState::Lobby);
+            }
+            return;
+        };
+        if !self.set_state(RoomState::InGame) {
+            return;
+        }
+        self.mode_slot = Some(slot);
+        self.countdown = None;
+        self.lobby.reset(ctx);
+        let language = self.room_config.language.as_deref();
+        let load = Arc::clone(&self.services.load);
+        let mut game = new_game(&self.players, &self.game_config, language, load);
+        self.replay.clear();
+        self.deserters.clear();
         game.on_begin(ctx);
         self.game = Some(game);
+        self.audit.record(AuditEvent::GameStarted {
+            mode: self.game_config.mode,
+            players: self
+                .players
+                .iter()
+                .flatten()
+                .map(|x| x.transient_id)
+                .collect(),
+        });
+        self.publish(ServerEvent::GameStarted {
+            room: String::from_utf8_lossy(&self.code).into_owned(),
+            mode: self.game_config.mode,
+            players: self.player_count,
+        });
+        let availability = if self.wants_backfill() {
+            Availability::Backfill
+        } else {
+            Availability::Unavailable(RoomUnavailablityReason::GameStarted)
+        };
         self.room_manager.do_send(UpdateRoomMatchAvailability {
-            code: self.code.clone(),
-            availability: Availability::Unavailable(RoomUnavailablityReason::GameStarted),
+            code: self.code,
+            availability,
         });
         self.notify_clients(OutgoingMessage::GameStarted, None);
-        todo!("inform players that game has started!");
+    }
+    /// Whether there are enough players in the lobby for a game to start
+    fn can_start(&self) -> bool {
+        self.room_config.kind != RoomKind::Announcement
+            && self.state != RoomState::InGame
+            && self.player_count >= self.room_config.min_players as usize
+    }
+    /// Must be called whenever the player count or the room's settings change. Public rooms
+    /// start counting down as soon as they reach [RoomConfig::min_players], and any pending
+    /// countdown is called off once the room drops below it.
+    fn update_countdown(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if !self.can_start() {
+            self.cancel_countdown(ctx);
+        } else if self.room_config.public {
+            self.begin_countdown(ctx);
+        }
+    }
+    /// Only starts counting down from the lobby
+    fn begin_countdown(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.state.in_lobby() && self.set_state(RoomState::Starting) && self.countdown.is_none()
+        {
+            let duration = Duration::from_secs(START_COUNTDOWN);
+            let handle = ctx.notify_later(CountdownElapsed, duration);
+            self.countdown = Some((handle, Instant::now() + duration));
+            self.notify_clients(OutgoingMessage::StartingIn(Deadline::after(duration)), None);
+            self.warn(Countdown::StartingIn, duration, None);
+        }
+    }
+    fn cancel_countdown(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if let Some((handle, _)) = self.countdown.take() {
+            ctx.cancel_future(handle);
+            self.set_state(RoomState::Lobby);
+            self.notify_clients(OutgoingMessage::StartCancelled, None);
+        }
     }
     pub fn notify_clients(&self, msg: OutgoingMessage, target: Option<usize>) {
         if let Some(idx) = target {
-            self.players
+            let player = self
+                .players
                 .get(idx)
                 .as_ref()
                 .expect("target doesnt exist!")
                 .as_ref()
-                .expect("target cannot be an inactive player!")
-                .addr
-                .do_send(SerializedMessage(msg));
+                .expect("target cannot be an inactive player!");
+            self.observers.send(Observation::Direct {
+                to: player.transient_id,
+                message: &msg,
+            });
+            self.traffic.borrow_mut().record(1, Instant::now());
+            self.deliver(player.transient_id, &player.addr, msg);
+            return;
+        }
+        let members = self.player_count + self.spectators.len();
+        self.traffic
+            .borrow_mut()
+            .record(members as u64, Instant::now());
+        let priority = msg.priority();
+        let frame = self.history.borrow_mut().record(msg);
+        // The frame is only parsed back for observers, who see broadcasts as they went out
+        if !self.observers.is_empty() {
+            if let Ok(raw) = serde_json::from_str::<&RawValue>(&frame) {
+                self.observers.send(Observation::Broadcast(raw));
+            }
+        }
+        // Every member gets the same frame, transcoded at most once for those who need it
+        let frame = SharedFrame::from(frame);
+        if members >= FANOUT_THRESHOLD {
+            let mut partitions = self.partitions.borrow_mut();
+            let partitions = partitions.get_or_insert_with(|| {
+                let spectators = self.spectators.iter().map(|(id, addr)| (*id, addr.clone()));
+                self.services.fanout.partition(
+                    self.players
+                        .iter()
+                        .flatten()
+                        .map(|x| (x.transient_id, x.addr.clone()))
+                        .chain(spectators),
+                )
+            });
+            self.services
+                .fanout
+                .broadcast(self.code, Frame(frame, priority), partitions);
         } else {
             for player in self.players.iter().filter_map(|x| x.as_ref()) {
-                player.addr.do_send(SerializedMessage(msg.clone()));
+                let frame = Frame(frame.clone(), priority);
+                self.deliver_frame(player.transient_id, &player.addr, frame);
+            }
+            for (id, addr) in &self.spectators {
+                self.deliver_frame(*id, addr, Frame(frame.clone(), priority));
+            }
+        }
+    }
+    /// Has the sessions of the members, or of the targeted player only, put a countdown in words
+    /// for clients that show the server's text as is, see [crate::session::catalog]
+    fn warn(&self, countdown: Countdown, remaining: Duration, target: Option<usize>) {
+        let warn = || Warn {
+            countdown,
+            remaining,
+        };
+        if let Some(idx) = target {
+            if let Some(player) = self.players.get(idx).and_then(Option::as_ref) {
+                player.addr.do_send(warn());
+            }
+            return;
+        }
+        let players = self.players.iter().flatten().map(|x| &x.addr);
+        for addr in players.chain(self.spectators.values()) {
+            addr.do_send(warn());
+        }
+    }
+    /// Sends the message to a single member, recording it as a dead letter if their session has
+    /// already stopped
+    fn deliver(&self, id: TransientId, addr: &Addr<Session>, msg: OutgoingMessage) {
+        match addr.try_send(SerializedMessage(msg)) {
+            Ok(()) => self.history.borrow_mut().sent_direct(id),
+            // A busy session still gets the message, it just skips the mailbox limit
+            Err(SendError::Full(msg)) => {
+                addr.do_send(msg);
+                self.history.borrow_mut().sent_direct(id);
+            }
+            Err(SendError::Closed(SerializedMessage(msg))) => {
+                let frame = serde_json::to_string(&msg).unwrap_or_default();
+                // Players keep their seat for a while, they get the message if they come back
+                if self.id_map.contains_key(&id) {
+                    self.history.borrow_mut().keep_missed(id, frame.into());
+                    return;
+                }
+                self.services.dead_letters.do_send(
+                    DeadLetter::new(DeadLetterReason::SessionGone, Some(id))
+                        .room(&self.code)
+                        .frame(&frame),
+                );
+            }
+        }
+    }
+    /// Tells a member they are out of the room, recording it as a dead letter if their session
+    /// has already stopped, see [Room::deliver]
+    fn clear(
+        &self,
+        id: TransientId,
+        addr: &Addr<Session>,
+        reason: RemoveReason,
+        by: Option<String>,
+    ) {
+        match addr.try_send(ClearRoom { reason, by }) {
+            Ok(()) => {}
+            Err(SendError::Full(msg)) => addr.do_send(msg),
+            // Players who never came back are gone for good, there is nobody to tell
+            Err(SendError::Closed(_)) if matches!(reason, RemoveReason::Disconnected) => {}
+            Err(SendError::Closed(ClearRoom { reason, by })) => {
+                let msg = OutgoingMessage::removed(reason, by);
+                let frame = serde_json::to_string(&msg).unwrap_or_default();
+                self.services.dead_letters.do_send(
+                    DeadLetter::new(DeadLetterReason::SessionGone, Some(id))
+                        .room(&self.code)
+                        .frame(&frame),
+                );
+            }
+        }
+    }
+    /// Same as [Room::deliver] for a message that is serialized already
+    fn deliver_frame(&self, id: TransientId, addr: &Addr<Session>, frame: Frame) {
+        match addr.try_send(frame) {
+            Ok(()) => {}
+            Err(SendError::Full(frame)) => addr.do_send(frame),
+            Err(SendError::Closed(Frame(frame, _))) => {
+                self.services.dead_letters.do_send(
+                    DeadLetter::new(DeadLetterReason::SessionGone, Some(id))
+                        .room(&self.code)
+                        .frame(frame.json()),
+                );
+            }
+        }
+    }
+    /// Whether the running game is short on players and takes in random joiners. Only public
+    /// rooms are ever backfilled.
+    fn wants_backfill(&self) -> bool {
+        self.room_config.public
+            && !self.locked
+            && !self.room_config.is_full(self.player_count)
+            && self
+                .game
+                .as_ref()
+                .is_some_and(|game| game.wants_players(self.player_count))
+    }
+    /// Puts the event on the server's [crate::events::EventBus]
+    fn publish(&self, event: ServerEvent) {
+        self.services.events.do_send(Publish(event));
+    }
+    /// Host migration: the connected player in the lowest seat takes over. A player whose seat is
+    /// held for them only does if the leader is gone for good and nobody connected is left.
+    fn migrate_leadership(&mut self) {
+        let mut others = self
+            .players
+            .iter()
+            .flatten()
+            .map(|player| player.transient_id)
+            .filter(|id| *id != self.leader);
+        let connected = others.clone().find(|id| !self.disconnected.contains_key(id));
+        let next = if self.id_map.contains_key(&self.leader) {
+            connected
+        } else {
+            connected.or_else(|| others.next())
+        };
+        if let Some(next) = next {
+            self.set_leader(next);
+        }
+    }
+    fn set_leader(&mut self, leader: TransientId) {
+        self.audit.record(AuditEvent::LeaderChanged {
+            from: self.leader,
+            to: leader,
+        });
+        self.leader = leader;
+        self.notify_clients(OutgoingMessage::LeaderChanged(leader), None);
+    }
+    fn roster_entry(&self, player: &PlayerInRoom) -> RosterEntry {
+        RosterEntry {
+            id: player.transient_id,
+            name: player.profile.name.clone(),
+            avatar: player.profile.avatar.clone(),
+            color: player.profile.color,
+            leader: player.transient_id == self.leader,
+            bot: is_bot(&player.user),
+        }
+    }
+    fn roster(&self) -> Vec<RosterEntry> {
+        self.players
+            .iter()
+            .flatten()
+            .map(|x| self.roster_entry(x))
+            .collect()
+    }
+    /// Runs the player's profile through the profanity filter and makes sure nobody else in the
+    /// room goes by the same name. Players without a profile get a placeholder, numbered if
+    /// someone already picked its name for themselves.
+    fn resolve_profile(
+        &self,
+        id: TransientId,
+        profile: Option<Profile>,
+    ) -> Result<Profile, JoinRoomError> {
+        let Some(profile) = profile else {
+            let mut profile = Profile::placeholder(id);
+            let name = profile.name.clone();
+            let mut n = 1;
+            while self.name_taken(&profile.name) {
+                n += 1;
+                profile.name = format!("{name} ({n})");
             }
+            return Ok(profile);
+        };
+        let profile = filter_name(&self.services.profanity, profile)
+            .ok_or(JoinRoomError::InappropriateName)?;
+        if self.name_taken(&profile.name) {
+            Err(JoinRoomError::NameTaken)
+        } else {
+            Ok(profile)
         }
     }
-    pub fn get_id(&self, idx: usize) -> Option<TransientId> {
+    /// Whether one of the players goes by the name, regardless of case
+    fn name_taken(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
         self.players
-            .get(idx)
-            .and_then(|x| x.as_ref().map(|data| data.transient_id))
+            .iter()
+            .flatten()
+            .any(|x| x.profile.name.to_lowercase() == name)
     }
-    pub fn get_players(&self) -> &Vec<Option<PlayerInRoom>> {
-        &self.players
+    /// Records that a player did something, postponing the room's expiry
+    fn touch(&mut self) {
+        self.last_activity = Instant::now();
+        self.expiry_warned = false;
+    }
+    /// Warns the players once the room has been idle for long enough and closes it with
+    /// [RemoveReason::RoomExpired] when the whole [InactivityConfig::timeout] has passed. Rooms
+    /// never expire while a game is running.
+    fn check_inactivity(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if self.state == RoomState::InGame {
+            self.touch();
+            return;
+        }
+        let InactivityConfig { timeout, warning } = self.services.inactivity;
+        let idle = self.last_activity.elapsed();
+        if idle >= timeout {
+            log::info!(
+                "room {} expired after {}s of inactivity",
+                String::from_utf8_lossy(&self.code),
+                idle.as_secs()
+            );
+            self.close_reason = RemoveReason::RoomExpired;
+            self.close(true, ctx);
+        } else if !self.expiry_warned && idle + warning >= timeout {
+            self.expiry_warned = true;
+            let deadline = Deadline::after(timeout - idle);
+            self.notify_clients(OutgoingMessage::RoomExpiring(deadline), None);
+            self.warn(Countdown::RoomExpiring, timeout - idle, None);
+        }
+    }
+    /// Puts a player joining the full room in line for a seat
+    fn enqueue(&mut self, joiner: Joiner, ctx: &mut <Self as Actor>::Context) -> JoinRoomError {
+        let id = joiner.session.0;
+        if self.id_map.contains_key(&id) || self.queue.iter().any(|x| x.session.0 == id) {
+            return JoinRoomError::AlreadyInRoom;
+        }
+        if self.queue.len() >= MAX_QUEUE_LENGTH {
+            return JoinRoomError::RoomFull;
+        }
+        let position = self.queue.len() + 1;
+        joiner.session.1.do_send(Queued {
+            room: ctx.address(),
+            position,
+        });
+        self.queue.push_back(joiner);
+        JoinRoomError::Queued(position)
+    }
+    /// Seats the players at the front of the queue for as long as there are free seats and no
+    /// game that keeps them from joining
+    fn admit_queued(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let mut admitted = false;
+        while !self.locked
+            && (self.state != RoomState::InGame || self.wants_backfill())
+            && !self.room_config.is_full(self.player_count)
+        {
+            let Some(joiner) = self.queue.pop_front() else {
+                break;
+            };
+            let session = joiner.session.1.clone();
+            if !session.connected() {
+                continue;
+            }
+            admitted = true;
+            session.do_send(QueueOutcome(self.add_player(joiner, false, ctx)));
+        }
+        if admitted {
+            self.announce_queue_positions();
+        }
+    }
+    /// Puts the results of a practice game on the seed's leaderboard, returning the board
+    fn record_practice(
+        &self,
+        seed: PracticeSeed,
+        summary: &GameSummary,
+        users: &HashMap<TransientId, UserId>,
+    ) -> PracticeBoard {
+        let time = summary.turns.iter().map(|turn| turn.took).sum();
+        for result in &summary.standings {
+            if let (Some(user), Some(name)) = (users.get(&result.id), &result.name) {
+                let practice = &self.services.practice;
+                practice.record(seed, user.clone(), name.clone(), result.score, time);
+            }
+        }
+        self.services.practice.get(seed)
+    }
+    /// Relays chat and reactions held back while the server was shedding load
+    fn flush_deferred(&mut self) {
+        for msg in std::mem::take(&mut self.deferred) {
+            self.notify_clients(msg, None);
+        }
+    }
+    /// Lets everyone in the queue know how far along they are
+    fn announce_queue_positions(&self) {
+        for (idx, joiner) in self.queue.iter().enumerate() {
+            let msg = SerializedMessage(OutgoingMessage::QueuePosition(idx + 1));
+            joiner.session.1.do_send(msg);
+        }
+    }
+    /// Turns everyone in the queue away, for when the room closes or stops queueing
+    fn clear_queue(&mut self, reason: JoinRoomError) {
+        for joiner in self.queue.drain(..) {
+            joiner.session.1.do_send(QueueOutcome(Err(reason.clone())));
+        }
+    }
+    /// Must be called whenever a member joins, leaves or is replaced
+    fn members_changed(&mut self) {
+        self.partitions.get_mut().take();
+        self.report_occupancy();
+    }
+    fn spectators_changed(&mut self) {
+        self.partitions.get_mut().take();
+        self.room_manager.do_send(SpectatorAvailability {
+            code: self.code,
+            full: self.room_config.spectators_full(self.spectators.len()),
+        });
+    }
+    fn report_occupancy(&self) {
+        let users = self
+            .players
+            .iter()
+            .flatten()
+            .map(|player| &player.user)
+            .filter(|user| !is_bot(user));
+        let rtts = self
+            .players
+            .iter()
+            .flatten()
+            .filter_map(|player| player.rtt);
+        self.room_manager.do_send(RoomOccupancy {
+            code: self.code,
+            players: self.player_count,
+            rating: self.services.ratings.average(users),
+            latency: latency::average(rtts),
+        });
     }
 }
 
-impl Actor for Room {
-    type Context = Context<Self>;
-    fn stopped(&mut self, ctx: &mut Self::Context) {
-        if let Some(game) = &mut self.game {
+impl Room {
+    /// Greets the leader and sets up the periodic work, for a new room as well as one taken
+    /// out of the pool
+    fn open(&mut self, ctx: &mut <Self as Actor>::Context) {
+        self.notify_clients(OutgoingMessage::Roster(self.roster()), Some(0));
+        self.publish(ServerEvent::RoomCreated {
+            room: String::from_utf8_lossy(&self.code).into_owned(),
+            kind: self.room_config.kind,
+            public: self.room_config.public,
+            mode: self.game_config.mode,
+        });
+        if self.room_config.kind == RoomKind::Practice {
+            self.begin_countdown(ctx);
+        }
+        let polls = ctx.run_interval(Duration::from_secs(POLL_INTERVAL), |act, ctx| {
+            if act.room_config.kind == RoomKind::Standard
+                && act.state.in_lobby()
+                && act.player_count > 1
+                && !act.lobby.is_polling()
+            {
+                act.lobby.start_poll(ctx);
+            }
+        });
+        let inactivity = ctx.run_interval(
+            Duration::from_secs(INACTIVITY_CHECK_INTERVAL),
+            Self::check_inactivity,
+        );
+        let queue = ctx.run_interval(Duration::from_secs(QUEUE_UPDATE_INTERVAL), |act, _| {
+            // Players waiting in line hear about their position again once the load drops
+            if !act.services.load.degraded() {
+                act.announce_queue_positions()
+            }
+        });
+        let chat = ctx.run_interval(FLUSH_INTERVAL, |act, _| {
+            act.flush_ticks += 1;
+            if !act.services.load.skips(act.flush_ticks) {
+                act.flush_deferred();
+            }
+        });
+        self.timers = vec![polls, inactivity, queue, chat];
+        if let Some(lifetime) = &self.room_config.lifetime {
+            for warning in lifetime.warnings.iter().filter(|x| **x < lifetime.duration) {
+                let warning = *warning;
+                let timer = ctx.run_later(lifetime.duration - warning, move |act, _| {
+                    let deadline = Deadline::after(warning);
+                    act.notify_clients(OutgoingMessage::RoomEnding(deadline), None);
+                    act.warn(Countdown::RoomEnding, warning, None);
+                });
+                self.timers.push(timer);
+            }
+            let timer = ctx.notify_later(LifetimeElapsed, lifetime.duration);
+            self.timers.push(timer);
+        }
+    }
+    /// Puts the room back up for matchmaking after a game and lets in the players who queued up
+    /// for a seat meanwhile
+    fn reopen(&mut self, ctx: &mut <Self as Actor>::Context) {
+        self.set_state(RoomState::Lobby);
+        // Locked rooms only come back once they are unlocked
+        if !self.locked {
+            self.room_manager.do_send(UpdateRoomMatchAvailability {
+                code: self.code,
+                availability: Availability::Available,
+            });
+        }
+        self.admit_queued(ctx);
+        self.update_countdown(ctx);
+    }
+    /// Settles the running rematch vote as soon as a majority of the players agreed or can no
+    /// longer agree. Votes of players who left are not counted.
+    fn tally_rematch(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if !self.lobby.is_voting_rematch() || self.player_count == 0 {
+            return;
+        }
+        let (mut agree, mut refuse) = (0, 0);
+        for (id, vote) in self.lobby.rematch_votes() {
+            if !self.id_map.contains_key(id) {
+                continue;
+            }
+            if *vote {
+                agree += 1;
+            } else {
+                refuse += 1;
+            }
+        }
+        self.notify_clients(OutgoingMessage::RematchVotes { agree, refuse }, None);
+        let majority = self.player_count / 2 + 1;
+        if agree >= majority {
+            // Too many players might have left to play again
+            self.end_rematch_vote(self.can_start(), ctx);
+        } else if refuse + majority > self.player_count {
+            self.end_rematch_vote(false, ctx);
+        }
+    }
+    /// Restarts the game with the same settings if the players agreed on a rematch, otherwise
+    /// the room goes back to the open pool
+    fn end_rematch_vote(&mut self, rematch: bool, ctx: &mut <Self as Actor>::Context) {
+        self.lobby.end_rematch_vote(ctx);
+        self.notify_clients(OutgoingMessage::RematchDecided(rematch), None);
+        if rematch {
+            self.start_game(ctx);
+        } else {
+            self.reopen(ctx);
+        }
+    }
+    /// Sends everyone still in the room away and tells the room manager. An `idle` room keeps
+    /// its actor around for the room manager to reuse, otherwise the actor is stopping.
+    fn close(&mut self, idle: bool, ctx: &mut <Self as Actor>::Context) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        for timer in self.timers.drain(..) {
+            ctx.cancel_future(timer);
+        }
+        if let Some(handle) = self.summarizing.take() {
+            ctx.cancel_future(handle);
+        }
+        if let Some((handle, _)) = self.countdown.take() {
+            ctx.cancel_future(handle);
+        }
+        for (_, handle) in self.disconnected.drain() {
+            ctx.cancel_future(handle);
+        }
+        self.lobby.reset(ctx);
+        if let Some(mut game) = self.game.take() {
             game.on_end(ctx);
         }
-        for player in self.players.iter().filter(|x| x.is_some()) {
-            let PlayerInRoom { addr, .. } = player.as_ref().unwrap();
-            addr.do_send(SerializedMessage(OutgoingMessage::RemoveFromRoom(
-                RemoveReason::RoomClosed,
-            )));
+        self.mode_slot = None;
+        let players = self.players.iter_mut().filter_map(Option::take);
+        let members: Vec<_> = players
+            .map(|player| (player.transient_id, player.addr))
+            .chain(self.spectators.drain())
+            .collect();
+        for (id, addr) in members {
+            self.clear(id, &addr, self.close_reason, None);
         }
-        self.room_manager.do_send(OnRoomClosed(self.code.clone()));
+        self.id_map.clear();
+        self.player_count = 0;
+        self.clear_queue(JoinRoomError::RoomNotFound);
+        self.observers.detach_all();
+        self.publish(ServerEvent::RoomClosed {
+            room: String::from_utf8_lossy(&self.code).into_owned(),
+            reason: self.close_reason,
+        });
+        self.audit.record(AuditEvent::Closed {
+            reason: self.close_reason,
+        });
+        self.room_manager.do_send(OnRoomClosed {
+            code: self.code,
+            idle,
+            audit: self.audit.entries(),
+            privacy_mode: self.room_config.privacy_mode,
+        });
+    }
+}
+
+impl Actor for Room {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.open(ctx);
+    }
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.close(false, ctx);
+    }
+}
+
+/// Hands a room idling in the pool to a new leader. The room starts over with the settings
+/// given, keeping only its code.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResetRoom {
+    pub leader: Joiner,
+    pub room_config: RoomConfig,
+    pub game_config: GameConfigOptions,
+}
+
+impl Handler<ResetRoom> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: ResetRoom, ctx: &mut Self::Context) -> Self::Result {
+        let generation = self.generation + 1;
+        *self = Room::new(
+            self.code,
+            self.room_manager.clone(),
+            msg.leader,
+            msg.room_config,
+            msg.game_config,
+            self.services.clone(),
+        );
+        self.generation = generation;
+        self.open(ctx);
     }
 }
 
@@ -127,6 +940,61 @@ pub struct RemovePlayer {
     pub reason: RemoveReason,
 }
 
+/// Admin request for the room's [AuditTrail]
+#[derive(Message)]
+#[rtype(result = "Vec<AuditRecord>")]
+pub struct GetAudit;
+
+impl Handler<GetAudit> for Room {
+    type Result = MessageResult<GetAudit>;
+    fn handle(&mut self, _: GetAudit, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.audit.entries())
+    }
+}
+
+/// How busy the room is, for diagnostics snapshots
+#[derive(Message, Clone)]
+#[rtype(result = "RoomTraffic")]
+pub struct GetTraffic;
+
+impl Handler<GetTraffic> for Room {
+    type Result = MessageResult<GetTraffic>;
+    fn handle(&mut self, _: GetTraffic, _: &mut Self::Context) -> Self::Result {
+        MessageResult(RoomTraffic {
+            code: String::from_utf8_lossy(&self.code).into_owned(),
+            players: self.player_count,
+            spectators: self.spectators.len(),
+            rate: self.traffic.borrow_mut().rate(Instant::now()),
+            behind: self.history.borrow().behind(),
+        })
+    }
+}
+
+/// Attaches an admin to the room as an invisible observer, see [super::observer]
+#[derive(Message)]
+#[rtype(result = "Result<(), ObserveError>")]
+pub struct AddObserver(pub Recipient<Observed>);
+
+impl Handler<AddObserver> for Room {
+    type Result = Result<(), ObserveError>;
+    fn handle(&mut self, msg: AddObserver, _: &mut Self::Context) -> Self::Result {
+        if self.closed {
+            return Err(ObserveError::RoomNotFound);
+        }
+        let snapshot = Observation::Snapshot {
+            state: self.state,
+            leader: self.leader,
+            roster: self.roster(),
+            spectators: self.spectators.len(),
+            queued: self.queue.len(),
+            metadata: &self.room_config.metadata,
+            seq: self.history.borrow().seq(),
+        };
+        self.observers.attach(msg.0, snapshot)
+    }
+}
+
+/// Stops the room for good, rather than leaving it to idle in the pool
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct CloseRoom;
@@ -138,83 +1006,388 @@ pub enum JoinRoomError {
     AlreadyInRoom,
     RoomNotFound,
     InvalidCode,
+    /// No room matched the player's preferences before they stopped waiting
+    NoMatch,
+    NameTaken,
+    InappropriateName,
+    /// The room's leader kicked the player from this room
+    Banned,
+    /// The room's leader locked the room
+    RoomLocked,
+    /// The room is full and the player waits in line at this position, they hear back once
+    /// they get a seat or drop out of the queue
+    Queued(usize),
+    /// The invite is malformed, was already used, or belongs to a room that no longer exists
+    InvalidInvite,
+    InviteExpired,
+    /// The server is overloaded and opens no new rooms for now
+    ServerBusy,
+    /// Every room code is in use, the server cannot open another room for now
+    NoCodeAvailable,
+    /// Every spectator slot of the room is taken
+    SpectatorsFull,
+    /// The room is behind a password and the player gave none or the wrong one
+    WrongPassword,
+    /// The password for a new room is longer than [super::MAX_PASSWORD_LENGTH]
+    InvalidPassword,
+    /// The player limit asked for a new room is out of range
+    InvalidPlayerLimit,
+    /// No word list covers the language asked for a new room, see
+    /// [crate::game::words::supported_language]
+    UnsupportedLanguage,
     InternalServerError,
 }
 
 #[derive(Message)]
 #[rtype(result = "Result<(RoomCode, Addr<Room>), JoinRoomError>")]
-pub struct AddPlayer(pub super::SessionPair);
+pub struct AddPlayer(pub Joiner);
+
+/// Someone about to take a seat in a room
+#[derive(Clone)]
+pub struct Joiner {
+    pub session: super::SessionPair,
+    pub user: UserId,
+    pub profile: Option<Profile>,
+    /// Password the player gave for the room, see [RoomConfig::password]
+    pub password: Option<Box<str>>,
+    /// Rooms the player opens count against the share of this class, see [crate::capacity]
+    pub class: CapacityClass,
+    /// Round trip time of the player's connection, see [crate::session::latency]
+    pub rtt: Option<Duration>,
+}
 
 impl Handler<AddPlayer> for Room {
     type Result = Result<(RoomCode, Addr<Room>), JoinRoomError>;
     fn handle(&mut self, msg: AddPlayer, ctx: &mut Self::Context) -> Self::Result {
-        let (id, addr) = msg.0;
+        // Only invites get players past the password, see [AddInvitedPlayer]
+        let given = msg.0.password.as_deref();
+        if !password::admits(self.room_config.password.as_ref(), given) {
+            return Err(JoinRoomError::WrongPassword);
+        }
+        if self.room_config.waiting_queue
+            && self.room_config.is_full(self.player_count)
+            && !self.locked
+            && !self.banned.contains_key(&msg.0.user)
+        {
+            self.touch();
+            return Err(self.enqueue(msg.0, ctx));
+        }
+        self.add_player(msg.0, false, ctx)
+    }
+}
+
+/// Joins a room through one of its invites, see [CreateInvite]
+#[derive(Message)]
+#[rtype(result = "Result<(RoomCode, Addr<Room>), JoinRoomError>")]
+pub struct AddInvitedPlayer {
+    pub joiner: Joiner,
+    pub nonce: u64,
+}
+
+impl Handler<AddInvitedPlayer> for Room {
+    type Result = Result<(RoomCode, Addr<Room>), JoinRoomError>;
+    fn handle(&mut self, msg: AddInvitedPlayer, ctx: &mut Self::Context) -> Self::Result {
+        // A room reusing the code of the one the invite was made for knows nothing about it
+        let Some(invitation) = self.invites.get(&msg.nonce) else {
+            return Err(JoinRoomError::InvalidInvite);
+        };
+        if invitation.expires_at <= unix_time() {
+            self.invites.remove(&msg.nonce);
+            return Err(JoinRoomError::InviteExpired);
+        }
+        let single_use = invitation.single_use;
+        let result = self.add_player(msg.joiner, true, ctx);
+        if result.is_ok() && single_use {
+            self.invites.remove(&msg.nonce);
+        }
+        result
+    }
+}
+
+impl Room {
+    /// Seats a new player. Invited players get in even if the room is full.
+    fn add_player(
+        &mut self,
+        joiner: Joiner,
+        invited: bool,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> Result<(RoomCode, Addr<Room>), JoinRoomError> {
+        // Matchmaking might still have had the room on its list when it closed
+        if self.closed {
+            return Err(JoinRoomError::RoomNotFound);
+        }
+        self.touch();
+        let Joiner {
+            session: (id, addr),
+            user,
+            profile,
+            rtt,
+            ..
+        } = joiner;
         /* The default behaviour is to not allow players to join a room while a game is currently
-         * in progress in that same room, however it may be deserible to add players to an ongoing
-         * game, in which case the following check should be disabled or replaced with some other
-         * more appropriate check */
-        let result = if self.game.is_some() {
+         * in progress in that same room, unless the game mode asks for more players (see
+         * [Room::wants_backfill]) */
+        let result = if self.state == RoomState::InGame && !self.wants_backfill() {
             Err(JoinRoomError::GameInProgress)
-        } else if self.player_count >= self.room_config.max_player_count as usize {
+        } else if self.locked {
+            Err(JoinRoomError::RoomLocked)
+        } else if !invited && self.room_config.is_full(self.player_count) {
             Err(JoinRoomError::RoomFull)
+        } else if self.banned.contains_key(&user) {
+            Err(JoinRoomError::Banned)
         } else {
-            if self.id_map.get(&id).is_some() {
+            if self.id_map.contains_key(&id) {
                 Err(JoinRoomError::AlreadyInRoom)
             } else {
-                if let Some((idx, free)) = self
-                    .players
-                    .iter_mut()
-                    .enumerate()
-                    .find(|(_, x)| x.is_none())
-                {
-                    free.replace(PlayerInRoom {
-                        addr,
-                        transient_id: id,
-                    });
-                    self.id_map.insert(id, idx);
-                } else {
-                    self.id_map.insert(id, self.players.len());
-                    self.players.push(Some(PlayerInRoom {
-                        addr,
-                        transient_id: id,
-                    }));
+                match self.resolve_profile(id, profile) {
+                    Ok(profile) => {
+                        let player = PlayerInRoom {
+                            addr,
+                            transient_id: id,
+                            user,
+                            profile,
+                            rtt,
+                        };
+                        // Everyone already in the room hears about the newcomer, who gets the
+                        // full roster once they have taken their seat
+                        self.audit.record(AuditEvent::Joined {
+                            player: id,
+                            user: player.user.clone(),
+                            name: player.profile.name.as_str().into(),
+                        });
+                        let entry = self.roster_entry(&player);
+                        self.notify_clients(OutgoingMessage::PlayerJoined(entry), None);
+                        if let Some((idx, free)) = self
+                            .players
+                            .iter_mut()
+                            .enumerate()
+                            .find(|(_, x)| x.is_none())
+                        {
+                            free.replace(player);
+                            self.id_map.insert(id, idx);
+                        } else {
+                            self.id_map.insert(id, self.players.len());
+                            self.players.push(Some(player));
+                        }
+                        self.player_count += 1;
+                        self.members_changed();
+                        let idx = self.id_map[&id];
+                        self.notify_clients(OutgoingMessage::Roster(self.roster()), Some(idx));
+                        self.notify_clients(OutgoingMessage::RoomState(self.state), Some(idx));
+                        if !self.room_config.metadata.is_empty() {
+                            let metadata = self.room_config.metadata.clone();
+                            self.notify_clients(OutgoingMessage::RoomMetadata(metadata), Some(idx));
+                        }
+                        if let Some((_, fires_at)) = self.countdown {
+                            self.notify_clients(
+                                OutgoingMessage::StartingIn(Deadline::at(fires_at)),
+                                Some(idx),
+                            );
+                            let remaining = fires_at.saturating_duration_since(Instant::now());
+                            self.warn(Countdown::StartingIn, remaining, Some(idx));
+                        }
+                        self.update_countdown(ctx);
+                        if let (Some(game), Some(player)) = (&mut self.game, &self.players[idx]) {
+                            // Backfilled players are dropped straight into the running game
+                            game.on_player_joined(ctx, idx, player);
+                            player.addr.do_send(RestoreState {
+                                code: self.code,
+                                room: ctx.address(),
+                                game: Some(game.get_state(idx)),
+                                state: self.state,
+                                metadata: self.room_config.metadata.clone(),
+                                seq: self.history.borrow().seq(),
+                            });
+                        }
+                        Ok((self.code, ctx.address()))
+                    }
+                    Err(err) => Err(err),
                 }
-                self.player_count += 1;
-                Ok((self.code.clone(), ctx.address()))
             }
         };
-        if self.player_count >= self.room_config.max_player_count as usize {
+        if self.room_config.is_full(self.player_count) {
             self.room_manager.do_send(UpdateRoomMatchAvailability {
-                code: self.code.clone(),
+                code: self.code,
                 availability: Availability::Unavailable(RoomUnavailablityReason::Full),
             });
+        } else if self.state == RoomState::InGame && !self.wants_backfill() {
+            self.room_manager.do_send(UpdateRoomMatchAvailability {
+                code: self.code,
+                availability: Availability::Unavailable(RoomUnavailablityReason::GameStarted),
+            });
         }
         result
     }
 }
 
+/// Lets someone watch the room, see [super::SpectateRoom]
+#[derive(Message)]
+#[rtype(result = "Result<(RoomCode, Addr<Room>), JoinRoomError>")]
+pub struct AddSpectator(pub Joiner);
+
+impl Handler<AddSpectator> for Room {
+    type Result = Result<(RoomCode, Addr<Room>), JoinRoomError>;
+    fn handle(&mut self, msg: AddSpectator, ctx: &mut Self::Context) -> Self::Result {
+        if self.closed {
+            return Err(JoinRoomError::RoomNotFound);
+        }
+        self.touch();
+        let Joiner {
+            session: (id, addr),
+            user,
+            ..
+        } = msg.0;
+        if self.locked {
+            Err(JoinRoomError::RoomLocked)
+        } else if self.banned.contains_key(&user) {
+            Err(JoinRoomError::Banned)
+        } else if self.id_map.contains_key(&id) || self.spectators.contains_key(&id) {
+            Err(JoinRoomError::AlreadyInRoom)
+        } else if self.room_config.spectators_full(self.spectators.len()) {
+            Err(JoinRoomError::SpectatorsFull)
+        } else {
+            addr.do_send(SerializedMessage(OutgoingMessage::Roster(self.roster())));
+            addr.do_send(SerializedMessage(OutgoingMessage::RoomState(self.state)));
+            if !self.room_config.metadata.is_empty() {
+                let metadata = self.room_config.metadata.clone();
+                addr.do_send(SerializedMessage(OutgoingMessage::RoomMetadata(metadata)));
+            }
+            self.spectators.insert(id, addr);
+            self.spectators_changed();
+            Ok((self.code, ctx.address()))
+        }
+    }
+}
+
 impl Handler<RemovePlayer> for Room {
     type Result = ();
     fn handle(&mut self, msg: RemovePlayer, ctx: &mut Self::Context) -> Self::Result {
-        self.player_count -= 1;
-        let player = self
+        if matches!(msg.reason, RemoveReason::Disconnected)
+            && self.id_map.contains_key(&msg.transient_id)
+        {
+            self.hold_seat(msg.transient_id, ctx);
+        } else {
+            self.remove_member(msg.transient_id, msg.reason, None, ctx);
+        }
+    }
+}
+
+/// How long the room holds on to the seat of a player who lost connection, if it does. The
+/// session manager keeps the session around for as long, so that the player can reclaim the seat.
+#[derive(Message)]
+#[rtype(result = "Option<Duration>")]
+pub struct HeldSeat(pub TransientId);
+
+impl Handler<HeldSeat> for Room {
+    type Result = Option<Duration>;
+    fn handle(&mut self, msg: HeldSeat, _: &mut Self::Context) -> Self::Result {
+        let grace = Duration::from_secs(self.room_config.reconnect_grace_secs);
+        self.disconnected.contains_key(&msg.0).then_some(grace)
+    }
+}
+
+impl Room {
+    /// Keeps the seat of a player who lost connection for [RoomConfig::reconnect_grace_secs],
+    /// removing them once it runs out unless they reconnected in the meantime, see
+    /// [ClientReconnection]
+    fn hold_seat(&mut self, transient_id: TransientId, ctx: &mut <Self as Actor>::Context) {
+        if self.disconnected.contains_key(&transient_id) {
+            return;
+        }
+        let grace = Duration::from_secs(self.room_config.reconnect_grace_secs);
+        let handle = ctx.run_later(grace, move |act, ctx| {
+            act.disconnected.remove(&transient_id);
+            act.remove_member(transient_id, RemoveReason::Disconnected, None, ctx);
+        });
+        self.disconnected.insert(transient_id, handle);
+        if let (Some(game), Some(idx)) = (&mut self.game, self.id_map.get(&transient_id)) {
+            game.set_connection(*idx, Connection::Reconnecting);
+        }
+        self.notify_clients(
+            OutgoingMessage::PlayerDisconnected {
+                player: transient_id,
+                deadline: Deadline::after(grace),
+            },
+            None,
+        );
+        // The room isn't left without anyone to lead it for the whole grace window
+        if self.leader == transient_id {
+            self.migrate_leadership();
+        }
+    }
+    /// Takes the member out of the room. `by` is the leader if they removed the member.
+    fn remove_member(
+        &mut self,
+        transient_id: TransientId,
+        reason: RemoveReason,
+        by: Option<TransientId>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        if let Some(handle) = self.disconnected.remove(&transient_id) {
+            ctx.cancel_future(handle);
+        }
+        if let Some(addr) = self.spectators.remove(&transient_id) {
+            if !matches!(reason, RemoveReason::LeaveRequested) {
+                self.clear(transient_id, &addr, reason, None);
+            }
+            self.spectators_changed();
+            return;
+        }
+        let Some(player) = self
             .id_map
-            .remove(&msg.transient_id)
-            .and_then(|idx| self.players.get_mut(idx).take());
-        match msg.reason {
+            .remove(&transient_id)
+            .and_then(|idx| self.players.get_mut(idx).and_then(Option::take))
+        else {
+            return;
+        };
+        self.player_count -= 1;
+        if self.game.is_some() && !is_bot(&player.user) {
+            self.deserters.push(player.user.clone());
+        }
+        self.chat_limiter.forget(transient_id);
+        self.history.get_mut().forget(transient_id);
+        self.audit.record(match reason {
+            RemoveReason::Kicked | RemoveReason::Banned => AuditEvent::Kicked {
+                player: transient_id,
+                by,
+            },
+            reason => AuditEvent::Left {
+                player: transient_id,
+                reason,
+            },
+        });
+        self.observers.send(Observation::Removed {
+            player: transient_id,
+            reason,
+        });
+        self.members_changed();
+        match reason {
             RemoveReason::LeaveRequested => {
                 /* We dont send a ClearRoom message if the client requested a leave since it is
                  * expected from them to already clear their self.room field before requesting a
                  * leave. */
             }
             reason => {
-                if let Some(entry) = player {
-                    if let Some(PlayerInRoom { addr, .. }) = entry {
-                        addr.do_send(ClearRoom { reason });
-                    }
-                }
+                let by = by
+                    .and_then(|by| self.id_map.get(&by))
+                    .and_then(|idx| self.players[*idx].as_ref())
+                    .map(|leader| leader.profile.name.clone());
+                self.clear(transient_id, &player.addr, reason, by)
             }
         }
+        self.notify_clients(OutgoingMessage::PlayerLeft(transient_id), None);
+        self.admit_queued(ctx);
+        self.update_countdown(ctx);
+        self.tally_rematch(ctx);
+        if self.leader == transient_id {
+            self.migrate_leadership();
+        }
+        if self.wants_backfill() {
+            self.room_manager.do_send(UpdateRoomMatchAvailability {
+                code: self.code,
+                availability: Availability::Backfill,
+            });
+        }
         /* It might be desirable to close the room, ending any ongoing games when there are less
          * than however many players are required to keep a game going. Handling this might
          * require further checks that are entirely dependant on the nature of the game itself,
@@ -222,7 +1395,38 @@ impl Handler<RemovePlayer> for Room {
          * By default, the room is only closed in the event where every participant has left or
          * been removed. */
         if self.player_count == 0 {
-            ctx.stop();
+            self.close(true, ctx);
+        }
+    }
+}
+
+/// Sent by the room manager to a lobby that has been short on players for too long, whose
+/// members are moved into the room with the code, see [super::merge]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MergeInto(pub RoomCode);
+
+impl Handler<MergeInto> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: MergeInto, ctx: &mut Self::Context) -> Self::Result {
+        // Players may have joined or a game started since the room manager last heard of us
+        if self.closed
+            || self.state != RoomState::Lobby
+            || self.player_count >= self.room_config.min_players as usize
+        {
+            return;
+        }
+        self.close_reason = RemoveReason::Merged;
+        let members = self
+            .players
+            .iter()
+            .flatten()
+            .map(|player| (player.transient_id, player.addr.clone()))
+            .collect::<Vec<_>>();
+        // The room closes along with the last member leaving
+        for (transient_id, addr) in members {
+            self.remove_member(transient_id, RemoveReason::Merged, None, ctx);
+            addr.do_send(MoveToRoom(msg.0));
         }
     }
 }
@@ -230,9 +1434,7 @@ impl Handler<RemovePlayer> for Room {
 impl Handler<CloseRoom> for Room {
     type Result = ();
     fn handle(&mut self, _: CloseRoom, ctx: &mut Self::Context) -> Self::Result {
-        if let Some(game) = &mut self.game {
-            game.on_end(ctx);
-        }
+        // The game is ended and the players sent away once the room has stopped
         ctx.stop();
     }
 }
@@ -247,58 +1449,985 @@ impl Handler<CloseRoom> for Room {
 pub struct ClientReconnection {
     pub replacee: TransientId,
     pub replacer: (TransientId, Addr<Session>),
+    /// Number of the last room event the client got, if it kept its state
+    pub last_seq: Option<u64>,
+}
+
+impl Room {
+    /// Catches a reconnecting member up, with only what they missed if the room still has all
+    /// of it and the full state otherwise
+    fn restore(
+        &self,
+        member: TransientId,
+        addr: &Addr<Session>,
+        idx: Option<usize>,
+        last_seq: Option<u64>,
+        room: Addr<Room>,
+    ) {
+        let mut history = self.history.borrow_mut();
+        // Clients that leave it to the server pick up after the last event they acknowledged
+        let last_seq = last_seq.or(history.acked(member));
+        match history.catch_up(member, last_seq) {
+            Some(events) => addr.do_send(ReplayEvents {
+                code: self.code,
+                room,
+                events,
+            }),
+            None => addr.do_send(RestoreState {
+                code: self.code,
+                room,
+                game: idx.and_then(|idx| Some(self.game.as_ref()?.get_state(idx))),
+                state: self.state,
+                metadata: self.room_config.metadata.clone(),
+                seq: history.seq(),
+            }),
+        };
+    }
+}
+
+/// The member's client got every room event up to the one numbered `seq`, see
+/// [crate::session::message::IncomingMessage::Ack]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Acknowledge {
+    pub member: TransientId,
+    pub seq: u64,
+}
+
+impl Handler<Acknowledge> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: Acknowledge, _: &mut Self::Context) -> Self::Result {
+        let history = self.history.get_mut();
+        let was_behind = history.is_behind(msg.member);
+        history.ack(msg.member, msg.seq);
+        if history.is_behind(msg.member) && !was_behind {
+            log::warn!(
+                "member {} of room {} fell behind at event {} of {}",
+                msg.member,
+                String::from_utf8_lossy(&self.code),
+                msg.seq,
+                history.seq()
+            );
+        }
+    }
 }
 
 impl Handler<ClientReconnection> for Room {
     type Result = ();
-    fn handle(&mut self, msg: ClientReconnection, _: &mut Self::Context) -> Self::Result {
-        let ClientReconnection { replacee, replacer } = msg;
+    fn handle(&mut self, msg: ClientReconnection, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        let ClientReconnection {
+            replacee,
+            replacer,
+            last_seq,
+        } = msg;
         let (new_id, new_addr) = replacer;
+        if let Some(handle) = self.disconnected.remove(&replacee) {
+            ctx.cancel_future(handle);
+        }
         if let Some(idx) = self.id_map.remove(&replacee) {
-            if let Some(old) = self.players.get_mut(idx) {
-                if let Some(old) = old.take() {
-                    new_addr.do_send(RestoreState {
-                        code: self.code.clone(),
-                        game: self.game.as_mut().map(|g| g.get_state(idx)).unwrap(),
-                    });
+            if let Some(Some(old)) = self.players.get_mut(idx).map(Option::take) {
+                if let Some(game) = &mut self.game {
+                    game.set_connection(idx, Connection::Connected);
+                    game.set_id(idx, new_id);
                 }
+                self.restore(replacee, &new_addr, Some(idx), last_seq, ctx.address());
+                // Anything kept for the old connection went out with the restore
+                self.history.get_mut().forget(replacee);
                 self.id_map.insert(new_id, idx);
-                *old = Some(PlayerInRoom {
+                if self.leader == replacee {
+                    self.leader = new_id;
+                }
+                let player = PlayerInRoom {
                     addr: new_addr,
                     transient_id: new_id,
-                });
+                    user: old.user,
+                    profile: old.profile,
+                    rtt: old.rtt,
+                };
+                let entry = self.roster_entry(&player);
+                self.players[idx] = Some(player);
+                self.members_changed();
+                self.notify_clients(OutgoingMessage::Roster(self.roster()), Some(idx));
+                self.notify_clients(
+                    OutgoingMessage::PlayerRejoined {
+                        previous: replacee,
+                        player: entry,
+                    },
+                    None,
+                );
+                // Everyone else was away too when the leader lost connection
+                if self.disconnected.contains_key(&self.leader) {
+                    self.set_leader(new_id);
+                }
             }
+        } else if self.spectators.remove(&replacee).is_some() {
+            self.restore(replacee, &new_addr, None, last_seq, ctx.address());
+            self.history.get_mut().forget(replacee);
+            self.spectators.insert(new_id, new_addr);
+            self.partitions.get_mut().take();
         }
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, Clone)]
 pub enum StartGameError {
+    NotInRoom,
     GameAlreadyRunning,
     NotLeader,
+    /// Games cannot be played in announcement rooms
+    NotAllowed,
+    /// The room has fewer than [RoomConfig::min_players] players
+    NotEnoughPlayers,
+    /// The players are still deciding on a rematch of the last game
+    VotingRematch,
+    /// The mode of the room runs as many games as the server allows, see [ModeLimits]
+    ModeAtCapacity,
+    InternalServerError,
 }
 
 #[derive(Message)]
 #[rtype(result = "Result<(), StartGameError>")]
-pub struct RequestStart(TransientId);
+pub struct RequestStart(pub TransientId);
 
 impl Handler<RequestStart> for Room {
     type Result = Result<(), StartGameError>;
     fn handle(&mut self, msg: RequestStart, ctx: &mut Self::Context) -> Self::Result {
-        if self.game.is_some() {
+        self.touch();
+        if self.room_config.kind == RoomKind::Announcement {
+            Err(StartGameError::NotAllowed)
+        } else if self.state == RoomState::InGame {
             Err(StartGameError::GameAlreadyRunning)
+        } else if self.state == RoomState::PostGame {
+            Err(StartGameError::VotingRematch)
+        } else if !self.room_config.public && self.leader != msg.0 {
+            Err(StartGameError::NotLeader)
+        } else if !self.can_start() {
+            Err(StartGameError::NotEnoughPlayers)
+        } else if self.services.modes.at_capacity(self.game_config.mode) {
+            Err(StartGameError::ModeAtCapacity)
         } else {
-            if self.room_config.public {
-                self.start_game(ctx);
-                Ok(())
-            } else {
-                if self.leader == msg.0 {
-                    self.start_game(ctx);
-                    Ok(())
-                } else {
-                    Err(StartGameError::NotLeader)
-                }
+            // Games never start on the spot, everyone gets the countdown to get ready
+            self.begin_countdown(ctx);
+            Ok(())
+        }
+    }
+}
+
+/// Fired once the room reached the end of its [RoomConfig::lifetime]
+#[derive(Message)]
+#[rtype(result = "()")]
+struct LifetimeElapsed;
+
+impl Handler<LifetimeElapsed> for Room {
+    type Result = ();
+    fn handle(&mut self, _: LifetimeElapsed, ctx: &mut Self::Context) -> Self::Result {
+        log::info!(
+            "room {} reached the end of its lifetime",
+            String::from_utf8_lossy(&self.code)
+        );
+        self.lifetime_over = true;
+        self.close_reason = RemoveReason::RoomExpired;
+        if self.state == RoomState::InGame {
+            // The results of the game are sent out before the room closes
+            ctx.notify(GameOver);
+        } else if self.summarizing.is_none() {
+            self.close(true, ctx);
+        }
+    }
+}
+
+/// Fired once the start countdown runs out
+#[derive(Message)]
+#[rtype(result = "()")]
+struct CountdownElapsed;
+
+impl Handler<CountdownElapsed> for Room {
+    type Result = ();
+    fn handle(&mut self, _: CountdownElapsed, ctx: &mut Self::Context) -> Self::Result {
+        if self.countdown.take().is_none() {
+            return;
+        }
+        if self.can_start() {
+            self.start_game(ctx);
+        } else {
+            self.set_state(RoomState::Lobby);
+        }
+    }
+}
+
+/// Sent by the running game to the room it belongs to whenever a message has to reach every
+/// member of the room.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Broadcast(pub OutgoingMessage);
+
+impl Handler<Broadcast> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: Broadcast, _: &mut Self::Context) -> Self::Result {
+        // Left over from before the room closed, nobody is there to hear it
+        if self.closed {
+            return;
+        }
+        if self.game.is_some() {
+            self.replay.record(&msg.0, Instant::now());
+        }
+        self.notify_clients(msg.0, None);
+    }
+}
+
+impl Handler<Highlight> for Room {
+    type Result = ();
+    fn handle(&mut self, _: Highlight, _: &mut Self::Context) -> Self::Result {
+        if self.closed {
+            return;
+        }
+        if let Some(clip) = self.replay.clip(true, Instant::now()) {
+            self.notify_clients(
+                OutgoingMessage::InstantReplay(message::Result::Success(clip)),
+                None,
+            );
+        }
+    }
+}
+
+/// Request of a member for the last moments of the game, see [super::instant_replay]
+#[derive(Message)]
+#[rtype(result = "Result<ReplayClip, ReplayError>")]
+pub struct RequestReplay(pub TransientId);
+
+impl Handler<RequestReplay> for Room {
+    type Result = Result<ReplayClip, ReplayError>;
+    fn handle(&mut self, msg: RequestReplay, _: &mut Self::Context) -> Self::Result {
+        if !self.id_map.contains_key(&msg.0) && !self.spectators.contains_key(&msg.0) {
+            return Err(ReplayError::NotInRoom);
+        }
+        self.replay
+            .clip(false, Instant::now())
+            .ok_or(ReplayError::NothingToReplay)
+    }
+}
+
+impl Handler<TurnTimeout> for Room {
+    type Result = ();
+    fn handle(&mut self, _: TurnTimeout, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(game) = &mut self.game {
+            self.observers.send(Observation::TurnTimeout);
+            game.on_turn_timeout(ctx);
+        }
+    }
+}
+
+impl Handler<GameTimer> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: GameTimer, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(game) = &mut self.game {
+            game.on_timer(ctx, msg);
+        }
+    }
+}
+
+impl Handler<GameOver> for Room {
+    type Result = ();
+    fn handle(&mut self, _: GameOver, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut game) = self.game.take() {
+            game.on_end(ctx);
+            self.mode_slot = None;
+            self.observers.send(Observation::GameOver);
+            self.publish(ServerEvent::GameFinished {
+                room: String::from_utf8_lossy(&self.code).into_owned(),
+                mode: self.game_config.mode,
+                players: self.player_count,
+            });
+            self.set_state(RoomState::PostGame);
+            let mut users = HashMap::new();
+            let deserters = std::mem::take(&mut self.deserters);
+            let results = game
+                .scores()
+                .into_iter()
+                .map(|(id, score)| {
+                    let player = self
+                        .id_map
+                        .get(&id)
+                        .and_then(|idx| self.players[*idx].as_ref());
+                    // Bots play along without being rated
+                    if let Some(player) = player.filter(|x| !is_bot(&x.user)) {
+                        users.insert(id, player.user.clone());
+                    }
+                    PlayerResult {
+                        id,
+                        name: player.map(|x| x.profile.name.clone()),
+                        score,
+                        rank: 0,
+                    }
+                })
+                .collect();
+            // The room only becomes available again once the results are out, but keeps handling
+            // its members in the meantime
+            let generation = self.generation;
+            let summarizing = ctx.spawn(
+                self.services
+                    .jobs
+                    .run(Summarize {
+                        room: String::from_utf8_lossy(&self.code).into_owned(),
+                        results,
+                        turns: game.take_turn_log(),
+                        metadata: self.room_config.metadata.clone(),
+                        archive: Arc::clone(&self.services.archive),
+                        sealed: self.room_config.privacy_mode,
+                    })
+                    .into_actor(self)
+                    .map(move |res, act, ctx| {
+                        // The room closed and went to a new leader in the meantime
+                        if act.generation != generation {
+                            return;
+                        }
+                        act.summarizing = None;
+                        match res {
+                            Ok(mut summary) => {
+                                // Practice games go on the seed's leaderboard instead of being
+                                // rated
+                                let board = act
+                                    .game_config
+                                    .seed
+                                    .map(|seed| act.record_practice(seed, &summary, &users));
+                                if !act.game_config.share_turn_log {
+                                    summary.turns.clear();
+                                }
+                                if board.is_none() {
+                                    let ranks = summary
+                                        .standings
+                                        .iter()
+                                        .filter_map(|x| Some((users.get(&x.id)?.clone(), x.rank)))
+                                        .collect::<Vec<_>>();
+                                    act.services.ratings.record(&ranks, &deserters);
+                                }
+                                act.report_occupancy();
+                                act.notify_clients(OutgoingMessage::GameEnd(summary), None);
+                                if let Some(board) = board {
+                                    act.notify_clients(OutgoingMessage::PracticeBoard(board), None);
+                                }
+                            }
+                            Err(err) => log::error!("failed to summarize game: {err}"),
+                        }
+                        if act.lifetime_over {
+                            act.close(true, ctx);
+                        } else {
+                            // The players get to decide on a rematch before the room opens up
+                            // again
+                            act.lobby.start_rematch_vote(ctx);
+                        }
+                    }),
+            );
+            self.summarizing = Some(summarizing);
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum GameInputError {
+    NotInRoom,
+    NoGameRunning,
+    Rejected(InputError),
+    InternalServerError,
+}
+
+/// Gameplay input from one of the room's players, forwarded to the running game
+#[derive(Message)]
+#[rtype(result = "Result<(), GameInputError>")]
+pub struct SubmitInput {
+    pub transient_id: TransientId,
+    pub input: Input,
+}
+
+impl Handler<SubmitInput> for Room {
+    type Result = Result<(), GameInputError>;
+    fn handle(&mut self, msg: SubmitInput, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        let idx = *self
+            .id_map
+            .get(&msg.transient_id)
+            .ok_or(GameInputError::NotInRoom)?;
+        let game = self.game.as_mut().ok_or(GameInputError::NoGameRunning)?;
+        let result = game.on_input(ctx, idx, &msg.input);
+        self.observers.send(Observation::Input {
+            player: msg.transient_id,
+            input: &msg.input,
+            rejected: result.as_ref().err(),
+        });
+        result.map_err(GameInputError::Rejected)
+    }
+}
+
+/// Lobby only interaction from one of the room's players, ignored once a game has started
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LobbyInteraction {
+    pub transient_id: TransientId,
+    pub action: LobbyAction,
+}
+
+impl Handler<LobbyInteraction> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: LobbyInteraction, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.room_config.kind == RoomKind::Announcement
+            || self.state == RoomState::InGame
+            || !self.id_map.contains_key(&msg.transient_id)
+        {
+            return;
+        }
+        let rematch = matches!(msg.action, LobbyAction::Rematch(_));
+        self.lobby.handle(ctx, msg.transient_id, msg.action);
+        if rematch {
+            self.tally_rematch(ctx);
+        }
+    }
+}
+
+impl Handler<CloseRematchVote> for Room {
+    type Result = ();
+    fn handle(&mut self, _: CloseRematchVote, ctx: &mut Self::Context) -> Self::Result {
+        if self.lobby.is_voting_rematch() {
+            self.end_rematch_vote(false, ctx);
+        }
+    }
+}
+
+impl Handler<Reaction> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: Reaction, _: &mut Self::Context) -> Self::Result {
+        if !self.services.load.degraded() {
+            self.flush_deferred();
+            self.notify_clients(msg.0, None);
+        } else if self.deferred.len() < MAX_DEFERRED {
+            self.deferred.push(msg.0);
+        }
+    }
+}
+
+impl Handler<ClosePoll> for Room {
+    type Result = ();
+    fn handle(&mut self, _: ClosePoll, ctx: &mut Self::Context) -> Self::Result {
+        self.lobby.close_poll(ctx);
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum KickError {
+    NotInRoom,
+    NotLeader,
+    /// The target is not a member of the room
+    NoSuchPlayer,
+    /// Leaders cannot kick themselves
+    CannotKickSelf,
+    InternalServerError,
+}
+
+/// Leader request to remove another player from the room, banning them from joining the room
+/// again for as long as it exists or until the leader unbans them
+#[derive(Message)]
+#[rtype(result = "Result<(), KickError>")]
+pub struct KickPlayer {
+    pub transient_id: TransientId,
+    pub target: TransientId,
+}
+
+impl Handler<KickPlayer> for Room {
+    type Result = Result<(), KickError>;
+    fn handle(&mut self, msg: KickPlayer, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(KickError::NotLeader);
+        }
+        if msg.target == msg.transient_id {
+            return Err(KickError::CannotKickSelf);
+        }
+        let player = self
+            .id_map
+            .get(&msg.target)
+            .and_then(|idx| self.players[*idx].as_ref())
+            .ok_or(KickError::NoSuchPlayer)?;
+        let entry = BannedPlayer {
+            id: player.transient_id,
+            name: player.profile.name.clone(),
+        };
+        self.banned.insert(player.user.clone(), entry);
+        self.remove_member(
+            msg.target,
+            RemoveReason::Banned,
+            Some(msg.transient_id),
+            ctx,
+        );
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum BanError {
+    NotInRoom,
+    NotLeader,
+    /// Nobody was kicked with the given transient id
+    NotBanned,
+    InternalServerError,
+}
+
+/// Leader request to let a kicked player join the room again
+#[derive(Message)]
+#[rtype(result = "Result<(), BanError>")]
+pub struct Unban {
+    pub transient_id: TransientId,
+    pub target: TransientId,
+}
+
+impl Handler<Unban> for Room {
+    type Result = Result<(), BanError>;
+    fn handle(&mut self, msg: Unban, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(BanError::NotLeader);
+        }
+        let count = self.banned.len();
+        self.banned.retain(|_, entry| entry.id != msg.target);
+        if self.banned.len() == count {
+            Err(BanError::NotBanned)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Leader request for everyone currently banned from the room
+#[derive(Message)]
+#[rtype(result = "Result<Vec<BannedPlayer>, BanError>")]
+pub struct ListBans(pub TransientId);
+
+impl Handler<ListBans> for Room {
+    type Result = Result<Vec<BannedPlayer>, BanError>;
+    fn handle(&mut self, msg: ListBans, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.0 {
+            return Err(BanError::NotLeader);
+        }
+        Ok(self.banned.values().cloned().collect())
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum InviteError {
+    NotInRoom,
+    NotLeader,
+    /// Practice rooms are played alone
+    NotAllowed,
+    InternalServerError,
+}
+
+/// An invite handed out by the leader, see [CreateInvite]
+struct Invitation {
+    /// Seconds since the unix epoch
+    expires_at: u64,
+    single_use: bool,
+}
+
+/// Leader request for an invite token. Invited players can join even if the room is full or
+/// private, without the room code ever being shared.
+#[derive(Message)]
+#[rtype(result = "Result<String, InviteError>")]
+pub struct CreateInvite {
+    pub transient_id: TransientId,
+    /// Single use invites stop working once someone joined with them, others can be used until
+    /// they expire
+    pub single_use: bool,
+}
+
+impl Handler<CreateInvite> for Room {
+    type Result = Result<String, InviteError>;
+    fn handle(&mut self, msg: CreateInvite, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(InviteError::NotLeader);
+        }
+        if self.room_config.kind == RoomKind::Practice {
+            return Err(InviteError::NotAllowed);
+        }
+        let now = unix_time();
+        self.invites
+            .retain(|_, invitation| invitation.expires_at > now);
+        let invite = Invite {
+            code: self.code,
+            nonce: fastrand::u64(..),
+            expires_at: now + self.services.invites.ttl().as_secs(),
+        };
+        self.invites.insert(
+            invite.nonce,
+            Invitation {
+                expires_at: invite.expires_at,
+                single_use: msg.single_use,
+            },
+        );
+        Ok(self.services.invites.sign(&invite))
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum PromoteError {
+    NotInRoom,
+    NotLeader,
+    /// The target is not a member of the room
+    NoSuchPlayer,
+    InternalServerError,
+}
+
+/// Leader request to hand leadership of the room over to another member
+#[derive(Message)]
+#[rtype(result = "Result<(), PromoteError>")]
+pub struct PromoteLeader {
+    pub transient_id: TransientId,
+    pub target: TransientId,
+}
+
+impl Handler<PromoteLeader> for Room {
+    type Result = Result<(), PromoteError>;
+    fn handle(&mut self, msg: PromoteLeader, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            Err(PromoteError::NotLeader)
+        } else if !self.id_map.contains_key(&msg.target) {
+            Err(PromoteError::NoSuchPlayer)
+        } else {
+            if msg.target != self.leader {
+                self.set_leader(msg.target);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum LockError {
+    NotInRoom,
+    NotLeader,
+    InternalServerError,
+}
+
+/// Leader request to lock or unlock the room
+#[derive(Message)]
+#[rtype(result = "Result<(), LockError>")]
+pub struct SetLocked {
+    pub transient_id: TransientId,
+    pub locked: bool,
+}
+
+impl Handler<SetLocked> for Room {
+    type Result = Result<(), LockError>;
+    fn handle(&mut self, msg: SetLocked, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(LockError::NotLeader);
+        }
+        if self.locked == msg.locked {
+            return Ok(());
+        }
+        self.locked = msg.locked;
+        let availability = if self.locked {
+            Some(Availability::Unavailable(RoomUnavailablityReason::Locked))
+        } else if self.state.in_lobby() {
+            Some(Availability::Available)
+        } else if self.wants_backfill() {
+            Some(Availability::Backfill)
+        } else {
+            // The room is put back into matchmaking once the running game and the rematch vote
+            // are over
+            None
+        };
+        if let Some(availability) = availability {
+            self.room_manager.do_send(UpdateRoomMatchAvailability {
+                code: self.code,
+                availability,
+            });
+        }
+        self.notify_clients(OutgoingMessage::RoomLocked(self.locked), None);
+        self.admit_queued(ctx);
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum PauseError {
+    NotInRoom,
+    NotLeader,
+    NoGameRunning,
+    InternalServerError,
+}
+
+/// Leader request to pause or resume the running game. Nobody gets to play and every timer of
+/// the game is held while it is paused.
+#[derive(Message)]
+#[rtype(result = "Result<(), PauseError>")]
+pub struct SetPaused {
+    pub transient_id: TransientId,
+    pub paused: bool,
+}
+
+impl Handler<SetPaused> for Room {
+    type Result = Result<(), PauseError>;
+    fn handle(&mut self, msg: SetPaused, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(PauseError::NotLeader);
+        }
+        let Some(game) = self.game.as_mut() else {
+            return Err(PauseError::NoGameRunning);
+        };
+        if game.paused() == msg.paused {
+            return Ok(());
+        }
+        if msg.paused {
+            game.on_pause(ctx);
+        } else {
+            game.on_resume(ctx);
+        }
+        self.notify_clients(OutgoingMessage::GamePaused(msg.paused), None);
+        Ok(())
+    }
+}
+
+/// Leader request to set an entry of the room's metadata, see [super::metadata]
+#[derive(Message)]
+#[rtype(result = "Result<(), MetadataError>")]
+pub struct SetMetadata {
+    pub transient_id: TransientId,
+    pub key: String,
+    /// Empty to remove the entry
+    pub value: String,
+}
+
+impl Handler<SetMetadata> for Room {
+    type Result = Result<(), MetadataError>;
+    fn handle(&mut self, msg: SetMetadata, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(MetadataError::NotLeader);
+        }
+        let value = match self.services.profanity.check(&msg.value) {
+            Verdict::Clean => msg.value,
+            Verdict::Masked(masked) => masked,
+            Verdict::Escalated(masked) => {
+                log::warn!(
+                    target: "moderation",
+                    "profanity in metadata of room {}: {:?}",
+                    String::from_utf8_lossy(&self.code),
+                    msg.value
+                );
+                masked
             }
+            Verdict::Rejected => return Err(MetadataError::Inappropriate),
+        };
+        self.room_config.metadata.set(&msg.key, &value)?;
+        let metadata = self.room_config.metadata.clone();
+        self.room_manager.do_send(RoomMetadataChanged {
+            code: self.code,
+            metadata: metadata.clone(),
+        });
+        self.notify_clients(OutgoingMessage::RoomMetadata(metadata), None);
+        Ok(())
+    }
+}
+
+/// Leader request to change the room's settings in between games
+#[derive(Message)]
+#[rtype(result = "Result<(), SettingsError>")]
+pub struct UpdateRoomSettings {
+    pub transient_id: TransientId,
+    pub update: SettingsUpdate,
+}
+
+impl Handler<UpdateRoomSettings> for Room {
+    type Result = Result<(), SettingsError>;
+    fn handle(&mut self, msg: UpdateRoomSettings, ctx: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Err(SettingsError::NotLeader);
+        }
+        if !self.state.in_lobby() {
+            return Err(SettingsError::GameInProgress);
+        }
+        msg.update.apply(
+            &mut self.room_config,
+            &mut self.game_config,
+            self.player_count,
+            self.spectators.len(),
+            &self.services.profanity,
+        )?;
+        self.room_manager.do_send(RoomSettingsChanged {
+            code: self.code,
+            full: self.room_config.is_full(self.player_count),
+            spectators_full: self.room_config.spectators_full(self.spectators.len()),
+            listing: Listing::new(&self.room_config, &self.game_config),
+        });
+        let settings = RoomSettings::new(&self.room_config, &self.game_config);
+        self.notify_clients(OutgoingMessage::RoomSettings(settings), None);
+        self.admit_queued(ctx);
+        if !self.room_config.waiting_queue {
+            self.clear_queue(JoinRoomError::RoomFull);
         }
+        self.update_countdown(ctx);
+        Ok(())
+    }
+}
+
+/// Leader request to give the room a vanity alias that can be joined in place of its code
+#[derive(Message)]
+#[rtype(result = "Result<Box<str>, AliasError>")]
+pub struct RequestAlias {
+    pub transient_id: TransientId,
+    pub alias: Box<str>,
+}
+
+impl Handler<RequestAlias> for Room {
+    type Result = ResponseFuture<Result<Box<str>, AliasError>>;
+    fn handle(&mut self, msg: RequestAlias, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        if self.leader != msg.transient_id {
+            return Box::pin(async { Err(AliasError::NotLeader) });
+        }
+        let request = self.room_manager.send(RegisterAlias {
+            code: self.code,
+            alias: msg.alias,
+        });
+        Box::pin(async move {
+            request
+                .await
+                .unwrap_or(Err(AliasError::InternalServerError))
+        })
+    }
+}
+
+/// Chat message from one of the room's players, relayed to everyone in the room
+#[derive(Message)]
+#[rtype(result = "Result<(), ChatError>")]
+pub struct Chat {
+    pub transient_id: TransientId,
+    pub text: String,
+}
+
+impl Handler<Chat> for Room {
+    type Result = Result<(), ChatError>;
+    fn handle(&mut self, msg: Chat, _: &mut Self::Context) -> Self::Result {
+        self.touch();
+        let Chat { transient_id, text } = msg;
+        if !self.id_map.contains_key(&transient_id) {
+            return Err(ChatError::NotInRoom);
+        }
+        let text = text.trim();
+        if self.room_config.kind == RoomKind::Announcement && transient_id != self.leader {
+            return Err(ChatError::ListenOnly);
+        }
+        if text.is_empty() {
+            return Err(ChatError::Empty);
+        }
+        if text.len() > MAX_CHAT_LENGTH {
+            return Err(ChatError::TooLong);
+        }
+        let bot = self.id_map.get(&transient_id).is_some_and(|idx| {
+            self.players[*idx]
+                .as_ref()
+                .is_some_and(|player| is_bot(&player.user))
+        });
+        if !self.chat_limiter.allow(transient_id, bot) {
+            return Err(ChatError::RateLimited);
+        }
+        if self.game.as_ref().is_some_and(|game| game.is_secret(text)) {
+            return Err(ChatError::RevealsWord);
+        }
+        let text = match self.services.profanity.check(text) {
+            Verdict::Clean => text.to_string(),
+            Verdict::Masked(masked) => masked,
+            Verdict::Escalated(masked) => {
+                log::warn!(
+                    target: "moderation",
+                    "profanity in chat from {transient_id} in room {}: {text:?}",
+                    String::from_utf8_lossy(&self.code)
+                );
+                masked
+            }
+            Verdict::Rejected => return Err(ChatError::Profanity),
+        };
+        let msg = OutgoingMessage::ChatMessage {
+            from: transient_id,
+            text,
+        };
+        if self.services.load.degraded() {
+            if self.deferred.len() >= MAX_DEFERRED {
+                return Err(ChatError::ServerBusy);
+            }
+            self.deferred.push(msg);
+        } else {
+            // Anything held back goes out first so that the conversation stays in order
+            self.flush_deferred();
+            self.notify_clients(msg, None);
+        }
+        Ok(())
+    }
+}
+
+/// Masks or refuses a display name according to the profanity filter
+fn filter_name(profanity: &ProfanityFilter, mut profile: Profile) -> Option<Profile> {
+    match profanity.check(&profile.name) {
+        Verdict::Clean => {}
+        Verdict::Masked(masked) => profile.name = masked,
+        Verdict::Escalated(masked) => {
+            log::warn!(target: "moderation", "profanity in display name: {:?}", profile.name);
+            profile.name = masked;
+        }
+        Verdict::Rejected => return None,
+    }
+    Some(profile)
+}
+
+/// A queued player gave up on the room, see [Room::enqueue]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaveQueue(pub TransientId);
+
+impl Handler<LeaveQueue> for Room {
+    type Result = ();
+    fn handle(&mut self, msg: LeaveQueue, _: &mut Self::Context) -> Self::Result {
+        let len = self.queue.len();
+        self.queue.retain(|joiner| joiner.session.0 != msg.0);
+        if self.queue.len() != len {
+            self.announce_queue_positions();
+        }
+    }
+}
+
+/// Summary of the room handed over to the next process during deploys
+#[derive(Message, Clone)]
+#[rtype(result = "RoomSummary")]
+pub struct SummarizeRoom;
+
+impl Handler<SummarizeRoom> for Room {
+    type Result = MessageResult<SummarizeRoom>;
+    fn handle(&mut self, _: SummarizeRoom, _: &mut Self::Context) -> Self::Result {
+        let members = self
+            .players
+            .iter()
+            .flatten()
+            .map(|player| MemberSummary {
+                user: player.user.clone(),
+                profile: player.profile.clone(),
+            })
+            .collect();
+        let lifetime = self.room_config.lifetime.as_ref();
+        MessageResult(RoomSummary {
+            code: String::from_utf8_lossy(&self.code).into_owned(),
+            kind: self.room_config.kind,
+            settings: RoomSettings::new(&self.room_config, &self.game_config),
+            metadata: self.room_config.metadata.clone(),
+            password: self.room_config.password.clone(),
+            lifetime: lifetime.map(|x| x.left(self.opened, Instant::now())),
+            words: self.game_config.words.clone(),
+            members,
+        })
     }
 }
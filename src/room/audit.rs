@@ -0,0 +1,173 @@
+//! Every room keeps a record of who came and went, who led it and when its games started, so
+//! that moderators looking into a player report can tell what happened in the room even when
+//! nobody was watching it at the time. The record only holds the latest events, and is kept
+//! around for a while after the room closes since reports tend to come in once a game is over.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use super::invite::unix_time;
+use super::RoomCode;
+use crate::game::GameMode;
+use crate::session::message::RemoveReason;
+use crate::session::{TransientId, UserId};
+
+/// Most events a room keeps, older ones are dropped first
+const MAX_AUDIT_ENTRIES: usize = 200;
+/// Most trails of closed rooms kept, those of the rooms that closed first are dropped first
+const MAX_CLOSED_TRAILS: usize = 256;
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "kind", content = "data")]
+pub enum AuditEvent {
+    Joined {
+        player: TransientId,
+        user: UserId,
+        name: Box<str>,
+    },
+    Left {
+        player: TransientId,
+        reason: RemoveReason,
+    },
+    /// The player was removed and barred from rejoining, by the leader or by the server if
+    /// [None], for instance on behalf of a moderator
+    Kicked {
+        player: TransientId,
+        by: Option<TransientId>,
+    },
+    LeaderChanged {
+        from: TransientId,
+        to: TransientId,
+    },
+    GameStarted {
+        mode: GameMode,
+        players: Vec<TransientId>,
+    },
+    Closed {
+        reason: RemoveReason,
+    },
+}
+
+#[derive(Serialize, Clone)]
+pub struct AuditRecord {
+    /// Seconds since the unix epoch
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// The latest events of a room, oldest first
+#[derive(Default)]
+pub struct AuditTrail(VecDeque<AuditRecord>);
+
+impl AuditTrail {
+    pub fn record(&mut self, event: AuditEvent) {
+        if self.0.len() >= MAX_AUDIT_ENTRIES {
+            self.0.pop_front();
+        }
+        self.0.push_back(AuditRecord {
+            at: unix_time(),
+            event,
+        });
+    }
+    pub fn entries(&self) -> Vec<AuditRecord> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+/// The events of a room, as kept for moderators
+#[derive(Clone)]
+pub enum Trail {
+    Entries(Vec<AuditRecord>),
+    /// The entries of a room that closed in privacy mode, sealed as JSON with the key of the
+    /// room, see [super::vault]
+    Sealed(Box<str>),
+}
+
+/// Trails of the rooms that closed most recently, oldest first. A code only has the trail of the
+/// last room that went by it.
+#[derive(Default)]
+pub struct ClosedTrails(VecDeque<(RoomCode, Trail)>);
+
+impl ClosedTrails {
+    pub fn keep(&mut self, code: RoomCode, trail: Trail) {
+        self.0.retain(|(x, _)| *x != code);
+        if self.0.len() >= MAX_CLOSED_TRAILS {
+            self.0.pop_front();
+        }
+        self.0.push_back((code, trail));
+    }
+    pub fn get(&self, code: &RoomCode) -> Option<Trail> {
+        self.0
+            .iter()
+            .find(|(x, _)| x == code)
+            .map(|(_, trail)| trail.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::GetRoomAudit;
+    use crate::testing::Server;
+    use serde_json::json;
+
+    fn left(player: TransientId) -> AuditEvent {
+        AuditEvent::Left {
+            player,
+            reason: RemoveReason::LeaveRequested,
+        }
+    }
+
+    #[test]
+    fn keeps_the_latest_events() {
+        let mut trail = AuditTrail::default();
+        for player in 0..MAX_AUDIT_ENTRIES as u64 + 5 {
+            trail.record(left(player.into()));
+        }
+        let entries = trail.entries();
+        assert_eq!(entries.len(), MAX_AUDIT_ENTRIES);
+        assert!(matches!(
+            entries[0].event,
+            AuditEvent::Left { player, .. } if player == 5.into()
+        ));
+        let json = serde_json::to_string(&entries[0]).unwrap();
+        assert!(json.contains(r#""kind":"Left""#), "{json}");
+        assert!(json.contains(r#""at":"#), "{json}");
+    }
+
+    #[actix::test]
+    async fn trails_outlive_their_room() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        let target = ann.expect("PlayerJoined").await["id"].clone();
+        let kick = json!({ "target": target });
+        ann.send(json!({ "kind": "KickPlayer", "data": kick }))
+            .await;
+        ben.expect("RemoveFromRoom").await;
+        ann.send(json!({ "kind": "LeaveRoom" })).await;
+        let code = RoomCode::try_from(code.as_bytes()).unwrap();
+        // The room closes along with its last player leaving
+        let trail = server.room_manager.send(GetRoomAudit(code)).await.unwrap();
+        let Some(Trail::Entries(entries)) = trail else {
+            panic!("the trail is kept once the room closes");
+        };
+        let entries = json!(entries);
+        let events: Vec<_> = entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| &x["kind"])
+            .collect();
+        assert_eq!(events, ["Joined", "Joined", "Kicked", "Left", "Closed"]);
+        assert_eq!(entries[2]["data"]["player"], target);
+        assert!(entries[2]["data"]["by"].is_u64());
+    }
+}
@@ -0,0 +1,139 @@
+use actix::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::matching::speaks;
+use super::metadata::RoomMetadata;
+use super::{RoomInfo, RoomManager};
+use crate::game::GameMode;
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RoomStatus {
+    /// Waiting in the lobby for the next game
+    Waiting,
+    Playing,
+    Full,
+    /// The leader locked the room against new players
+    Locked,
+}
+
+/// Which page of the room browser to show and which rooms to leave out of it. Unset filters
+/// match any room.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RoomQuery {
+    /// Starts from zero
+    pub page: usize,
+    pub page_size: Option<usize>,
+    pub mode: Option<GameMode>,
+    pub language: Option<Box<str>>,
+    pub status: Option<RoomStatus>,
+    /// Leaves out rooms that cannot be joined right now
+    pub joinable: bool,
+}
+
+/// A public room as shown in the room browser
+#[derive(Serialize, Clone)]
+pub struct RoomListing {
+    pub code: String,
+    pub players: usize,
+    pub max_players: u8,
+    pub mode: GameMode,
+    pub language: Option<Box<str>>,
+    pub status: RoomStatus,
+    #[serde(skip_serializing_if = "RoomMetadata::is_empty")]
+    pub metadata: RoomMetadata,
+}
+
+#[derive(Serialize, Clone)]
+pub enum ListRoomsError {
+    InternalServerError,
+}
+
+#[derive(Serialize, Clone)]
+pub struct RoomPage {
+    pub rooms: Vec<RoomListing>,
+    pub page: usize,
+    /// Rooms matching the filters across all pages
+    pub total: usize,
+}
+
+impl RoomInfo {
+    fn status(&self) -> RoomStatus {
+        if self.locked {
+            RoomStatus::Locked
+        } else if self.full {
+            RoomStatus::Full
+        } else if self.playing {
+            RoomStatus::Playing
+        } else {
+            RoomStatus::Waiting
+        }
+    }
+}
+
+impl RoomQuery {
+    /// Rooms in the backfill pool can be joined despite the game running in them
+    fn matches(&self, room: &RoomInfo, backfill: bool) -> bool {
+        let status = room.status();
+        self.mode.is_none_or(|mode| mode == room.listing.mode)
+            && self
+                .language
+                .as_ref()
+                .is_none_or(|language| speaks(&room.listing, language))
+            && self.status.is_none_or(|x| x == status)
+            && (!self.joinable || status == RoomStatus::Waiting || backfill)
+    }
+}
+
+/// Public rooms for the room browser, fullest first
+#[derive(Message)]
+#[rtype(result = "RoomPage")]
+pub struct ListRooms(pub RoomQuery);
+
+impl Handler<ListRooms> for RoomManager {
+    type Result = MessageResult<ListRooms>;
+    fn handle(&mut self, msg: ListRooms, _: &mut Self::Context) -> Self::Result {
+        let query = msg.0;
+        let page_size = query
+            .page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let in_progress = self.backfill.iter().map(|room| (room, true));
+        let mut rooms = self
+            .reserved
+            .iter()
+            .chain(self.open.iter())
+            .map(|room| (room, false))
+            .chain(in_progress)
+            .filter(|((_, room), backfill)| room.listing.public && query.matches(room, *backfill))
+            .map(|(room, _)| room)
+            .collect::<Vec<_>>();
+        // Ties are broken by code so that pages stay put while nothing changes
+        rooms.sort_unstable_by(|(a_code, a), (b_code, b)| {
+            b.players.cmp(&a.players).then(a_code.cmp(b_code))
+        });
+        let total = rooms.len();
+        let rooms = rooms
+            .into_iter()
+            .skip(query.page.saturating_mul(page_size))
+            .take(page_size)
+            .map(|(code, room)| RoomListing {
+                code: String::from_utf8_lossy(code).into_owned(),
+                players: room.players,
+                max_players: room.listing.max_players,
+                mode: room.listing.mode,
+                language: room.listing.language.clone(),
+                status: room.status(),
+                metadata: room.listing.metadata.clone(),
+            })
+            .collect();
+        MessageResult(RoomPage {
+            rooms,
+            page: query.page,
+            total,
+        })
+    }
+}
@@ -0,0 +1,66 @@
+use crate::session::TransientId;
+use ahash::HashMap;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Longest chat message (in bytes) that gets relayed
+pub const MAX_CHAT_LENGTH: usize = 256;
+/// Number of messages a player can send within a single [CHAT_WINDOW]
+const CHAT_BURST: u8 = 5;
+/// Number of messages a bot can send within a single [CHAT_WINDOW], quiz masters and the like
+/// talk a lot more than players
+const BOT_CHAT_BURST: u8 = 30;
+/// Length (in seconds) of the window chat messages are counted over
+const CHAT_WINDOW: u64 = 5;
+/// How often chat and reactions held back while the server sheds load are relayed, stretched by
+/// [crate::load::Load::skips] for as long as the load lasts
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Most chat messages and reactions a room holds back while the server sheds load, reactions
+/// past that are dropped
+pub const MAX_DEFERRED: usize = 32;
+
+#[derive(Serialize, Clone)]
+pub enum ChatError {
+    NotInRoom,
+    Empty,
+    TooLong,
+    RateLimited,
+    /// Only the broadcaster can talk in announcement rooms
+    ListenOnly,
+    /// The message gives away the secret word of the running game and was not relayed
+    RevealsWord,
+    /// The message contains profanity and the server is configured to refuse such messages
+    Profanity,
+    /// The server is overloaded and already holds back as much chat as it can
+    ServerBusy,
+    InternalServerError,
+}
+
+/// Per-sender chat rate limiter allowing bursts of [CHAT_BURST] messages every [CHAT_WINDOW], or
+/// [BOT_CHAT_BURST] for bots
+#[derive(Default)]
+pub struct ChatLimiter {
+    windows: HashMap<TransientId, (Instant, u8)>,
+}
+
+impl ChatLimiter {
+    /// Records a message from the sender, returning false if they are over their budget.
+    pub fn allow(&mut self, sender: TransientId, bot: bool) -> bool {
+        let burst = if bot { BOT_CHAT_BURST } else { CHAT_BURST };
+        let now = Instant::now();
+        let (start, count) = self.windows.entry(sender).or_insert((now, 0));
+        if now.duration_since(*start) >= Duration::from_secs(CHAT_WINDOW) {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= burst {
+            false
+        } else {
+            *count += 1;
+            true
+        }
+    }
+    pub fn forget(&mut self, sender: TransientId) {
+        self.windows.remove(&sender);
+    }
+}
@@ -0,0 +1,69 @@
+/// Environment variable holding a comma separated list of extra strings to block in room codes
+const DENYLIST_VAR: &str = "ROOM_CODE_DENYLIST";
+
+/// Strings that must never show up in a room code, whether generated or picked by a player
+const DEFAULT_DENYLIST: &[&str] = &[
+    "ANAL", "ANUS", "ARSE", "CLIT", "COCK", "COON", "CUNT", "DICK", "DYKE", "FAG", "FUCK", "GOOK",
+    "HOMO", "JIZZ", "KIKE", "KKK", "NAZI", "NIGG", "PAKI", "PISS", "PORN", "PUSS", "RAPE", "SEX",
+    "SHIT", "SLUT", "SPIC", "TITS", "TWAT", "WANK", "WHORE",
+];
+
+/// Denylist of strings that room codes may not contain. Entries are matched case-insensitively
+/// anywhere within the code so that longer custom codes can't smuggle them in either.
+pub struct Denylist {
+    entries: Vec<Box<[u8]>>,
+}
+
+impl Denylist {
+    pub fn new<'a>(entries: impl IntoIterator<Item = &'a str>) -> Self {
+        let entries = entries
+            .into_iter()
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| entry.to_ascii_uppercase().into_bytes().into_boxed_slice())
+            .collect();
+        Self { entries }
+    }
+    /// The default denylist extended with any entries from the environment
+    pub fn from_env() -> Self {
+        let extra = std::env::var(DENYLIST_VAR).unwrap_or_default();
+        Self::new(DEFAULT_DENYLIST.iter().copied().chain(extra.split(',')))
+    }
+    pub fn is_blocked(&self, code: &[u8]) -> bool {
+        let code = code.to_ascii_uppercase();
+        self.entries.iter().any(|entry| {
+            entry.len() <= code.len() && code.windows(entry.len()).any(|x| x == &entry[..])
+        })
+    }
+}
+
+impl Default for Denylist {
+    fn default() -> Self {
+        Self::new(DEFAULT_DENYLIST.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Denylist;
+    use crate::room::generate_room_id;
+
+    #[test]
+    fn matches_substrings_case_insensitively() {
+        let denylist = Denylist::new(["abc"]);
+        assert!(denylist.is_blocked(b"ABCD"));
+        assert!(denylist.is_blocked(b"xabc"));
+        assert!(!denylist.is_blocked(b"ABDC"));
+        assert!(!denylist.is_blocked(b"AB"));
+    }
+
+    #[test]
+    fn generator_never_yields_blocked_codes() {
+        // Single letters block a large share of the code space, so a generator that ignored the
+        // denylist would trip this almost immediately
+        let denylist = Denylist::new(["A", "E", "I", "O", "U", "7"]);
+        for _ in 0..10_000 {
+            assert!(!denylist.is_blocked(&generate_room_id(&denylist).0));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use super::RoomCode;
+use crate::deadletter::{DeadLetter, DeadLetterReason, DeadLetters};
+use crate::session::actor::{Frame, Session};
+use crate::session::backpressure::Priority;
+use crate::session::sink::SharedFrame;
+use crate::session::TransientId;
+use actix::dev::SendError;
+use actix::{Actor, Addr, Arbiter, Context, Handler, Message};
+use std::sync::Arc;
+
+/// Rooms with at least this many members hand their broadcasts off to the [FanoutPool]
+pub const FANOUT_THRESHOLD: usize = 256;
+
+/// Recipients handled by a single [Broadcaster] for one room
+pub type Partition = Arc<[(TransientId, Addr<Session>)]>;
+
+/// Helper actor that writes an already serialized frame to a subset of a room's members
+pub struct Broadcaster {
+    dead_letters: Addr<DeadLetters>,
+}
+
+impl Actor for Broadcaster {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Deliver {
+    pub room: RoomCode,
+    pub frame: SharedFrame,
+    pub priority: Priority,
+    pub recipients: Partition,
+}
+
+impl Handler<Deliver> for Broadcaster {
+    type Result = ();
+    fn handle(&mut self, msg: Deliver, _: &mut Self::Context) -> Self::Result {
+        for (id, recipient) in msg.recipients.iter() {
+            match recipient.try_send(Frame(msg.frame.clone(), msg.priority)) {
+                Ok(()) => {}
+                Err(SendError::Full(frame)) => recipient.do_send(frame),
+                Err(SendError::Closed(_)) => self.dead_letters.do_send(
+                    DeadLetter::new(DeadLetterReason::SessionGone, Some(*id))
+                        .room(&msg.room)
+                        .frame(msg.frame.json()),
+                ),
+            }
+        }
+    }
+}
+
+/// Pool of [Broadcaster]s, each running on its own arbiter, shared by every room. Large rooms
+/// split their members into one partition per broadcaster so that a single room actor doesn't
+/// have to write every frame to thousands of sessions by itself.
+#[derive(Clone)]
+pub struct FanoutPool {
+    helpers: Arc<[Addr<Broadcaster>]>,
+}
+
+impl FanoutPool {
+    pub fn new(workers: usize, dead_letters: Addr<DeadLetters>) -> Self {
+        let helpers = (0..workers.max(1))
+            .map(|_| {
+                let dead_letters = dead_letters.clone();
+                Broadcaster::start_in_arbiter(&Arbiter::new().handle(), |_| Broadcaster {
+                    dead_letters,
+                })
+            })
+            .collect();
+        Self { helpers }
+    }
+    /// Splits the recipients into one partition per broadcaster
+    pub fn partition(
+        &self,
+        recipients: impl Iterator<Item = (TransientId, Addr<Session>)>,
+    ) -> Vec<Partition> {
+        let mut partitions = vec![Vec::new(); self.helpers.len()];
+        for (i, recipient) in recipients.enumerate() {
+            partitions[i % self.helpers.len()].push(recipient);
+        }
+        partitions.into_iter().map(Partition::from).collect()
+    }
+    pub fn broadcast(&self, room: RoomCode, frame: Frame, partitions: &[Partition]) {
+        let Frame(frame, priority) = frame;
+        for (helper, recipients) in self.helpers.iter().zip(partitions) {
+            helper.do_send(Deliver {
+                room,
+                frame: frame.clone(),
+                priority,
+                recipients: recipients.clone(),
+            });
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use ahash::HashMap;
+use bytestring::ByteString;
+use std::collections::VecDeque;
+
+use crate::session::message::{OutgoingMessage, Sequenced};
+use crate::session::TransientId;
+
+/// Most room wide events a room keeps around for members catching up after a reconnect
+const HISTORY_LENGTH: usize = 256;
+/// Most messages meant for a single member a room keeps around while the member is away
+const MISSED_LENGTH: usize = 32;
+/// Members acknowledging events further behind the latest one than this have fallen behind
+pub const MAX_ACK_LAG: u64 = 64;
+
+/// The latest events broadcast to a whole room, numbered in the order they went out. Members
+/// reconnecting with the number of the last event they got are only sent the ones they missed,
+/// as long as the room still has all of them.
+#[derive(Default)]
+pub struct History {
+    /// Number of the latest event, zero before the first one
+    seq: u64,
+    /// Serialized events, oldest first
+    frames: VecDeque<ByteString>,
+    /// Messages meant for members whose connection was gone when they were sent, see
+    /// [History::keep_missed]
+    missed: HashMap<TransientId, Missed>,
+    /// Number of the latest event when a message meant for the member last went to their
+    /// session, see [History::sent_direct]
+    direct: HashMap<TransientId, u64>,
+    /// Latest event every member acknowledged, for members whose client does
+    acked: HashMap<TransientId, u64>,
+}
+
+/// Messages a member missed while away, each with the number of the latest event when it was
+/// sent so that it is replayed in the same order
+#[derive(Default)]
+struct Missed {
+    frames: VecDeque<(u64, ByteString)>,
+    /// Some were dropped to stay within [MISSED_LENGTH], the member needs the full state
+    overflowed: bool,
+}
+
+impl History {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+    /// Numbers the event and keeps it, returning the frame to broadcast
+    pub fn record(&mut self, msg: OutgoingMessage) -> ByteString {
+        self.seq += 1;
+        let frame: ByteString = Sequenced { msg, seq: self.seq }.into();
+        if self.frames.len() >= HISTORY_LENGTH {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame.clone());
+        frame
+    }
+    /// Keeps a message meant for a member that could not be delivered, to replay once they are
+    /// back
+    pub fn keep_missed(&mut self, member: TransientId, frame: ByteString) {
+        let missed = self.missed.entry(member).or_default();
+        if missed.frames.len() >= MISSED_LENGTH {
+            missed.frames.pop_front();
+            missed.overflowed = true;
+        }
+        missed.frames.push_back((self.seq, frame));
+    }
+    /// Notes that a message meant for the member went to their session. The session may have
+    /// lost it to a connection that was going away, so members reconnecting without the event
+    /// that came after it get the full state rather than a replay.
+    pub fn sent_direct(&mut self, member: TransientId) {
+        self.direct.insert(member, self.seq);
+    }
+    /// Drops what was kept for a member who left for good
+    pub fn forget(&mut self, member: TransientId) {
+        self.missed.remove(&member);
+        self.direct.remove(&member);
+        self.acked.remove(&member);
+    }
+    /// Records that the member got every event up to the one numbered `seq`. Numbers the room
+    /// has not reached yet are ignored, as are ones older than the member acknowledged before.
+    pub fn ack(&mut self, member: TransientId, seq: u64) {
+        if seq <= self.seq {
+            let acked = self.acked.entry(member).or_default();
+            *acked = seq.max(*acked);
+        }
+    }
+    pub fn acked(&self, member: TransientId) -> Option<u64> {
+        self.acked.get(&member).copied()
+    }
+    /// Whether the member acknowledges events more than [MAX_ACK_LAG] behind the latest one
+    pub fn is_behind(&self, member: TransientId) -> bool {
+        self.acked(member)
+            .is_some_and(|acked| self.seq - acked > MAX_ACK_LAG)
+    }
+    /// Number of members that have fallen behind, see [History::is_behind]
+    pub fn behind(&self) -> usize {
+        self.acked
+            .values()
+            .filter(|acked| self.seq - **acked > MAX_ACK_LAG)
+            .count()
+    }
+    /// Everything a member reconnecting after the event numbered `seq` missed, room wide events
+    /// and the messages meant for them alike, in the order they were sent. [None] if some of it
+    /// was dropped already, a message meant for them may have been lost since that event, see
+    /// [History::sent_direct], or the member kept no state, in which case they need the full
+    /// state instead.
+    pub fn catch_up(&mut self, member: TransientId, seq: Option<u64>) -> Option<Vec<ByteString>> {
+        let missed = self.missed.remove(&member).unwrap_or_default();
+        let direct = self.direct.get(&member).copied();
+        let seq = seq.filter(|seq| !missed.overflowed && direct.is_none_or(|sent| sent < *seq))?;
+        let events = self.since(seq)?;
+        let mut direct = missed.frames.into_iter().peekable();
+        let mut frames = Vec::new();
+        for (event, frame) in (seq + 1..).zip(events) {
+            while let Some((_, missed)) = direct.next_if(|(after, _)| *after < event) {
+                frames.push(missed);
+            }
+            frames.push(frame.clone());
+        }
+        frames.extend(direct.map(|(_, missed)| missed));
+        Some(frames)
+    }
+    /// Events that came after the one numbered `seq`, [None] if some of them were dropped
+    /// already or the number is from some other room
+    pub fn since(&self, seq: u64) -> Option<impl Iterator<Item = &ByteString>> {
+        let missed = usize::try_from(self.seq.checked_sub(seq)?).ok()?;
+        (missed <= self.frames.len()).then(|| self.frames.iter().skip(self.frames.len() - missed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u64) -> OutgoingMessage {
+        OutgoingMessage::PlayerLeft(n.into())
+    }
+
+    #[test]
+    fn only_missed_events_are_replayed() {
+        let mut history = History::default();
+        for n in 1..=3 {
+            history.record(event(n));
+        }
+        let missed = history.since(1).unwrap().collect::<Vec<_>>();
+        assert_eq!(missed.len(), 2);
+        assert!(missed[0].contains(r#""seq":2"#));
+        assert!(missed[1].contains(r#""seq":3"#));
+        assert_eq!(history.since(3).unwrap().count(), 0);
+        assert_eq!(history.since(0).unwrap().count(), 3);
+        // Ahead of the room, the client must have been somewhere else
+        assert!(history.since(4).is_none());
+    }
+
+    #[test]
+    fn missed_messages_are_replayed_in_order() {
+        let mut history = History::default();
+        history.record(event(1));
+        history.keep_missed(7.into(), "first".into());
+        history.record(event(2));
+        history.keep_missed(7.into(), "second".into());
+        history.keep_missed(8.into(), "elsewhere".into());
+        let missed = history.catch_up(7.into(), Some(0)).unwrap();
+        assert_eq!(missed.len(), 4);
+        assert!(missed[0].contains(r#""seq":1"#));
+        assert_eq!(missed[1], "first");
+        assert!(missed[2].contains(r#""seq":2"#));
+        assert_eq!(missed[3], "second");
+        // Replayed once only
+        assert_eq!(history.catch_up(7.into(), Some(2)).unwrap().len(), 0);
+        for _ in 0..=MISSED_LENGTH {
+            history.keep_missed(8.into(), "more".into());
+        }
+        assert!(history.catch_up(8.into(), Some(2)).is_none());
+    }
+
+    #[test]
+    fn messages_that_may_be_lost_call_for_the_full_state() {
+        let mut history = History::default();
+        history.record(event(1));
+        history.sent_direct(7.into());
+        history.record(event(2));
+        // Got the event after it, so the message before it too
+        assert_eq!(history.catch_up(7.into(), Some(2)).unwrap().len(), 0);
+        history.sent_direct(7.into());
+        history.record(event(3));
+        assert!(history.catch_up(7.into(), Some(2)).is_none());
+        history.forget(7.into());
+        assert_eq!(history.catch_up(7.into(), Some(2)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn acks_tell_members_that_fell_behind() {
+        let mut history = History::default();
+        for n in 1..=MAX_ACK_LAG + 2 {
+            history.record(event(n));
+        }
+        history.ack(1.into(), 1);
+        history.ack(2.into(), 2);
+        // Acks are never taken back, and numbers from the future are ignored
+        history.ack(2.into(), 1);
+        history.ack(3.into(), MAX_ACK_LAG + 10);
+        assert!(history.is_behind(1.into()));
+        assert!(!history.is_behind(2.into()));
+        assert_eq!(history.acked(3.into()), None);
+        assert_eq!(history.behind(), 1);
+        history.forget(1.into());
+        assert_eq!(history.behind(), 0);
+    }
+
+    #[test]
+    fn falls_back_once_the_buffer_moved_on() {
+        let mut history = History::default();
+        for n in 0..HISTORY_LENGTH as u64 + 10 {
+            history.record(event(n));
+        }
+        assert!(history.since(5).is_none());
+        assert_eq!(history.since(10).unwrap().count(), HISTORY_LENGTH);
+    }
+}
@@ -0,0 +1,117 @@
+//! Instant replays. Rooms keep the game events of the last half minute around so that players can
+//! watch a moment again right after it happened, long before the game is over. Game modes can
+//! also flag a moment as worth watching again, which plays it back to the whole room, see
+//! [crate::game::Highlight].
+
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::session::message::OutgoingMessage;
+
+/// How far back instant replays go
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(30);
+/// Most events kept for a replay however recent they are, so that a burst of events cannot grow
+/// the buffer without bounds
+const MAX_REPLAY_EVENTS: usize = 256;
+
+/// Game events of the running game that happened within [REPLAY_WINDOW], oldest first
+#[derive(Default)]
+pub struct ReplayBuffer {
+    events: VecDeque<(Instant, Box<RawValue>)>,
+}
+
+/// A game event as played back, along with how long ago it happened
+#[derive(Serialize, Clone)]
+pub struct ReplayedEvent {
+    /// Milliseconds between the event and the replay
+    pub ago: u64,
+    pub event: Box<RawValue>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ReplayClip {
+    /// Whether the game mode flagged the moment, as opposed to a player asking for the replay
+    pub highlight: bool,
+    pub events: Vec<ReplayedEvent>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub enum ReplayError {
+    NotInRoom,
+    /// No game event happened recently enough
+    NothingToReplay,
+    InternalServerError,
+}
+
+impl ReplayBuffer {
+    pub fn record(&mut self, msg: &OutgoingMessage, now: Instant) {
+        let event = match serde_json::value::to_raw_value(msg) {
+            Ok(event) => event,
+            Err(err) => {
+                log::error!("dropping unserializable event from the instant replay: {err}");
+                return;
+            }
+        };
+        self.prune(now);
+        if self.events.len() >= MAX_REPLAY_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back((now, event));
+    }
+    /// Forgets the events of the previous game
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+    /// Every event within the window, [None] if there are none
+    pub fn clip(&mut self, highlight: bool, now: Instant) -> Option<ReplayClip> {
+        self.prune(now);
+        if self.events.is_empty() {
+            return None;
+        }
+        let events = self
+            .events
+            .iter()
+            .map(|(at, event)| ReplayedEvent {
+                ago: now.saturating_duration_since(*at).as_millis() as u64,
+                event: event.clone(),
+            })
+            .collect();
+        Some(ReplayClip { highlight, events })
+    }
+    fn prune(&mut self, now: Instant) {
+        while let Some((at, _)) = self.events.front() {
+            if now.saturating_duration_since(*at) <= REPLAY_WINDOW {
+                break;
+            }
+            self.events.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_recent_events_are_replayed() {
+        let mut buffer = ReplayBuffer::default();
+        let start = Instant::now();
+        assert!(buffer.clip(false, start).is_none());
+        buffer.record(&OutgoingMessage::WordUpdate("____".into()), start);
+        buffer.record(
+            &OutgoingMessage::WordExpired("word".into()),
+            start + Duration::from_secs(20),
+        );
+        let clip = buffer.clip(true, start + Duration::from_secs(40)).unwrap();
+        assert!(clip.highlight);
+        assert_eq!(clip.events.len(), 1);
+        assert_eq!(clip.events[0].ago, 20_000);
+        assert!(clip.events[0].event.get().contains("WordExpired"));
+        buffer.clear();
+        assert!(buffer
+            .clip(false, start + Duration::from_secs(40))
+            .is_none());
+    }
+}
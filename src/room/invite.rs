@@ -0,0 +1,164 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{RoomCode, MAX_ROOM_CODE_LENGTH, MIN_ROOM_CODE_LENGTH};
+
+const DEFAULT_INVITE_TTL: u64 = 3600;
+const MAC_LENGTH: usize = 32;
+/// Length of the nonce and expiry following the room code
+const TRAILER_LENGTH: usize = 16;
+
+type Digest = Hmac<Sha256>;
+
+/// What an invite token vouches for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Invite {
+    pub code: RoomCode,
+    /// Identifies the invite within its room, which keeps track of the ones it handed out
+    pub nonce: u64,
+    /// Seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum TokenError {
+    /// The token is malformed or was not signed by this server
+    Invalid,
+    Expired,
+}
+
+/// Signs and verifies invite tokens with HMAC-SHA256, so players cannot forge invites to rooms
+/// they were never invited to
+pub struct InviteSigner {
+    key: Box<[u8]>,
+    ttl: Duration,
+}
+
+impl InviteSigner {
+    pub fn new(secret: &[u8], ttl: Duration) -> Self {
+        Self {
+            key: secret.into(),
+            ttl,
+        }
+    }
+    /// Reads `INVITE_SECRET` and `INVITE_TTL` (in seconds). Without a secret a random one is
+    /// picked, which is fine as long as invites do not need to outlive the process.
+    pub fn from_env() -> Self {
+        let secret = std::env::var("INVITE_SECRET")
+            .ok()
+            .filter(|x| !x.is_empty())
+            .map_or_else(|| rand::random::<[u8; 32]>().to_vec(), String::into_bytes);
+        let ttl = std::env::var("INVITE_TTL")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_INVITE_TTL);
+        Self::new(&secret, Duration::from_secs(ttl))
+    }
+    /// How long invites stay valid for
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+    pub fn sign(&self, invite: &Invite) -> String {
+        let mut token = Vec::with_capacity(invite.code.len() + TRAILER_LENGTH + MAC_LENGTH);
+        token.extend_from_slice(&invite.code);
+        token.extend_from_slice(&invite.nonce.to_be_bytes());
+        token.extend_from_slice(&invite.expires_at.to_be_bytes());
+        let mac = self.mac(&token).finalize().into_bytes();
+        token.extend_from_slice(&mac);
+        URL_SAFE_NO_PAD.encode(token)
+    }
+    pub fn verify(&self, token: &str) -> Result<Invite, TokenError> {
+        let token = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| TokenError::Invalid)?;
+        // The code is as long as it was when the invite was made, which does not need to be how
+        // long the codes this process generates are
+        let overhead = TRAILER_LENGTH + MAC_LENGTH;
+        let code_lengths = MIN_ROOM_CODE_LENGTH + overhead..=MAX_ROOM_CODE_LENGTH + overhead;
+        if !code_lengths.contains(&token.len()) {
+            return Err(TokenError::Invalid);
+        }
+        let (payload, mac) = token.split_at(token.len() - MAC_LENGTH);
+        // Compared in constant time so that the mac cannot be guessed byte by byte
+        self.mac(payload)
+            .verify_slice(mac)
+            .map_err(|_| TokenError::Invalid)?;
+        let (code, rest) = payload.split_at(payload.len() - TRAILER_LENGTH);
+        let (nonce, expires_at) = rest.split_at(8);
+        let invite = Invite {
+            code: code.try_into().unwrap(),
+            nonce: u64::from_be_bytes(nonce.try_into().unwrap()),
+            expires_at: u64::from_be_bytes(expires_at.try_into().unwrap()),
+        };
+        if invite.expires_at <= unix_time() {
+            return Err(TokenError::Expired);
+        }
+        Ok(invite)
+    }
+    fn mac(&self, data: &[u8]) -> Digest {
+        let mut digest = Digest::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        digest.update(data);
+        digest
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite(expires_at: u64) -> Invite {
+        Invite {
+            code: RoomCode::try_from(&b"AB12"[..]).unwrap(),
+            nonce: 42,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn signed_tokens_verify() {
+        let signer = InviteSigner::new(b"secret", Duration::from_secs(60));
+        let invite = invite(unix_time() + 60);
+        assert_eq!(signer.verify(&signer.sign(&invite)), Ok(invite));
+    }
+
+    #[test]
+    fn tampered_and_foreign_tokens_are_rejected() {
+        let signer = InviteSigner::new(b"secret", Duration::from_secs(60));
+        let token = signer.sign(&invite(unix_time() + 60));
+        let mut tampered = token.clone().into_bytes();
+        tampered[0] = if tampered[0] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert_eq!(signer.verify(&tampered), Err(TokenError::Invalid));
+        let other = InviteSigner::new(b"other secret", Duration::from_secs(60));
+        assert_eq!(other.verify(&token), Err(TokenError::Invalid));
+        assert_eq!(signer.verify("not a token"), Err(TokenError::Invalid));
+    }
+
+    #[test]
+    fn codes_of_any_supported_length_round_trip() {
+        let signer = InviteSigner::new(b"secret", Duration::from_secs(60));
+        for code in [&b"AB1"[..], b"AB12CD34"] {
+            let invite = Invite {
+                code: RoomCode::try_from(code).unwrap(),
+                ..invite(unix_time() + 60)
+            };
+            assert_eq!(signer.verify(&signer.sign(&invite)), Ok(invite));
+        }
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let signer = InviteSigner::new(b"secret", Duration::from_secs(60));
+        let token = signer.sign(&invite(unix_time() - 1));
+        assert_eq!(signer.verify(&token), Err(TokenError::Expired));
+    }
+}
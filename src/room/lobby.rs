@@ -0,0 +1,191 @@
+use super::actor::{Broadcast, Room};
+use crate::session::{
+    message::{Deadline, OutgoingMessage},
+    TransientId,
+};
+use actix::{AsyncContext, Context, Message, SpawnHandle};
+use ahash::{HashMap, HashMapExt};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// How often (in seconds) the room runs a quick poll while players are waiting in the lobby
+pub const POLL_INTERVAL: u64 = 45;
+/// How long (in seconds) a poll stays open for votes
+const POLL_DURATION: u64 = 15;
+/// How long (in seconds) players have to vote for a rematch once a game ends
+pub const REMATCH_VOTE_DURATION: u64 = 20;
+/// Emojis players can ping the lobby with. Anything else is dropped, so that pings can't be used
+/// to send the room text the profanity filter never saw.
+const EMOJIS: &[&str] = &[
+    "\u{1F44B}",        // waving hand
+    "\u{1F44D}",        // thumbs up
+    "\u{1F44E}",        // thumbs down
+    "\u{1F602}",        // tears of joy
+    "\u{1F62E}",        // open mouth
+    "\u{1F622}",        // crying
+    "\u{1F621}",        // pouting
+    "\u{1F389}",        // party popper
+    "\u{1F525}",        // fire
+    "\u{2764}\u{FE0F}", // red heart
+];
+
+/// Questions the room picks its quick polls from
+const POLLS: &[(&str, &[&str])] = &[
+    ("Cats or dogs?", &["Cats", "Dogs"]),
+    ("Best time to play?", &["Morning", "Afternoon", "Night"]),
+    ("Pineapple on pizza?", &["Yes", "No", "Only on Fridays"]),
+    (
+        "Favourite season?",
+        &["Spring", "Summer", "Autumn", "Winter"],
+    ),
+];
+
+/// Interactions only available while the room is waiting for a game to start
+#[derive(Deserialize)]
+#[serde(tag = "kind", content = "data")]
+pub enum LobbyAction {
+    /// One of [EMOJIS]
+    Emoji(String),
+    Vote(usize),
+    /// Whether the player wants a rematch, only counted while a rematch vote is running
+    Rematch(bool),
+}
+
+struct Poll {
+    question: &'static str,
+    options: &'static [&'static str],
+    votes: HashMap<TransientId, usize>,
+    timer: SpawnHandle,
+}
+
+struct RematchVote {
+    votes: HashMap<TransientId, bool>,
+    timer: SpawnHandle,
+}
+
+/// Lobby only state, thrown away as soon as a game starts
+#[derive(Default)]
+pub struct Lobby {
+    poll: Option<Poll>,
+    rematch: Option<RematchVote>,
+}
+
+/// Fired on the [Room] to relay a reaction, which it holds back while the server sheds load
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Reaction(pub OutgoingMessage);
+
+/// Fired on the [Room] when the currently running poll runs out of time
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClosePoll;
+
+/// Fired on the [Room] when the players did not agree on a rematch in time
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseRematchVote;
+
+impl Lobby {
+    pub fn handle(&mut self, ctx: &mut Context<Room>, player: TransientId, action: LobbyAction) {
+        match action {
+            LobbyAction::Emoji(emoji) => {
+                if EMOJIS.contains(&emoji.as_str()) {
+                    ctx.notify(Reaction(OutgoingMessage::EmojiPing { player, emoji }));
+                }
+            }
+            LobbyAction::Vote(option) => {
+                if let Some(poll) = &mut self.poll {
+                    if option < poll.options.len() {
+                        poll.votes.insert(player, option);
+                    }
+                }
+            }
+            LobbyAction::Rematch(agree) => {
+                if let Some(rematch) = &mut self.rematch {
+                    rematch.votes.insert(player, agree);
+                }
+            }
+        }
+    }
+    pub fn is_polling(&self) -> bool {
+        self.poll.is_some()
+    }
+    pub fn start_poll(&mut self, ctx: &mut Context<Room>) {
+        let (question, options) = POLLS[fastrand::usize(..POLLS.len())];
+        let timer = ctx.notify_later(ClosePoll, Duration::from_secs(POLL_DURATION));
+        self.poll = Some(Poll {
+            question,
+            options,
+            votes: HashMap::new(),
+            timer,
+        });
+        ctx.notify(Broadcast(OutgoingMessage::PollStarted {
+            question: question.to_string(),
+            options: options.iter().map(|x| x.to_string()).collect(),
+        }));
+    }
+    pub fn close_poll(&mut self, ctx: &mut Context<Room>) {
+        if let Some(poll) = self.poll.take() {
+            let mut votes = vec![0; poll.options.len()];
+            for option in poll.votes.values() {
+                votes[*option] += 1;
+            }
+            ctx.notify(Broadcast(OutgoingMessage::PollResults {
+                question: poll.question.to_string(),
+                votes,
+            }));
+        }
+    }
+    pub fn is_voting_rematch(&self) -> bool {
+        self.rematch.is_some()
+    }
+    /// Asks the players whether they want to play again, see [Room::tally_rematch]
+    pub fn start_rematch_vote(&mut self, ctx: &mut Context<Room>) {
+        let duration = Duration::from_secs(REMATCH_VOTE_DURATION);
+        let timer = ctx.notify_later(CloseRematchVote, duration);
+        self.rematch = Some(RematchVote {
+            votes: HashMap::new(),
+            timer,
+        });
+        ctx.notify(Broadcast(OutgoingMessage::RematchVoteStarted(
+            Deadline::after(duration),
+        )));
+    }
+    /// Votes cast in the running rematch vote, including those of players who left since
+    pub fn rematch_votes(&self) -> impl Iterator<Item = (&TransientId, &bool)> {
+        self.rematch.iter().flat_map(|rematch| rematch.votes.iter())
+    }
+    pub fn end_rematch_vote(&mut self, ctx: &mut Context<Room>) {
+        if let Some(rematch) = self.rematch.take() {
+            ctx.cancel_future(rematch.timer);
+        }
+    }
+    /// Drops any lobby state, cancelling the running poll without announcing its results.
+    pub fn reset(&mut self, ctx: &mut Context<Room>) {
+        if let Some(poll) = self.poll.take() {
+            ctx.cancel_future(poll.timer);
+        }
+        self.end_rematch_vote(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Server;
+    use serde_json::json;
+
+    #[actix::test]
+    async fn only_known_emojis_are_pinged() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        ann.expect("Result").await;
+        for emoji in ["free text", EMOJIS[1]] {
+            let ping = json!({ "kind": "Emoji", "data": emoji });
+            ann.send(json!({ "kind": "Lobby", "data": ping })).await;
+        }
+        assert_eq!(ann.expect("EmojiPing").await["emoji"], EMOJIS[1]);
+    }
+}
@@ -0,0 +1,131 @@
+use super::settings::{MAX_PLAYER_LIMIT, MIN_PLAYER_LIMIT};
+use super::{Listing, DEFAULT_PLAYER_LIMIT};
+use crate::game::words::{primary_language, supported_language};
+use crate::game::GameMode;
+use serde::Deserialize;
+
+pub(super) fn speaks(room: &Listing, language: &str) -> bool {
+    room.language
+        .as_ref()
+        .is_some_and(|x| **x == *primary_language(language))
+}
+
+/// What a player looking for a random room cares about. Unset fields match any room.
+#[derive(Deserialize, Clone)]
+pub struct MatchPreferences {
+    pub mode: Option<GameMode>,
+    pub language: Option<Box<str>>,
+    /// Bounds on the player limit of the room, for players who want a small or a crowded game
+    pub min_players: Option<u8>,
+    pub max_players: Option<u8>,
+    /// Language of the player's client. Unlike [MatchPreferences::language] this is only a
+    /// preference: rooms in this language are picked over others but any room will do.
+    #[serde(skip)]
+    pub locale: Option<Box<str>>,
+    /// Seconds to keep looking for an existing room before giving up. Players that don't set
+    /// this get a new room, set up according to their preferences, when nothing matches. Players
+    /// that do only get a new room once enough of them are waiting for the same kind of room.
+    pub max_wait: Option<u64>,
+    /// Whether the player can be dropped into a game that is already running
+    #[serde(default = "allow_in_progress")]
+    pub allow_in_progress: bool,
+}
+
+fn allow_in_progress() -> bool {
+    true
+}
+
+impl Default for MatchPreferences {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            language: None,
+            min_players: None,
+            max_players: None,
+            locale: None,
+            max_wait: None,
+            allow_in_progress: allow_in_progress(),
+        }
+    }
+}
+
+impl MatchPreferences {
+    pub(super) fn matches(&self, room: &Listing) -> bool {
+        self.mode.is_none_or(|mode| mode == room.mode)
+            && self
+                .language
+                .as_ref()
+                .is_none_or(|language| speaks(room, language))
+            && self.min_players.is_none_or(|x| room.max_players >= x)
+            && self.max_players.is_none_or(|x| room.max_players <= x)
+    }
+    /// Whether the room is in the language of the player's client
+    pub(super) fn prefers(&self, room: &Listing) -> bool {
+        self.locale
+            .as_ref()
+            .is_some_and(|locale| speaks(room, locale))
+    }
+    /// Language for a room created for this player, if there is a word list for it
+    pub(super) fn room_language(&self) -> Option<Box<str>> {
+        self.language
+            .as_deref()
+            .or(self.locale.as_deref())
+            .and_then(supported_language)
+            .map(Box::from)
+    }
+    /// Player limit for a room created for this player, as close to the default as their bounds
+    /// allow
+    pub(super) fn room_player_limit(&self) -> u8 {
+        DEFAULT_PLAYER_LIMIT
+            .max(self.min_players.unwrap_or(0))
+            .min(self.max_players.unwrap_or(u8::MAX))
+            .clamp(MIN_PLAYER_LIMIT, MAX_PLAYER_LIMIT)
+    }
+    /// Whether the player would rather keep waiting than have a new room created just for them
+    pub(super) fn waits(&self) -> bool {
+        self.max_wait.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::{actor::GameConfigOptions, RoomConfig};
+
+    fn listing(max_player_count: u8) -> Listing {
+        let room_config = RoomConfig {
+            max_player_count,
+            ..Default::default()
+        };
+        Listing::new(&room_config, &GameConfigOptions::default())
+    }
+
+    #[test]
+    fn rooms_are_sized_within_the_bounds_asked_for() {
+        let crowded = MatchPreferences {
+            min_players: Some(8),
+            max_players: Some(10),
+            ..Default::default()
+        };
+        assert!(!crowded.matches(&listing(3)));
+        assert!(crowded.matches(&listing(8)));
+        assert!(!crowded.matches(&listing(12)));
+        assert_eq!(crowded.room_player_limit(), 8);
+        let small = MatchPreferences {
+            max_players: Some(4),
+            ..Default::default()
+        };
+        assert!(small.matches(&listing(3)));
+        assert_eq!(small.room_player_limit(), 4);
+        assert_eq!(
+            MatchPreferences::default().room_player_limit(),
+            DEFAULT_PLAYER_LIMIT
+        );
+        // Nothing fits bounds that tight, the smallest room that does is created instead
+        let alone = MatchPreferences {
+            max_players: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(alone.room_player_limit(), MIN_PLAYER_LIMIT);
+    }
+}
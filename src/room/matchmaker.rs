@@ -0,0 +1,365 @@
+use actix::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use super::actor::{AddPlayer, GameConfigOptions, JoinRoomError, Joiner, RemovePlayer, Room};
+use super::matching::MatchPreferences;
+use super::{CreateRoom, Listing, RoomConfig, RoomManager, RoomPair, DEFAULT_MIN_PLAYERS};
+use crate::game::GameMode;
+use crate::load::Load;
+use crate::rating::Ratings;
+use crate::session::latency;
+use crate::session::message::RemoveReason;
+use crate::session::TransientId;
+
+/// How often waiting players are matched up
+const MATCH_TICK: Duration = Duration::from_millis(500);
+
+/// A room random joins can be placed in, as the room manager last heard of it
+pub(super) struct Candidate {
+    addr: Addr<Room>,
+    listing: Listing,
+    players: usize,
+    rating: f64,
+    latency: Option<Duration>,
+    /// Running a game that is short on players
+    in_progress: bool,
+}
+
+/// Rooms open to random joins, running games short on players first
+#[derive(Message)]
+#[rtype(result = "Vec<Candidate>")]
+struct MatchCandidates;
+
+impl Handler<MatchCandidates> for RoomManager {
+    type Result = MessageResult<MatchCandidates>;
+    fn handle(&mut self, _: MatchCandidates, _: &mut Self::Context) -> Self::Result {
+        let in_progress = self.backfill.values().map(|room| (room, true));
+        let open = self.open.values().map(|room| (room, false));
+        let candidates = in_progress
+            .chain(open)
+            .map(|(room, in_progress)| Candidate {
+                addr: room.addr.clone(),
+                listing: room.listing.clone(),
+                players: room.players,
+                rating: room.rating,
+                latency: room.latency,
+                in_progress,
+            })
+            .collect();
+        MessageResult(candidates)
+    }
+}
+
+/// A player waiting to be placed in a random room
+struct Ticket {
+    joiner: Joiner,
+    preferences: MatchPreferences,
+    /// Until when the player is willing to wait for a room, see [MatchPreferences::max_wait]
+    deadline: Option<Instant>,
+    reply: oneshot::Sender<Result<RoomPair, JoinRoomError>>,
+}
+
+impl Ticket {
+    /// The player gave up on the search or went away
+    fn abandoned(&self) -> bool {
+        self.reply.is_closed() || !self.joiner.session.1.connected()
+    }
+    /// Hands the player the seat they got, or gives it up again if they stopped waiting for it
+    /// while it was being set up
+    fn seated(
+        reply: oneshot::Sender<Result<RoomPair, JoinRoomError>>,
+        room: RoomPair,
+        id: TransientId,
+    ) {
+        let addr = room.addr.clone();
+        if reply.send(Ok(room)).is_err() {
+            addr.do_send(RemovePlayer {
+                transient_id: id,
+                reason: RemoveReason::LeaveRequested,
+            });
+        }
+    }
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= now)
+    }
+    /// Settings of the room the player would get if nothing fits, players who would get the
+    /// same room are matched up together
+    fn room_key(&self) -> (GameMode, Option<Box<str>>, u8) {
+        (
+            self.preferences.mode.unwrap_or_default(),
+            self.preferences.room_language(),
+            self.preferences.room_player_limit(),
+        )
+    }
+}
+
+/// Collects players looking for a random room and places them in batches every [MATCH_TICK].
+/// Players are put in the best fitting open room first, and the ones left over are grouped with
+/// others who want the same kind of room so that they start out in a room together.
+pub struct Matchmaker {
+    room_manager: Addr<RoomManager>,
+    ratings: Arc<Ratings>,
+    load: Arc<Load>,
+    /// Ticks since the matchmaker started, batches are placed less often while the server sheds
+    /// load
+    ticks: u64,
+    /// In the order the players started looking
+    tickets: Vec<Ticket>,
+    /// Whether a batch is being placed, tickets coming in meanwhile go into the next one
+    matching: bool,
+}
+
+impl Matchmaker {
+    pub fn new(room_manager: Addr<RoomManager>, ratings: Arc<Ratings>, load: Arc<Load>) -> Self {
+        Self {
+            room_manager,
+            ratings,
+            load,
+            ticks: 0,
+            tickets: Vec::new(),
+            matching: false,
+        }
+    }
+    fn tick(&mut self, ctx: &mut Context<Self>) {
+        self.ticks += 1;
+        if self.matching || self.tickets.is_empty() || self.load.skips(self.ticks) {
+            return;
+        }
+        self.matching = true;
+        self.room_manager
+            .send(MatchCandidates)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                act.matching = false;
+                match res {
+                    Ok(candidates) => act.place(candidates, ctx),
+                    Err(err) => log::error!("cannot list rooms for matchmaking: {err}"),
+                }
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn place(&mut self, candidates: Vec<Candidate>, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let mut seats = candidates
+            .iter()
+            .map(|room| (room.listing.max_players as usize).saturating_sub(room.players))
+            .collect::<Vec<_>>();
+        let mut groups: Vec<Vec<Ticket>> = Vec::new();
+        for ticket in std::mem::take(&mut self.tickets) {
+            if ticket.abandoned() {
+                continue;
+            }
+            let preferences = &ticket.preferences;
+            let rating = self.ratings.get(&ticket.joiner.user);
+            // Rooms in the player's own language come first, then those close to their rating,
+            // then those of players with connections like theirs, otherwise the order is kept
+            let found = candidates
+                .iter()
+                .enumerate()
+                .filter(|(idx, room)| {
+                    seats[*idx] > 0
+                        && (!room.in_progress || preferences.allow_in_progress)
+                        && preferences.matches(&room.listing)
+                })
+                .min_by_key(|(_, room)| {
+                    (
+                        !preferences.prefers(&room.listing),
+                        !self.ratings.within_band(room.rating, rating),
+                        !latency::similar(room.latency, ticket.joiner.rtt),
+                    )
+                });
+            if let Some((idx, room)) = found {
+                seats[idx] -= 1;
+                self.seat(room.addr.clone(), ticket, ctx);
+            } else if let Some(group) = groups
+                .iter_mut()
+                .find(|group| group[0].room_key() == ticket.room_key())
+            {
+                group.push(ticket);
+            } else {
+                groups.push(vec![ticket]);
+            }
+        }
+        for group in groups {
+            // Players waiting for an existing room only get a new one once enough of them can
+            // start a game in it together
+            let create = group.len() >= DEFAULT_MIN_PLAYERS as usize
+                || group.iter().any(|ticket| !ticket.preferences.waits());
+            if create {
+                let limit = group[0].preferences.room_player_limit() as usize;
+                let mut group = group.into_iter().peekable();
+                while group.peek().is_some() {
+                    self.start_room(group.by_ref().take(limit).collect(), ctx);
+                }
+            } else {
+                for ticket in group {
+                    self.requeue(ticket, now);
+                }
+            }
+        }
+    }
+    /// Keeps the player waiting for the next batch, unless they ran out of time
+    fn requeue(&mut self, ticket: Ticket, now: Instant) {
+        if ticket.expired(now) {
+            let _ = ticket.reply.send(Err(JoinRoomError::NoMatch));
+        } else {
+            self.tickets.push(ticket);
+        }
+    }
+    fn seat(&mut self, room: Addr<Room>, ticket: Ticket, ctx: &mut Context<Self>) {
+        let Ticket {
+            joiner,
+            preferences,
+            deadline,
+            reply,
+        } = ticket;
+        if reply.is_closed() {
+            return;
+        }
+        room.send(AddPlayer(joiner.clone()))
+            .into_actor(self)
+            .then(move |res, act, _| {
+                match res {
+                    Ok(Ok((code, addr))) => {
+                        Ticket::seated(reply, RoomPair { code, addr }, joiner.session.0);
+                    }
+                    // The room filled up or started a game since the batch was put together
+                    Ok(Err(JoinRoomError::RoomFull | JoinRoomError::GameInProgress)) => {
+                        let ticket = Ticket {
+                            joiner,
+                            preferences,
+                            deadline,
+                            reply,
+                        };
+                        act.requeue(ticket, Instant::now());
+                    }
+                    Ok(Err(err)) => {
+                        let _ = reply.send(Err(err));
+                    }
+                    Err(err) => {
+                        log::error!("{err}");
+                        let _ = reply.send(Err(JoinRoomError::InternalServerError));
+                    }
+                }
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    /// Creates a room for a group of players, led by the one who has been waiting the longest
+    fn start_room(&mut self, group: Vec<Ticket>, ctx: &mut Context<Self>) {
+        let mut group = group.into_iter();
+        let Some(leader) = group.next() else {
+            return;
+        };
+        let rest = group.collect::<Vec<_>>();
+        let id = leader.joiner.session.0;
+        let (room_config, game_config) = room_for(&leader.preferences);
+        self.room_manager
+            .send(CreateRoom {
+                leader: leader.joiner,
+                room_config,
+                game_config,
+            })
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(Ok(room)) => {
+                        let addr = room.addr.clone();
+                        Ticket::seated(leader.reply, room, id);
+                        for ticket in rest {
+                            act.seat(addr.clone(), ticket, ctx);
+                        }
+                    }
+                    Ok(Err(err)) => {
+                        let _ = leader.reply.send(Err(err));
+                        for ticket in rest {
+                            act.requeue(ticket, Instant::now());
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("{err}");
+                        let _ = leader.reply.send(Err(JoinRoomError::InternalServerError));
+                        for ticket in rest {
+                            act.requeue(ticket, Instant::now());
+                        }
+                    }
+                }
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+}
+
+/// Room set up the way the player asked for
+fn room_for(preferences: &MatchPreferences) -> (RoomConfig, GameConfigOptions) {
+    let room_config = RoomConfig {
+        language: preferences.room_language(),
+        max_player_count: preferences.room_player_limit(),
+        ..Default::default()
+    };
+    let game_config = GameConfigOptions {
+        mode: preferences.mode.unwrap_or_default(),
+        ..Default::default()
+    };
+    (room_config, game_config)
+}
+
+impl Actor for Matchmaker {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(MATCH_TICK, |act, ctx| act.tick(ctx));
+    }
+}
+
+/// Puts a player in line for the next batch
+#[derive(Message)]
+#[rtype(result = "Result<RoomPair, JoinRoomError>")]
+pub struct FindMatch {
+    pub joiner: Joiner,
+    pub preferences: MatchPreferences,
+}
+
+impl Handler<FindMatch> for Matchmaker {
+    type Result = ResponseFuture<Result<RoomPair, JoinRoomError>>;
+    fn handle(&mut self, msg: FindMatch, _: &mut Self::Context) -> Self::Result {
+        let FindMatch {
+            joiner,
+            preferences,
+        } = msg;
+        let transient_id = joiner.session.0;
+        // A player only looks for one room at a time
+        self.tickets
+            .retain(|ticket| ticket.joiner.session.0 != transient_id);
+        let (reply, result) = oneshot::channel();
+        let deadline = preferences
+            .max_wait
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        self.tickets.push(Ticket {
+            joiner,
+            preferences,
+            deadline,
+            reply,
+        });
+        Box::pin(async move {
+            result
+                .await
+                .unwrap_or(Err(JoinRoomError::InternalServerError))
+        })
+    }
+}
+
+/// Takes a player out of line, for when they go for another room or leave
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CancelMatch(pub TransientId);
+
+impl Handler<CancelMatch> for Matchmaker {
+    type Result = ();
+    fn handle(&mut self, msg: CancelMatch, _: &mut Self::Context) -> Self::Result {
+        self.tickets
+            .retain(|ticket| ticket.joiner.session.0 != msg.0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Public lobbies that sit below their minimum player count for too long are merged into one
+//! another, so that players spread thin across many rooms get to play instead of waiting for
+//! others to join. The members of the smaller room are moved into the larger one, which has to
+//! play the same mode in the same language and have enough free seats for all of them.
+
+use std::time::{Duration, Instant};
+
+use super::actor::MergeInto;
+use super::{RoomCode, RoomManager};
+use crate::game::GameMode;
+
+/// How often the room manager looks for rooms to merge
+pub(super) const MERGE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a room has to be short on players before it is merged into another one
+const MERGE_AFTER: Duration = Duration::from_secs(60);
+
+/// What the room manager knows about a lobby that is short on players
+struct Candidate<'a> {
+    code: RoomCode,
+    players: usize,
+    max_players: usize,
+    mode: GameMode,
+    language: Option<&'a str>,
+}
+
+impl RoomManager {
+    /// Merges every lobby that has been short on players for long enough into a larger one
+    pub(super) fn merge_rooms(&mut self) {
+        let now = Instant::now();
+        let candidates = self
+            .open
+            .iter()
+            .filter(|(_, room)| {
+                room.understaffed_since
+                    .is_some_and(|since| now.duration_since(since) >= MERGE_AFTER)
+            })
+            .map(|(code, room)| Candidate {
+                code: *code,
+                players: room.players,
+                max_players: room.listing.max_players as usize,
+                mode: room.listing.mode,
+                language: room.listing.language.as_deref(),
+            })
+            .collect::<Vec<_>>();
+        for (from, into) in plan(candidates) {
+            if let Some(room) = self.open.get_mut(&from) {
+                // Keeps the room from being picked again before it is gone
+                room.understaffed_since = None;
+                log::info!(
+                    "merging room {} into {}",
+                    String::from_utf8_lossy(&from),
+                    String::from_utf8_lossy(&into)
+                );
+                room.addr.do_send(MergeInto(into));
+                self.stats.merged += 1;
+            }
+        }
+    }
+}
+
+/// Pairs up the rooms to merge, smaller ones first, each into the fullest room that still has
+/// enough seats for their members. Every room takes part in one merge at most.
+fn plan(mut candidates: Vec<Candidate>) -> Vec<(RoomCode, RoomCode)> {
+    // Ties are broken by code so that the plan doesn't depend on the order of the map
+    candidates.sort_unstable_by(|a, b| a.players.cmp(&b.players).then((*a.code).cmp(&*b.code)));
+    let mut merged = vec![false; candidates.len()];
+    let mut merges = Vec::new();
+    for from in 0..candidates.len() {
+        if merged[from] {
+            continue;
+        }
+        let room = &candidates[from];
+        let into = (0..candidates.len()).rev().find(|&into| {
+            let target = &candidates[into];
+            into != from
+                && !merged[into]
+                && target.players >= room.players
+                && target.players + room.players <= target.max_players
+                && target.mode == room.mode
+                && target.language == room.language
+        });
+        if let Some(into) = into {
+            merged[from] = true;
+            merged[into] = true;
+            merges.push((room.code, candidates[into].code));
+        }
+    }
+    merges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(code: &str) -> RoomCode {
+        RoomCode::try_from(code.as_bytes()).unwrap()
+    }
+
+    fn candidate(room: &str, players: usize, language: Option<&'static str>) -> Candidate<'static> {
+        Candidate {
+            code: code(room),
+            players,
+            max_players: 4,
+            mode: GameMode::Standard,
+            language,
+        }
+    }
+
+    #[test]
+    fn smaller_rooms_move_into_the_fullest_room_that_fits_them() {
+        let merges = plan(vec![
+            candidate("AAAA", 1, None),
+            candidate("BBBB", 3, None),
+            candidate("CCCC", 2, None),
+            candidate("DDDD", 1, Some("de")),
+        ]);
+        // The pair doesn't fit anywhere once the single player took the last seat, and nobody
+        // else plays in german
+        assert_eq!(merges, [(code("AAAA"), code("BBBB"))]);
+        let merges = plan(vec![
+            candidate("AAAA", 2, None),
+            candidate("BBBB", 2, None),
+            candidate("CCCC", 3, None),
+        ]);
+        assert_eq!(merges, [(code("AAAA"), code("BBBB"))]);
+    }
+}
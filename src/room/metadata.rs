@@ -0,0 +1,93 @@
+//! Free-form details the leader attaches to their room, such as the name of the tournament it is
+//! part of, a link to the stream or the house rules. The room doesn't interpret any of it, it is
+//! only shown to members and in the room browser and kept along with the results of its games.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Most entries a room can hold
+const MAX_ENTRIES: usize = 8;
+/// Longest key, in bytes
+const MAX_KEY_LENGTH: usize = 32;
+/// Longest value, in bytes
+const MAX_VALUE_LENGTH: usize = 512;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum MetadataError {
+    NotInRoom,
+    NotLeader,
+    /// Keys are made of up to [MAX_KEY_LENGTH] letters, digits, `_`, `-` and `.`
+    InvalidKey,
+    ValueTooLong,
+    /// The room already has [MAX_ENTRIES] entries
+    TooManyEntries,
+    Inappropriate,
+    InternalServerError,
+}
+
+/// Key-value pairs the leader set on the room, sorted by key
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct RoomMetadata(BTreeMap<Box<str>, Box<str>>);
+
+impl RoomMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Sets the entry, or removes it if `value` is empty
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), MetadataError> {
+        let valid_key = !key.is_empty()
+            && key.len() <= MAX_KEY_LENGTH
+            && key
+                .bytes()
+                .all(|x| x.is_ascii_alphanumeric() || matches!(x, b'_' | b'-' | b'.'));
+        if !valid_key {
+            return Err(MetadataError::InvalidKey);
+        }
+        let value = value.trim();
+        if value.is_empty() {
+            self.0.remove(key);
+            return Ok(());
+        }
+        if value.len() > MAX_VALUE_LENGTH {
+            return Err(MetadataError::ValueTooLong);
+        }
+        if self.0.len() >= MAX_ENTRIES && !self.0.contains_key(key) {
+            return Err(MetadataError::TooManyEntries);
+        }
+        self.0.insert(key.into(), value.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_bounded() {
+        let mut metadata = RoomMetadata::default();
+        metadata.set("tournament", "Spring Cup").unwrap();
+        assert_eq!(metadata.set("", "x"), Err(MetadataError::InvalidKey));
+        assert_eq!(
+            metadata.set("stream url", "x"),
+            Err(MetadataError::InvalidKey)
+        );
+        let long = "x".repeat(MAX_VALUE_LENGTH + 1);
+        assert_eq!(
+            metadata.set("rules", &long),
+            Err(MetadataError::ValueTooLong)
+        );
+        for n in 1..MAX_ENTRIES {
+            metadata.set(&format!("key{n}"), "value").unwrap();
+        }
+        assert_eq!(
+            metadata.set("one_more", "x"),
+            Err(MetadataError::TooManyEntries)
+        );
+        // Existing entries can still be changed or cleared once the room is at the limit
+        metadata.set("tournament", "Summer Cup").unwrap();
+        metadata.set("key1", " ").unwrap();
+        metadata.set("one_more", "x").unwrap();
+        assert_eq!(metadata.0.len(), MAX_ENTRIES);
+    }
+}
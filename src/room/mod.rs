@@ -4,23 +4,195 @@ use ahash::{HashMap, HashMapExt};
 use actor::Room;
 use fastrand::Rng;
 
-use crate::session::{actor::Session, TransientId};
+use crate::capacity::Capacity;
+use crate::deadletter::DeadLetters;
+use crate::events::EventBus;
+use crate::game::limits::ModeLimits;
+use crate::game::summary::GameArchive;
+use crate::game::words::supported_language;
+use crate::game::GameMode;
+use crate::jobs::JobPool;
+use crate::load::Load;
+use crate::profanity::ProfanityFilter;
+use crate::rating::Ratings;
+use crate::server::handover::{RESUME_WINDOW, WARM_WINDOW};
+use crate::session::profile::Profile;
+use crate::session::{actor::Session, TransientId, UserId};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-use self::actor::{AddPlayer, JoinRoomError};
+use self::actor::{
+    AddInvitedPlayer, AddObserver, AddPlayer, AddSpectator, CloseRoom, GameConfigOptions, GetAudit,
+    JoinRoomError, Joiner, ResetRoom,
+};
+use self::audit::{AuditRecord, ClosedTrails, Trail};
+use self::denylist::Denylist;
+use self::fanout::FanoutPool;
+use self::invite::{InviteSigner, TokenError};
+use self::matching::MatchPreferences;
+use self::matchmaker::{CancelMatch, FindMatch, Matchmaker};
+use self::metadata::RoomMetadata;
+use self::observer::{ObserveError, Observed};
+use self::password::RoomPassword;
+use self::placement::{ArbiterPool, PlacementMetrics};
+use self::practice::{PracticeBoards, PracticeSeed};
+use self::region::regions;
+use self::settings::RoomSettings;
 pub mod actor;
+pub mod audit;
+pub mod browser;
+pub mod chat;
+pub mod denylist;
+pub mod fanout;
+pub mod history;
+pub mod instant_replay;
+pub mod invite;
+pub mod lobby;
+pub mod matching;
+pub mod matchmaker;
+pub mod merge;
+pub mod metadata;
+pub mod observer;
+pub mod password;
+pub mod placement;
+pub mod practice;
+pub mod region;
+pub mod settings;
+pub mod state;
+pub mod traffic;
+pub mod vault;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoomKind {
+    /// Regular room where players get together to play games
+    #[default]
+    Standard,
+    /// Broadcast-only room for events: there is one broadcaster (the leader) and any number of
+    /// listeners. Only the leader's messages are relayed and games cannot be started.
+    Announcement,
+    /// Solo room playing a seeded puzzle, see [practice]. The game starts as soon as the room
+    /// opens and its settings are fixed.
+    Practice,
+}
 
 pub struct RoomConfig {
     public: bool,
     max_player_count: u8,
+    /// Number of players needed before a game can start. Public rooms count down to the next
+    /// game on their own as soon as this many players are in.
+    min_players: u8,
+    kind: RoomKind,
+    /// Language the room is meant to be played in, if the creator picked one
+    language: Option<Box<str>>,
+    /// Players joining while the room is full wait in line for a seat instead of being turned
+    /// away
+    waiting_queue: bool,
+    /// Number of people who can watch the room without a seat, on top of
+    /// [RoomConfig::max_player_count]
+    max_spectators: u8,
+    /// How long the room stays open at most, rooms without one stay open for as long as they
+    /// are used. Rooms opened without one get [RoomServices::lifetime].
+    lifetime: Option<RoomLifetime>,
+    /// Seconds a player's seat is kept for them after they lose connection, they are removed
+    /// from the room if they don't reconnect in time
+    reconnect_grace_secs: u64,
+    /// Details the leader attached to the room, see [metadata]
+    metadata: RoomMetadata,
+    /// Players joining by code have to give it, see [Joiner::password]. Invited players and
+    /// members coming back get in without it.
+    password: Option<RoomPassword>,
+    /// The summaries of the room's games and its audit trail are sealed once archived, see
+    /// [vault]
+    privacy_mode: bool,
 }
 
+/// Longest password a room can be given, in bytes
+pub const MAX_PASSWORD_LENGTH: usize = 64;
 const DEFAULT_PLAYER_LIMIT: u8 = 6;
+const DEFAULT_MIN_PLAYERS: u8 = 2;
+const DEFAULT_SPECTATOR_LIMIT: u8 = 8;
+const DEFAULT_RECONNECT_GRACE: u64 = 15;
+
+impl RoomConfig {
+    fn practice() -> Self {
+        Self {
+            public: false,
+            max_player_count: 1,
+            min_players: 1,
+            kind: RoomKind::Practice,
+            language: None,
+            waiting_queue: false,
+            max_spectators: 0,
+            lifetime: None,
+            reconnect_grace_secs: DEFAULT_RECONNECT_GRACE,
+            metadata: RoomMetadata::default(),
+            password: None,
+            privacy_mode: false,
+        }
+    }
+    /// Room set up the way the client opening it asked for. Rooms behind a password are never
+    /// public, the matchmaker would have nobody to send to them. Neither are announcement rooms,
+    /// random joiners are looking for a game.
+    pub fn requested(
+        public: bool,
+        announcement: bool,
+        max_players: Option<u8>,
+        language: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<Self, JoinRoomError> {
+        if password.is_some_and(|x| x.trim().len() > MAX_PASSWORD_LENGTH) {
+            return Err(JoinRoomError::InvalidPassword);
+        }
+        let max_player_count = max_players.unwrap_or(DEFAULT_PLAYER_LIMIT);
+        if !(settings::MIN_PLAYER_LIMIT..=settings::MAX_PLAYER_LIMIT).contains(&max_player_count) {
+            return Err(JoinRoomError::InvalidPlayerLimit);
+        }
+        let language = match language {
+            Some(language) => {
+                Some(supported_language(language).ok_or(JoinRoomError::UnsupportedLanguage)?)
+            }
+            None => None,
+        };
+        let password = password.and_then(RoomPassword::new);
+        Ok(Self {
+            public: public && password.is_none() && !announcement,
+            kind: if announcement {
+                RoomKind::Announcement
+            } else {
+                RoomKind::Standard
+            },
+            max_player_count,
+            language: language.map(Box::from),
+            password,
+            ..Default::default()
+        })
+    }
+    /// Announcement rooms ignore [RoomConfig::max_player_count] altogether
+    fn is_full(&self, player_count: usize) -> bool {
+        self.kind != RoomKind::Announcement && player_count >= self.max_player_count as usize
+    }
+    fn spectators_full(&self, spectator_count: usize) -> bool {
+        spectator_count >= self.max_spectators as usize
+    }
+}
 
 impl Default for RoomConfig {
     fn default() -> Self {
         Self {
             public: true,
             max_player_count: DEFAULT_PLAYER_LIMIT,
+            min_players: DEFAULT_MIN_PLAYERS,
+            kind: Default::default(),
+            language: None,
+            waiting_queue: false,
+            max_spectators: DEFAULT_SPECTATOR_LIMIT,
+            lifetime: None,
+            reconnect_grace_secs: DEFAULT_RECONNECT_GRACE,
+            metadata: RoomMetadata::default(),
+            password: None,
+            privacy_mode: false,
         }
     }
 }
@@ -29,62 +201,555 @@ struct RoomInfo {
     addr: Addr<Room>,
     playing: bool,
     full: bool,
+    /// Every spectator slot is taken, which leaves the seats for players open and the other
+    /// way around
+    spectators_full: bool,
+    /// Locked by its leader, the room stays out of matchmaking until it is unlocked
+    locked: bool,
+    /// Index of the arbiter the room runs on within the [ArbiterPool]
+    arbiter: usize,
+    listing: Listing,
+    /// Number of players in the room, shown in the room browser
+    players: usize,
+    /// Average rating of the players in the room, random joins prefer rooms close to their own
+    rating: f64,
+    /// Average round trip time of the players in the room, see [crate::session::latency]
+    latency: Option<Duration>,
+    /// Since when the room has had fewer players than it needs for a game, see [merge]
+    understaffed_since: Option<Instant>,
 }
 
-impl RoomInfo {
-    fn new(addr: Addr<Room>) -> Self {
+/// Settings of a room the room manager needs for matchmaking and the room browser
+#[derive(Clone)]
+pub struct Listing {
+    /// Private rooms can only be joined through their code
+    public: bool,
+    /// Matched against the preferences of random joiners, see [MatchPreferences]
+    mode: GameMode,
+    language: Option<Box<str>>,
+    /// Full rooms with a waiting queue still take in players joining by code
+    waiting_queue: bool,
+    max_players: u8,
+    min_players: u8,
+    metadata: RoomMetadata,
+}
+
+impl Listing {
+    fn new(room_config: &RoomConfig, game_config: &GameConfigOptions) -> Self {
         Self {
+            public: room_config.public,
+            mode: game_config.mode,
+            language: room_config.language.clone(),
+            waiting_queue: room_config.waiting_queue,
+            max_players: room_config.max_player_count,
+            min_players: room_config.min_players,
+            metadata: room_config.metadata.clone(),
+        }
+    }
+}
+
+impl RoomInfo {
+    fn new(
+        addr: Addr<Room>,
+        arbiter: usize,
+        listing: Listing,
+        rating: f64,
+        latency: Option<Duration>,
+    ) -> Self {
+        let mut room = Self {
             addr,
             playing: false,
             full: false,
+            spectators_full: false,
+            locked: false,
+            arbiter,
+            listing,
+            // Rooms are started with their leader in them
+            players: 1,
+            rating,
+            latency,
+            understaffed_since: None,
+        };
+        room.update_understaffed();
+        room
+    }
+    fn update_understaffed(&mut self) {
+        if self.players >= self.listing.min_players as usize {
+            self.understaffed_since = None;
+        } else if self.understaffed_since.is_none() {
+            self.understaffed_since = Some(Instant::now());
         }
     }
     fn reset(&mut self) {
         self.full = false;
+        self.spectators_full = false;
         self.playing = false;
+        self.locked = false;
+    }
+    fn mark_unavailable(&mut self, reason: RoomUnavailablityReason) {
+        match reason {
+            RoomUnavailablityReason::Full => self.full = true,
+            RoomUnavailablityReason::GameStarted => self.playing = true,
+            RoomUnavailablityReason::Locked => self.locked = true,
+        }
     }
 }
 
-pub const ROOM_CODE_LENGTH: usize = 4;
-pub type RoomCode = [u8; ROOM_CODE_LENGTH];
+const DEFAULT_ROOM_CODE_LENGTH: usize = 4;
+pub const MIN_ROOM_CODE_LENGTH: usize = 3;
+pub const MAX_ROOM_CODE_LENGTH: usize = 8;
+const ROOM_CODE_CHARSET: &[u8] = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".as_bytes();
+/// Vanity aliases are always longer than generated codes so that the two can never collide
+pub const MAX_ALIAS_LENGTH: usize = 16;
+/// Times a freshly generated code may turn out to be taken before room creation gives up
+const MAX_CODE_ATTEMPTS: usize = 64;
+/// Most stopped rooms kept around for reuse, see [RoomManager::pool_limit]
+const MAX_POOLED_ROOMS: usize = 1 << 12;
+
+/// Length of the room codes this server generates, read from `ROOM_CODE_LENGTH` on first use
+/// and clamped to [MIN_ROOM_CODE_LENGTH]..=[MAX_ROOM_CODE_LENGTH]
+pub fn room_code_length() -> usize {
+    static LENGTH: OnceLock<usize> = OnceLock::new();
+    *LENGTH.get_or_init(|| {
+        std::env::var("ROOM_CODE_LENGTH")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_ROOM_CODE_LENGTH)
+            .clamp(MIN_ROOM_CODE_LENGTH, MAX_ROOM_CODE_LENGTH)
+    })
+}
+
+/// Number of distinct room codes this server can generate, denylisted ones included. Codes
+/// start with the region of the server if it has one, see [region].
+pub fn room_code_space() -> u64 {
+    let random = room_code_length() - usize::from(regions().is_some());
+    (ROOM_CODE_CHARSET.len() as u64).pow(random as u32)
+}
+
+/// Code a room is joined by. Dereferences to its characters.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RoomCode {
+    bytes: [u8; MAX_ROOM_CODE_LENGTH],
+    len: u8,
+}
+
+impl Deref for RoomCode {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Fails if the code is shorter than [MIN_ROOM_CODE_LENGTH] or longer than
+/// [MAX_ROOM_CODE_LENGTH]. Codes of other lengths than [room_code_length] are accepted so that
+/// rooms handed over by a process generating longer or shorter codes keep theirs.
+impl TryFrom<&[u8]> for RoomCode {
+    type Error = ();
+    fn try_from(code: &[u8]) -> Result<Self, ()> {
+        if !(MIN_ROOM_CODE_LENGTH..=MAX_ROOM_CODE_LENGTH).contains(&code.len()) {
+            return Err(());
+        }
+        let mut bytes = [0; MAX_ROOM_CODE_LENGTH];
+        bytes[..code.len()].copy_from_slice(code);
+        Ok(Self {
+            bytes,
+            len: code.len() as u8,
+        })
+    }
+}
+
+/// A room as referred to by a client, either through its generated code, a vanity alias or an
+/// invite token. A practice seed stands for a new practice room of its own.
+pub enum RoomRef {
+    Code(RoomCode),
+    Alias(Box<str>),
+    Invite(Box<str>),
+    Practice(PracticeSeed),
+}
+
+/// Validates and normalizes a vanity alias, returning [None] if it is not a well formed alias.
+/// Aliases are case insensitive and stored in upper case, same as generated codes.
+pub fn normalize_alias(alias: &str) -> Option<Box<str>> {
+    let valid = alias.len() > room_code_length()
+        && alias.len() <= MAX_ALIAS_LENGTH
+        && alias.bytes().all(|x| x.is_ascii_alphanumeric());
+    valid.then(|| alias.to_ascii_uppercase().into_boxed_str())
+}
+
+/// Server wide helpers that every room gets a handle to
+#[derive(Clone)]
+pub struct RoomServices {
+    /// Filters player supplied text
+    pub profanity: Arc<ProfanityFilter>,
+    /// Broadcasts to very large audiences
+    pub fanout: FanoutPool,
+    /// Runs CPU heavy work off the room actors
+    pub jobs: JobPool,
+    /// Collects messages that could not be delivered to their client
+    pub dead_letters: Addr<DeadLetters>,
+    pub inactivity: InactivityConfig,
+    /// Lifetime of the rooms opened without one, see [RoomLifetime::from_env]
+    pub lifetime: Option<RoomLifetime>,
+    /// Signs the invites leaders hand out and checks the ones players join with
+    pub invites: Arc<InviteSigner>,
+    /// Updated from the results of every game
+    pub ratings: Arc<Ratings>,
+    /// Whether the server sheds load, see [crate::load::LoadMonitor]
+    pub load: Arc<Load>,
+    /// Leaderboards of the practice puzzles
+    pub practice: Arc<PracticeBoards>,
+    /// Told whenever a game starts or ends
+    pub events: Addr<EventBus>,
+    /// Caps the rooms open at once, see [crate::capacity]
+    pub capacity: Arc<Capacity>,
+    /// Caps the games running at once in every mode
+    pub modes: Arc<ModeLimits>,
+    /// Keeps the summaries of finished games
+    pub archive: Arc<GameArchive>,
+}
+
+const DEFAULT_INACTIVITY_TIMEOUT: u64 = 600;
+const DEFAULT_INACTIVITY_WARNING: u64 = 60;
+
+/// How long a room can go without a game or a message from its players before it is closed
+#[derive(Clone, Copy)]
+pub struct InactivityConfig {
+    pub timeout: Duration,
+    /// How long before closing the players are warned
+    pub warning: Duration,
+}
+
+impl InactivityConfig {
+    /// Reads `ROOM_INACTIVITY_TIMEOUT` and `ROOM_INACTIVITY_WARNING`, both in seconds
+    pub fn from_env() -> Self {
+        let read = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            timeout: Duration::from_secs(read(
+                "ROOM_INACTIVITY_TIMEOUT",
+                DEFAULT_INACTIVITY_TIMEOUT,
+            )),
+            warning: Duration::from_secs(read(
+                "ROOM_INACTIVITY_WARNING",
+                DEFAULT_INACTIVITY_WARNING,
+            )),
+        }
+    }
+}
+
+const DEFAULT_LIFETIME_WARNINGS: &[u64] = &[300, 60];
+
+/// Time after which a room closes however busy it is. Members are warned ahead of time, and a
+/// game still running when the time is up is ended early with the scores it reached.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RoomLifetime {
+    pub duration: Duration,
+    /// How long before closing the members are warned, every one of them in turn
+    pub warnings: Vec<Duration>,
+}
+
+impl RoomLifetime {
+    /// Reads `ROOM_LIFETIME` in seconds, rooms live for as long as they are used without it.
+    /// `ROOM_LIFETIME_WARNINGS` lists the warnings to give as comma separated seconds, five
+    /// minutes and one minute before closing by default.
+    pub fn from_env() -> Option<Self> {
+        let duration = std::env::var("ROOM_LIFETIME").ok()?;
+        let Some(duration) = duration.parse().ok().filter(|x| *x > 0) else {
+            log::error!("ignoring malformed ROOM_LIFETIME {duration:?}");
+            return None;
+        };
+        let warnings = match std::env::var("ROOM_LIFETIME_WARNINGS") {
+            Ok(warnings) => warnings
+                .split(',')
+                .filter_map(|x| {
+                    let warning = x.trim().parse().ok();
+                    if warning.is_none() {
+                        log::error!("ignoring malformed ROOM_LIFETIME_WARNINGS entry {x:?}");
+                    }
+                    warning
+                })
+                .collect(),
+            Err(_) => DEFAULT_LIFETIME_WARNINGS.to_vec(),
+        };
+        Some(Self {
+            duration: Duration::from_secs(duration),
+            warnings: warnings.into_iter().map(Duration::from_secs).collect(),
+        })
+    }
+    /// What is left of the lifetime at the time given, with the warnings still to come
+    pub fn left(&self, opened: Instant, now: Instant) -> Self {
+        Self {
+            duration: self
+                .duration
+                .saturating_sub(now.saturating_duration_since(opened)),
+            warnings: self.warnings.clone(),
+        }
+    }
+}
 
 pub struct RoomManager {
     free: HashMap<RoomCode, RoomInfo>,
     reserved: HashMap<RoomCode, RoomInfo>,
     open: HashMap<RoomCode, RoomInfo>,
+    /// Public rooms running a game that is short on players, see [Availability::Backfill]
+    backfill: HashMap<RoomCode, RoomInfo>,
+    /// Vanity aliases picked by room leaders, mapped to the code of the room they point to
+    aliases: HashMap<Box<str>, RoomCode>,
+    /// Strings that room codes are not allowed to contain
+    denylist: Denylist,
+    /// Shared with every room
+    services: RoomServices,
+    /// Arbiters new rooms are spread across
+    placement: ArbiterPool,
+    stats: RoomStats,
+    /// Rooms handed over by the previous process that none of their members came back to yet,
+    /// see [crate::server::handover]
+    migrated: HashMap<RoomCode, RoomSummary>,
+    /// Rooms of the instance being cut over from, set up again by whoever joins them first, see
+    /// [WarmRooms]
+    warm: HashMap<RoomCode, WarmRoom>,
+    /// Places random joins, started along with the room manager
+    matchmaker: Option<Addr<Matchmaker>>,
+    /// What happened in the rooms that closed lately, see [GetRoomAudit]
+    closed_trails: ClosedTrails,
+}
+
+/// What a room looked like in the process that handed it over, enough to set it up again
+#[derive(Serialize, Deserialize)]
+pub struct RoomSummary {
+    pub code: String,
+    pub kind: RoomKind,
+    pub settings: RoomSettings,
+    #[serde(default)]
+    pub metadata: RoomMetadata,
+    #[serde(default)]
+    pub password: Option<RoomPassword>,
+    /// What was left of the room's lifetime, which it carries on with in the next process
+    #[serde(default)]
+    pub lifetime: Option<RoomLifetime>,
+    /// Words the leader gave the room, which its settings only count
+    #[serde(default)]
+    pub words: Option<Arc<[Box<str>]>>,
+    pub members: Vec<MemberSummary>,
+}
+
+/// A room as exported for a blue-green cutover, which is its summary minus its members
+#[derive(Serialize, Deserialize)]
+pub struct WarmRoom {
+    pub code: String,
+    pub kind: RoomKind,
+    pub settings: RoomSettings,
+    #[serde(default)]
+    pub metadata: RoomMetadata,
+    #[serde(default)]
+    pub password: Option<RoomPassword>,
+    #[serde(default)]
+    pub lifetime: Option<RoomLifetime>,
+    #[serde(default)]
+    pub words: Option<Arc<[Box<str>]>>,
+}
+
+impl From<RoomSummary> for WarmRoom {
+    fn from(summary: RoomSummary) -> Self {
+        Self {
+            code: summary.code,
+            kind: summary.kind,
+            settings: summary.settings,
+            metadata: summary.metadata,
+            password: summary.password,
+            lifetime: summary.lifetime,
+            words: summary.words,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MemberSummary {
+    pub user: UserId,
+    pub profile: Profile,
+}
+
+/// Running totals since the server started, sampled by the [crate::watchdog::Watchdog]
+#[derive(Clone, Copy, Default)]
+pub struct RoomStats {
+    pub created: u64,
+    /// Requests to join a specific or random room, not counting players who chose to wait for a
+    /// match
+    pub joins: u64,
+    pub join_failures: u64,
+    /// Generated codes thrown away because they contained a denylisted string or were in use
+    pub code_rerolls: u64,
+    /// Lobbies short on players merged into another one, see [merge]
+    pub merged: u64,
+    pub live_rooms: usize,
+    /// Stopped rooms kept around for reuse
+    pub pooled_rooms: usize,
 }
 
 impl RoomManager {
-    pub fn new() -> Self {
-        const capacity: usize = 1 << 12;
-        let free: HashMap<RoomCode, RoomInfo> = HashMap::with_capacity(capacity);
-        let reserved: HashMap<RoomCode, RoomInfo> = HashMap::with_capacity(capacity);
-        let open: HashMap<RoomCode, RoomInfo> = HashMap::with_capacity(capacity);
+    pub fn new(denylist: Denylist, services: RoomServices, placement: ArbiterPool) -> Self {
+        const CAPACITY: usize = 1 << 12;
+        let free: HashMap<RoomCode, RoomInfo> = HashMap::with_capacity(Self::pool_limit());
+        let reserved: HashMap<RoomCode, RoomInfo> = HashMap::with_capacity(CAPACITY);
+        let open: HashMap<RoomCode, RoomInfo> = HashMap::with_capacity(CAPACITY);
         Self {
             free,
             reserved,
             open,
+            backfill: HashMap::new(),
+            aliases: HashMap::new(),
+            denylist,
+            services,
+            placement,
+            stats: Default::default(),
+            migrated: HashMap::new(),
+            warm: HashMap::new(),
+            matchmaker: None,
+            closed_trails: ClosedTrails::default(),
         }
     }
+    /// Rooms that are open, pooled ones aside
+    fn live_rooms(&self) -> usize {
+        self.reserved.len() + self.open.len() + self.backfill.len()
+    }
+    /// Room with the code, unless it is idling in the pool
+    fn live_room(&self, code: &RoomCode) -> Option<&RoomInfo> {
+        self.reserved
+            .get(code)
+            .or(self.open.get(code))
+            .or(self.backfill.get(code))
+    }
+    /// Code of the room going by the alias. Codes longer than the ones this server generates
+    /// look like aliases, they belong to rooms handed over by a process that generated those.
+    fn unalias(&self, alias: &str) -> Option<RoomCode> {
+        self.aliases
+            .get(alias)
+            .copied()
+            .or_else(|| RoomCode::try_from(alias.as_bytes()).ok())
+    }
     fn get_free(&mut self) -> Option<(RoomCode, RoomInfo)> {
-        if let Some(code) = self.free.keys().find(|_| true).cloned() {
-            Some((code, self.free.remove(&code).unwrap()))
-        } else {
-            None
-        }
+        // Codes of rooms awaiting their members from the previous process or instance are
+        // spoken for
+        let code = *self
+            .free
+            .keys()
+            .find(|code| !self.migrated.contains_key(*code) && !self.warm.contains_key(*code))?;
+        Some((code, self.free.remove(&code).unwrap()))
+    }
+    /// Most stopped rooms to keep around. Pooled codes are handed out again, but short codes
+    /// leave little room to spare, so the pool never takes up more than a sixteenth of them.
+    fn pool_limit() -> usize {
+        let space = usize::try_from(room_code_space() / 16).unwrap_or(usize::MAX);
+        space.min(MAX_POOLED_ROOMS)
     }
-    fn release(&mut self, key: RoomCode) {
-        self.reserved.remove(&key).map(|x| self.free.insert(key, x));
+    /// Whether a live room or one awaiting its members from the previous process or instance
+    /// goes by the code
+    fn code_taken(&self, code: &RoomCode) -> bool {
+        self.reserved.contains_key(code)
+            || self.open.contains_key(code)
+            || self.backfill.contains_key(code)
+            || self.migrated.contains_key(code)
+            || self.warm.contains_key(code)
+    }
+    /// Generates a code no other room goes by, giving up after [MAX_CODE_ATTEMPTS] taken ones
+    fn new_code(&mut self) -> Option<RoomCode> {
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let (code, rerolls) = generate_room_id(&self.denylist);
+            self.stats.code_rerolls += rerolls;
+            if self.code_taken(&code) {
+                self.stats.code_rerolls += 1;
+                continue;
+            }
+            // The pooled room that went by the code is done for good
+            if let Some(room) = self.free.remove(&code) {
+                room.addr.do_send(CloseRoom);
+            }
+            return Some(code);
+        }
+        log::error!("no free room code after {MAX_CODE_ATTEMPTS} attempts");
+        None
     }
     fn create(
         &mut self,
-        leader: (TransientId, Addr<Session>),
+        leader: Joiner,
+        mut room_config: RoomConfig,
+        game_config: GameConfigOptions,
+        room_manager: Addr<Self>,
+    ) -> Result<RoomPair, JoinRoomError> {
+        if self.services.load.degraded() {
+            return Err(JoinRoomError::ServerBusy);
+        }
+        if !self
+            .services
+            .capacity
+            .room_available(self.live_rooms(), leader.class)
+        {
+            return Err(JoinRoomError::ServerBusy);
+        }
+        if room_config.lifetime.is_none() {
+            room_config.lifetime = self.services.lifetime.clone();
+        }
+        let room = match self.get_free() {
+            Some((code, room)) => self.reuse(code, room, leader, room_config, game_config),
+            None => {
+                let code = self.new_code().ok_or(JoinRoomError::NoCodeAvailable)?;
+                self.spawn(code, leader, room_config, game_config, room_manager)
+            }
+        };
+        self.stats.created += 1;
+        Ok(room)
+    }
+    /// Hands a room from the pool to a new leader, keeping its code and actor
+    fn reuse(
+        &mut self,
+        code: RoomCode,
+        room: RoomInfo,
+        leader: Joiner,
+        room_config: RoomConfig,
+        game_config: GameConfigOptions,
+    ) -> RoomPair {
+        let listing = Listing::new(&room_config, &game_config);
+        let rating = self.services.ratings.get(&leader.user);
+        let latency = leader.rtt;
+        self.placement.occupy(room.arbiter);
+        room.addr.do_send(ResetRoom {
+            leader,
+            room_config,
+            game_config,
+        });
+        let addr = room.addr;
+        let room = RoomInfo::new(addr.clone(), room.arbiter, listing, rating, latency);
+        self.reserved.insert(code, room);
+        RoomPair { code, addr }
+    }
+    fn spawn(
+        &mut self,
+        code: RoomCode,
+        leader: Joiner,
         room_config: RoomConfig,
+        game_config: GameConfigOptions,
         room_manager: Addr<Self>,
     ) -> RoomPair {
-        let code = generate_room_id();
-        let addr = Room::new(code, room_manager, leader, room_config).start();
-        let room = RoomInfo::new(addr.clone());
+        let services = self.services.clone();
+        let (arbiter, handle) = self.placement.place();
+        let listing = Listing::new(&room_config, &game_config);
+        let rating = self.services.ratings.get(&leader.user);
+        let latency = leader.rtt;
+        let addr = Room::start_in_arbiter(&handle, move |_| {
+            Room::new(
+                code,
+                room_manager,
+                leader,
+                room_config,
+                game_config,
+                services,
+            )
+        });
+        let room = RoomInfo::new(addr.clone(), arbiter, listing, rating, latency);
         self.reserved.insert(code, room);
         RoomPair { code, addr }
     }
@@ -92,6 +757,12 @@ impl RoomManager {
 
 impl Actor for RoomManager {
     type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let ratings = Arc::clone(&self.services.ratings);
+        let load = Arc::clone(&self.services.load);
+        self.matchmaker = Some(Matchmaker::new(ctx.address(), ratings, load).start());
+        ctx.run_interval(merge::MERGE_CHECK_INTERVAL, |act, _| act.merge_rooms());
+    }
 }
 
 #[derive(MessageResponse)]
@@ -102,62 +773,127 @@ pub struct RoomPair {
 
 type SessionPair = (TransientId, Addr<Session>);
 
+/// Opens a new room led by the client, see [RoomConfig::requested]
 #[derive(Message)]
-#[rtype(result = "RoomPair")]
-struct CreateRoom {
-    leader: (TransientId, Addr<Session>),
-    room_config: RoomConfig,
+#[rtype(result = "Result<RoomPair, JoinRoomError>")]
+pub struct CreateRoom {
+    pub leader: Joiner,
+    pub room_config: RoomConfig,
+    pub game_config: GameConfigOptions,
 }
 
 impl Handler<CreateRoom> for RoomManager {
-    type Result = RoomPair;
+    type Result = Result<RoomPair, JoinRoomError>;
     fn handle(&mut self, msg: CreateRoom, ctx: &mut Self::Context) -> Self::Result {
         let CreateRoom {
             leader,
             room_config,
+            game_config,
         } = msg;
-        self.create(leader, room_config, ctx.address())
+        self.create(leader, room_config, game_config, ctx.address())
     }
 }
 
 #[derive(Message)]
 #[rtype(result = "Result<RoomPair, JoinRoomError>")]
 pub struct JoinRoom {
-    pub session: SessionPair,
-    pub code: Option<RoomCode>,
+    pub joiner: Joiner,
+    /// Random joins look for a room that fits the preferences instead
+    pub target: Option<RoomRef>,
+    pub preferences: MatchPreferences,
 }
 
 impl Handler<JoinRoom> for RoomManager {
     type Result = ResponseActFuture<Self, Result<RoomPair, JoinRoomError>>;
     fn handle(&mut self, msg: JoinRoom, ctx: &mut Self::Context) -> Self::Result {
+        Box::pin(self.join(msg, ctx).map(|res, act, _| {
+            match res {
+                Err(JoinRoomError::NoMatch) => {}
+                Err(_) => {
+                    act.stats.joins += 1;
+                    act.stats.join_failures += 1;
+                }
+                Ok(_) => act.stats.joins += 1,
+            }
+            res
+        }))
+    }
+}
+
+impl RoomManager {
+    fn join(
+        &mut self,
+        msg: JoinRoom,
+        ctx: &mut Context<Self>,
+    ) -> ResponseActFuture<Self, Result<RoomPair, JoinRoomError>> {
+        let code = match msg.target {
+            Some(RoomRef::Code(code)) => Some(code),
+            Some(RoomRef::Alias(alias)) => match self.unalias(&alias) {
+                Some(code) => Some(code),
+                None => return Box::pin(actix::fut::ready(Err(JoinRoomError::RoomNotFound))),
+            },
+            Some(RoomRef::Invite(token)) => return self.join_invited(msg.joiner, &token),
+            Some(RoomRef::Practice(seed)) => {
+                let game_config = GameConfigOptions {
+                    seed: Some(seed),
+                    ..Default::default()
+                };
+                let room = self.create(
+                    msg.joiner,
+                    RoomConfig::practice(),
+                    game_config,
+                    ctx.address(),
+                );
+                return Box::pin(actix::fut::ready(room));
+            }
+            None => None,
+        };
         /* If the message contains a room code, then we look for that room in both private and
          * public room pools. */
-        if let Some(code) = msg.code {
+        if let Some(code) = code {
             if let Some(RoomInfo {
                 addr,
                 playing,
                 full,
+                listing,
                 ..
             }) = self.reserved.get(&code)
             {
-                if *playing {
+                // The room decides for itself whether to queue the player up
+                if listing.waiting_queue && (*playing || *full) {
+                    Box::pin(
+                        addr.send(AddPlayer(msg.joiner))
+                            .into_actor(self)
+                            .then(|res, _, _| {
+                                actix::fut::ready(
+                                    res.map_or(Err(JoinRoomError::InternalServerError), |res| {
+                                        res.map(|(code, addr)| RoomPair { addr, code })
+                                    }),
+                                )
+                            }),
+                    )
+                } else if *playing {
                     Box::pin(actix::fut::ready(Err(JoinRoomError::GameInProgress)).into_actor(self))
                 } else if *full {
                     Box::pin(actix::fut::ready(Err(JoinRoomError::RoomFull)).into_actor(self))
                 } else {
-                    Box::pin(addr.send(AddPlayer(msg.session)).into_actor(self).then(
-                        |res, _, _| {
-                            actix::fut::ready(
-                                res.map_or(Err(JoinRoomError::InternalServerError), |res| {
-                                    res.map(|(code, addr)| RoomPair { addr, code })
-                                }),
-                            )
-                        },
-                    ))
+                    Box::pin(
+                        addr.send(AddPlayer(msg.joiner))
+                            .into_actor(self)
+                            .then(|res, _, _| {
+                                actix::fut::ready(
+                                    res.map_or(Err(JoinRoomError::InternalServerError), |res| {
+                                        res.map(|(code, addr)| RoomPair { addr, code })
+                                    }),
+                                )
+                            }),
+                    )
                 }
-            } else if let Some(RoomInfo { addr, .. }) = self.open.get(&code) {
+            } else if let Some(RoomInfo { addr, .. }) =
+                self.open.get(&code).or(self.backfill.get(&code))
+            {
                 Box::pin(
-                    addr.send(AddPlayer(msg.session))
+                    addr.send(AddPlayer(msg.joiner))
                         .into_actor(self)
                         .then(|res, _, _| {
                             actix::fut::ready(
@@ -176,44 +912,113 @@ impl Handler<JoinRoom> for RoomManager {
                     panic!("A public room cannot be out of the matching pool unless its full or has a game running in it!");
                 }
             } else {
-                Box::pin(actix::fut::ready(Err(JoinRoomError::RoomNotFound)))
-            }
-        } else {
-            /* Otherwise, the user probably wants to join a random room.
-             * This might involve complex matchmaking algorithms which should be injected here
-             * as necessary.
-             * By default we add the player to the first open public room we can find.
-             */
-            if let Some(found) = self.open.iter().find(|_| /* match criteria */ true) {
-                Box::pin(
-                    found
-                        .1
-                        .addr
-                        .send(AddPlayer(msg.session))
-                        .into_actor(self)
-                        .then(|res, _, _| {
-                            actix::fut::ready(
-                                res.map_or(Err(JoinRoomError::InternalServerError), |res| {
-                                    res.map(|(code, addr)| RoomPair { addr, code })
-                                }),
-                            )
-                        }),
-                )
-            } else {
-                let info = Ok(self.create(msg.session, Default::default(), ctx.address()));
+                let info = self.restore(code, msg.joiner, ctx.address());
                 Box::pin(actix::fut::ready(info))
             }
+        } else {
+            // Otherwise the player wants a random room, which is up to the matchmaker
+            let Some(matchmaker) = &self.matchmaker else {
+                return Box::pin(actix::fut::ready(Err(JoinRoomError::InternalServerError)));
+            };
+            Box::pin(
+                matchmaker
+                    .send(FindMatch {
+                        joiner: msg.joiner,
+                        preferences: msg.preferences,
+                    })
+                    .into_actor(self)
+                    .map(|res, _, _| res.unwrap_or(Err(JoinRoomError::InternalServerError))),
+            )
         }
     }
 }
 
+impl RoomManager {
+    /// Sets a room handed over by the previous process up again under the same code, as soon as
+    /// the first of its members comes back. They lead the room until they hand leadership over.
+    /// Rooms warmed up from another instance are set up again by whoever joins them first, as
+    /// long as they know the password.
+    fn restore(
+        &mut self,
+        code: RoomCode,
+        joiner: Joiner,
+        room_manager: Addr<Self>,
+    ) -> Result<RoomPair, JoinRoomError> {
+        let member = self
+            .migrated
+            .get(&code)
+            .is_some_and(|room| room.members.iter().any(|member| member.user == joiner.user));
+        let room = if member {
+            self.migrated.remove(&code).unwrap().into()
+        } else {
+            let Some(room) = self.warm.get(&code) else {
+                return Err(JoinRoomError::RoomNotFound);
+            };
+            if !password::admits(room.password.as_ref(), joiner.password.as_deref()) {
+                return Err(JoinRoomError::WrongPassword);
+            }
+            self.warm.remove(&code).unwrap()
+        };
+        let (mut room_config, mut game_config) = room.settings.restore(room.kind);
+        game_config.words = room.words;
+        room_config.metadata = room.metadata;
+        room_config.password = room.password;
+        // The room carries on with whatever was left of its lifetime
+        room_config.lifetime = room.lifetime;
+        Ok(self.spawn(code, joiner, room_config, game_config, room_manager))
+    }
+    /// Invites get players into rooms that are full or private, but the room still turns them
+    /// away if the invite was revoked or already used up
+    fn join_invited(
+        &mut self,
+        joiner: Joiner,
+        token: &str,
+    ) -> ResponseActFuture<Self, Result<RoomPair, JoinRoomError>> {
+        let invite = match self.services.invites.verify(token) {
+            Ok(invite) => invite,
+            Err(TokenError::Invalid) => {
+                return Box::pin(actix::fut::ready(Err(JoinRoomError::InvalidInvite)))
+            }
+            Err(TokenError::Expired) => {
+                return Box::pin(actix::fut::ready(Err(JoinRoomError::InviteExpired)))
+            }
+        };
+        let room = self
+            .reserved
+            .get(&invite.code)
+            .or_else(|| self.open.get(&invite.code))
+            .or_else(|| self.backfill.get(&invite.code));
+        let Some(RoomInfo { addr, .. }) = room else {
+            return Box::pin(actix::fut::ready(Err(JoinRoomError::RoomNotFound)));
+        };
+        Box::pin(
+            addr.send(AddInvitedPlayer {
+                joiner,
+                nonce: invite.nonce,
+            })
+            .into_actor(self)
+            .then(|res, _, _| {
+                actix::fut::ready(res.map_or(Err(JoinRoomError::InternalServerError), |res| {
+                    res.map(|(code, addr)| RoomPair { addr, code })
+                }))
+            }),
+        )
+    }
+}
+
 pub enum RoomUnavailablityReason {
     Full,
     GameStarted,
+    Locked,
 }
 
+/// Rooms never report themselves as [Availability::Available] or [Availability::Backfill] while
+/// they are locked, so either of them also lifts the lock
 pub enum Availability {
+    /// No game is running and the room takes in players again
     Available,
+    /// A game is running but the mode wants more players, so random joiners can be routed in
+    Backfill,
     Unavailable(RoomUnavailablityReason),
 }
 
@@ -230,56 +1035,499 @@ impl Handler<UpdateRoomMatchAvailability> for RoomManager {
         let code = msg.code;
         match msg.availability {
             Availability::Available => {
-                if let Some(room) = self.reserved.remove(&code) {
-                    if !room.full && !room.playing {
+                if let Some(mut room) = self.backfill.remove(&code) {
+                    room.playing = false;
+                    self.open.insert(code, room);
+                } else if let Some(mut room) = self.reserved.remove(&code) {
+                    room.playing = false;
+                    room.locked = false;
+                    if !room.full && room.listing.public {
                         self.open.insert(code, room);
                     } else {
                         self.reserved.insert(code, room);
                     }
                 }
             }
+            Availability::Backfill => {
+                let room = self
+                    .open
+                    .remove(&code)
+                    .or_else(|| self.reserved.remove(&code));
+                if let Some(mut room) = room {
+                    room.playing = true;
+                    room.full = false;
+                    room.locked = false;
+                    self.backfill.insert(code, room);
+                }
+            }
             Availability::Unavailable(reason) => {
-                if let Some(mut room) = self.open.remove(&code) {
-                    match reason {
-                        RoomUnavailablityReason::Full => room.full = true,
-                        RoomUnavailablityReason::GameStarted => room.playing = true,
-                    }
+                if let Some(mut room) = self
+                    .open
+                    .remove(&code)
+                    .or_else(|| self.backfill.remove(&code))
+                {
+                    room.mark_unavailable(reason);
                     self.reserved.insert(code, room);
                 } else if let Some(room) = self.reserved.get_mut(&code) {
-                    match reason {
-                        RoomUnavailablityReason::Full => room.full = true,
-                        RoomUnavailablityReason::GameStarted => room.playing = true,
-                    }
+                    room.mark_unavailable(reason);
                 }
             }
         }
     }
 }
 
-/// Rooms notify the server of their stopping so that the server can remove said room from its
+/// Sent by rooms whenever their leader changes settings that affect matchmaking
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RoomSettingsChanged {
+    pub code: RoomCode,
+    pub full: bool,
+    pub spectators_full: bool,
+    pub listing: Listing,
+}
+
+impl Handler<RoomSettingsChanged> for RoomManager {
+    type Result = ();
+    fn handle(&mut self, msg: RoomSettingsChanged, _: &mut Self::Context) -> Self::Result {
+        let code = msg.code;
+        let room = self
+            .open
+            .remove(&code)
+            .or_else(|| self.reserved.remove(&code));
+        let Some(mut room) = room else {
+            return;
+        };
+        room.full = msg.full;
+        room.spectators_full = msg.spectators_full;
+        room.listing = msg.listing;
+        room.update_understaffed();
+        // Settings only change in between games, so the room is either open or reserved
+        if room.listing.public && !room.full && !room.playing && !room.locked {
+            self.open.insert(code, room);
+        } else {
+            self.reserved.insert(code, room);
+        }
+    }
+}
+
+/// Sent by rooms whenever spectators come or go. Spectator slots are tracked apart from the
+/// seats, so this never takes a room in or out of matchmaking.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SpectatorAvailability {
+    pub code: RoomCode,
+    pub full: bool,
+}
+
+impl Handler<SpectatorAvailability> for RoomManager {
+    type Result = ();
+    fn handle(&mut self, msg: SpectatorAvailability, _: &mut Self::Context) -> Self::Result {
+        let room = self
+            .open
+            .get_mut(&msg.code)
+            .or(self.reserved.get_mut(&msg.code))
+            .or(self.backfill.get_mut(&msg.code));
+        if let Some(room) = room {
+            room.spectators_full = msg.full;
+        }
+    }
+}
+
+/// Lets someone watch a room without taking a seat in it. Rooms can be watched while a game is
+/// running or every seat is taken, as long as they have a spectator slot left.
+#[derive(Message)]
+#[rtype(result = "Result<RoomPair, JoinRoomError>")]
+pub struct SpectateRoom {
+    pub joiner: Joiner,
+    pub target: RoomRef,
+}
+
+impl Handler<SpectateRoom> for RoomManager {
+    type Result = ResponseActFuture<Self, Result<RoomPair, JoinRoomError>>;
+    fn handle(&mut self, msg: SpectateRoom, _: &mut Self::Context) -> Self::Result {
+        let code = match msg.target {
+            RoomRef::Code(code) => Some(code),
+            RoomRef::Alias(alias) => self.unalias(&alias),
+            RoomRef::Invite(_) | RoomRef::Practice(_) => None,
+        };
+        let Some(room) = code.and_then(|code| self.live_room(&code)) else {
+            return Box::pin(actix::fut::ready(Err(JoinRoomError::RoomNotFound)));
+        };
+        if room.spectators_full {
+            return Box::pin(actix::fut::ready(Err(JoinRoomError::SpectatorsFull)));
+        }
+        Box::pin(
+            room.addr
+                .send(AddSpectator(msg.joiner))
+                .into_actor(self)
+                .map(|res, _, _| {
+                    res.map_or(Err(JoinRoomError::InternalServerError), |res| {
+                        res.map(|(code, addr)| RoomPair { addr, code })
+                    })
+                }),
+        )
+    }
+}
+
+/// Admin request to watch a room as an invisible observer, see [observer]
+#[derive(Message)]
+#[rtype(result = "Result<(), ObserveError>")]
+pub struct ObserveRoom {
+    pub code: RoomCode,
+    pub observer: Recipient<Observed>,
+}
+
+impl Handler<ObserveRoom> for RoomManager {
+    type Result = ResponseFuture<Result<(), ObserveError>>;
+    fn handle(&mut self, msg: ObserveRoom, _: &mut Self::Context) -> Self::Result {
+        let Some(room) = self.live_room(&msg.code) else {
+            return Box::pin(async { Err(ObserveError::RoomNotFound) });
+        };
+        let request = room.addr.send(AddObserver(msg.observer));
+        Box::pin(async move {
+            request
+                .await
+                .unwrap_or(Err(ObserveError::InternalServerError))
+        })
+    }
+}
+
+/// Admin request for what happened in a room lately, see [audit]. Rooms that closed a while ago
+/// are looked up in [ClosedTrails]. [None] if no room had the code.
+#[derive(Message)]
+#[rtype(result = "Option<Trail>")]
+pub struct GetRoomAudit(pub RoomCode);
+
+impl Handler<GetRoomAudit> for RoomManager {
+    type Result = ResponseFuture<Option<Trail>>;
+    fn handle(&mut self, msg: GetRoomAudit, _: &mut Self::Context) -> Self::Result {
+        let Some(room) = self.live_room(&msg.0) else {
+            let trail = self.closed_trails.get(&msg.0);
+            return Box::pin(async { trail });
+        };
+        let request = room.addr.send(GetAudit);
+        Box::pin(async move { request.await.ok().map(Trail::Entries) })
+    }
+}
+
+/// Whether a room with the code lives on this server
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct RoomExists(pub RoomCode);
+
+impl Handler<RoomExists> for RoomManager {
+    type Result = bool;
+    fn handle(&mut self, msg: RoomExists, _: &mut Self::Context) -> Self::Result {
+        self.live_room(&msg.0).is_some() || self.warm.contains_key(&msg.0)
+    }
+}
+
+/// Stops looking for a random room for the player
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopMatching(pub TransientId);
+
+impl Handler<StopMatching> for RoomManager {
+    type Result = ();
+    fn handle(&mut self, msg: StopMatching, _: &mut Self::Context) -> Self::Result {
+        if let Some(matchmaker) = &self.matchmaker {
+            matchmaker.do_send(CancelMatch(msg.0));
+        }
+    }
+}
+
+/// Sent by rooms whenever players join or leave
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RoomOccupancy {
+    pub code: RoomCode,
+    pub players: usize,
+    /// Average rating of the players
+    pub rating: f64,
+    /// Average round trip time of the players, if any of them was measured
+    pub latency: Option<Duration>,
+}
+
+impl Handler<RoomOccupancy> for RoomManager {
+    type Result = ();
+    fn handle(&mut self, msg: RoomOccupancy, _: &mut Self::Context) -> Self::Result {
+        let room = self
+            .open
+            .get_mut(&msg.code)
+            .or(self.reserved.get_mut(&msg.code))
+            .or(self.backfill.get_mut(&msg.code));
+        if let Some(room) = room {
+            room.players = msg.players;
+            room.rating = msg.rating;
+            room.latency = msg.latency;
+            room.update_understaffed();
+        }
+    }
+}
+
+/// Sent by rooms whenever their leader changes the room's metadata, which can happen at any
+/// time unlike the rest of its settings
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RoomMetadataChanged {
+    pub code: RoomCode,
+    pub metadata: RoomMetadata,
+}
+
+impl Handler<RoomMetadataChanged> for RoomManager {
+    type Result = ();
+    fn handle(&mut self, msg: RoomMetadataChanged, _: &mut Self::Context) -> Self::Result {
+        let room = self
+            .open
+            .get_mut(&msg.code)
+            .or(self.reserved.get_mut(&msg.code))
+            .or(self.backfill.get_mut(&msg.code));
+        if let Some(room) = room {
+            room.listing.metadata = msg.metadata;
+        }
+    }
+}
+
+/// Rooms notify the server of their closing so that the server can remove said room from its
 /// matching queue. Rooms are expected to reset their settings before sending this message.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct OnRoomClosed(pub RoomCode);
+pub struct OnRoomClosed {
+    pub code: RoomCode,
+    /// The room's actor keeps running and can be reused, rather than stopping
+    pub idle: bool,
+    /// What happened in the room, kept for moderators, see [ClosedTrails]
+    pub audit: Vec<AuditRecord>,
+    /// The audit trail is sealed before it is kept, see [RoomConfig::privacy_mode]
+    pub privacy_mode: bool,
+}
 
 impl Handler<OnRoomClosed> for RoomManager {
     type Result = ();
     fn handle(&mut self, msg: OnRoomClosed, _: &mut Self::Context) -> Self::Result {
-        if let Some(mut room) = self.open.remove(&msg.0).or(self.reserved.remove(&msg.0)) {
+        self.aliases.retain(|_, code| *code != msg.code);
+        let trail = if msg.privacy_mode {
+            let entries = serde_json::to_vec(&msg.audit).unwrap_or_default();
+            Trail::Sealed(self.services.archive.seal(&msg.code, &entries).into())
+        } else {
+            Trail::Entries(msg.audit)
+        };
+        self.closed_trails.keep(msg.code, trail);
+        let room = self
+            .open
+            .remove(&msg.code)
+            .or_else(|| self.reserved.remove(&msg.code))
+            .or_else(|| self.backfill.remove(&msg.code));
+        if let Some(mut room) = room {
+            self.placement.release(room.arbiter);
             room.reset();
-            // Push room onto list of available rooms for pooling
-            self.free.insert(msg.0, room);
+            if !msg.idle {
+                return;
+            }
+            // Push room onto list of available rooms for pooling, or let it go if the pool is
+            // full
+            if self.free.len() < Self::pool_limit() {
+                self.free.insert(msg.code, room);
+            } else {
+                room.addr.do_send(CloseRoom);
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub enum AliasError {
+    NotInRoom,
+    NotLeader,
+    InvalidAlias,
+    Blocked,
+    Taken,
+    InternalServerError,
+}
+
+/// Registers a vanity alias for a room, replacing any alias the room had before.
+/// Sent by rooms on behalf of their leader.
+#[derive(Message)]
+#[rtype(result = "Result<Box<str>, AliasError>")]
+pub struct RegisterAlias {
+    pub code: RoomCode,
+    pub alias: Box<str>,
+}
+
+impl Handler<RegisterAlias> for RoomManager {
+    type Result = Result<Box<str>, AliasError>;
+    fn handle(&mut self, msg: RegisterAlias, _: &mut Self::Context) -> Self::Result {
+        let alias = normalize_alias(&msg.alias).ok_or(AliasError::InvalidAlias)?;
+        if self.denylist.is_blocked(alias.as_bytes()) {
+            return Err(AliasError::Blocked);
+        }
+        match self.aliases.get(&alias) {
+            Some(code) if *code == msg.code => return Ok(alias),
+            Some(_) => return Err(AliasError::Taken),
+            None => {}
+        }
+        // Handed over rooms might go by a code that looks like the alias, see
+        // [RoomManager::unalias]
+        let taken = RoomCode::try_from(alias.as_bytes())
+            .is_ok_and(|code| code != msg.code && self.live_room(&code).is_some());
+        if taken {
+            return Err(AliasError::Taken);
+        }
+        self.aliases.retain(|_, code| *code != msg.code);
+        self.aliases.insert(alias.clone(), msg.code);
+        Ok(alias)
+    }
+}
+
+/// Number of rooms running on each arbiter of the placement pool
+#[derive(Message)]
+#[rtype(result = "PlacementMetrics")]
+pub struct GetPlacementMetrics;
+
+impl Handler<GetPlacementMetrics> for RoomManager {
+    type Result = MessageResult<GetPlacementMetrics>;
+    fn handle(&mut self, _: GetPlacementMetrics, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.placement.metrics())
+    }
+}
+
+/// Every live room, for the handover to the next process
+#[derive(Message)]
+#[rtype(result = "Vec<Addr<Room>>")]
+pub struct ExportRooms;
+
+impl Handler<ExportRooms> for RoomManager {
+    type Result = MessageResult<ExportRooms>;
+    fn handle(&mut self, _: ExportRooms, _: &mut Self::Context) -> Self::Result {
+        let rooms = self.reserved.values().chain(self.open.values());
+        let rooms = rooms.chain(self.backfill.values());
+        MessageResult(rooms.map(|room| room.addr.clone()).collect())
+    }
+}
+
+/// Asks every live room the same thing, see [ExportRooms]
+pub async fn ask_rooms<M>(
+    room_manager: &Addr<RoomManager>,
+    msg: M,
+) -> Result<Vec<M::Result>, MailboxError>
+where
+    M: Message + Clone + Send + 'static,
+    M::Result: Send,
+    Room: Handler<M>,
+{
+    let rooms = room_manager.send(ExportRooms).await?;
+    // Every room is asked at once, they answer from their own arbiters
+    let requests = rooms
+        .iter()
+        .map(|room| room.send(msg.clone()))
+        .collect::<Vec<_>>();
+    let mut answers = Vec::with_capacity(requests.len());
+    for request in requests {
+        // Rooms that stopped in the meantime are left out
+        if let Ok(answer) = request.await {
+            answers.push(answer);
+        }
+    }
+    Ok(answers)
+}
+
+/// Rooms handed over by the previous process. They are set up again once one of their members
+/// comes back, and forgotten about if nobody does within [RESUME_WINDOW].
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ImportRooms(pub Vec<RoomSummary>);
+
+impl Handler<ImportRooms> for RoomManager {
+    type Result = ();
+    fn handle(&mut self, msg: ImportRooms, ctx: &mut Self::Context) -> Self::Result {
+        for room in msg.0 {
+            match room.code.as_bytes().try_into() {
+                Ok(code) => {
+                    self.migrated.insert(code, room);
+                }
+                Err(_) => log::error!("handed over room has an invalid code {}", room.code),
+            }
         }
+        ctx.run_later(RESUME_WINDOW, |act, _| act.migrated.clear());
     }
 }
 
-fn generate_room_id() -> RoomCode {
-    const CHARSET: &'static [u8] = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".as_bytes();
-    let mut arr = [0; ROOM_CODE_LENGTH];
+/// Rooms exported by the instance being cut over from, see
+/// [crate::server::handover::export_rooms]. Their codes are held back so that join links keep
+/// resolving until players move over, and forgotten about if nobody joins within
+/// [WARM_WINDOW]. Codes already in use here are skipped. Returns how many rooms were warmed up.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct WarmRooms(pub Vec<WarmRoom>);
+
+impl Handler<WarmRooms> for RoomManager {
+    type Result = usize;
+    fn handle(&mut self, msg: WarmRooms, ctx: &mut Self::Context) -> Self::Result {
+        let mut warmed = Vec::with_capacity(msg.0.len());
+        for room in msg.0 {
+            let Ok(code) = RoomCode::try_from(room.code.as_bytes()) else {
+                log::error!("cannot warm up room with an invalid code {}", room.code);
+                continue;
+            };
+            if self.code_taken(&code) {
+                log::warn!("not warming up room {}, its code is in use", room.code);
+                continue;
+            }
+            // The pooled room that went by the code is done for good
+            if let Some(room) = self.free.remove(&code) {
+                room.addr.do_send(CloseRoom);
+            }
+            self.warm.insert(code, room);
+            warmed.push(code);
+        }
+        let count = warmed.len();
+        ctx.run_later(WARM_WINDOW, move |act, _| {
+            for code in warmed {
+                act.warm.remove(&code);
+            }
+        });
+        count
+    }
+}
+
+/// Counters the watchdog derives its rates from
+#[derive(Message)]
+#[rtype(result = "RoomStats")]
+pub struct GetRoomStats;
+
+impl Handler<GetRoomStats> for RoomManager {
+    type Result = MessageResult<GetRoomStats>;
+    fn handle(&mut self, _: GetRoomStats, _: &mut Self::Context) -> Self::Result {
+        MessageResult(RoomStats {
+            live_rooms: self.live_rooms(),
+            pooled_rooms: self.free.len(),
+            ..self.stats
+        })
+    }
+}
+
+/// Generates a random room code, prefixed with the region of the server if it has one, rerolling
+/// until it contains nothing from the denylist. Also returns the number of rerolls it took.
+fn generate_room_id(denylist: &Denylist) -> (RoomCode, u64) {
+    let mut arr = [0; MAX_ROOM_CODE_LENGTH];
+    let arr = &mut arr[..room_code_length()];
+    let prefix = match regions() {
+        Some(regions) => {
+            arr[0] = regions.local();
+            1
+        }
+        None => 0,
+    };
     let mut rng = Rng::new();
-    for i in 0..ROOM_CODE_LENGTH {
-        let r = rng.usize(0..CHARSET.len());
-        arr[i] = CHARSET[r];
+    let mut rerolls = 0;
+    loop {
+        for x in arr[prefix..].iter_mut() {
+            *x = ROOM_CODE_CHARSET[rng.usize(0..ROOM_CODE_CHARSET.len())];
+        }
+        if !denylist.is_blocked(arr) {
+            let code = RoomCode::try_from(&*arr).expect("length is within bounds");
+            return (code, rerolls);
+        }
+        rerolls += 1;
     }
-    arr
 }
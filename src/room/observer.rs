@@ -0,0 +1,212 @@
+//! Admins can attach to any room as invisible observers to debug it live, for instance when
+//! players report a stuck game. Observers are not members: they are left out of the roster and
+//! the room's limits, and the players are never told about them. On top of everything the room
+//! broadcasts, they see what it sends to single members and internal events that never reach
+//! the players at all.
+
+use actix::{Message, Recipient};
+use bytestring::ByteString;
+use serde::Serialize;
+use serde_json::value::RawValue;
+
+use super::metadata::RoomMetadata;
+use super::state::RoomState;
+use crate::game::validation::InputError;
+use crate::game::Input;
+use crate::session::message::{OutgoingMessage, RemoveReason, RosterEntry};
+use crate::session::TransientId;
+
+/// Most observers attached to a single room at once
+const MAX_OBSERVERS: usize = 4;
+
+#[derive(Serialize, Clone, Debug)]
+pub enum ObserveError {
+    RoomNotFound,
+    TooManyObservers,
+    InternalServerError,
+}
+
+/// Delivered to an observer for every [Observation], serialized already
+#[derive(Message)]
+#[rtype(result = "()")]
+pub enum Observed {
+    Event(ByteString),
+    /// The room closed, nothing more is coming
+    Closed,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", content = "data")]
+pub enum Observation<'a> {
+    /// Sent first, what the room looked like as the observer attached
+    Snapshot {
+        state: RoomState,
+        leader: TransientId,
+        roster: Vec<RosterEntry>,
+        spectators: usize,
+        queued: usize,
+        metadata: &'a RoomMetadata,
+        /// Number of the latest broadcast
+        seq: u64,
+    },
+    /// A message sent to every member, exactly as they got it
+    Broadcast(&'a RawValue),
+    /// A message sent to a single member
+    Direct {
+        to: TransientId,
+        message: &'a OutgoingMessage,
+    },
+    /// Gameplay input from a player, along with the reason the game turned it down if it did
+    Input {
+        player: TransientId,
+        input: &'a Input,
+        rejected: Option<&'a InputError>,
+    },
+    /// The turn holder ran out of time
+    TurnTimeout,
+    GameOver,
+    Removed {
+        player: TransientId,
+        reason: RemoveReason,
+    },
+}
+
+/// Observers attached to a room
+#[derive(Default)]
+pub struct Observers(Vec<Recipient<Observed>>);
+
+impl Observers {
+    /// Attaches the observer, sending it the snapshot first
+    pub fn attach(
+        &mut self,
+        observer: Recipient<Observed>,
+        snapshot: Observation,
+    ) -> Result<(), ObserveError> {
+        self.0.retain(Recipient::connected);
+        if self.0.len() >= MAX_OBSERVERS {
+            return Err(ObserveError::TooManyObservers);
+        }
+        let frame = serialize(&snapshot).ok_or(ObserveError::InternalServerError)?;
+        observer.do_send(Observed::Event(frame));
+        self.0.push(observer);
+        Ok(())
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn send(&self, observation: Observation) {
+        if self.is_empty() {
+            return;
+        }
+        if let Some(frame) = serialize(&observation) {
+            for observer in &self.0 {
+                observer.do_send(Observed::Event(frame.clone()));
+            }
+        }
+    }
+    /// Lets every observer know that the room closed
+    pub fn detach_all(&mut self) {
+        for observer in self.0.drain(..) {
+            observer.do_send(Observed::Closed);
+        }
+    }
+}
+
+fn serialize(observation: &Observation) -> Option<ByteString> {
+    serde_json::to_string(observation)
+        .map(ByteString::from)
+        .map_err(|err| log::error!("cannot serialize observation: {err}"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::{ObserveRoom, RoomCode};
+    use crate::testing::Server;
+    use actix::{Actor, Context, Handler};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+    /// Passes on every event it observes, [None] once the room closed
+    struct Recorder(UnboundedSender<Option<Value>>);
+
+    impl Actor for Recorder {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Observed> for Recorder {
+        type Result = ();
+        fn handle(&mut self, msg: Observed, _: &mut Self::Context) -> Self::Result {
+            let event = match msg {
+                Observed::Event(frame) => Some(serde_json::from_str(&frame).unwrap()),
+                Observed::Closed => None,
+            };
+            let _ = self.0.send(event);
+        }
+    }
+
+    #[actix::test]
+    async fn observers_follow_the_room_until_it_closes() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let text = created["data"]["data"].as_str().unwrap().to_owned();
+        let code = RoomCode::try_from(text.as_bytes()).unwrap();
+        let attach = || {
+            let (sender, events) = unbounded_channel();
+            let observer = Recorder(sender).start().recipient();
+            (
+                server.room_manager.send(ObserveRoom { code, observer }),
+                events,
+            )
+        };
+        let (attached, mut events) = attach();
+        attached.await.unwrap().unwrap();
+        for _ in 1..MAX_OBSERVERS {
+            attach().0.await.unwrap().unwrap();
+        }
+        let refused = attach().0.await.unwrap();
+        assert!(matches!(refused, Err(ObserveError::TooManyObservers)));
+        let unknown = RoomCode::try_from(&b"ZZZZZZZZ"[..code.len()]).unwrap();
+        let (sender, _) = unbounded_channel();
+        let observer = Recorder(sender).start().recipient();
+        let missing = server.room_manager.send(ObserveRoom {
+            code: unknown,
+            observer,
+        });
+        assert!(matches!(
+            missing.await.unwrap(),
+            Err(ObserveError::RoomNotFound)
+        ));
+
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": text })).await;
+        // The players never hear of the observers
+        let joined = ben.expect("JoinRoomResult").await;
+        assert!(!joined.to_string().contains("Observ"));
+        ann.send(json!({ "kind": "LeaveRoom" })).await;
+        ben.send(json!({ "kind": "LeaveRoom" })).await;
+
+        let mut kinds = Vec::new();
+        let timeout = Duration::from_secs(2);
+        while let Some(event) = actix::clock::timeout(timeout, events.recv())
+            .await
+            .unwrap()
+            .unwrap()
+        {
+            let kind = &event["kind"];
+            kinds.push(match &event["data"]["kind"] {
+                Value::String(inner) if kind == "Broadcast" => format!("Broadcast {inner}"),
+                _ => kind.as_str().unwrap().to_owned(),
+            });
+        }
+        assert_eq!(kinds[0], "Snapshot");
+        assert!(kinds.contains(&"Broadcast PlayerJoined".to_owned()));
+        assert!(kinds.iter().any(|x| x == "Removed"));
+    }
+}
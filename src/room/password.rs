@@ -0,0 +1,100 @@
+//! Passwords keeping rooms to the players told about them. Rooms only hold on to a salted digest
+//! of their password, so that neither the summaries handed over to the next process nor the
+//! rooms exported for a cutover carry it in the clear.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+
+const SALT_LENGTH: usize = 16;
+
+type Digest = Hmac<Sha256>;
+
+/// Salted digest of the password of a room, serialized as the salt and the digest in base64,
+/// separated by a dot
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RoomPassword {
+    salt: [u8; SALT_LENGTH],
+    digest: Box<[u8]>,
+}
+
+impl RoomPassword {
+    /// Digest of the password with whitespace around it trimmed, [None] if that leaves nothing
+    pub fn new(password: &str) -> Option<Self> {
+        let password = password.trim();
+        if password.is_empty() {
+            return None;
+        }
+        let salt: [u8; SALT_LENGTH] = rand::random();
+        let digest = digest(&salt, password).finalize().into_bytes();
+        Some(Self {
+            salt,
+            digest: digest.as_slice().into(),
+        })
+    }
+    /// Whether the password a player gave is this one, trimmed the same way. Takes as long
+    /// however much of it matches.
+    pub fn matches(&self, password: &str) -> bool {
+        digest(&self.salt, password.trim())
+            .verify_slice(&self.digest)
+            .is_ok()
+    }
+}
+
+/// Whether a player who gave the password, if any, gets into a room behind the one given
+pub fn admits(password: Option<&RoomPassword>, given: Option<&str>) -> bool {
+    password.is_none_or(|password| given.is_some_and(|given| password.matches(given)))
+}
+
+fn digest(salt: &[u8], password: &str) -> Digest {
+    let mut digest = Digest::new_from_slice(salt).expect("HMAC takes keys of any length");
+    digest.update(password.as_bytes());
+    digest
+}
+
+impl Serialize for RoomPassword {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let salt = URL_SAFE_NO_PAD.encode(self.salt);
+        let digest = URL_SAFE_NO_PAD.encode(&self.digest);
+        serializer.serialize_str(&format!("{salt}.{digest}"))
+    }
+}
+
+impl<'de> Deserialize<'de> for RoomPassword {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let invalid = || de::Error::custom("malformed room password digest");
+        let (salt, digest) = text.split_once('.').ok_or_else(invalid)?;
+        let salt = URL_SAFE_NO_PAD.decode(salt).map_err(|_| invalid())?;
+        let digest = URL_SAFE_NO_PAD.decode(digest).map_err(|_| invalid())?;
+        Ok(Self {
+            salt: salt.try_into().map_err(|_| invalid())?,
+            digest: digest.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_digest_is_kept_and_passwords_are_trimmed() {
+        assert_eq!(RoomPassword::new("  "), None);
+        let password = RoomPassword::new(" hunter2 ").unwrap();
+        assert!(password.matches("hunter2"));
+        assert!(password.matches("hunter2\n"));
+        assert!(!password.matches("hunter"));
+        let exported = serde_json::to_string(&password).unwrap();
+        assert!(!exported.contains("hunter2"));
+        let imported: RoomPassword = serde_json::from_str(&exported).unwrap();
+        assert!(imported.matches("hunter2"));
+        // Same password, different salt
+        assert_ne!(RoomPassword::new("hunter2").unwrap(), password);
+        assert!(serde_json::from_str::<RoomPassword>(r#""hunter2""#).is_err());
+        assert!(admits(None, None));
+        assert!(!admits(Some(&password), None));
+        assert!(admits(Some(&password), Some("hunter2")));
+    }
+}
@@ -0,0 +1,94 @@
+use actix::Arbiter;
+use actix::ArbiterHandle;
+use serde::Serialize;
+
+/// Environment variable setting the number of arbiters rooms are spread across
+const WORKERS_VAR: &str = "ROOM_WORKERS";
+/// Environment variable selecting the placement strategy (`round_robin` or `least_loaded`)
+const STRATEGY_VAR: &str = "ROOM_PLACEMENT";
+
+#[derive(Clone, Copy, Serialize, Debug)]
+pub enum PlacementStrategy {
+    RoundRobin,
+    LeastLoaded,
+}
+
+/// Rooms currently running on each arbiter of the pool
+#[derive(Serialize)]
+pub struct PlacementMetrics {
+    pub strategy: PlacementStrategy,
+    pub rooms_per_arbiter: Vec<usize>,
+}
+
+/// Pool of arbiters that new [super::actor::Room]s get distributed across, so that rooms make
+/// use of every core instead of all running on the system arbiter.
+pub struct ArbiterPool {
+    /// Each arbiter along with the number of rooms placed on it
+    arbiters: Vec<(ArbiterHandle, usize)>,
+    strategy: PlacementStrategy,
+    next: usize,
+}
+
+impl ArbiterPool {
+    pub fn new(workers: usize, strategy: PlacementStrategy) -> Self {
+        let arbiters = (0..workers.max(1))
+            .map(|_| (Arbiter::new().handle(), 0))
+            .collect();
+        Self {
+            arbiters,
+            strategy,
+            next: 0,
+        }
+    }
+    /// One arbiter per available core by default
+    pub fn from_env() -> Self {
+        let workers = std::env::var(WORKERS_VAR)
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()));
+        let strategy = match std::env::var(STRATEGY_VAR).as_deref() {
+            Ok("least_loaded") => PlacementStrategy::LeastLoaded,
+            _ => PlacementStrategy::RoundRobin,
+        };
+        log::info!("placing rooms across {workers} arbiters ({strategy:?})");
+        Self::new(workers, strategy)
+    }
+    /// Picks the arbiter the next room should run on, returning its index in the pool (to be
+    /// handed back to [ArbiterPool::release] once the room closes) along with its handle.
+    pub fn place(&mut self) -> (usize, ArbiterHandle) {
+        let idx = match self.strategy {
+            PlacementStrategy::RoundRobin => {
+                let idx = self.next;
+                self.next = (self.next + 1) % self.arbiters.len();
+                idx
+            }
+            PlacementStrategy::LeastLoaded => self
+                .arbiters
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, rooms))| *rooms)
+                .map(|(idx, _)| idx)
+                .expect("pool cannot be empty!"),
+        };
+        let (handle, rooms) = &mut self.arbiters[idx];
+        *rooms += 1;
+        (idx, handle.clone())
+    }
+    /// Counts a pooled room that is taken back into use on the arbiter it already runs on
+    pub fn occupy(&mut self, idx: usize) {
+        if let Some((_, rooms)) = self.arbiters.get_mut(idx) {
+            *rooms += 1;
+        }
+    }
+    pub fn release(&mut self, idx: usize) {
+        if let Some((_, rooms)) = self.arbiters.get_mut(idx) {
+            *rooms = rooms.saturating_sub(1);
+        }
+    }
+    pub fn metrics(&self) -> PlacementMetrics {
+        PlacementMetrics {
+            strategy: self.strategy,
+            rooms_per_arbiter: self.arbiters.iter().map(|(_, rooms)| *rooms).collect(),
+        }
+    }
+}
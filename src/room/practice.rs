@@ -0,0 +1,187 @@
+//! Practice puzzles are solo games played from a seed. The same seed always deals the same
+//! puzzle, so a player can share it by link and compare their result with everyone else who
+//! played it on the seed's leaderboard.
+
+use ahash::{HashMap, HashMapExt};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::RwLock;
+
+use super::invite::unix_time;
+use crate::session::UserId;
+
+/// Entries kept on the leaderboard of a seed
+const BOARD_SIZE: usize = 10;
+/// Most seeds with a leaderboard, the least recently played one is dropped to make space
+const MAX_BOARDS: usize = 4096;
+
+/// Seed of a practice puzzle. Clients get it as a hex string since it doesn't fit in a
+/// javascript number.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PracticeSeed(pub u64);
+
+impl PracticeSeed {
+    pub fn random() -> Self {
+        Self(fastrand::u64(..))
+    }
+    pub fn parse(seed: &str) -> Option<Self> {
+        let valid =
+            !seed.is_empty() && seed.len() <= 16 && seed.bytes().all(|x| x.is_ascii_hexdigit());
+        valid.then(|| Self(u64::from_str_radix(seed, 16).unwrap()))
+    }
+}
+
+impl std::fmt::Display for PracticeSeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl Serialize for PracticeSeed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PracticeSeed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let seed = String::deserialize(deserializer)?;
+        Self::parse(&seed).ok_or_else(|| serde::de::Error::custom("malformed practice seed"))
+    }
+}
+
+/// A player's best result on a seed
+#[derive(Serialize, Clone)]
+pub struct PracticeScore {
+    #[serde(skip)]
+    user: UserId,
+    pub name: String,
+    pub score: usize,
+    /// Milliseconds the player spent on their turns, breaks ties between equal scores
+    pub time: u64,
+    /// Seconds since the unix epoch
+    pub at: u64,
+}
+
+impl PracticeScore {
+    /// Whether the result ranks above the other one
+    fn beats(&self, other: &Self) -> bool {
+        (self.score, std::cmp::Reverse(self.time)) > (other.score, std::cmp::Reverse(other.time))
+    }
+}
+
+/// Leaderboard of a seed, sent to the player after a practice game and served over HTTP for
+/// the link they share
+#[derive(Serialize, Clone)]
+pub struct PracticeBoard {
+    pub seed: PracticeSeed,
+    /// Best first
+    pub entries: Vec<PracticeScore>,
+}
+
+struct Board {
+    entries: Vec<PracticeScore>,
+    last_played: u64,
+}
+
+/// Leaderboards of the seeds played, kept for as long as the server runs
+pub struct PracticeBoards {
+    boards: RwLock<HashMap<PracticeSeed, Board>>,
+}
+
+impl Default for PracticeBoards {
+    fn default() -> Self {
+        Self {
+            boards: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl PracticeBoards {
+    pub fn get(&self, seed: PracticeSeed) -> PracticeBoard {
+        let entries = self
+            .boards
+            .read()
+            .unwrap()
+            .get(&seed)
+            .map(|board| board.entries.clone())
+            .unwrap_or_default();
+        PracticeBoard { seed, entries }
+    }
+    /// Puts the result on the seed's leaderboard if it is the player's best so far and good
+    /// enough to make it
+    pub fn record(&self, seed: PracticeSeed, user: UserId, name: String, score: usize, time: u64) {
+        let at = unix_time();
+        let mut boards = self.boards.write().unwrap();
+        if boards.len() >= MAX_BOARDS && !boards.contains_key(&seed) {
+            let stale = boards
+                .iter()
+                .min_by_key(|(_, board)| board.last_played)
+                .map(|(seed, _)| *seed);
+            if let Some(stale) = stale {
+                boards.remove(&stale);
+            }
+        }
+        let board = boards.entry(seed).or_insert_with(|| Board {
+            entries: Vec::new(),
+            last_played: at,
+        });
+        board.last_played = at;
+        let result = PracticeScore {
+            user,
+            name,
+            score,
+            time,
+            at,
+        };
+        let previous = board.entries.iter().position(|x| x.user == result.user);
+        if let Some(idx) = previous {
+            if !result.beats(&board.entries[idx]) {
+                return;
+            }
+            board.entries.remove(idx);
+        }
+        let idx = board.entries.partition_point(|x| !result.beats(x));
+        if idx < BOARD_SIZE {
+            board.entries.insert(idx, result);
+            board.entries.truncate(BOARD_SIZE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_round_trip_through_their_string_form() {
+        let seed = PracticeSeed(0x00ab_cdef_0123_4567);
+        assert_eq!(seed.to_string(), "00abcdef01234567");
+        assert_eq!(PracticeSeed::parse(&seed.to_string()), Some(seed));
+        assert_eq!(PracticeSeed::parse("ff"), Some(PracticeSeed(0xff)));
+        assert_eq!(PracticeSeed::parse(""), None);
+        assert_eq!(PracticeSeed::parse("00abcdef012345678"), None);
+        assert_eq!(PracticeSeed::parse("+ff"), None);
+    }
+
+    #[test]
+    fn leaderboards_keep_the_best_result_of_every_player() {
+        let boards = PracticeBoards::default();
+        let seed = PracticeSeed(7);
+        boards.record(seed, "a".into(), "A".into(), 10, 5000);
+        boards.record(seed, "b".into(), "B".into(), 10, 4000);
+        boards.record(seed, "c".into(), "C".into(), 12, 9000);
+        // Worse than their first try
+        boards.record(seed, "a".into(), "A".into(), 8, 1000);
+        let names = |board: PracticeBoard| {
+            board
+                .entries
+                .into_iter()
+                .map(|x| x.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(boards.get(seed)), ["C", "B", "A"]);
+        boards.record(seed, "a".into(), "A".into(), 12, 1000);
+        assert_eq!(names(boards.get(seed)), ["A", "C", "B"]);
+        assert!(boards.get(PracticeSeed(8)).entries.is_empty());
+    }
+}
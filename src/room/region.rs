@@ -0,0 +1,89 @@
+//! Fleets spread over several regions tell their rooms apart by the first character of the room
+//! code, which names the region hosting the room. A join link opened on a node of the wrong
+//! region sends the client on to the region the room lives in instead of failing to find it.
+
+use ahash::{HashMap, HashMapExt};
+use std::sync::OnceLock;
+
+use super::ROOM_CODE_CHARSET;
+
+pub struct Regions {
+    /// Character the codes of the rooms of this region start with
+    local: u8,
+    /// Base URL of every other region, by the character their codes start with
+    hosts: HashMap<u8, Box<str>>,
+}
+
+/// The region this node belongs to, read from `REGION` on first use, along with the other
+/// regions listed in `REGION_HOSTS` as comma separated `X=https://host` pairs. Room codes carry
+/// no region without the former.
+pub fn regions() -> Option<&'static Regions> {
+    static REGIONS: OnceLock<Option<Regions>> = OnceLock::new();
+    REGIONS
+        .get_or_init(|| {
+            let local = std::env::var("REGION").ok().filter(|x| !x.is_empty())?;
+            let hosts = std::env::var("REGION_HOSTS").unwrap_or_default();
+            let regions = Regions::parse(&local, &hosts);
+            if regions.is_none() {
+                log::error!("region {local} is not a single letter or digit, codes carry none");
+            }
+            regions
+        })
+        .as_ref()
+}
+
+impl Regions {
+    fn parse(local: &str, hosts: &str) -> Option<Self> {
+        let local = region_id(local)?;
+        let mut regions = HashMap::new();
+        for entry in hosts.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            match entry.split_once('=') {
+                Some((region, host)) if region_id(region).is_some_and(|x| x != local) => {
+                    let region = region_id(region).expect("checked above");
+                    regions.insert(region, host.trim().trim_end_matches('/').into());
+                }
+                _ => log::error!("ignoring malformed region host {entry}"),
+            }
+        }
+        Some(Self {
+            local,
+            hosts: regions,
+        })
+    }
+    pub fn local(&self) -> u8 {
+        self.local
+    }
+    /// Base URL of the region hosting the room, [None] if it is hosted in this one or in a
+    /// region this node doesn't know about
+    pub fn home_of(&self, code: &[u8]) -> Option<&str> {
+        let region = *code.first()?;
+        if region == self.local {
+            return None;
+        }
+        self.hosts.get(&region).map(|x| &**x)
+    }
+}
+
+fn region_id(region: &str) -> Option<u8> {
+    match region.trim().to_ascii_uppercase().as_bytes() {
+        [id] if ROOM_CODE_CHARSET.contains(id) => Some(*id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rooms_of_other_regions_are_sent_to_their_host() {
+        let regions = Regions::parse("e", "U=https://us.example.com/, E=http://x, A, 9=http://ap")
+            .expect("e is a valid region");
+        assert_eq!(regions.local(), b'E');
+        assert_eq!(regions.home_of(b"ABCD"), None);
+        assert_eq!(regions.home_of(b"EBCD"), None);
+        assert_eq!(regions.home_of(b"UBCD"), Some("https://us.example.com"));
+        assert_eq!(regions.home_of(b"9XYZ"), Some("http://ap"));
+        assert!(Regions::parse("EU", "").is_none());
+    }
+}
@@ -0,0 +1,401 @@
+use super::practice::PracticeSeed;
+use super::{RoomConfig, RoomKind, DEFAULT_RECONNECT_GRACE, DEFAULT_SPECTATOR_LIMIT};
+use crate::game::engine::MAX_UPCOMING_TURNS;
+use crate::game::words::{custom_words, supported_language};
+use crate::game::GameMode;
+use crate::profanity::{ProfanityFilter, Verdict};
+use crate::room::actor::GameConfigOptions;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Smallest player limit a leader can pick, anything less wouldn't leave room for a game
+pub(super) const MIN_PLAYER_LIMIT: u8 = 2;
+/// Largest player limit a leader can pick
+pub(super) const MAX_PLAYER_LIMIT: u8 = 16;
+/// Largest spectator limit a leader can pick
+const MAX_SPECTATOR_LIMIT: u8 = 64;
+/// Bounds (in seconds) on the turn duration a leader can pick
+const MIN_TURN_DURATION: u64 = 5;
+const MAX_TURN_DURATION: u64 = 120;
+/// Bounds (in seconds) on the round duration a leader can pick
+const MIN_ROUND_DURATION: u64 = 30;
+const MAX_ROUND_DURATION: u64 = 900;
+/// Bounds (in seconds) on the hint interval a leader can pick
+const MIN_HINT_INTERVAL: u64 = 5;
+const MAX_HINT_INTERVAL: u64 = 300;
+/// Longest pause (in milliseconds) between two turns a leader can pick
+const MAX_TURN_HANDOFF: u64 = 3000;
+/// Longest reconnection grace window (in seconds) a leader can pick
+const MAX_RECONNECT_GRACE: u64 = 600;
+
+/// Changes the leader wants to make to the room, unset fields are left as they are
+#[derive(Deserialize)]
+pub struct SettingsUpdate {
+    pub max_player_count: Option<u8>,
+    pub min_players: Option<u8>,
+    /// Zero keeps everyone from watching the room
+    pub max_spectators: Option<u8>,
+    pub public: Option<bool>,
+    pub mode: Option<GameMode>,
+    /// Seconds every turn lasts
+    pub turn_duration: Option<u64>,
+    /// Milliseconds between one turn ending and the next one starting, zero for no pause
+    pub turn_handoff: Option<u64>,
+    /// Seconds every round lasts at most, zero for no limit. Only for [GameMode::timed] modes.
+    pub round_duration: Option<u64>,
+    /// Seconds between two hints, zero for no hints. Only for [GameMode::timed] modes.
+    pub hint_interval: Option<u64>,
+    /// How many of the next turn holders to announce ahead of time, zero to turn it off
+    pub upcoming_turns: Option<u8>,
+    /// Language the room is played in, which also picks the word list
+    pub language: Option<Box<str>>,
+    /// Whether players joining a full room wait in line for a seat
+    pub waiting_queue: Option<bool>,
+    /// Whether the results of a game come with its turn by turn log
+    pub share_turn_log: Option<bool>,
+    /// Seconds a disconnected player's seat is kept for them, zero removes them right away
+    pub reconnect_grace: Option<u64>,
+    /// Words the game picks from instead of the language's list, an empty list goes back to it
+    pub words: Option<Vec<String>>,
+    /// Whether what the room's games and its audit trail leave behind is kept encrypted
+    pub privacy_mode: Option<bool>,
+}
+
+/// Current settings of a room, broadcast to every member whenever the leader changes them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoomSettings {
+    pub max_player_count: u8,
+    pub min_players: u8,
+    #[serde(default = "default_max_spectators")]
+    pub max_spectators: u8,
+    pub public: bool,
+    pub mode: GameMode,
+    pub turn_duration: u64,
+    /// Milliseconds, zero if turns are handed over right away
+    #[serde(default)]
+    pub turn_handoff: u64,
+    /// Zero if rounds have no time limit
+    #[serde(default)]
+    pub round_duration: u64,
+    /// Zero if the game gives no hints
+    #[serde(default)]
+    pub hint_interval: u64,
+    pub upcoming_turns: u8,
+    pub language: Option<Box<str>>,
+    #[serde(default)]
+    pub waiting_queue: bool,
+    #[serde(default)]
+    pub share_turn_log: bool,
+    #[serde(default = "default_reconnect_grace")]
+    pub reconnect_grace: u64,
+    /// Seed of the puzzle played in a practice room, for the player to share
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<PracticeSeed>,
+    /// How many words the leader gave the room to play with, zero if it plays with its
+    /// language's list. The words themselves are kept from the players who have to guess them.
+    #[serde(default)]
+    pub custom_words: usize,
+    /// Summaries of the room's games and its audit trail are sealed once archived, see
+    /// [super::vault]
+    #[serde(default)]
+    pub privacy_mode: bool,
+}
+
+fn default_max_spectators() -> u8 {
+    DEFAULT_SPECTATOR_LIMIT
+}
+
+fn default_reconnect_grace() -> u64 {
+    DEFAULT_RECONNECT_GRACE
+}
+
+#[derive(Serialize, Clone)]
+pub enum SettingsError {
+    NotInRoom,
+    NotLeader,
+    /// Settings can only be changed between games
+    GameInProgress,
+    /// Announcement rooms have no settings to speak of, and those of practice rooms are fixed
+    NotAllowed,
+    /// The limit is out of bounds or lower than the number of players already in the room
+    InvalidPlayerLimit,
+    /// The minimum is below two players or above the player limit
+    InvalidMinPlayers,
+    /// The limit is out of bounds or lower than the number of people already watching
+    InvalidSpectatorLimit,
+    InvalidTurnDuration,
+    InvalidTurnHandoff,
+    /// The duration is out of bounds, or the mode has no round limits
+    InvalidRoundDuration,
+    /// The interval is out of bounds or not shorter than the rounds, or the mode gives no hints
+    InvalidHintInterval,
+    /// More upcoming turns were asked for than the engine announces
+    InvalidUpcomingTurns,
+    InvalidReconnectGrace,
+    /// There is no word list for the language
+    UnsupportedLanguage,
+    /// Too many words, or one of them is too long or not made of letters only
+    InvalidWords,
+    /// One of the words didn't get past the profanity filter
+    Inappropriate,
+    InternalServerError,
+}
+
+impl RoomSettings {
+    pub(super) fn new(room_config: &RoomConfig, game_config: &GameConfigOptions) -> Self {
+        Self {
+            max_player_count: room_config.max_player_count,
+            min_players: room_config.min_players,
+            max_spectators: room_config.max_spectators,
+            public: room_config.public,
+            mode: game_config.mode,
+            turn_duration: game_config.turn_duration.as_secs(),
+            turn_handoff: game_config.turn_handoff.as_millis() as u64,
+            round_duration: game_config.round_duration.map_or(0, |x| x.as_secs()),
+            hint_interval: game_config.hint_interval.map_or(0, |x| x.as_secs()),
+            upcoming_turns: game_config.upcoming_turns,
+            language: room_config.language.clone(),
+            waiting_queue: room_config.waiting_queue,
+            share_turn_log: game_config.share_turn_log,
+            reconnect_grace: room_config.reconnect_grace_secs,
+            seed: game_config.seed,
+            custom_words: game_config.words.as_ref().map_or(0, |x| x.len()),
+            privacy_mode: room_config.privacy_mode,
+        }
+    }
+    /// Configuration of a room set up again from its settings, see [super::RoomSummary]. Its
+    /// words are handed over apart from them, see [super::RoomSummary::words].
+    pub(super) fn restore(self, kind: RoomKind) -> (RoomConfig, GameConfigOptions) {
+        let room_config = RoomConfig {
+            public: self.public,
+            max_player_count: self.max_player_count,
+            min_players: self.min_players,
+            kind,
+            language: self.language,
+            waiting_queue: self.waiting_queue,
+            max_spectators: self.max_spectators,
+            // Handed over along with the settings, see [super::RoomSummary::lifetime]
+            lifetime: None,
+            reconnect_grace_secs: self.reconnect_grace,
+            // Handed over along with the settings, see [super::RoomSummary::metadata]
+            metadata: Default::default(),
+            password: None,
+            privacy_mode: self.privacy_mode,
+        };
+        let game_config = GameConfigOptions {
+            mode: self.mode,
+            turn_duration: Duration::from_secs(self.turn_duration),
+            turn_handoff: Duration::from_millis(self.turn_handoff),
+            round_duration: seconds(self.round_duration),
+            hint_interval: seconds(self.hint_interval),
+            upcoming_turns: self.upcoming_turns,
+            share_turn_log: self.share_turn_log,
+            seed: self.seed,
+            ..Default::default()
+        };
+        (room_config, game_config)
+    }
+}
+
+impl SettingsUpdate {
+    /// Validates the whole update before applying any of it, so that a bad field leaves the
+    /// room untouched. Words that the profanity filter would do anything about are turned down
+    /// regardless of its action, as masking them would leave nothing to guess.
+    pub(super) fn apply(
+        self,
+        room_config: &mut RoomConfig,
+        game_config: &mut GameConfigOptions,
+        player_count: usize,
+        spectator_count: usize,
+        profanity: &ProfanityFilter,
+    ) -> Result<(), SettingsError> {
+        if room_config.kind != RoomKind::Standard {
+            return Err(SettingsError::NotAllowed);
+        }
+        if let Some(limit) = self.max_player_count {
+            if !(MIN_PLAYER_LIMIT..=MAX_PLAYER_LIMIT).contains(&limit)
+                || (limit as usize) < player_count
+            {
+                return Err(SettingsError::InvalidPlayerLimit);
+            }
+        }
+        let limit = self
+            .max_player_count
+            .unwrap_or(room_config.max_player_count);
+        let min_players = self.min_players.unwrap_or(room_config.min_players);
+        if min_players < MIN_PLAYER_LIMIT || min_players > limit {
+            return Err(SettingsError::InvalidMinPlayers);
+        }
+        if let Some(limit) = self.max_spectators {
+            if limit > MAX_SPECTATOR_LIMIT || (limit as usize) < spectator_count {
+                return Err(SettingsError::InvalidSpectatorLimit);
+            }
+        }
+        if let Some(duration) = self.turn_duration {
+            if !(MIN_TURN_DURATION..=MAX_TURN_DURATION).contains(&duration) {
+                return Err(SettingsError::InvalidTurnDuration);
+            }
+        }
+        if self.turn_handoff.is_some_and(|x| x > MAX_TURN_HANDOFF) {
+            return Err(SettingsError::InvalidTurnHandoff);
+        }
+        let timed = self.mode.unwrap_or(game_config.mode).timed();
+        let round_duration = self
+            .round_duration
+            .map_or(game_config.round_duration, seconds);
+        if let Some(duration) = round_duration {
+            if !timed || !(MIN_ROUND_DURATION..=MAX_ROUND_DURATION).contains(&duration.as_secs()) {
+                return Err(SettingsError::InvalidRoundDuration);
+            }
+        }
+        let hint_interval = self
+            .hint_interval
+            .map_or(game_config.hint_interval, seconds);
+        if let Some(interval) = hint_interval {
+            if !timed
+                || !(MIN_HINT_INTERVAL..=MAX_HINT_INTERVAL).contains(&interval.as_secs())
+                || round_duration.is_some_and(|round| interval >= round)
+            {
+                return Err(SettingsError::InvalidHintInterval);
+            }
+        }
+        if self.upcoming_turns.is_some_and(|x| x > MAX_UPCOMING_TURNS) {
+            return Err(SettingsError::InvalidUpcomingTurns);
+        }
+        if self
+            .reconnect_grace
+            .is_some_and(|x| x > MAX_RECONNECT_GRACE)
+        {
+            return Err(SettingsError::InvalidReconnectGrace);
+        }
+        let language = match self.language {
+            Some(language) => {
+                Some(supported_language(&language).ok_or(SettingsError::UnsupportedLanguage)?)
+            }
+            None => None,
+        };
+        let words = match self.words {
+            Some(words) => Some(custom_words(&words).ok_or(SettingsError::InvalidWords)?),
+            None => None,
+        };
+        if let Some(words) = &words {
+            let clean = |word: &str| matches!(profanity.check(word), Verdict::Clean);
+            if !words.iter().all(|x| clean(x)) {
+                return Err(SettingsError::Inappropriate);
+            }
+        }
+        room_config.max_player_count = limit;
+        room_config.min_players = min_players;
+        if let Some(limit) = self.max_spectators {
+            room_config.max_spectators = limit;
+        }
+        if let Some(public) = self.public {
+            // Rooms behind a password stay private, see [RoomConfig::requested]
+            room_config.public = public && room_config.password.is_none();
+        }
+        if let Some(mode) = self.mode {
+            game_config.mode = mode;
+        }
+        if let Some(duration) = self.turn_duration {
+            game_config.turn_duration = Duration::from_secs(duration);
+        }
+        if let Some(handoff) = self.turn_handoff {
+            game_config.turn_handoff = Duration::from_millis(handoff);
+        }
+        game_config.round_duration = round_duration;
+        game_config.hint_interval = hint_interval;
+        if let Some(upcoming) = self.upcoming_turns {
+            game_config.upcoming_turns = upcoming;
+        }
+        if let Some(language) = language {
+            room_config.language = Some(language.into());
+        }
+        if let Some(waiting_queue) = self.waiting_queue {
+            room_config.waiting_queue = waiting_queue;
+        }
+        if let Some(share_turn_log) = self.share_turn_log {
+            game_config.share_turn_log = share_turn_log;
+        }
+        if let Some(grace) = self.reconnect_grace {
+            room_config.reconnect_grace_secs = grace;
+        }
+        if let Some(privacy_mode) = self.privacy_mode {
+            room_config.privacy_mode = privacy_mode;
+        }
+        if let Some(words) = words {
+            game_config.words = (!words.is_empty()).then_some(words);
+        }
+        Ok(())
+    }
+}
+
+/// Zero turns the timer off
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profanity::FilterAction;
+    use serde_json::json;
+
+    fn apply(update: serde_json::Value, game_config: &mut GameConfigOptions) -> Option<()> {
+        let update: SettingsUpdate = serde_json::from_value(update).unwrap();
+        update
+            .apply(
+                &mut RoomConfig::default(),
+                game_config,
+                0,
+                0,
+                &ProfanityFilter::new(["darn"], FilterAction::Mask),
+            )
+            .ok()
+    }
+
+    #[test]
+    fn timers_stay_within_bounds() {
+        let mut game_config = GameConfigOptions::default();
+        for out_of_bounds in [
+            json!({ "round_duration": MIN_ROUND_DURATION - 1 }),
+            json!({ "round_duration": MAX_ROUND_DURATION + 1 }),
+            json!({ "hint_interval": MIN_HINT_INTERVAL - 1 }),
+            json!({ "hint_interval": MAX_HINT_INTERVAL + 1 }),
+        ] {
+            assert!(apply(out_of_bounds, &mut game_config).is_none());
+        }
+        let timers = json!({ "round_duration": 60, "hint_interval": 15 });
+        assert!(apply(timers, &mut game_config).is_some());
+        assert_eq!(game_config.round_duration, Some(Duration::from_secs(60)));
+        assert_eq!(game_config.hint_interval, Some(Duration::from_secs(15)));
+        // Hints have to come within the round, and a bad update leaves the timers as they were
+        let update = json!({ "round_duration": 30, "hint_interval": 30 });
+        assert!(apply(update, &mut game_config).is_none());
+        assert!(apply(json!({ "round_duration": 10 }), &mut game_config).is_none());
+        assert_eq!(game_config.round_duration, Some(Duration::from_secs(60)));
+        // Zero turns them off
+        let off = json!({ "round_duration": 0, "hint_interval": 0 });
+        assert!(apply(off, &mut game_config).is_some());
+        assert_eq!(game_config.round_duration, None);
+        assert_eq!(game_config.hint_interval, None);
+    }
+
+    #[test]
+    fn custom_words_are_filtered_and_kept_from_the_players() {
+        let mut game_config = GameConfigOptions::default();
+        let update = json!({ "words": ["Lantern", "darn", "compass"] });
+        assert!(apply(update, &mut game_config).is_none());
+        assert!(apply(json!({ "words": ["two words"] }), &mut game_config).is_none());
+        assert!(game_config.words.is_none());
+        let update = json!({ "words": [" Lantern", "compass", "lantern"] });
+        assert!(apply(update, &mut game_config).is_some());
+        let words = game_config.words.as_deref().unwrap();
+        assert_eq!(words, [Box::from("lantern"), Box::from("compass")]);
+        let settings = RoomSettings::new(&RoomConfig::default(), &game_config);
+        assert_eq!(settings.custom_words, 2);
+        let broadcast = serde_json::to_string(&settings).unwrap();
+        assert!(!broadcast.contains("lantern"));
+        assert!(apply(json!({ "words": [] }), &mut game_config).is_some());
+        assert!(game_config.words.is_none());
+    }
+}
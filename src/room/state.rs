@@ -0,0 +1,53 @@
+use serde::Serialize;
+
+/// Where a room is between games. Rooms go from the lobby through the start countdown into a
+/// game, and once it ends stay together while the players vote for a rematch, after which they
+/// either play again or return to the lobby. Members are told whenever the state changes.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RoomState {
+    /// Waiting for enough players or for someone to start a game
+    #[default]
+    Lobby,
+    /// Counting down to the next game, see [crate::session::message::OutgoingMessage::StartingIn]
+    Starting,
+    InGame,
+    /// The game is over and the players vote for a rematch, see [super::lobby::Lobby]
+    PostGame,
+}
+
+impl RoomState {
+    /// Whether the room is allowed to go straight from this state to the next one
+    pub fn can_become(self, next: Self) -> bool {
+        use RoomState::*;
+        matches!(
+            (self, next),
+            (Lobby, Starting)
+                | (Starting, Lobby)
+                | (Starting, InGame)
+                | (InGame, PostGame)
+                | (PostGame, InGame)
+                | (PostGame, Lobby)
+        )
+    }
+    /// Whether the room waits for a game to start, the only time its settings can change and
+    /// lobby interactions go through
+    pub fn in_lobby(self) -> bool {
+        matches!(self, RoomState::Lobby | RoomState::Starting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_only_start_after_the_countdown_or_a_rematch() {
+        use RoomState::*;
+        assert!(Lobby.can_become(Starting));
+        assert!(Starting.can_become(InGame));
+        assert!(PostGame.can_become(InGame));
+        assert!(!Lobby.can_become(InGame));
+        assert!(!InGame.can_become(Lobby));
+        assert!(!PostGame.can_become(Starting));
+    }
+}
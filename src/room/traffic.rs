@@ -0,0 +1,86 @@
+//! How many frames a room sends out, for finding the rooms that put the most load on the server,
+//! see [crate::diagnostics]
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Frames are counted per window, the rate is taken over the last full window and the running one
+const WINDOW: Duration = Duration::from_secs(60);
+
+pub struct Traffic {
+    /// When the running window started
+    window_start: Instant,
+    /// Frames sent in the running window
+    current: u64,
+    /// Frames sent in the window before it
+    previous: u64,
+}
+
+/// A room as listed in a diagnostics snapshot
+#[derive(Serialize, Clone)]
+pub struct RoomTraffic {
+    pub code: String,
+    pub players: usize,
+    pub spectators: usize,
+    /// Frames sent out per second, see [Traffic::rate]
+    pub rate: f64,
+    /// Members whose clients acknowledge events far behind the latest one, see
+    /// [super::history::History::is_behind]
+    pub behind: usize,
+}
+
+impl Default for Traffic {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            current: 0,
+            previous: 0,
+        }
+    }
+}
+
+impl Traffic {
+    pub fn record(&mut self, frames: u64, now: Instant) {
+        self.roll(now);
+        self.current += frames;
+    }
+    /// Frames sent per second over the last [WINDOW], assuming the previous window was evenly
+    /// spread out
+    pub fn rate(&mut self, now: Instant) -> f64 {
+        self.roll(now);
+        let elapsed = now
+            .saturating_duration_since(self.window_start)
+            .as_secs_f64();
+        let window = WINDOW.as_secs_f64();
+        let previous = self.previous as f64 * (1.0 - elapsed / window);
+        (previous + self.current as f64) / window
+    }
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= WINDOW * 2 {
+            self.previous = 0;
+            self.current = 0;
+            self.window_start = now;
+        } else if elapsed >= WINDOW {
+            self.previous = std::mem::take(&mut self.current);
+            self.window_start += WINDOW;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_slides_over_the_last_window() {
+        let mut traffic = Traffic::default();
+        let start = traffic.window_start;
+        traffic.record(120, start);
+        assert_eq!(traffic.rate(start + Duration::from_secs(30)), 2.0);
+        // Half of the previous window is still within the last minute
+        traffic.record(30, start + Duration::from_secs(90));
+        assert_eq!(traffic.rate(start + Duration::from_secs(90)), 1.5);
+        assert_eq!(traffic.rate(start + Duration::from_secs(300)), 0.0);
+    }
+}
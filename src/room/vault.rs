@@ -0,0 +1,98 @@
+//! Encryption at rest for what rooms in privacy mode leave behind, the summaries of their games
+//! with every turn played and the trail of who came and went once they close. Every room has a
+//! key of its own, derived from a master key with HMAC-SHA256, so that the key of one room opens
+//! nothing else. What was sealed is only ever opened by the admin archive export.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Long enough for nonces to be picked at random without ever coming up twice
+const NONCE_LENGTH: usize = 24;
+
+type Digest = Hmac<Sha256>;
+
+/// Seals and opens the archived artifacts of rooms in privacy mode with XChaCha20-Poly1305
+pub struct Vault {
+    key: Box<[u8]>,
+}
+
+impl Vault {
+    pub fn new(master: &[u8]) -> Self {
+        Self { key: master.into() }
+    }
+    /// Reads `ARCHIVE_KEY`. Without one a random key is picked, and whatever was sealed can no
+    /// longer be opened once the process exits.
+    pub fn from_env() -> Self {
+        match std::env::var("ARCHIVE_KEY").ok().filter(|x| !x.is_empty()) {
+            Some(key) => Self::new(key.as_bytes()),
+            None => {
+                log::warn!("no archive key configured, sealed archives won't outlive the process");
+                Self::default()
+            }
+        }
+    }
+    /// Encrypts the data with the key of the room, the nonce and ciphertext come out in base64
+    pub fn seal(&self, room: &[u8], data: &[u8]) -> String {
+        let nonce: [u8; NONCE_LENGTH] = rand::random();
+        let ciphertext = self
+            .cipher(room)
+            .encrypt(XNonce::from_slice(&nonce), data)
+            .expect("the data fits in a single message");
+        let mut sealed = Vec::with_capacity(NONCE_LENGTH + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        URL_SAFE_NO_PAD.encode(sealed)
+    }
+    /// [None] if the data wasn't sealed for the room with this key or was tampered with since
+    pub fn open(&self, room: &[u8], sealed: &str) -> Option<Vec<u8>> {
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        self.cipher(room)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+    fn cipher(&self, room: &[u8]) -> XChaCha20Poly1305 {
+        let mut digest =
+            <Digest as Mac>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        digest.update(room);
+        XChaCha20Poly1305::new(&digest.finalize().into_bytes())
+    }
+}
+
+impl Default for Vault {
+    fn default() -> Self {
+        Self::new(&rand::random::<[u8; 32]>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_data_opens_with_the_key_of_its_room_only() {
+        let vault = Vault::new(b"secret");
+        let sealed = vault.seal(b"AB12", b"every turn played");
+        assert!(!sealed.contains("turn"));
+        assert_eq!(vault.open(b"AB12", &sealed).unwrap(), b"every turn played");
+        assert_eq!(vault.open(b"CD34", &sealed), None);
+        assert_eq!(Vault::new(b"other secret").open(b"AB12", &sealed), None);
+    }
+
+    #[test]
+    fn tampered_data_does_not_open() {
+        let vault = Vault::new(b"secret");
+        let mut sealed = vault.seal(b"AB12", b"every turn played").into_bytes();
+        let last = sealed.len() - 1;
+        sealed[last] = if sealed[last] == b'A' { b'B' } else { b'A' };
+        let sealed = String::from_utf8(sealed).unwrap();
+        assert_eq!(vault.open(b"AB12", &sealed), None);
+        assert_eq!(vault.open(b"AB12", "not sealed"), None);
+    }
+}
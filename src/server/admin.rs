@@ -0,0 +1,220 @@
+//! Access control for the admin API. Every admin request carries a bearer token, which maps to
+//! the principal it was issued to and their role. Handlers ask for the permission they need
+//! through [Admin::authorize], which also writes the attempt to the audit log.
+
+use actix::{Actor, Addr, Context, Handler, Message};
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest};
+use ahash::{HashMap, HashMapExt};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::fs::{File, OpenOptions};
+use std::future::{ready, Ready};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::room::invite::unix_time;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    /// Can look at metrics and diagnostics
+    Viewer,
+    /// Can also act on rooms and players
+    Moderator,
+    /// Can do anything, including changing how the server runs
+    Operator,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Permission {
+    View,
+    Moderate,
+    Operate,
+}
+
+impl Role {
+    fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Self::Viewer),
+            "moderator" => Some(Self::Moderator),
+            "operator" => Some(Self::Operator),
+            _ => None,
+        }
+    }
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            Self::Viewer => &[Permission::View],
+            Self::Moderator => &[Permission::View, Permission::Moderate],
+            Self::Operator => &[Permission::View, Permission::Moderate, Permission::Operate],
+        }
+    }
+}
+
+/// What an admin token stands for
+#[derive(Clone)]
+struct Claims {
+    principal: Arc<str>,
+    role: Role,
+}
+
+/// Admin tokens issued, keyed by their SHA-1 digest so that looking one up takes the same time
+/// however much of it a caller guessed right
+pub struct AdminTokens(HashMap<[u8; 20], Claims>);
+
+impl AdminTokens {
+    /// Reads `ADMIN_TOKENS`, a comma separated list of `principal:role:token` entries where the
+    /// role is one of `viewer`, `moderator` or `operator`. The admin API turns everyone away
+    /// without it.
+    pub fn from_env() -> Self {
+        let mut tokens = HashMap::new();
+        let entries = std::env::var("ADMIN_TOKENS").unwrap_or_default();
+        for entry in entries.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let mut parts = entry.splitn(3, ':');
+            let (Some(principal), Some(role), Some(token)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                log::error!("skipping malformed admin token entry");
+                continue;
+            };
+            let Some(role) = Role::parse(role) else {
+                log::error!("skipping admin token of {principal}: unknown role {role}");
+                continue;
+            };
+            let claims = Claims {
+                principal: principal.into(),
+                role,
+            };
+            tokens.insert(Sha1::digest(token).into(), claims);
+        }
+        if tokens.is_empty() {
+            log::warn!("no admin tokens configured, the admin api is closed");
+        }
+        Self(tokens)
+    }
+    fn claims(&self, token: &str) -> Option<&Claims> {
+        self.0.get(&<[u8; 20]>::from(Sha1::digest(token)))
+    }
+}
+
+#[cfg(test)]
+impl AdminTokens {
+    /// Only the one token, issued to the principal with the role
+    pub fn single(principal: &str, role: Role, token: &str) -> Self {
+        let claims = Claims {
+            principal: principal.into(),
+            role,
+        };
+        Self(HashMap::from_iter([(Sha1::digest(token).into(), claims)]))
+    }
+}
+
+/// The caller of an admin endpoint, taken from the `Authorization: Bearer` header. Requests
+/// without a known token are answered with 401.
+pub struct Admin {
+    claims: Claims,
+    audit: Addr<AuditLog>,
+}
+
+impl Admin {
+    /// Checks that the caller's role grants the permission, answering 403 otherwise. The
+    /// attempt is written to the audit log either way.
+    pub fn authorize(&self, permission: Permission, action: &str) -> actix_web::Result<()> {
+        let allowed = self.claims.role.permissions().contains(&permission);
+        self.audit.do_send(AuditEntry {
+            at: unix_time(),
+            principal: self.claims.principal.clone(),
+            role: self.claims.role,
+            action: action.into(),
+            allowed,
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(actix_web::error::ErrorForbidden("not allowed"))
+        }
+    }
+    fn from_request(req: &HttpRequest) -> actix_web::Result<Self> {
+        let (Some(tokens), Some(audit)) = (
+            req.app_data::<Data<AdminTokens>>(),
+            req.app_data::<Data<Addr<AuditLog>>>(),
+        ) else {
+            return Err(actix_web::error::ErrorInternalServerError(
+                "admin api not set up",
+            ));
+        };
+        let unauthorized = || actix_web::error::ErrorUnauthorized("missing or unknown token");
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .ok_or_else(unauthorized)?;
+        let claims = tokens.claims(token.trim()).ok_or_else(unauthorized)?;
+        Ok(Self {
+            claims: claims.clone(),
+            audit: audit.get_ref().clone(),
+        })
+    }
+}
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<actix_web::Result<Self>>;
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Admin::from_request(req))
+    }
+}
+
+/// A privileged action someone attempted through the admin API
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch
+    pub at: u64,
+    pub principal: Arc<str>,
+    pub role: Role,
+    pub action: Box<str>,
+    /// Whether the role allowed it
+    pub allowed: bool,
+}
+
+/// Records every admin action. Entries are appended to the file named by `ADMIN_AUDIT_LOG` as
+/// JSON lines if set, and logged otherwise.
+pub struct AuditLog {
+    log: Option<File>,
+}
+
+impl AuditLog {
+    pub fn from_env() -> Self {
+        let log = std::env::var("ADMIN_AUDIT_LOG").ok().and_then(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|err| log::error!("cannot open admin audit log {path}: {err}"))
+                .ok()
+        });
+        Self { log }
+    }
+}
+
+impl Actor for AuditLog {
+    type Context = Context<Self>;
+}
+
+impl Handler<AuditEntry> for AuditLog {
+    type Result = ();
+    fn handle(&mut self, msg: AuditEntry, _: &mut Self::Context) -> Self::Result {
+        let line = serde_json::to_string(&msg).unwrap_or_default();
+        match &mut self.log {
+            Some(log) => {
+                if let Err(err) = writeln!(log, "{line}") {
+                    log::error!("failed to write admin audit entry {line}: {err}");
+                }
+            }
+            None => log::info!("admin audit: {line}"),
+        }
+    }
+}
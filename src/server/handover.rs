@@ -0,0 +1,278 @@
+use actix::prelude::*;
+use actix_web::dev::ServerHandle;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::room::WarmRoom;
+use crate::room::{
+    actor::SummarizeRoom, ask_rooms, ExportRooms, ImportRooms, RoomManager, RoomSummary,
+};
+use crate::session::actor::{Migrate, Session};
+use crate::session::{ExportSessions, ImportSessions, SessionManager, SessionSummary};
+
+/// How long handed over sessions and rooms are kept around for their clients to come back
+pub const RESUME_WINDOW: Duration = Duration::from_secs(60);
+/// How long rooms warmed up from another instance keep their codes for players to come over, see
+/// [crate::room::WarmRooms]
+pub const WARM_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// How long the previous process lets requests in flight finish once it handed over, before it
+/// lets go of its port
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+/// The port is still held by the previous process while it hands over and shuts down, so binding
+/// is retried [BIND_RETRY_INTERVAL] apart for this long
+const BIND_TIMEOUT: Duration = Duration::from_secs(SHUTDOWN_TIMEOUT.as_secs() + 5);
+const BIND_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lines sent from the previous process to its replacement over the handover socket, each one
+/// serialized as JSON
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data")]
+enum HandoverRecord {
+    Room(RoomSummary),
+    Session(SessionSummary),
+    /// Everything has been sent, the previous process is about to let go of the port
+    Done,
+}
+
+/// Socket processes hand their state over on during deploys, read from `HANDOVER_SOCKET`.
+/// Handovers are off if it is unset.
+pub fn socket_path() -> Option<String> {
+    std::env::var("HANDOVER_SOCKET")
+        .ok()
+        .filter(|x| !x.is_empty())
+}
+
+/// Takes over from the process already running on this host, if one is listening on the
+/// socket. Its rooms and sessions are imported so that its clients can resume where they left
+/// off. Games in progress are not carried over.
+pub async fn take_over(
+    path: &str,
+    session_manager: &Addr<SessionManager>,
+    room_manager: &Addr<RoomManager>,
+) {
+    let Ok(stream) = UnixStream::connect(path).await else {
+        // Nobody to take over from
+        return;
+    };
+    log::info!("taking over from the previous process");
+    let mut lines = BufReader::new(stream).lines();
+    let mut rooms = Vec::new();
+    let mut sessions = Vec::new();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => match serde_json::from_str(&line) {
+                Ok(HandoverRecord::Room(room)) => rooms.push(room),
+                Ok(HandoverRecord::Session(session)) => sessions.push(session),
+                Ok(HandoverRecord::Done) => break,
+                Err(err) => log::error!("skipping malformed handover record: {err}"),
+            },
+            Ok(None) => {
+                log::warn!("previous process hung up before finishing the handover");
+                break;
+            }
+            Err(err) => {
+                log::error!("handover interrupted: {err}");
+                break;
+            }
+        }
+    }
+    log::info!(
+        "took over {} rooms and {} sessions",
+        rooms.len(),
+        sessions.len()
+    );
+    room_manager.do_send(ImportRooms(rooms));
+    session_manager.do_send(ImportSessions(sessions));
+}
+
+/// Binds the server's port, waiting for the previous process to let go of it if necessary
+pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<TcpListener> {
+    let deadline = Instant::now() + BIND_TIMEOUT;
+    loop {
+        match TcpListener::bind(&addr) {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && Instant::now() < deadline => {
+                actix::clock::sleep(BIND_RETRY_INTERVAL).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Waits for the next process to connect on the socket, then stops accepting connections,
+/// streams the state of every room and session to it and tells every client to reconnect with a
+/// resume token before shutting the server down.
+pub async fn serve(
+    path: String,
+    server: ServerHandle,
+    session_manager: Addr<SessionManager>,
+    room_manager: Addr<RoomManager>,
+) {
+    // The socket file is either left over from a crash or belongs to the process this one took
+    // over from, which is done with it either way
+    let _ = std::fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("cannot listen for handovers on {path}: {err}");
+            return;
+        }
+    };
+    let mut stream = match listener.accept().await {
+        Ok((stream, _)) => stream,
+        Err(err) => {
+            log::error!("cannot accept handover: {err}");
+            return;
+        }
+    };
+    log::info!("handing over to the next process");
+    server.pause().await;
+    match hand_over(&mut stream, &session_manager, &room_manager).await {
+        Ok(tokens) => {
+            // Clients are only told to reconnect once the next process knows their tokens
+            for (session, token) in tokens {
+                session.do_send(Migrate(token));
+            }
+        }
+        Err(err) => log::error!("handover failed: {err}"),
+    }
+    drop(stream);
+    server.stop(true).await;
+}
+
+async fn hand_over(
+    stream: &mut UnixStream,
+    session_manager: &Addr<SessionManager>,
+    room_manager: &Addr<RoomManager>,
+) -> io::Result<Vec<(Addr<Session>, String)>> {
+    let rooms = room_manager
+        .send(ExportRooms)
+        .await
+        .map_err(io::Error::other)?;
+    let mut room_of = HashMap::new();
+    for room in rooms {
+        let Ok(summary) = room.send(SummarizeRoom).await else {
+            // The room stopped in the meantime
+            continue;
+        };
+        for member in &summary.members {
+            let seat = (summary.code.clone(), member.profile.clone());
+            room_of.insert(member.user.clone(), seat);
+        }
+        write(stream, &HandoverRecord::Room(summary)).await?;
+    }
+    let sessions = session_manager
+        .send(ExportSessions)
+        .await
+        .map_err(io::Error::other)?;
+    let mut tokens = Vec::with_capacity(sessions.len());
+    for (user, session, locale) in sessions {
+        let token = format!("{:032x}", rand::random::<u128>());
+        let (room, profile) = room_of.remove(&user).unzip();
+        let summary = SessionSummary {
+            token: token.clone(),
+            user,
+            room,
+            profile,
+            locale,
+        };
+        write(stream, &HandoverRecord::Session(summary)).await?;
+        tokens.push((session, token));
+    }
+    write(stream, &HandoverRecord::Done).await?;
+    stream.flush().await?;
+    Ok(tokens)
+}
+
+async fn write(stream: &mut UnixStream, record: &HandoverRecord) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    stream.write_all(&line).await
+}
+
+/// Every live room with just enough to set it up again on another instance, for blue-green
+/// cutovers. The standby instance is handed them through [crate::room::WarmRooms] before
+/// traffic moves over.
+pub async fn export_rooms(room_manager: &Addr<RoomManager>) -> Result<Vec<WarmRoom>, MailboxError> {
+    let summaries = ask_rooms(room_manager, SummarizeRoom).await?;
+    Ok(summaries.into_iter().map(WarmRoom::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::{RoomLifetime, WarmRooms};
+    use crate::testing::Server;
+    use serde_json::json;
+
+    #[actix::test]
+    async fn exported_rooms_are_joined_on_the_standby_with_their_password() {
+        let live = Server::start();
+        let ann = live.connect().await;
+        ann.login("ann").await;
+        let request = json!({ "max_players": 4, "password": "secret" });
+        ann.send(json!({ "kind": "CreateRoom", "data": request })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+
+        let rooms = export_rooms(&live.room_manager).await.unwrap();
+        assert_eq!(rooms.len(), 1);
+        assert!(!serde_json::to_string(&rooms).unwrap().contains("secret"));
+        let standby = Server::start();
+        // Over the wire, the way operators hand them over
+        let mut rooms: Vec<WarmRoom> = serde_json::from_value(json!(rooms)).unwrap();
+        let mut invalid: WarmRoom = serde_json::from_value(json!(rooms[0])).unwrap();
+        invalid.code = "not a code".into();
+        rooms.push(invalid);
+        let warmed = standby.room_manager.send(WarmRooms(rooms)).await.unwrap();
+        assert_eq!(warmed, 1);
+
+        let ben = standby.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        let refused = ben.expect("JoinRoomResult").await;
+        assert_eq!(refused["data"]["kind"], "WrongPassword");
+        let join = json!({ "code": code, "password": "secret" });
+        ben.send(json!({ "kind": "JoinRoom", "data": join })).await;
+        assert_eq!(ben.expect("JoinRoomResult").await["status"], "Success");
+        // Set up once, the code is taken from then on
+        let again = export_rooms(&live.room_manager).await.unwrap();
+        let warmed = standby.room_manager.send(WarmRooms(again)).await.unwrap();
+        assert_eq!(warmed, 0);
+    }
+
+    #[actix::test]
+    async fn rooms_carry_on_with_what_was_left_of_their_lifetime() {
+        let lifetime = |secs| RoomLifetime {
+            duration: Duration::from_secs(secs),
+            warnings: vec![Duration::from_secs(30)],
+        };
+        let live = Server::with_lifetime(lifetime(60));
+        let ann = live.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let rooms = export_rooms(&live.room_manager).await.unwrap();
+        let left = rooms[0].lifetime.clone().unwrap();
+        assert!(left.duration < Duration::from_secs(60));
+        assert_eq!(left.warnings, [Duration::from_secs(30)]);
+
+        // Rooms opened on the standby live for longer, the one handed over doesn't start over
+        let standby = Server::with_lifetime(lifetime(3600));
+        let warmed = standby.room_manager.send(WarmRooms(rooms)).await.unwrap();
+        assert_eq!(warmed, 1);
+        let ben = standby.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        assert_eq!(ben.expect("JoinRoomResult").await["status"], "Success");
+        let rooms = export_rooms(&standby.room_manager).await.unwrap();
+        let restored = rooms[0].lifetime.clone().unwrap();
+        assert!(restored.duration <= left.duration);
+    }
+}
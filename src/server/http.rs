@@ -1,30 +1,578 @@
-use actix::{Actor, Addr};
+use actix::{Actor, Addr, AsyncContext, Context};
 use actix_web::{
-    web::{get, Data, Payload},
+    body::MessageBody,
+    http::header::{ACCEPT_LANGUAGE, LOCATION, SEC_WEBSOCKET_EXTENSIONS},
+    web::{block, get, post, Data, Json, Path, Payload, Query},
     App, HttpRequest, HttpResponse, HttpServer,
 };
 use actix_web_actors::ws;
 
-use crate::session::{SessionManager, actor::Session};
-use crate::room::RoomManager;
+use crate::session::{BroadcastToAll, ConnectionPolicy, SessionManager, TraceUser, TransientId};
+use crate::session::{message::Announcement, MAX_ANNOUNCEMENT_LENGTH};
+use crate::session::{actor::Session, features::FeatureFlags, timings::SessionTimings};
+use crate::session::bot::BotKeys;
+use crate::session::backplane::BackplaneConfig;
+use crate::session::backpressure::{Backlog, BackpressureConfig, Drained};
+use crate::session::deflate::{DeflateConfig, Deflated, Inflated};
+use crate::session::sink::{Encoding, WsConnection, WsSink};
+use super::{handover, poll::{self, PollRegistry}, tail, tcp};
+use super::admin::{Admin, AdminTokens, AuditLog, Permission};
+use crate::analytics::{Analytics, AnalyticsConfig};
+use crate::bridge::{BridgeConfig, EventBridge};
+use crate::capacity::{Capacity, CapacityConfig, KEY_HEADER};
+use crate::deadletter::{DeadLetters, GetDeadLetters};
+use crate::diagnostics;
+use crate::events::EventBus;
+use crate::game::limits::ModeLimits;
+use crate::game::summary::GameArchive;
+use crate::jobs::JobPool;
+use crate::load::{Load, LoadConfig, LoadMonitor};
+use crate::version::BuildInfo;
+use crate::watchdog::{GetScalingReport, Watchdog, WatchdogConfig};
+use crate::webhook::{Outbound, RoomWebhooks};
+use crate::profanity::ProfanityFilter;
+use crate::rating::Ratings;
+use crate::room::{
+    browser::{ListRooms, RoomQuery},
+    denylist::Denylist, fanout::FanoutPool, invite::InviteSigner, placement::ArbiterPool,
+    practice::{PracticeBoards, PracticeSeed}, GetPlacementMetrics, InactivityConfig, RoomManager, RoomServices,
+    GetRoomAudit, RoomCode, RoomExists, RoomLifetime, region::regions, WarmRoom, WarmRooms,
+    audit::Trail,
+};
+
+/// Most preferred language of the client according to its `Accept-Language` header
+fn client_locale(req: &HttpRequest) -> Option<Box<str>> {
+    let header = req.headers().get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+    let tag = header.split([',', ';']).next()?.trim();
+    (!tag.is_empty() && tag != "*").then(|| tag.into())
+}
+
+/// Key of the tagged traffic the client belongs to, see [crate::capacity]
+pub(super) fn capacity_key(req: &HttpRequest) -> Option<&str> {
+    req.headers().get(KEY_HEADER)?.to_str().ok()
+}
+
+#[derive(serde::Deserialize)]
+struct SocketQuery {
+    /// `json` unless the client asks for `msgpack`, see [crate::session::msgpack]
+    #[serde(default)]
+    encoding: Encoding,
+    /// Version of the protocol the client speaks, see [crate::version::PROTOCOL_VERSION]
+    protocol: Option<u32>,
+}
 
 async fn socket(
     req: HttpRequest,
     payload: Payload,
+    query: Query<SocketQuery>,
     data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+    features: Data<FeatureFlags>,
+    dead_letters: Data<Addr<DeadLetters>>,
+    capacity: Data<Capacity>,
 ) -> actix_web::Result<HttpResponse> {
+    // Turned away before the upgrade so that the client can tell and retry later
+    let permit = capacity.into_inner().admit_key(capacity_key(&req))?;
     let (session_manager, room_manager) = data.get_ref();
-    ws::start(Session::new(session_manager.to_owned(), room_manager.to_owned()), &req, payload)
+    let timings = req.app_data::<Data<SessionTimings>>().expect("registered with the app");
+    let backpressure =
+        req.app_data::<Data<BackpressureConfig>>().expect("registered with the app");
+    let deflate = req.app_data::<Data<DeflateConfig>>().expect("registered with the app");
+    let compressed = deflate.negotiate(&req);
+    // The session and its connection need each other's address, so the session's context is
+    // created ahead of it
+    let ctx = Context::new();
+    let backlog = Backlog::default();
+    let connection = WsConnection::new(ctx.address(), query.encoding, backlog.clone());
+    let (connection, response) =
+        ws::WsResponseBuilder::new(connection, &req, Inflated::new(payload, compressed))
+            .start_with_addr()?;
+    ctx.run(Session::new(
+        session_manager.to_owned(),
+        room_manager.to_owned(),
+        features.into_inner(),
+        dead_letters.get_ref().clone(),
+        ***timings,
+        permit,
+        WsSink::new(connection, backlog.clone()),
+    )
+    .locale(client_locale(&req))
+    .speaking(query.protocol)
+    .backpressure(***backpressure));
+    // Frames leave the connection's backlog once the socket takes them
+    let mut response = response.map_body(|_, body| Drained::new(body, backlog));
+    if !compressed {
+        return Ok(response.map_into_boxed_body());
+    }
+    response.headers_mut().insert(SEC_WEBSOCKET_EXTENSIONS, deflate.response());
+    Ok(response
+        .map_body(|_, body| Deflated::new(body.boxed(), deflate.threshold))
+        .map_into_boxed_body())
+}
+
+async fn placement_metrics(
+    admin: Admin,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::View, "read placement metrics")?;
+    let (_, room_manager) = data.get_ref();
+    let metrics = room_manager
+        .send(GetPlacementMetrics)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(metrics))
+}
+
+async fn rooms(
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+    query: Query<RoomQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (_, room_manager) = data.get_ref();
+    let page = room_manager
+        .send(ListRooms(query.into_inner()))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Leaderboard of a practice puzzle, for the page a shared practice link opens
+async fn practice_board(
+    boards: Data<PracticeBoards>,
+    seed: Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let seed = PracticeSeed::parse(&seed)
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("malformed practice seed"))?;
+    Ok(HttpResponse::Ok().json(boards.get(seed)))
+}
+
+#[derive(serde::Deserialize)]
+struct DeadLetterQuery {
+    target: Option<TransientId>,
+    limit: Option<usize>,
+}
+
+async fn dead_letters(
+    admin: Admin,
+    dead_letters: Data<Addr<DeadLetters>>,
+    query: Query<DeadLetterQuery>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::View, "list dead letters")?;
+    let report = dead_letters
+        .send(GetDeadLetters {
+            target: query.target,
+            limit: query.limit.unwrap_or(100),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Code of the room a request path is about, which is case insensitive
+pub(super) fn room_code(code: &str) -> actix_web::Result<RoomCode> {
+    RoomCode::try_from(code.to_ascii_uppercase().as_bytes())
+        .map_err(|_| actix_web::error::ErrorBadRequest("malformed room code"))
+}
+
+/// Where a shared room link leads. Rooms hosted in another region are redirected to the join
+/// link of that region, see [crate::room::region].
+async fn join_link(
+    code: Path<String>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    let code = room_code(&code)?;
+    let room = String::from_utf8_lossy(&code);
+    if let Some(host) = regions().and_then(|regions| regions.home_of(&code)) {
+        return Ok(HttpResponse::TemporaryRedirect()
+            .insert_header((LOCATION, format!("{host}/join/{room}")))
+            .finish());
+    }
+    let (_, room_manager) = data.get_ref();
+    let exists = room_manager
+        .send(RoomExists(code))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !exists {
+        return Err(actix_web::error::ErrorNotFound("RoomNotFound"));
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "room": room })))
+}
+
+/// Who joined, left or led the room and when its games started, for looking into player reports
+async fn room_audit(
+    admin: Admin,
+    code: Path<String>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::View, &format!("read audit trail of room {code}"))?;
+    let code = room_code(&code)?;
+    let (_, room_manager) = data.get_ref();
+    let trail = room_manager
+        .send(GetRoomAudit(code))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("no such room"))?;
+    match trail {
+        Trail::Entries(entries) => Ok(HttpResponse::Ok().json(entries)),
+        Trail::Sealed(_) => Err(actix_web::error::ErrorForbidden(
+            "the room was in privacy mode, its trail only comes with its archive",
+        )),
+    }
+}
+
+/// The audit trail and game summaries archived for a room, with what rooms in privacy mode
+/// sealed opened. Sealed archives are not opened anywhere else, see [crate::room::vault].
+async fn room_archive(
+    admin: Admin,
+    code: Path<String>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+    archive: Data<GameArchive>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::Moderate, &format!("export archive of room {code}"))?;
+    let code = room_code(&code)?;
+    let (_, room_manager) = data.get_ref();
+    let trail = room_manager
+        .send(GetRoomAudit(code))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let audit = match trail {
+        Some(Trail::Entries(entries)) => serde_json::to_value(entries)?,
+        Some(Trail::Sealed(sealed)) => archive
+            .open_sealed(&code, &sealed)
+            .and_then(|entries| serde_json::from_slice(&entries).ok())
+            .ok_or_else(|| actix_web::error::ErrorInternalServerError("cannot open the trail"))?,
+        None => serde_json::Value::Null,
+    };
+    let room = String::from_utf8_lossy(&code).into_owned();
+    let archive = archive.into_inner();
+    let summaries = block(move || archive.export(&room))
+        .await?
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "audit": audit, "summaries": summaries })))
+}
+
+/// How long a user is traced for unless the request says otherwise, see [TraceUser]
+const DEFAULT_TRACE_DURATION: u64 = 5 * 60;
+/// Longest a user can be traced for at once, in seconds
+const MAX_TRACE_DURATION: u64 = 30 * 60;
+
+#[derive(serde::Deserialize)]
+struct TraceQuery {
+    /// Seconds to trace the user for, zero stops tracing them
+    duration: Option<u64>,
+}
+
+/// Logs every frame to and from a user for a while, see [TraceUser]
+async fn trace_user(
+    admin: Admin,
+    user: Path<String>,
+    query: Query<TraceQuery>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    let duration = query
+        .duration
+        .unwrap_or(DEFAULT_TRACE_DURATION)
+        .min(MAX_TRACE_DURATION);
+    admin.authorize(Permission::Operate, &format!("trace user {user} for {duration}s"))?;
+    let (session_manager, _) = data.get_ref();
+    let online = session_manager
+        .send(TraceUser {
+            user: user.into_inner().into(),
+            duration: std::time::Duration::from_secs(duration),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "online": online, "duration": duration })))
 }
+
+/// Sends an announcement to every connected client, see [BroadcastToAll]
+async fn announce(
+    admin: Admin,
+    announcement: Json<Announcement>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    let mut announcement = announcement.into_inner();
+    announcement.text = announcement.text.trim().to_owned();
+    let text = &announcement.text;
+    if text.is_empty() || text.len() > MAX_ANNOUNCEMENT_LENGTH {
+        return Err(actix_web::error::ErrorBadRequest("announcement is empty or too long"));
+    }
+    admin.authorize(Permission::Operate, &format!("announce {text:?}"))?;
+    let (session_manager, _) = data.get_ref();
+    let delivered = session_manager
+        .send(BroadcastToAll(announcement))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "delivered": delivered })))
+}
+
+/// Codes and settings of every live room, to warm up the standby instance with during a
+/// blue-green cutover
+async fn export_rooms(
+    admin: Admin,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::Operate, "export rooms")?;
+    let (_, room_manager) = data.get_ref();
+    let rooms = handover::export_rooms(room_manager)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(rooms))
+}
+
+/// Holds back the codes of rooms exported by the instance being cut over from, so that their
+/// join links keep working here, see [WarmRooms]
+async fn warm_rooms(
+    admin: Admin,
+    rooms: Json<Vec<WarmRoom>>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::Operate, &format!("warm up {} rooms", rooms.len()))?;
+    let (_, room_manager) = data.get_ref();
+    let warmed = room_manager
+        .send(WarmRooms(rooms.into_inner()))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "warmed": warmed })))
+}
+
+/// Snapshot of the server to attach to bug reports, see [crate::diagnostics]
+async fn diagnostics(
+    admin: Admin,
+    sources: Data<diagnostics::Sources>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::View, "capture diagnostics")?;
+    let snapshot = sources
+        .capture()
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(snapshot))
+}
+
+async fn scaling_report(
+    admin: Admin,
+    watchdog: Data<Addr<Watchdog>>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::View, "read scaling report")?;
+    let report = watchdog
+        .send(GetScalingReport)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Every game mode and whether it can be played right now, see [crate::game::limits]
+async fn modes(modes: Data<ModeLimits>) -> HttpResponse {
+    HttpResponse::Ok().json(modes.availability())
+}
+
+async fn version(features: Data<FeatureFlags>) -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo::new(&features))
+}
+
 pub async fn start() -> std::io::Result<()> {
-    let session_manager = SessionManager::new().start();
-    let room_manager = RoomManager::new().start();
-    HttpServer::new(move || {
+    let events = EventBus::default().start();
+    let profanity = std::sync::Arc::new(ProfanityFilter::load());
+    let session_manager = SessionManager::new(
+        BotKeys::from_env(),
+        events.clone(),
+        BackplaneConfig::from_env(),
+        ConnectionPolicy::from_env(),
+        profanity.clone(),
+    )
+    .start();
+    let workers = std::thread::available_parallelism().map_or(1, |x| x.get());
+    let dead_letters = DeadLetters::from_env().start();
+    let jobs = JobPool::new(workers);
+    let outbound = Outbound::start();
+    let load = std::sync::Arc::new(Load::default());
+    let practice = Data::new(PracticeBoards::default());
+    let capacity = Data::new(Capacity::new(CapacityConfig::from_env()));
+    let modes = Data::new(ModeLimits::from_env());
+    let archive = Data::new(GameArchive::from_env());
+    let services = RoomServices {
+        profanity,
+        fanout: FanoutPool::new(workers, dead_letters.clone()),
+        jobs,
+        dead_letters: dead_letters.clone(),
+        inactivity: InactivityConfig::from_env(),
+        lifetime: RoomLifetime::from_env(),
+        invites: std::sync::Arc::new(InviteSigner::from_env()),
+        ratings: std::sync::Arc::new(Ratings::from_env()),
+        load: load.clone(),
+        practice: practice.clone().into_inner(),
+        events: events.clone(),
+        capacity: capacity.clone().into_inner(),
+        modes: modes.clone().into_inner(),
+        archive: archive.clone().into_inner(),
+    };
+    let placement = ArbiterPool::from_env();
+    let room_manager = RoomManager::new(Denylist::from_env(), services, placement).start();
+    LoadMonitor::new(LoadConfig::from_env(), load.clone(), room_manager.clone(), events.clone())
+        .start();
+    if let Some(config) = AnalyticsConfig::from_env() {
+        Analytics::new(config, events.clone(), outbound.clone()).start();
+    }
+    if let Some(config) = BridgeConfig::from_env() {
+        EventBridge::new(config, events.clone(), outbound.clone()).start();
+    }
+    if let Some(webhooks) = RoomWebhooks::from_env(events.clone(), outbound.clone()) {
+        webhooks.start();
+    }
+    let watchdog =
+        Watchdog::new(WatchdogConfig::from_env(), room_manager.clone(), events, outbound).start();
+    let features = Data::new(FeatureFlags::from_env());
+    let timings = Data::new(SessionTimings::from_env());
+    let backpressure = Data::new(BackpressureConfig::from_env());
+    let deflate = Data::new(DeflateConfig::from_env());
+    let admin_tokens = Data::new(AdminTokens::from_env());
+    let audit_log = AuditLog::from_env().start();
+    let diagnostics = Data::new(diagnostics::Sources {
+        session_manager: session_manager.clone(),
+        room_manager: room_manager.clone(),
+        dead_letters: dead_letters.clone(),
+        load,
+        features: features.clone().into_inner(),
+        workers,
+    });
+    log::info!("starting {}", BuildInfo::new(&features));
+    let handover_socket = handover::socket_path();
+    if let Some(path) = &handover_socket {
+        handover::take_over(path, &session_manager, &room_manager).await;
+    }
+    let listener = handover::bind("0.0.0.0:8000").await?;
+    let managers = (session_manager.clone(), room_manager.clone());
+    let poll_registry = PollRegistry::new(
+        session_manager.clone(),
+        room_manager.clone(),
+        features.clone().into_inner(),
+        dead_letters.clone(),
+        **timings,
+        capacity.clone().into_inner(),
+    )
+    .start();
+    if let Some(addr) = tcp::address() {
+        let gateway = tcp::Gateway {
+            session_manager: session_manager.clone(),
+            room_manager: room_manager.clone(),
+            features: features.clone().into_inner(),
+            dead_letters: dead_letters.clone(),
+            timings: **timings,
+            capacity: capacity.clone().into_inner(),
+        };
+        actix::spawn(gateway.serve(addr));
+    }
+    #[cfg(feature = "soak")]
+    if let Some(config) = crate::soak::SoakConfig::from_env() {
+        crate::soak::LoadGenerator::new(
+            config,
+            session_manager.clone(),
+            room_manager.clone(),
+            features.clone().into_inner(),
+            dead_letters.clone(),
+            **timings,
+            capacity.clone().into_inner(),
+        )
+        .start();
+    }
+    let server = HttpServer::new(move || {
         App::new()
             .route("/ws", get().to(socket))
+            .route("/poll", get().to(poll::poll))
+            .route("/send", post().to(poll::send))
+            .route("/version", get().to(version))
+            .route("/modes", get().to(self::modes))
+            .route("/rooms", get().to(rooms))
+            .route("/practice/{seed}", get().to(practice_board))
+            .route("/join/{code}", get().to(join_link))
+            .route("/metrics/placement", get().to(placement_metrics))
+            .route("/metrics/scaling", get().to(scaling_report))
+            .route("/admin/dead-letters", get().to(self::dead_letters))
+            .route("/admin/diagnostics", get().to(self::diagnostics))
+            .route("/admin/rooms/export", get().to(export_rooms))
+            .route("/admin/rooms/warm", post().to(warm_rooms))
+            .route("/admin/announce", post().to(announce))
+            .route("/admin/users/{user}/trace", post().to(trace_user))
+            .route("/admin/rooms/{code}/tail", get().to(tail::tail))
+            .route("/admin/rooms/{code}/audit", get().to(room_audit))
+            .route("/admin/rooms/{code}/archive", get().to(room_archive))
             .app_data(Data::new((session_manager.clone(), room_manager.clone())))
+            .app_data(features.clone())
+            .app_data(timings.clone())
+            .app_data(backpressure.clone())
+            .app_data(deflate.clone())
+            .app_data(Data::new(dead_letters.clone()))
+            .app_data(Data::new(watchdog.clone()))
+            .app_data(Data::new(poll_registry.clone()))
+            .app_data(admin_tokens.clone())
+            .app_data(practice.clone())
+            .app_data(capacity.clone())
+            .app_data(modes.clone())
+            .app_data(archive.clone())
+            .app_data(diagnostics.clone())
+            .app_data(Data::new(audit_log.clone()))
     })
-    .bind("0.0.0.0:8000")?
-    .run()
-    .await
+    .shutdown_timeout(handover::SHUTDOWN_TIMEOUT.as_secs())
+    .listen(listener)?
+    .run();
+    if let Some(path) = handover_socket {
+        let (session_manager, room_manager) = managers;
+        actix::spawn(handover::serve(path, server.handle(), session_manager, room_manager));
+    }
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::admin::Role;
+    use crate::testing::Server;
+    use actix_web::{http::StatusCode, test};
+    use serde_json::{json, Value};
+
+    #[actix::test]
+    async fn trails_of_private_rooms_only_open_in_their_archive() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let settings = json!({ "privacy_mode": true });
+        ann.send(json!({ "kind": "UpdateRoomSettings", "data": settings })).await;
+        ann.expect("UpdateRoomSettingsResult").await;
+        ann.send(json!({ "kind": "LeaveRoom" })).await;
+        ann.expect("LeaveRoomResult").await;
+        // The room closes along with its last player leaving, shortly after
+        actix::clock::sleep(std::time::Duration::from_millis(100)).await;
+        let tokens = AdminTokens::single("eve", Role::Moderator, "secret");
+        let app = test::init_service(
+            App::new()
+                .route("/admin/rooms/{code}/audit", get().to(room_audit))
+                .route("/admin/rooms/{code}/archive", get().to(room_archive))
+                .app_data(Data::new(tokens))
+                .app_data(Data::new(AuditLog::from_env().start()))
+                .app_data(Data::from(std::sync::Arc::clone(&server.services.archive)))
+                .app_data(Data::new((
+                    server.session_manager.clone(),
+                    server.room_manager.clone(),
+                ))),
+        )
+        .await;
+        let request = |path: &str, token: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/admin/rooms/{code}/{path}"))
+                .insert_header(("Authorization", format!("Bearer {token}")))
+                .to_request()
+        };
+        let response = test::call_service(&app, request("audit", "secret")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let archive: Value =
+            test::call_and_read_body_json(&app, request("archive", "secret")).await;
+        let events: Vec<_> = archive["audit"]
+            .as_array()
+            .expect("the trail is opened")
+            .iter()
+            .map(|x| &x["kind"])
+            .collect();
+        assert_eq!(events, ["Joined", "Left", "Closed"]);
+    }
 }
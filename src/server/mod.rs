@@ -1,4 +1,9 @@
+pub mod admin;
+pub mod handover;
 pub mod http;
+pub mod poll;
+pub mod tail;
+pub mod tcp;
 /*use crate::room::{self, AddPlayer, ClientReconnection, JoinRoomError, PlayerInRoom, Room};
 use crate::session::{Session, UserId};
 use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
@@ -0,0 +1,417 @@
+use actix::prelude::*;
+use actix_web::rt::time::timeout;
+use actix_web::web::{Data, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use ahash::{HashMap, HashMapExt};
+use bytestring::ByteString;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use super::http::capacity_key;
+use crate::capacity::{Capacity, Refusal};
+use crate::deadletter::DeadLetters;
+use crate::room::RoomManager;
+use crate::session::actor::{Disconnected, Incoming, Session};
+use crate::session::timings::SessionTimings;
+use crate::session::{
+    backpressure::Backlog,
+    features::FeatureFlags,
+    sink::{ClientSink, SharedFrame},
+    SessionManager,
+};
+
+/// How long a poll is held open waiting for messages before it is answered empty
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+/// Connections nobody polled for this long are dropped along with their session
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Answer to a poll, every message the client has not acknowledged yet
+#[derive(Serialize)]
+pub struct PollResponse {
+    /// Identifies the connection in the next polls and sends
+    pub session: Arc<str>,
+    pub messages: Vec<PolledMessage>,
+    /// The session ended, no messages follow the ones in this response
+    pub closed: bool,
+}
+
+#[derive(Serialize)]
+pub struct PolledMessage {
+    /// Acknowledged by passing it back as `ack` in the next poll
+    pub seq: u64,
+    pub data: Box<RawValue>,
+}
+
+/// HTTP long-poll transport for networks that block websockets. The client opens a connection by
+/// polling `/poll` without a session, then keeps polling with the session it was handed and the
+/// last sequence number it received, and sends its messages to `/send`. Messages stay buffered
+/// until they are acknowledged, so that a poll lost on the way can be retried.
+pub struct PollConnection {
+    id: Arc<str>,
+    session: Addr<Session>,
+    registry: Addr<PollRegistry>,
+    /// Messages not acknowledged yet, oldest first
+    buffer: VecDeque<(u64, ByteString)>,
+    /// Bytes of the messages in the buffer, see [PollSink]
+    backlog: Backlog,
+    next_seq: u64,
+    /// Poll held open until a message comes in
+    waiter: Option<oneshot::Sender<PollResponse>>,
+    last_poll: Instant,
+    /// The session is gone, the connection only stays around until the client got everything
+    closed: bool,
+}
+
+impl PollConnection {
+    fn respond(&mut self) {
+        let Some(waiter) = self.waiter.take() else {
+            return;
+        };
+        let messages = self
+            .buffer
+            .iter()
+            .filter_map(
+                |(seq, frame)| match RawValue::from_string(frame.to_string()) {
+                    Ok(data) => Some(PolledMessage { seq: *seq, data }),
+                    Err(err) => {
+                        log::error!("dropping malformed message to poll client: {err}");
+                        None
+                    }
+                },
+            )
+            .collect();
+        // The poll might have timed out in the meantime, the messages go out with the next one
+        let _ = waiter.send(PollResponse {
+            session: self.id.clone(),
+            messages,
+            closed: self.closed,
+        });
+    }
+    fn idle(&self) -> bool {
+        let waiting = self.waiter.as_ref().is_some_and(|x| !x.is_closed());
+        !waiting && self.last_poll.elapsed() >= IDLE_TIMEOUT
+    }
+}
+
+impl Actor for PollConnection {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(IDLE_CHECK_INTERVAL, |act, ctx| {
+            if act.idle() {
+                ctx.stop();
+            }
+        });
+    }
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.session.do_send(Disconnected);
+        self.registry.do_send(Forget(self.id.clone()));
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "oneshot::Receiver<PollResponse>")]
+struct Poll {
+    /// Last sequence number the client received
+    ack: u64,
+}
+
+impl Handler<Poll> for PollConnection {
+    type Result = MessageResult<Poll>;
+    fn handle(&mut self, msg: Poll, ctx: &mut Self::Context) -> Self::Result {
+        self.last_poll = Instant::now();
+        while self.buffer.front().is_some_and(|(seq, _)| *seq <= msg.ack) {
+            if let Some((_, frame)) = self.buffer.pop_front() {
+                self.backlog.pop(frame.len());
+            }
+        }
+        // A client only polls once at a time, an older poll still open was given up on
+        self.respond();
+        let (waiter, response) = oneshot::channel();
+        self.waiter = Some(waiter);
+        if !self.buffer.is_empty() || self.closed {
+            self.respond();
+        }
+        if self.closed && self.buffer.is_empty() {
+            ctx.stop();
+        }
+        MessageResult(response)
+    }
+}
+
+/// Message from the client, `false` if the session is gone
+#[derive(Message)]
+#[rtype(result = "bool")]
+struct Receive(ByteString);
+
+impl Handler<Receive> for PollConnection {
+    type Result = bool;
+    fn handle(&mut self, msg: Receive, _: &mut Self::Context) -> Self::Result {
+        if !self.closed {
+            self.session.do_send(Incoming(msg.0));
+        }
+        !self.closed
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Outgoing(ByteString);
+
+impl Handler<Outgoing> for PollConnection {
+    type Result = ();
+    fn handle(&mut self, msg: Outgoing, _: &mut Self::Context) -> Self::Result {
+        self.next_seq += 1;
+        self.buffer.push_back((self.next_seq, msg.0));
+        self.respond();
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Close;
+
+impl Handler<Close> for PollConnection {
+    type Result = ();
+    fn handle(&mut self, _: Close, _: &mut Self::Context) -> Self::Result {
+        self.closed = true;
+        self.respond();
+    }
+}
+
+/// Session's end of a [PollConnection]. Messages count towards the backlog from the moment they
+/// are sent until the client acknowledges them.
+struct PollSink {
+    connection: Addr<PollConnection>,
+    backlog: Backlog,
+}
+
+impl ClientSink for PollSink {
+    fn send(&mut self, frame: SharedFrame) {
+        self.backlog.push(frame.json().len());
+        self.connection.do_send(Outgoing(frame.into_json()));
+    }
+    fn close(&mut self) {
+        self.connection.do_send(Close);
+    }
+    fn backlog(&self) -> usize {
+        self.backlog.queued()
+    }
+}
+
+/// Keeps track of the open long-poll connections by their session id
+pub struct PollRegistry {
+    connections: HashMap<Arc<str>, Addr<PollConnection>>,
+    session_manager: Addr<SessionManager>,
+    room_manager: Addr<RoomManager>,
+    features: Arc<FeatureFlags>,
+    dead_letters: Addr<DeadLetters>,
+    timings: SessionTimings,
+    capacity: Arc<Capacity>,
+}
+
+impl PollRegistry {
+    pub fn new(
+        session_manager: Addr<SessionManager>,
+        room_manager: Addr<RoomManager>,
+        features: Arc<FeatureFlags>,
+        dead_letters: Addr<DeadLetters>,
+        timings: SessionTimings,
+        capacity: Arc<Capacity>,
+    ) -> Self {
+        Self {
+            connections: HashMap::new(),
+            session_manager,
+            room_manager,
+            features,
+            dead_letters,
+            timings,
+            capacity,
+        }
+    }
+}
+
+impl Actor for PollRegistry {
+    type Context = Context<Self>;
+}
+
+/// Starts a new session for a long-poll client showing the capacity key, if any
+#[derive(Message)]
+#[rtype(result = "Result<(Arc<str>, Addr<PollConnection>), Refusal>")]
+struct Open(Option<Box<str>>);
+
+impl Handler<Open> for PollRegistry {
+    type Result = Result<(Arc<str>, Addr<PollConnection>), Refusal>;
+    fn handle(&mut self, msg: Open, ctx: &mut Self::Context) -> Self::Result {
+        let permit = self.capacity.admit_key(msg.0.as_deref())?;
+        let id: Arc<str> = format!("{:032x}", rand::random::<u128>()).into();
+        // The session and its connection need each other's address, so the session's context
+        // is created ahead of it
+        let session_ctx = Context::new();
+        let backlog = Backlog::default();
+        let connection = PollConnection {
+            id: id.clone(),
+            session: session_ctx.address(),
+            registry: ctx.address(),
+            buffer: VecDeque::new(),
+            backlog: backlog.clone(),
+            next_seq: 0,
+            waiter: None,
+            last_poll: Instant::now(),
+            closed: false,
+        }
+        .start();
+        session_ctx.run(Session::new(
+            self.session_manager.clone(),
+            self.room_manager.clone(),
+            Arc::clone(&self.features),
+            self.dead_letters.clone(),
+            self.timings,
+            permit,
+            PollSink {
+                connection: connection.clone(),
+                backlog,
+            },
+        ));
+        self.connections.insert(id.clone(), connection.clone());
+        Ok((id, connection))
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Addr<PollConnection>>")]
+struct Lookup(Arc<str>);
+
+impl Handler<Lookup> for PollRegistry {
+    type Result = Option<Addr<PollConnection>>;
+    fn handle(&mut self, msg: Lookup, _: &mut Self::Context) -> Self::Result {
+        self.connections.get(&msg.0).cloned()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Forget(Arc<str>);
+
+impl Handler<Forget> for PollRegistry {
+    type Result = ();
+    fn handle(&mut self, msg: Forget, _: &mut Self::Context) -> Self::Result {
+        self.connections.remove(&msg.0);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PollQuery {
+    /// Opens a new connection if unset
+    session: Option<Box<str>>,
+    #[serde(default)]
+    ack: u64,
+}
+
+pub async fn poll(
+    req: HttpRequest,
+    registry: Data<Addr<PollRegistry>>,
+    query: Query<PollQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let (id, connection) = match &query.session {
+        Some(id) => {
+            let id = Arc::from(&**id);
+            let connection = registry
+                .send(Lookup(Arc::clone(&id)))
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            match connection {
+                Some(connection) => (id, connection),
+                None => return Ok(HttpResponse::NotFound().finish()),
+            }
+        }
+        None => registry
+            .send(Open(capacity_key(&req).map(Into::into)))
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)??,
+    };
+    let response = connection
+        .send(Poll { ack: query.ack })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    match timeout(POLL_TIMEOUT, response).await {
+        Ok(Ok(response)) => Ok(HttpResponse::Ok().json(response)),
+        // The connection stopped while the poll was held open
+        Ok(Err(_)) => Ok(HttpResponse::NotFound().finish()),
+        Err(_) => Ok(HttpResponse::Ok().json(PollResponse {
+            session: id,
+            messages: Vec::new(),
+            closed: false,
+        })),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SendQuery {
+    session: Box<str>,
+}
+
+/// Hands a message from the client to its session, the body holds the same JSON as a websocket
+/// message would
+pub async fn send(
+    registry: Data<Addr<PollRegistry>>,
+    query: Query<SendQuery>,
+    body: String,
+) -> actix_web::Result<HttpResponse> {
+    let connection = registry
+        .send(Lookup(Arc::from(&*query.session)))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let Some(connection) = connection else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let delivered = connection
+        .send(Receive(body.into()))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if delivered {
+        Ok(HttpResponse::Accepted().finish())
+    } else {
+        Ok(HttpResponse::Gone().finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[actix::test]
+    async fn clients_stay_connected_for_as_long_as_they_send_messages() {
+        let server = testing::Server::start();
+        let registry = PollRegistry::new(
+            server.session_manager.clone(),
+            server.room_manager.clone(),
+            Arc::default(),
+            server.services.dead_letters.clone(),
+            testing::impatient(),
+            Arc::clone(&server.services.capacity),
+        )
+        .start();
+        let (_, connection) = registry.send(Open(None)).await.unwrap().unwrap();
+        // Long-poll clients are never pinged, only what they send keeps them alive
+        let hello = r#"{"kind":"Hello","data":1}"#;
+        for _ in 0..20 {
+            assert!(connection.send(Receive(hello.into())).await.unwrap());
+            actix::clock::sleep(Duration::from_millis(25)).await;
+        }
+        let poll = connection.send(Poll { ack: 0 }).await.unwrap();
+        let response = poll.await.unwrap();
+        assert!(!response.closed);
+        let ack = response.messages.last().unwrap().seq;
+        // Once the client goes quiet the session ends
+        actix::clock::sleep(Duration::from_millis(500)).await;
+        let poll = connection.send(Poll { ack }).await.unwrap();
+        assert!(poll.await.unwrap().closed);
+        assert!(!connection.send(Receive(hello.into())).await.unwrap_or(false));
+    }
+}
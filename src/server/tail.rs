@@ -0,0 +1,154 @@
+//! Websocket admins tail a room over, see [crate::room::observer]. The socket only ever sends,
+//! anything the admin writes to it is ignored.
+
+use actix::prelude::*;
+use actix_web::web::{Data, Path, Payload};
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web_actors::ws::{self, CloseCode, ProtocolError, WebsocketContext};
+
+use super::admin::{Admin, Permission};
+use super::http::room_code;
+use crate::room::observer::Observed;
+use crate::room::{ObserveRoom, RoomCode, RoomExists, RoomManager};
+use crate::session::SessionManager;
+
+struct RoomTail {
+    code: RoomCode,
+    room_manager: Addr<RoomManager>,
+}
+
+impl Actor for RoomTail {
+    type Context = WebsocketContext<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.room_manager
+            .send(ObserveRoom {
+                code: self.code,
+                observer: ctx.address().recipient(),
+            })
+            .into_actor(self)
+            .then(|res, _, ctx| {
+                let err = match res {
+                    Ok(Ok(())) => return actix::fut::ready(()),
+                    Ok(Err(err)) => format!("{err:?}"),
+                    Err(err) => {
+                        log::error!("cannot attach to room: {err}");
+                        "InternalServerError".into()
+                    }
+                };
+                ctx.close(Some((CloseCode::Policy, err).into()));
+                ctx.stop();
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ProtocolError>> for RoomTail {
+    fn handle(&mut self, item: Result<ws::Message, ProtocolError>, ctx: &mut Self::Context) {
+        match item {
+            Ok(ws::Message::Ping(bytes)) => ctx.pong(&bytes),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("{err}"),
+        }
+    }
+}
+
+impl Handler<Observed> for RoomTail {
+    type Result = ();
+    fn handle(&mut self, msg: Observed, ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            Observed::Event(frame) => ctx.text(frame),
+            Observed::Closed => {
+                ctx.close(Some(CloseCode::Normal.into()));
+                ctx.stop();
+            }
+        }
+    }
+}
+
+/// Attaches to the room as an invisible observer and streams everything that happens in it.
+/// Rooms that don't exist are turned away before the connection is upgraded.
+pub async fn tail(
+    admin: Admin,
+    req: HttpRequest,
+    payload: Payload,
+    code: Path<String>,
+    data: Data<(Addr<SessionManager>, Addr<RoomManager>)>,
+) -> actix_web::Result<HttpResponse> {
+    admin.authorize(Permission::View, &format!("tail room {code}"))?;
+    let code = room_code(&code)?;
+    let (_, room_manager) = data.get_ref();
+    let exists = room_manager
+        .send(RoomExists(code))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    if !exists {
+        return Err(actix_web::error::ErrorNotFound("RoomNotFound"));
+    }
+    ws::start(
+        RoomTail {
+            code,
+            room_manager: room_manager.clone(),
+        },
+        &req,
+        payload,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::admin::{AdminTokens, AuditLog, Role};
+    use crate::testing::Server;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web::get, App};
+    use serde_json::json;
+
+    #[actix::test]
+    async fn only_admins_tail_rooms_that_exist() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let lowercase = code.to_lowercase();
+        let missing = if code == "ZZZZ" { "YYYY" } else { "ZZZZ" };
+        let tokens = AdminTokens::single("eve", Role::Viewer, "secret");
+        let app = test::init_service(
+            App::new()
+                .route("/admin/rooms/{code}/tail", get().to(tail))
+                .app_data(Data::new(tokens))
+                .app_data(Data::new(AuditLog::from_env().start()))
+                .app_data(Data::new((
+                    server.session_manager.clone(),
+                    server.room_manager.clone(),
+                ))),
+        )
+        .await;
+        let request = |code: &str, token: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/admin/rooms/{code}/tail"))
+                .insert_header(("Authorization", format!("Bearer {token}")))
+                .insert_header(("Upgrade", "websocket"))
+                .insert_header(("Connection", "Upgrade"))
+                .insert_header(("Sec-WebSocket-Version", "13"))
+                .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+                .to_request()
+        };
+        for (code, token, status) in [
+            (code.as_str(), "guess", StatusCode::UNAUTHORIZED),
+            ("A", "secret", StatusCode::BAD_REQUEST),
+            (missing, "secret", StatusCode::NOT_FOUND),
+            // Codes are case insensitive
+            (&lowercase, "secret", StatusCode::SWITCHING_PROTOCOLS),
+        ] {
+            let response = test::call_service(&app, request(code, token)).await;
+            assert_eq!(response.status(), status, "tailing {code} with {token}");
+        }
+    }
+}
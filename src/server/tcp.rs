@@ -0,0 +1,248 @@
+use actix::prelude::*;
+use bytestring::ByteString;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use super::handover;
+use crate::capacity::{Capacity, CapacityClass};
+use crate::deadletter::DeadLetters;
+use crate::room::RoomManager;
+use crate::session::actor::{Disconnected, Incoming, Session};
+use crate::session::timings::SessionTimings;
+use crate::session::{
+    backpressure::Backlog,
+    features::FeatureFlags,
+    sink::{ClientSink, SharedFrame},
+    SessionManager,
+};
+
+/// Frames longer than this (in bytes) get the connection dropped
+const MAX_FRAME_LENGTH: usize = 64 * 1024;
+
+/// Address of the TCP gateway, read from `TCP_GATEWAY`. The gateway is off if it is unset.
+pub fn address() -> Option<String> {
+    std::env::var("TCP_GATEWAY").ok().filter(|x| !x.is_empty())
+}
+
+/// Plain TCP listener for clients that cannot speak websockets, such as consoles and
+/// microcontrollers. Every message is sent as a frame made of its length in bytes, as a 4 byte
+/// big endian integer, followed by the same JSON as on the websocket. TCP clients have no way to
+/// show a capacity key and always count as public traffic, see [crate::capacity].
+pub struct Gateway {
+    pub session_manager: Addr<SessionManager>,
+    pub room_manager: Addr<RoomManager>,
+    pub features: Arc<FeatureFlags>,
+    pub dead_letters: Addr<DeadLetters>,
+    pub timings: SessionTimings,
+    pub capacity: Arc<Capacity>,
+}
+
+impl Gateway {
+    pub async fn serve(self, addr: String) {
+        let listener = match handover::bind(&addr).await.and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        }) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("cannot start the tcp gateway on {addr}: {err}");
+                return;
+            }
+        };
+        log::info!("tcp gateway listening on {addr}");
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => self.connect(stream),
+                Err(err) => log::error!("cannot accept tcp client: {err}"),
+            }
+        }
+    }
+    fn connect(&self, stream: TcpStream) {
+        let Some(permit) = self.capacity.admit(CapacityClass::Public) else {
+            // Hanging up right away, the client can retry later
+            return log::warn!("turning away tcp client, the server is full");
+        };
+        if let Err(err) = stream.set_nodelay(true) {
+            log::warn!("cannot disable nagle's algorithm for tcp client: {err}");
+        }
+        let (reader, writer) = stream.into_split();
+        let (frames, outgoing) = unbounded_channel();
+        let backlog = Backlog::default();
+        let session = Session::new(
+            self.session_manager.clone(),
+            self.room_manager.clone(),
+            Arc::clone(&self.features),
+            self.dead_letters.clone(),
+            self.timings,
+            permit,
+            TcpSink {
+                frames: Some(frames),
+                backlog: backlog.clone(),
+            },
+        )
+        .start();
+        let reading = actix::spawn(read_frames(reader, session));
+        actix::spawn(async move {
+            match write_frames(writer, outgoing, backlog).await {
+                // The session is gone, whatever the client still sends has nowhere to go
+                Ok(()) => reading.abort(),
+                // The reader runs into the same problem and disconnects the session
+                Err(err) => log::error!("cannot write to tcp client: {err}"),
+            }
+        });
+    }
+}
+
+/// Queues frames for the connection's writer, which hangs up once the session closes the sink
+/// and everything queued before has been sent
+struct TcpSink {
+    frames: Option<UnboundedSender<ByteString>>,
+    /// Bytes queued that the writer has not written to the socket yet
+    backlog: Backlog,
+}
+
+impl ClientSink for TcpSink {
+    fn send(&mut self, frame: SharedFrame) {
+        if let Some(frames) = &self.frames {
+            self.backlog.push(frame.json().len());
+            // The writer only stops early if the client went away
+            let _ = frames.send(frame.into_json());
+        }
+    }
+    fn close(&mut self) {
+        self.frames.take();
+    }
+    fn backlog(&self) -> usize {
+        self.backlog.queued()
+    }
+}
+
+async fn read_frames(reader: OwnedReadHalf, session: Addr<Session>) {
+    let mut reader = BufReader::new(reader);
+    loop {
+        match read_frame(&mut reader).await {
+            Ok(Some(frame)) => {
+                if session.send(Incoming(frame)).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                log::error!("dropping tcp client: {err}");
+                break;
+            }
+        }
+    }
+    session.do_send(Disconnected);
+}
+
+/// Reads the next frame, or nothing if the client hung up in between two frames
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<ByteString>> {
+    let length = match reader.read_u32().await {
+        Ok(length) => length as usize,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if length > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+    }
+    let mut frame = vec![0; length];
+    reader.read_exact(&mut frame).await?;
+    let frame = String::from_utf8(frame)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "frame is not utf-8"))?;
+    Ok(Some(frame.into()))
+}
+
+/// Writes frames until the session closes its sink, then hangs up
+async fn write_frames(
+    mut writer: OwnedWriteHalf,
+    mut frames: UnboundedReceiver<ByteString>,
+    backlog: Backlog,
+) -> io::Result<()> {
+    while let Some(frame) = frames.recv().await {
+        let mut buf = Vec::with_capacity(4 + frame.len());
+        buf.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        buf.extend_from_slice(frame.as_bytes());
+        writer.write_all(&buf).await?;
+        backlog.pop(frame.len());
+    }
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::time::Duration;
+
+    async fn write_frame(client: &mut BufReader<TcpStream>, frame: &str) {
+        let mut buf = (frame.len() as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(frame.as_bytes());
+        client.get_mut().write_all(&buf).await.unwrap();
+    }
+
+    #[actix::test]
+    async fn clients_stay_connected_for_as_long_as_they_send_frames() {
+        let server = testing::Server::start();
+        let gateway = Gateway {
+            session_manager: server.session_manager.clone(),
+            room_manager: server.room_manager.clone(),
+            features: Arc::default(),
+            dead_letters: server.services.dead_letters.clone(),
+            timings: testing::impatient(),
+            capacity: Arc::clone(&server.services.capacity),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await;
+        gateway.connect(listener.accept().await.unwrap().0);
+        let mut client = BufReader::new(client.unwrap());
+        // TCP clients are never pinged, only what they send keeps them alive
+        let hello = r#"{"kind":"Hello","data":1}"#;
+        for _ in 0..20 {
+            write_frame(&mut client, hello).await;
+            actix::clock::sleep(Duration::from_millis(25)).await;
+        }
+        write_frame(&mut client, r#"{"kind":"Login","data":"ann"}"#).await;
+        let logged_in = async {
+            loop {
+                let frame = read_frame(&mut client).await.unwrap().expect("still connected");
+                if frame.contains("LoginResult") {
+                    break;
+                }
+            }
+        };
+        actix::clock::timeout(Duration::from_secs(1), logged_in).await.unwrap();
+        // Once the client goes quiet the connection is dropped
+        let hung_up = async { while read_frame(&mut client).await.unwrap().is_some() {} };
+        actix::clock::timeout(Duration::from_secs(1), hung_up).await.unwrap();
+    }
+
+    #[actix::test]
+    async fn frames_count_towards_the_backlog_until_written() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Connecting completes before the listener gets to accept
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await;
+        let accepted = listener.accept().await;
+        let (frames, outgoing) = unbounded_channel();
+        let mut sink = TcpSink {
+            frames: Some(frames),
+            backlog: Backlog::default(),
+        };
+        let frame = ByteString::from(r#"{"kind":"Welcome"}"#);
+        sink.send(frame.clone().into());
+        assert_eq!(sink.backlog(), frame.len());
+        sink.close();
+        let (_, writer) = accepted.unwrap().0.into_split();
+        write_frames(writer, outgoing, sink.backlog.clone())
+            .await
+            .unwrap();
+        assert_eq!(sink.backlog(), 0);
+        let mut client = BufReader::new(client.unwrap());
+        assert_eq!(read_frame(&mut client).await.unwrap(), Some(frame));
+        assert_eq!(read_frame(&mut client).await.unwrap(), None);
+    }
+}
@@ -1,25 +1,74 @@
-use crate::room::actor::JoinRoomError;
-use crate::room::{JoinRoom, RoomManager, RoomPair, ROOM_CODE_LENGTH};
+use crate::capacity::SessionPermit;
+use crate::deadletter::{DeadLetter, DeadLetterReason, DeadLetters};
+use crate::game::Input;
+use crate::version::{protocol_supported, BuildInfo, LEGACY_PROTOCOL_VERSION};
+use crate::room::actor::{
+    Acknowledge, BanError, Chat, CreateInvite, GameConfigOptions, GameInputError, InviteError,
+    JoinRoomError, Joiner, KickError, KickPlayer, LeaveQueue, ListBans, LobbyInteraction,
+    LockError, PauseError, PromoteError, PromoteLeader, RemovePlayer, RequestAlias, RequestReplay,
+    RequestStart, SetLocked, SetMetadata, SetPaused, StartGameError, SubmitInput, Unban,
+    UpdateRoomSettings,
+};
+use crate::room::browser::{ListRooms, ListRoomsError, RoomQuery};
+use crate::room::chat::ChatError;
+use crate::room::instant_replay::ReplayError;
+use crate::room::matching::MatchPreferences;
+use crate::room::metadata::{MetadataError, RoomMetadata};
+use crate::room::practice::PracticeSeed;
+use crate::room::settings::{SettingsError, SettingsUpdate};
+use crate::room::state::RoomState;
+use crate::room::{
+    normalize_alias, room_code_length, AliasError, CreateRoom, JoinRoom, RoomConfig, RoomManager,
+    RoomPair, RoomRef, SpectateRoom, StopMatching, MAX_ROOM_CODE_LENGTH, MIN_ROOM_CODE_LENGTH,
+};
 use actix::prelude::*;
-use actix_web_actors::ws::{self, ProtocolError, WebsocketContext};
+use bytestring::ByteString;
+use serde_json::value::RawValue;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use super::backpressure::{self, BackpressureConfig, Priority};
+use super::bot::{is_bot, BotLoginError};
+use super::catalog::{Countdown, Locale};
+use super::features::FeatureFlags;
+use super::latency::{Latency, LATENCY_REPORTS_SINCE};
+use super::profile::Profile;
+use super::presence::{
+    AnswerInvite, FriendInviteError, FriendsError, InRoom, InviteFriend, UpdateFriends,
+};
+use super::ratelimit::{RateLimiter, Verdict, BOT_FACTOR};
+use super::sink::{check_frame, ClientSink, SharedFrame};
+use super::timings::SessionTimings;
 use super::{message, RoomCode};
 
-use super::message::{IncomingMessage, OutgoingMessage};
-use super::{Register, Room, SessionManager, UpdateSessionRoomInfo};
-use super::{TransientId, Unregister};
+use super::message::{
+    IncomingEnvelope, IncomingMessage, JoinTarget, LeaveRoomError, LoginAs, OutgoingMessage,
+    PracticeRoom, Reply, RequestId, ResultOf,
+};
+use super::{Connect, Disconnect, LoginError, Register, RegisterBot, Resume, ResumeError};
+use super::{Room, SessionManager};
+use super::{
+    QueryPresence, TransientId, Unregister, UpdateSessionRoomInfo, Whisper, WhisperError,
+};
 use crate::session::message::RemoveReason;
 
 pub type UserId = Arc<str>;
 
-/// How long should we wait before completely disconnecting the client if inactive
-const RECONNECTION_TIME_LIMIT: u64 = 15;
-/// How frequently should the client check for staleness
-const HB_CHECK_INTERVAL: u64 = 5;
-/// How frequently should the client send heartbeat messages
-const HB_TIME_LIMIT: u64 = 2;
+/// Log target traced frames are written to, see [Trace]. It logs at info level so that tracing a
+/// session does not take turning up the log level of the whole server.
+pub const TRACE_TARGET: &str = "session_trace";
+/// Messages whose data, or the data of their result, is a secret of the client's, such as its
+/// resume token, bot key or an invite to a room
+const SECRET_KINDS: [&str; 6] = [
+    "LoginResult",
+    "ResumeResult",
+    "Reconnect",
+    "Resume",
+    "BotLogin",
+    "CreateInviteResult",
+];
+/// Fields left out of traced frames wherever they are
+const SECRET_FIELDS: [&str; 3] = ["password", "token", "invite"];
 
 /// Client session responsible for keeping track of client identity,
 /// handling client messages, etc
@@ -29,150 +78,1313 @@ pub struct Session {
     /// Server id that is used to identify the stream the client is connected over
     /// (this is a transient id and is not persisted)
     transient_id: Option<TransientId>,
-    /// Last recorded heartbeat time
+    /// When the client was last heard from, through a message or a pong
     hb: Instant,
+    /// When the client last sent a message that wasn't protocol housekeeping, see
+    /// [IncomingMessage::is_activity]
+    last_activity: Instant,
     /// Address of the [Server] actor
     session_manager: Addr<SessionManager>,
-    /// [SpawnHandle] of the reconnection timer if the client has disconnected
-    /// If the client doesnt reconnect before this timer runs out, the client
-    /// will be removed from any rooms they might be in
-    reconnection_timer: Option<SpawnHandle>,
+    /// [SpawnHandle] of the timer stopping the session once its connection went stale
+    stale_timer: Option<SpawnHandle>,
     /// [Addr] of the [Room] actor, if the client is in a room
     room: Option<Addr<Room>>,
     room_manager: Addr<RoomManager>,
+    /// Features switched off on this server, shared by all sessions
+    features: Arc<FeatureFlags>,
+    /// Display name and looks presented to the rooms the client joins
+    profile: Option<Profile>,
+    /// Pending search for a random room
+    match_search: Option<SpawnHandle>,
+    dead_letters: Addr<DeadLetters>,
+    /// Language of the client, random joins favor rooms played in it
+    locale: Option<Box<str>>,
+    /// Locale the client declared when logging in, the text it shows as is gets translated to
+    /// it, see [super::catalog]
+    catalog: Option<Locale>,
+    /// Full room the client waits in line for a seat in, if any
+    queued: Option<Addr<Room>>,
+    /// Transport the client is connected over
+    sink: Box<dyn ClientSink>,
+    /// Counts the session against the share of its class, see [crate::capacity]
+    permit: SessionPermit,
+    /// Budgets for the messages the client sends, see [super::ratelimit]
+    limiter: RateLimiter,
+    /// Frames to and from the client are logged until then, see [Trace]
+    traced_until: Option<Instant>,
+    /// Version of the protocol the client speaks, see [IncomingMessage::Hello]
+    protocol: u32,
+    /// Id the client gave the request being handled, echoed in its result, see [Session::reply]
+    request: Option<RequestId>,
+    /// Round trip time of the client's connection, random joins prefer rooms of players with
+    /// similar ones
+    latency: Latency,
+    timings: SessionTimings,
+    /// When to shed messages to a client that falls behind, and when to give up on it
+    backpressure: BackpressureConfig,
+    /// Set once the client fell too far behind, nothing is sent to it anymore until the
+    /// session stops
+    overflowed: bool,
 }
 
 impl Session {
-    pub fn new(session_manager: Addr<SessionManager>, room_manager: Addr<RoomManager>) -> Self {
+    pub fn new(
+        session_manager: Addr<SessionManager>,
+        room_manager: Addr<RoomManager>,
+        features: Arc<FeatureFlags>,
+        dead_letters: Addr<DeadLetters>,
+        timings: SessionTimings,
+        permit: SessionPermit,
+        sink: impl ClientSink,
+    ) -> Self {
         Self {
+            sink: Box::new(sink),
+            dead_letters,
+            locale: None,
+            catalog: None,
             room_manager,
+            features,
             transient_id: None,
             id: None,
             hb: Instant::now(),
+            last_activity: Instant::now(),
             session_manager,
-            reconnection_timer: None,
+            stale_timer: None,
             room: None,
+            profile: None,
+            match_search: None,
+            queued: None,
+            permit,
+            limiter: RateLimiter::default(),
+            traced_until: None,
+            protocol: LEGACY_PROTOCOL_VERSION,
+            request: None,
+            latency: Latency::new(Instant::now()),
+            timings,
+            backpressure: BackpressureConfig::default(),
+            overflowed: false,
         }
     }
-    /// checks for ping every [HB_CHECK_INTERVAL] seconds.
-    /// If the last ping was recorded earlier than [HB_TIME_LIMIT] seconds ago, then the
-    /// client must have disconnected or have had some kind of network interruption
+    /// Limits on the messages queued for the client, see [super::backpressure]
+    pub fn backpressure(self, backpressure: BackpressureConfig) -> Self {
+        Self {
+            backpressure,
+            ..self
+        }
+    }
+    /// Language the client asked for when connecting, if it did
+    pub fn locale(self, locale: Option<Box<str>>) -> Self {
+        Self { locale, ..self }
+    }
+    /// Version of the protocol the client stated when connecting, if it did
+    pub fn speaking(self, protocol: Option<u32>) -> Self {
+        Self {
+            protocol: protocol.unwrap_or(self.protocol),
+            ..self
+        }
+    }
+    /// Turns away clients speaking a version of the protocol the server does not
+    fn check_protocol(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if !protocol_supported(self.protocol) {
+            log::warn!("disconnecting client speaking protocol v{}", self.protocol);
+            self.send(OutgoingMessage::ForceDisconnect(RemoveReason::UnsupportedProtocol));
+            ctx.stop();
+        }
+    }
+    /// Hands a message to the transport the client is connected over
+    fn send(&mut self, msg: impl Into<ByteString>) {
+        self.send_with(msg.into(), Priority::Normal);
+    }
+    /// Hands a frame to the transport unless the client is too far behind to take it. Clients
+    /// that fall behind lose low priority frames first and are disconnected if they keep falling
+    /// behind, see [super::backpressure].
+    fn send_with(&mut self, frame: ByteString, priority: Priority) {
+        self.send_frame(frame.into(), priority);
+    }
+    /// Same as [Session::send_with] for a frame that may be shared with other sessions
+    fn send_frame(&mut self, frame: SharedFrame, priority: Priority) {
+        if self.overflowed {
+            return;
+        }
+        let queued = self.sink.backlog();
+        match self.backpressure.check(queued, priority) {
+            backpressure::Verdict::Send => {}
+            backpressure::Verdict::Shed => return,
+            backpressure::Verdict::Disconnect => {
+                log::warn!("disconnecting {:?}, {queued} bytes behind", self.id);
+                self.overflowed = true;
+                // Queued behind everything else, the client only learns why if it catches up.
+                // The session stops on its next heartbeat.
+                let msg: ByteString =
+                    OutgoingMessage::ForceDisconnect(RemoveReason::SlowConnection).into();
+                self.sink.send(msg.into());
+                self.sink.close();
+                return;
+            }
+        }
+        self.trace("<-", frame.json());
+        self.sink.send(frame);
+    }
+    /// Answers the request being handled, see [IncomingEnvelope::request_id]
+    fn reply(&mut self, msg: OutgoingMessage) {
+        self.reply_to(self.request.clone(), msg);
+    }
+    /// Answers a request the session kept handling others after, which is why its id has to
+    /// be held on to by the caller
+    fn reply_to(&mut self, request_id: Option<RequestId>, msg: OutgoingMessage) {
+        let reply = Reply { msg, request_id };
+        let locale = self.catalog.unwrap_or_default();
+        self.send(reply.render(self.protocol, locale));
+    }
+    /// Logs a frame to or from the client while the session is traced, without its secrets
+    fn trace(&self, direction: &str, frame: &str) {
+        if self.traced_until.is_some_and(|until| Instant::now() < until) {
+            let user = self.id.as_deref().unwrap_or_default();
+            let frame = redacted(frame);
+            log::info!(target: TRACE_TARGET, "{user} {direction} {frame}");
+        }
+    }
+    /// checks for ping every [SessionTimings::heartbeat_interval].
+    /// If the last ping was recorded longer than [SessionTimings::heartbeat_timeout] ago, then
+    /// the client must have disconnected or have had some kind of network interruption, and is
+    /// given [SessionTimings::reconnection_time_limit] to pick back up. Clients that went idle
+    /// are disconnected on the spot, see [Session::is_idle]
     fn heartbeat(&mut self, ctx: &mut <Self as Actor>::Context) {
-        ctx.run_interval(Duration::from_secs(HB_CHECK_INTERVAL), |act, ctx| {
-            if Instant::now().duration_since(act.hb).as_secs() >= HB_TIME_LIMIT {
-                act.reconnection_timer = Some(ctx.run_later(
-                    Duration::from_secs(RECONNECTION_TIME_LIMIT),
-                    |_, ctx| {
-                        // This task is cancelled when the client reconnects with another stream.
-                        // See [Stop]
-                        ctx.stop();
-                    },
-                ));
+        ctx.run_interval(self.timings.heartbeat_interval, |act, ctx| {
+            // Clients that fell too far behind are not waited on, see [Session::send_with]
+            if act.overflowed {
+                ctx.stop();
+                return;
+            }
+            if act.is_idle() {
+                log::info!("disconnecting idle client {:?}", act.id);
+                act.send(OutgoingMessage::ForceDisconnect(RemoveReason::Idle));
+                ctx.stop();
+                return;
+            }
+            let stale = act.hb.elapsed() >= act.timings.heartbeat_timeout;
+            match act.stale_timer {
+                None if stale => {
+                    act.stale_timer = Some(ctx.run_later(
+                        act.timings.reconnection_time_limit,
+                        |_, ctx| ctx.stop(),
+                    ));
+                }
+                // The connection picked back up in time
+                Some(timer) if !stale => {
+                    ctx.cancel_future(timer);
+                    act.stale_timer = None;
+                }
+                _ => {}
             }
         });
     }
-    fn join_room(&mut self, code: Option<RoomCode>, ctx: &mut <Self as Actor>::Context) {
-        self.room_manager
+    /// Whether the client went quiet for longer than [SessionTimings::idle_timeout] without
+    /// being in a room, waiting for a seat in one or looking for one
+    fn is_idle(&self) -> bool {
+        self.room.is_none()
+            && self.queued.is_none()
+            && self.match_search.is_none()
+            && self.last_activity.elapsed() >= self.timings.idle_timeout
+    }
+    /// Pings the client every [SessionTimings::ping_interval] to keep track of its round trip
+    /// time, see [Pong]
+    fn ping(&mut self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(self.timings.ping_interval, |act, _| {
+            let payload = act.latency.ping(Instant::now());
+            act.sink.ping(payload);
+        });
+    }
+    /// Who the client joins rooms as, which it has to be logged in for
+    fn joiner(&self, ctx: &mut <Self as Actor>::Context) -> Result<Joiner, JoinRoomError> {
+        let (Some(transient_id), Some(user)) = (self.transient_id, &self.id) else {
+            log::error!("client asked for a room before logging in");
+            return Err(JoinRoomError::InternalServerError);
+        };
+        Ok(Joiner {
+            session: (transient_id, ctx.address()),
+            user: Arc::clone(user),
+            profile: self.profile.clone(),
+            password: None,
+            class: self.permit.class,
+            rtt: self.latency.rtt(),
+        })
+    }
+    /// Tells the session manager which room the client is in now, if any. Clients sitting in a
+    /// room quietly until they are out of it are given the full [SessionTimings::idle_timeout]
+    /// from then on.
+    fn moved(&mut self, room: Option<(Addr<Room>, &RoomCode)>) {
+        let transient_id = self.transient_id.expect("must be registered");
+        if room.is_none() {
+            self.last_activity = Instant::now();
+        }
+        if let Some(user) = &self.id {
+            self.session_manager.do_send(InRoom {
+                user: Arc::clone(user),
+                room: room
+                    .as_ref()
+                    .map(|(_, code)| String::from_utf8_lossy(code).into_owned()),
+            });
+        }
+        let addr = room.map(|(addr, _)| addr);
+        self.session_manager.do_send(UpdateSessionRoomInfo(transient_id, addr));
+    }
+    /// Keeps track of the room a client reconnecting to its session is back in
+    fn rejoined(&mut self, room: Addr<Room>, code: &RoomCode) {
+        if self.room.is_none() {
+            self.room = Some(room.clone());
+            self.moved(Some((room, code)));
+        }
+    }
+    /// Keeps track of the room the client got into
+    fn entered(
+        &mut self,
+        res: Result<Result<RoomPair, JoinRoomError>, MailboxError>,
+    ) -> Result<RoomCode, JoinRoomError> {
+        match res {
+            Ok(Ok(RoomPair { code, addr })) => {
+                self.room = Some(addr.clone());
+                self.moved(Some((addr, &code)));
+                Ok(code)
+            }
+            Ok(Err(err)) => Err(err),
+            Err(err) => {
+                log::error!("{err}");
+                Err(JoinRoomError::InternalServerError)
+            }
+        }
+    }
+    /// Asks the room manager for a seat, keeping track of the room on success
+    fn request_join(
+        &mut self,
+        target: Option<RoomRef>,
+        preferences: MatchPreferences,
+        password: Option<Box<str>>,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> ResponseActFuture<Self, Result<RoomCode, JoinRoomError>> {
+        let joiner = match self.joiner(ctx) {
+            Ok(joiner) => Joiner { password, ..joiner },
+            Err(err) => return Box::pin(actix::fut::ready(Err(err))),
+        };
+        let request = self
+            .room_manager
             .send(JoinRoom {
-                session: (
-                    self.transient_id.expect("must be registered"),
-                    ctx.address(),
-                ),
-                code,
+                joiner,
+                target,
+                preferences,
             })
             .into_actor(self)
-            .then(|res, act, ctx| {
+            .map(|res, act, _| act.entered(res));
+        Box::pin(request)
+    }
+    /// Watches a room without taking a seat in it
+    fn spectate(&mut self, target: RoomRef, ctx: &mut <Self as Actor>::Context) {
+        let joiner = match self.joiner(ctx) {
+            Ok(joiner) => joiner,
+            Err(err) => {
+                self.reply(OutgoingMessage::SpectateResult(message::Result::Error(err)));
+                return;
+            }
+        };
+        self.room_manager
+            .send(SpectateRoom { joiner, target })
+            .into_actor(self)
+            .map(|res, act, _| {
+                let result = match act.entered(res) {
+                    Ok(code) => {
+                        message::Result::Success(code_to_string(&code).unwrap().to_string())
+                    }
+                    Err(err) => message::Result::Error(err),
+                };
+                act.reply(OutgoingMessage::SpectateResult(result));
+            })
+            .wait(ctx);
+    }
+    /// `password` is only needed for rooms behind one, see [IncomingMessage::CreateRoom]
+    fn join_room(
+        &mut self,
+        target: RoomRef,
+        password: Option<Box<str>>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        self.request_join(Some(target), Default::default(), password, ctx)
+            .map(|res, act, _| {
+                let result = match res {
+                    Ok(code) => {
+                        message::Result::Success(code_to_string(&code).unwrap().to_string())
+                    }
+                    Err(err) => message::Result::Error(err),
+                };
+                act.reply(OutgoingMessage::JoinRoomResult(result));
+            })
+            .wait(ctx);
+    }
+    fn create_room(
+        &mut self,
+        room_config: Result<RoomConfig, JoinRoomError>,
+        game_config: GameConfigOptions,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let room_config = match room_config {
+            Ok(_) if self.room.is_some() => Err(JoinRoomError::AlreadyInRoom),
+            res => res,
+        };
+        let request = room_config.and_then(|room_config| Ok((room_config, self.joiner(ctx)?)));
+        let (room_config, leader) = match request {
+            Ok(request) => request,
+            Err(err) => {
+                self.reply(OutgoingMessage::Result(ResultOf::CreateRoom(
+                    message::Result::Error(err),
+                )));
+                return;
+            }
+        };
+        self.room_manager
+            .send(CreateRoom {
+                leader,
+                room_config,
+                game_config,
+            })
+            .into_actor(self)
+            .map(|res, act, _| {
+                let result = match act.entered(res) {
+                    Ok(code) => {
+                        message::Result::Success(code_to_string(&code).unwrap().to_string())
+                    }
+                    Err(err) => message::Result::Error(err),
+                };
+                act.reply(OutgoingMessage::Result(ResultOf::CreateRoom(result)));
+            })
+            .wait(ctx);
+    }
+    /// Opens a solo room playing the practice puzzle of the seed, or of a new one
+    fn practice(&mut self, seed: Option<PracticeSeed>, ctx: &mut <Self as Actor>::Context) {
+        let seed = seed.unwrap_or_else(PracticeSeed::random);
+        self.request_join(Some(RoomRef::Practice(seed)), Default::default(), None, ctx)
+            .map(move |res, act, _| {
+                let result = match res {
+                    Ok(code) => message::Result::Success(PracticeRoom {
+                        code: code_to_string(&code).unwrap().to_string(),
+                        seed,
+                    }),
+                    Err(err) => message::Result::Error(err),
+                };
+                act.reply(OutgoingMessage::PracticeResult(result));
+            })
+            .wait(ctx);
+    }
+    /// Looks for a random room that fits the preferences. The session keeps handling messages
+    /// while the matchmaker looks, which can take up to `max_wait` seconds.
+    fn find_match(
+        &mut self,
+        mut preferences: MatchPreferences,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        preferences.locale = self.locale.clone();
+        // Other requests are handled while the search goes on
+        let request = self.request.clone();
+        let search = self
+            .request_join(None, preferences, None, ctx)
+            .map(|res, act, _| {
+                act.match_search = None;
+                let msg = match res {
+                    Ok(code) => {
+                        OutgoingMessage::MatchFound(code_to_string(&code).unwrap().to_string())
+                    }
+                    Err(reason) => OutgoingMessage::MatchFailed { reason },
+                };
+                act.reply_to(request, msg);
+            });
+        self.match_search = Some(ctx.spawn(search));
+    }
+    /// Gives up on the random room the client is looking for, if any
+    fn stop_matching(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if let Some(handle) = self.match_search.take() {
+            ctx.cancel_future(handle);
+            if let Some(transient_id) = self.transient_id {
+                self.room_manager.do_send(StopMatching(transient_id));
+            }
+        }
+    }
+    fn submit_input(&mut self, input: Input, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::GameInputResult(message::Result::Error(
+                GameInputError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(SubmitInput {
+            transient_id,
+            input,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(GameInputError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::GameInputResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn set_room_alias(&mut self, alias: Box<str>, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::SetRoomAliasResult(message::Result::Error(
+                AliasError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(RequestAlias {
+            transient_id,
+            alias,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(alias)) => message::Result::Success(alias.into()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(AliasError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::SetRoomAliasResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn kick_player(&mut self, target: TransientId, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::KickPlayerResult(message::Result::Error(
+                KickError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(KickPlayer {
+            transient_id,
+            target,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(KickError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::KickPlayerResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn unban(&mut self, target: TransientId, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::UnbanResult(message::Result::Error(
+                BanError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(Unban {
+            transient_id,
+            target,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(BanError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::UnbanResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn list_bans(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::BanList(message::Result::Error(
+                BanError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(ListBans(transient_id))
+            .into_actor(self)
+            .then(|res, act, _| {
                 let result = match res {
-                    Ok(res) => match res {
-                        Ok(RoomPair { code, addr }) => {
-                            act.room = Some(addr.clone());
-                            act.session_manager.do_send(UpdateSessionRoomInfo(
-                                act.transient_id.expect("must be registered"),
-                                Some(addr),
-                            ));
-                            message::Result::Success(code_to_string(&code).unwrap().to_string())
+                    Ok(Ok(bans)) => message::Result::Success(bans),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(BanError::InternalServerError)
+                    }
+                };
+                act.reply(OutgoingMessage::BanList(result));
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+    fn instant_replay(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::InstantReplay(message::Result::Error(
+                ReplayError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(RequestReplay(transient_id))
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Ok(clip)) => message::Result::Success(clip),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(ReplayError::InternalServerError)
+                    }
+                };
+                act.reply(OutgoingMessage::InstantReplay(result));
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+    fn list_rooms(&mut self, query: RoomQuery, ctx: &mut <Self as Actor>::Context) {
+        self.room_manager
+            .send(ListRooms(query))
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(page) => message::Result::Success(page),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(ListRoomsError::InternalServerError)
+                    }
+                };
+                act.reply(OutgoingMessage::RoomList(result));
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+    fn promote_leader(&mut self, target: TransientId, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::PromoteLeaderResult(message::Result::Error(
+                PromoteError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(PromoteLeader {
+            transient_id,
+            target,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(PromoteError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::PromoteLeaderResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn set_locked(&mut self, locked: bool, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::SetLockedResult(message::Result::Error(
+                LockError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(SetLocked {
+            transient_id,
+            locked,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(LockError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::SetLockedResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn set_paused(&mut self, paused: bool, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::SetPausedResult(message::Result::Error(
+                PauseError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(SetPaused {
+            transient_id,
+            paused,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(PauseError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::SetPausedResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn start_game(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::Result(ResultOf::StartGame(message::Result::Error(
+                StartGameError::NotInRoom,
+            ))));
+            return;
+        };
+        room.send(RequestStart(transient_id))
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Ok(())) => message::Result::Success(()),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(StartGameError::InternalServerError)
+                    }
+                };
+                act.reply(OutgoingMessage::Result(ResultOf::StartGame(result)));
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+    fn set_room_metadata(
+        &mut self,
+        key: String,
+        value: String,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::SetRoomMetadataResult(message::Result::Error(
+                MetadataError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(SetMetadata {
+            transient_id,
+            key,
+            value,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(MetadataError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::SetRoomMetadataResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    /// Leaves the room for good, the room doesn't hold the client's seat as it does when the
+    /// connection drops
+    fn leave_room(&mut self, ctx: &mut <Self as Actor>::Context) {
+        self.stop_matching(ctx);
+        self.leave_queue();
+        let (Some(room), Some(transient_id)) = (self.room.take(), self.transient_id) else {
+            self.reply(OutgoingMessage::LeaveRoomResult(message::Result::Error(
+                LeaveRoomError::NotInRoom,
+            )));
+            return;
+        };
+        room.do_send(RemovePlayer {
+            transient_id,
+            reason: RemoveReason::LeaveRequested,
+        });
+        self.moved(None);
+        self.reply(OutgoingMessage::LeaveRoomResult(message::Result::Success(())));
+    }
+    /// Gives up the client's place in the queue of a full room, if it has one
+    fn leave_queue(&mut self) {
+        if let (Some(room), Some(transient_id)) = (self.queued.take(), self.transient_id) {
+            room.do_send(LeaveQueue(transient_id));
+        }
+    }
+    /// Picks up where the client left off on the previous process, rejoining its room if it was
+    /// in one. See [crate::server::handover].
+    fn resume(&mut self, token: &str, ctx: &mut <Self as Actor>::Context) {
+        if self.id.is_some() {
+            self.reply(OutgoingMessage::ResumeResult(message::Result::Error(
+                ResumeError::AlreadyLoggedIn,
+            )));
+            return;
+        }
+        self.session_manager
+            .send(Resume {
+                token: token.to_string(),
+                session_addr: ctx.address(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(Some((transient_id, summary))) => {
+                        act.id = Some(summary.user);
+                        act.transient_id = Some(transient_id);
+                        act.profile = summary.profile;
+                        act.catalog = summary.locale;
+                        act.reply(OutgoingMessage::ResumeResult(message::Result::Success(
+                            summary.token,
+                        )));
+                        // The room keeps its code even if this process generates longer ones
+                        let code = summary.room.as_deref().map(str::as_bytes);
+                        if let Some(Ok(code)) = code.map(RoomCode::try_from) {
+                            act.join_room(RoomRef::Code(code), None, ctx);
                         }
-                        Err(err) => message::Result::Error(err)
-                    },
+                    }
+                    Ok(None) => act.reply(OutgoingMessage::ResumeResult(message::Result::Error(
+                        ResumeError::InvalidToken,
+                    ))),
+                    Err(err) => {
+                        log::error!("{err}");
+                        act.reply(OutgoingMessage::ResumeResult(message::Result::Error(
+                            ResumeError::InternalServerError,
+                        )));
+                    }
+                }
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+    /// `reconnect` holds the resume token of the session the client lost and the last room
+    /// event it got, see [IncomingMessage::Reconnect]. A `locale` declared by the client takes
+    /// the place of the one its connection asked for, see [LoginAs::Localized].
+    fn login(
+        &mut self,
+        id: &str,
+        locale: Option<&str>,
+        reconnect: Option<(&str, Option<u64>)>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        if self.id.is_some() {
+            log::error!("attempting to re-login");
+            self.reply(OutgoingMessage::LoginResult(message::Result::Error(
+                LoginError::AlreadyLoggedIn,
+            )));
+        } else if is_bot(id) {
+            // Bots have to show their key, see [IncomingMessage::BotLogin]
+            log::error!("refusing player login as {id}");
+            self.reply(OutgoingMessage::LoginResult(message::Result::Error(LoginError::Reserved)));
+        } else {
+            if let Some(locale) = locale {
+                self.catalog = Some(Locale::parse(locale));
+                self.locale = Some(locale.into());
+            }
+            let id = Arc::from(id);
+            self.id = Some(Arc::clone(&id));
+            self.session_manager
+                .send(Register {
+                    session_addr: ctx.address(),
+                    user_id: id,
+                    reconnect: reconnect.map(|(token, last_seq)| (token.into(), last_seq)),
+                    locale: self.catalog,
+                })
+                .into_actor(self)
+                .then(|res, act, _| {
+                    let result = match res {
+                        Ok(Ok((transient_id, token))) => {
+                            act.transient_id = Some(transient_id);
+                            message::Result::Success(token.into())
+                        }
+                        Ok(Err(err)) => {
+                            act.id = None;
+                            message::Result::Error(err)
+                        }
+                        Err(err) => {
+                            log::error!("{err}");
+                            act.id = None;
+                            message::Result::Error(LoginError::InternalServerError)
+                        }
+                    };
+                    act.reply(OutgoingMessage::LoginResult(result));
+                    actix::fut::ready(())
+                })
+                .wait(ctx);
+        }
+    }
+    fn bot_login(&mut self, key: &str, ctx: &mut <Self as Actor>::Context) {
+        if self.id.is_some() {
+            self.reply(OutgoingMessage::BotLoginResult(message::Result::Error(
+                BotLoginError::AlreadyLoggedIn,
+            )));
+            return;
+        }
+        self.session_manager
+            .send(RegisterBot {
+                session_addr: ctx.address(),
+                key: key.into(),
+            })
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Some((transient_id, user_id))) => {
+                        act.id = Some(user_id);
+                        act.transient_id = Some(transient_id);
+                        act.limiter = RateLimiter::new(BOT_FACTOR);
+                        message::Result::Success(())
+                    }
+                    Ok(None) => message::Result::Error(BotLoginError::InvalidKey),
                     Err(err) => {
                         log::error!("{err}");
-                        message::Result::Error(JoinRoomError::InternalServerError)
+                        message::Result::Error(BotLoginError::InternalServerError)
                     }
                 };
-                ctx.text(OutgoingMessage::JoinRoomResult(result));
+                act.reply(OutgoingMessage::BotLoginResult(result));
                 actix::fut::ready(())
             })
             .wait(ctx);
     }
+    fn create_invite(&mut self, single_use: bool, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::CreateInviteResult(message::Result::Error(
+                InviteError::NotInRoom,
+            )));
+            return;
+        };
+        room.send(CreateInvite {
+            transient_id,
+            single_use,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(token)) => message::Result::Success(token),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(InviteError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::CreateInviteResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn update_room_settings(
+        &mut self,
+        update: SettingsUpdate,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::UpdateRoomSettingsResult(
+                message::Result::Error(SettingsError::NotInRoom),
+            ));
+            return;
+        };
+        room.send(UpdateRoomSettings {
+            transient_id,
+            update,
+        })
+        .into_actor(self)
+        .then(|res, act, _| {
+            let result = match res {
+                Ok(Ok(())) => message::Result::Success(()),
+                Ok(Err(err)) => message::Result::Error(err),
+                Err(err) => {
+                    log::error!("{err}");
+                    message::Result::Error(SettingsError::InternalServerError)
+                }
+            };
+            act.reply(OutgoingMessage::UpdateRoomSettingsResult(result));
+            actix::fut::ready(())
+        })
+        .wait(ctx);
+    }
+    fn chat(&mut self, text: String, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) else {
+            self.reply(OutgoingMessage::ChatRejected(ChatError::NotInRoom));
+            return;
+        };
+        let request = self.request.clone();
+        room.send(Chat { transient_id, text })
+            .into_actor(self)
+            .then(|res, act, _| {
+                match res {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => act.reply_to(request, OutgoingMessage::ChatRejected(err)),
+                    Err(err) => {
+                        log::error!("{err}");
+                        let err = ChatError::InternalServerError;
+                        act.reply_to(request, OutgoingMessage::ChatRejected(err));
+                    }
+                }
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn whisper(&mut self, to: UserId, text: String, ctx: &mut <Self as Actor>::Context) {
+        let Some(from) = self.id.clone() else {
+            self.reply(OutgoingMessage::WhisperResult(message::Result::Error(
+                WhisperError::NotLoggedIn,
+            )));
+            return;
+        };
+        let request = self.request.clone();
+        self.session_manager
+            .send(Whisper { from, to, text })
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Ok(())) => message::Result::Success(()),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(WhisperError::InternalServerError)
+                    }
+                };
+                act.reply_to(request, OutgoingMessage::WhisperResult(result));
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn query_presence(&mut self, users: Vec<String>, ctx: &mut <Self as Actor>::Context) {
+        let users = users.into_iter().map(UserId::from).collect();
+        let request = self.request.clone();
+        self.session_manager
+            .send(QueryPresence {
+                from: self.id.clone(),
+                users,
+            })
+            .into_actor(self)
+            .then(|res, act, _| {
+                match res {
+                    Ok(entries) => act.reply_to(request, OutgoingMessage::Presence(entries)),
+                    Err(err) => log::error!("{err}"),
+                }
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn update_friends(
+        &mut self,
+        add: Vec<String>,
+        remove: Vec<String>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let Some(user) = self.id.clone() else {
+            self.reply(OutgoingMessage::Friends(message::Result::Error(
+                FriendsError::NotLoggedIn,
+            )));
+            return;
+        };
+        let request = self.request.clone();
+        self.session_manager
+            .send(UpdateFriends {
+                user,
+                add: add.into_iter().map(UserId::from).collect(),
+                remove: remove.into_iter().map(UserId::from).collect(),
+            })
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Ok(entries)) => message::Result::Success(entries),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(FriendsError::InternalServerError)
+                    }
+                };
+                act.reply_to(request, OutgoingMessage::Friends(result));
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn invite_friend(&mut self, to: &str, ctx: &mut <Self as Actor>::Context) {
+        let Some(from) = self.id.clone() else {
+            self.reply(OutgoingMessage::InviteFriendResult(message::Result::Error(
+                FriendInviteError::NotLoggedIn,
+            )));
+            return;
+        };
+        let name = match &self.profile {
+            Some(profile) => profile.name.clone(),
+            None => from.to_string(),
+        };
+        let request = self.request.clone();
+        self.session_manager
+            .send(InviteFriend {
+                from,
+                name,
+                to: to.into(),
+            })
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Ok(id)) => message::Result::Success(id),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(FriendInviteError::InternalServerError)
+                    }
+                };
+                act.reply_to(request, OutgoingMessage::InviteFriendResult(result));
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
+    fn answer_invite(&mut self, id: u64, accept: bool, ctx: &mut <Self as Actor>::Context) {
+        let Some(user) = self.id.clone() else {
+            self.reply(OutgoingMessage::AnswerInviteResult(message::Result::Error(
+                FriendInviteError::NotLoggedIn,
+            )));
+            return;
+        };
+        let request = self.request.clone();
+        self.session_manager
+            .send(AnswerInvite { user, id, accept })
+            .into_actor(self)
+            .then(|res, act, _| {
+                let result = match res {
+                    Ok(Ok(())) => message::Result::Success(()),
+                    Ok(Err(err)) => message::Result::Error(err),
+                    Err(err) => {
+                        log::error!("{err}");
+                        message::Result::Error(FriendInviteError::InternalServerError)
+                    }
+                };
+                act.reply_to(request, OutgoingMessage::AnswerInviteResult(result));
+                actix::fut::ready(())
+            })
+            .spawn(ctx);
+    }
     fn handle_message(&mut self, msg: IncomingMessage, ctx: &mut <Self as Actor>::Context) {
+        let class = msg.rate_class();
+        match self.limiter.check(class, Instant::now()) {
+            Verdict::Allowed => {}
+            Verdict::Limited(retry_in) => {
+                self.reply(OutgoingMessage::RateLimited {
+                    class,
+                    retry_in: retry_in.as_millis() as u64,
+                });
+                return;
+            }
+            Verdict::Abusive => {
+                log::warn!("disconnecting {:?} for flooding {class:?} messages", self.id);
+                self.send(OutgoingMessage::ForceDisconnect(RemoveReason::RateLimited));
+                ctx.stop();
+                return;
+            }
+        }
+        if let Some(feature) = msg.required_feature() {
+            if !self.features.is_enabled(feature) {
+                self.reply(OutgoingMessage::FeatureDisabled(feature));
+                return;
+            }
+        }
+        if msg.is_activity() {
+            self.last_activity = Instant::now();
+        }
+        let since = msg.since();
+        if since > self.protocol {
+            self.reply(OutgoingMessage::UnsupportedMessage { since });
+            return;
+        }
         match msg {
-            IncomingMessage::Login(id) => {
-                if let Some(_) = &self.id {
-                    log::error!("attempting to re-login");
-                } else {
-                    let id = Arc::from(id);
-                    self.id = Some(Arc::clone(&id));
-                    self.session_manager
-                        .send(Register {
-                            session_addr: ctx.address(),
-                            user_id: id,
-                        })
-                        .into_actor(self)
-                        .then(|res, act, _| {
-                            match res {
-                                Ok(transient_id) => act.transient_id = Some(transient_id),
-                                Err(err) => log::error!("{err}"),
-                            }
-                            actix::fut::ready(())
-                        })
-                        .wait(ctx);
-                }
+            IncomingMessage::Hello(version) => {
+                self.protocol = version;
+                self.check_protocol(ctx);
+            }
+            IncomingMessage::Login(LoginAs::User(id)) => self.login(id, None, None, ctx),
+            IncomingMessage::Login(LoginAs::Localized { user, locale }) => {
+                self.login(user, Some(locale), None, ctx)
             }
+            IncomingMessage::Reconnect {
+                user,
+                token,
+                last_seq,
+                locale,
+            } => self.login(user, locale, Some((token, last_seq)), ctx),
+            IncomingMessage::BotLogin(key) => self.bot_login(key, ctx),
+            IncomingMessage::Resume(token) => self.resume(token, ctx),
             IncomingMessage::Logout => {
+                self.stop_matching(ctx);
+                self.leave_queue();
                 if let Some(transient_id) = self.transient_id.take() {
-                    self.id.take();
+                    let user = self.id.take();
                     let reason = RemoveReason::Logout;
                     self.session_manager.do_send(Unregister {
                         transient_id,
+                        user,
                         reason,
                     });
                 }
                 ctx.stop();
             }
-            IncomingMessage::JoinRoom(code) => {
-                let res = code.map_or(Ok(None), |code| {
-                    string_to_code(code).map_or_else(
-                        |_| {
-                            ctx.text(OutgoingMessage::JoinRoomResult(message::Result::Error(JoinRoomError::InvalidCode)));
-                            Err(())
-                        },
-                        |chars| Ok(Some(chars)),
-                    )
-                });
-                if let Ok(code) = res {
-                    self.join_room(code, ctx)
+            IncomingMessage::JoinRoom(target) => {
+                self.stop_matching(ctx);
+                self.leave_queue();
+                match target {
+                    Some(JoinTarget::Code(code)) => match room_ref(&code) {
+                        Ok(target) => self.join_room(target, None, ctx),
+                        Err(_) => self.reply(OutgoingMessage::JoinRoomResult(
+                            message::Result::Error(JoinRoomError::InvalidCode),
+                        )),
+                    },
+                    Some(JoinTarget::Protected { code, password }) => match room_ref(&code) {
+                        Ok(target) => self.join_room(target, Some(password.into()), ctx),
+                        Err(_) => self.reply(OutgoingMessage::JoinRoomResult(
+                            message::Result::Error(JoinRoomError::InvalidCode),
+                        )),
+                    },
+                    Some(JoinTarget::Invite { invite }) => {
+                        self.join_room(RoomRef::Invite(invite.into()), None, ctx)
+                    }
+                    Some(JoinTarget::Match(preferences)) => self.find_match(preferences, ctx),
+                    None => self.find_match(Default::default(), ctx),
+                }
+            }
+            IncomingMessage::Spectate(code) => {
+                self.stop_matching(ctx);
+                self.leave_queue();
+                match room_ref(code) {
+                    Ok(target) => self.spectate(target, ctx),
+                    Err(_) => self.reply(OutgoingMessage::SpectateResult(
+                        message::Result::Error(JoinRoomError::InvalidCode),
+                    )),
+                }
+            }
+            IncomingMessage::Practice(seed) => {
+                self.stop_matching(ctx);
+                self.leave_queue();
+                self.practice(seed, ctx);
+            }
+            IncomingMessage::CreateRoom {
+                public,
+                announcement,
+                max_players,
+                mode,
+                language,
+                password,
+            } => {
+                self.stop_matching(ctx);
+                self.leave_queue();
+                let room_config = RoomConfig::requested(
+                    public,
+                    announcement,
+                    max_players,
+                    language.as_deref(),
+                    password.as_deref(),
+                );
+                let game_config = GameConfigOptions {
+                    mode: mode.unwrap_or_default(),
+                    ..Default::default()
+                };
+                self.create_room(room_config, game_config, ctx);
+            }
+            IncomingMessage::LeaveRoom => self.leave_room(ctx),
+            IncomingMessage::GameInput(input) => self.submit_input(input, ctx),
+            IncomingMessage::SetRoomAlias(alias) => self.set_room_alias(alias.into(), ctx),
+            IncomingMessage::Chat(text) => self.chat(text, ctx),
+            IncomingMessage::Whisper { to, text } => self.whisper(to.into(), text, ctx),
+            IncomingMessage::Presence(users) => self.query_presence(users, ctx),
+            IncomingMessage::AddFriends(users) => self.update_friends(users, Vec::new(), ctx),
+            IncomingMessage::RemoveFriends(users) => self.update_friends(Vec::new(), users, ctx),
+            IncomingMessage::InviteFriend(user) => self.invite_friend(user, ctx),
+            IncomingMessage::AnswerInvite { id, accept } => self.answer_invite(id, accept, ctx),
+            IncomingMessage::SetProfile(profile) => {
+                let result = match profile.validate() {
+                    Ok(profile) => {
+                        self.profile = Some(profile);
+                        message::Result::Success(())
+                    }
+                    Err(err) => message::Result::Error(err),
+                };
+                self.reply(OutgoingMessage::SetProfileResult(result));
+            }
+            IncomingMessage::KickPlayer { target } => self.kick_player(target, ctx),
+            IncomingMessage::Unban(target) => self.unban(target, ctx),
+            IncomingMessage::ListBans => self.list_bans(ctx),
+            IncomingMessage::InstantReplay => self.instant_replay(ctx),
+            IncomingMessage::PromoteLeader(target) => self.promote_leader(target, ctx),
+            IncomingMessage::StartGame => self.start_game(ctx),
+            IncomingMessage::UpdateRoomSettings(update) => self.update_room_settings(update, ctx),
+            IncomingMessage::SetLocked(locked) => self.set_locked(locked, ctx),
+            IncomingMessage::SetPaused(paused) => self.set_paused(paused, ctx),
+            IncomingMessage::SetRoomMetadata { key, value } => {
+                self.set_room_metadata(key, value, ctx)
+            }
+            IncomingMessage::CreateInvite { single_use } => self.create_invite(single_use, ctx),
+            IncomingMessage::ListRooms(query) => self.list_rooms(query, ctx),
+            IncomingMessage::Ack(seq) => {
+                if let (Some(room), Some(member)) = (&self.room, self.transient_id) {
+                    room.do_send(Acknowledge { member, seq });
+                }
+            }
+            IncomingMessage::Lobby(action) => {
+                if let (Some(room), Some(transient_id)) = (&self.room, self.transient_id) {
+                    room.do_send(LobbyInteraction {
+                        transient_id,
+                        action,
+                    });
                 }
             }
-            _ => todo!("handle other messages"),
         }
     }
 }
 
 impl Actor for Session {
-    type Context = WebsocketContext<Self>;
+    type Context = Context<Self>;
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.send(OutgoingMessage::Welcome {
+            disabled_features: self.features.disabled().to_vec(),
+            server: BuildInfo::new(&self.features),
+        });
+        self.check_protocol(ctx);
         self.heartbeat(ctx);
+        self.ping(ctx);
+        self.session_manager.do_send(Connect(ctx.address()));
     }
     fn stopped(&mut self, ctx: &mut Self::Context) {
-        if let Some(spawn_handle) = self.reconnection_timer {
+        if let Some(spawn_handle) = self.stale_timer {
             ctx.cancel_future(spawn_handle);
         }
+        self.stop_matching(ctx);
+        self.leave_queue();
+        self.sink.close();
+        self.session_manager.do_send(Disconnect(ctx.address()));
         // Upon normal termination, the sessions id should be removed before disconnection,
         // if not done so, it means something probably went wrong and therefore should be notified
         // to the session_manager and to any related rooms
         if let Some(transient_id) = self.transient_id.take() {
+            // The room holds on to the client's seat until they reconnect or run out of time
+            if let Some(room) = self.room.take() {
+                room.do_send(RemovePlayer {
+                    transient_id,
+                    reason: RemoveReason::Disconnected,
+                });
+            }
             self.session_manager.do_send(Unregister {
                 transient_id,
+                user: self.id.take(),
                 /* Removal reason in this message is only used if the client was still in a room at
                  * the time of termination which can only be possible due to either a network
                  * disconnection or a crash on the client side. Upon normal termination, the client
@@ -183,42 +1395,107 @@ impl Actor for Session {
     }
 }
 
-impl StreamHandler<Result<ws::Message, ProtocolError>> for Session {
-    fn handle(&mut self, item: Result<ws::Message, ProtocolError>, ctx: &mut Self::Context) {
-        match item {
-            Ok(msg) => match msg {
-                ws::Message::Text(text) => match serde_json::from_str::<IncomingMessage>(&text) {
-                    Ok(msg) => self.handle_message(msg, ctx),
-
-                    Err(err) => log::error!("Failed to deserialize message: {err}"),
-                },
-                ws::Message::Ping(bytes) => ctx.pong(&bytes),
-                ws::Message::Close(reason) => ctx.close(reason),
-                _ => {}
-            },
-            Err(err) => log::error!("{err}"),
+/// Raw text message received from the client, parsed by the session itself so that transports
+/// only have to deal with framing
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Incoming(pub ByteString);
+
+impl Handler<Incoming> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Incoming, ctx: &mut Self::Context) -> Self::Result {
+        // Anything the client sends shows the connection is alive, transports without pings
+        // have nothing else to show for it
+        self.hb = Instant::now();
+        // Checked here rather than by every transport, before spending anything on the frame
+        if let Err(violation) = check_frame(&msg.0) {
+            log::warn!("disconnecting {:?}: {violation}", self.id);
+            self.send(OutgoingMessage::ForceDisconnect(RemoveReason::MalformedFrame));
+            ctx.stop();
+            return;
+        }
+        self.trace("->", &msg.0);
+        match serde_json::from_str::<IncomingEnvelope>(&msg.0) {
+            Ok(envelope) => {
+                self.request = envelope.request_id;
+                self.handle_message(envelope.message, ctx);
+            }
+            Err(err) => log::error!("Failed to deserialize message: {err}"),
         }
     }
 }
 
+/// Answer of the client to a ping, carrying the payload it was sent with, see [ClientSink::ping]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Pong(pub Vec<u8>);
+
+impl Handler<Pong> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Pong, _: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+        if let Some(rtt) = self.latency.pong(&msg.0, now) {
+            self.hb = now;
+            if self.protocol >= LATENCY_REPORTS_SINCE {
+                let report = OutgoingMessage::LatencyReport {
+                    rtt: rtt.as_millis() as u64,
+                };
+                self.send_with(report.into(), Priority::Low);
+            }
+        }
+    }
+}
+
+/// Sent by the transport once the client's connection is gone
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnected;
+
+impl Handler<Disconnected> for Session {
+    type Result = ();
+    fn handle(&mut self, _: Disconnected, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct SerializedMessage(pub OutgoingMessage);
 
 impl Handler<SerializedMessage> for Session {
     type Result = ();
-    fn handle(&mut self, msg: SerializedMessage, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: SerializedMessage, _: &mut Self::Context) -> Self::Result {
         match serde_json::to_string(&msg.0) {
-            Ok(str) => ctx.text(str),
-            Err(err) => log::error!("error serializing message: {err}"),
+            Ok(str) => self.send_with(str.into(), msg.0.priority()),
+            Err(err) => {
+                log::error!("error serializing message: {err}");
+                self.dead_letters.do_send(
+                    DeadLetter::new(DeadLetterReason::Serialization, self.transient_id)
+                        .detail(err.to_string()),
+                );
+            }
         }
     }
 }
 
+/// A message that has already been serialized, used when the same payload goes out to a large
+/// number of sessions so that it only has to be serialized once. Carries the
+/// [OutgoingMessage::priority] of the message it was serialized from.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Frame(pub SharedFrame, pub Priority);
+
+impl Handler<Frame> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Frame, _: &mut Self::Context) -> Self::Result {
+        self.send_frame(msg.0, msg.1)
+    }
+}
+
 /// Sent by the session_manager in the event where the client reconnects from a different stream.
-/// This message is required because the older session controller (the one receiving this message)
-/// might have a reconnection timer, which upon evaluating will result in the permanent removal of
-/// the client from any rooms they might be in before losing connection.
+/// The older session controller (the one receiving this message) is stopped while the room the
+/// client was in hands their seat over to the new one, see
+/// [crate::room::actor::ClientReconnection].
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Stop;
@@ -240,52 +1517,298 @@ impl Handler<Stop> for Session {
 #[rtype(result = "()")]
 pub struct ClearRoom {
     pub reason: RemoveReason,
+    /// Name of the player who removed the client, if one did
+    pub by: Option<String>,
 }
 
 impl Handler<ClearRoom> for Session {
     type Result = ();
-    fn handle(&mut self, msg: ClearRoom, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ClearRoom, _: &mut Self::Context) -> Self::Result {
         let _ = self.room.take();
-        let msg = OutgoingMessage::RemoveFromRoom(msg.reason);
+        let detail = match self.catalog {
+            Some(locale) => Some(locale.removed(msg.reason, msg.by.as_deref())),
+            // Kicks were explained in English before clients could declare a locale
+            None => msg.by.map(|by| Locale::En.removed(msg.reason, Some(&by))),
+        };
+        let msg = OutgoingMessage::removed(msg.reason, detail);
         let msg = serde_json::to_string(&msg).unwrap();
-        ctx.text(msg);
-        self.session_manager.do_send(UpdateSessionRoomInfo(
-            self.transient_id.expect("must be registered"),
-            None,
-        ));
+        self.send(msg);
+        self.moved(None);
+    }
+}
+
+/// Sent by rooms along with their countdowns, clients that declared a locale get a
+/// [OutgoingMessage::Notice] putting the countdown in words
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Warn {
+    pub countdown: Countdown,
+    /// Left on the countdown when the room sent the warning
+    pub remaining: Duration,
+}
+
+impl Handler<Warn> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Warn, _: &mut Self::Context) -> Self::Result {
+        if let Some(locale) = self.catalog {
+            self.send(OutgoingMessage::Notice {
+                key: msg.countdown.localization_key(),
+                text: locale.countdown(msg.countdown, msg.remaining),
+            });
+        }
+    }
+}
+
+/// Sent by a room merged into another one once it let the client go, see [crate::room::merge].
+/// The client is moved into the other room right away.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MoveToRoom(pub RoomCode);
+
+impl Handler<MoveToRoom> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: MoveToRoom, ctx: &mut Self::Context) -> Self::Result {
+        if self.room.is_none() {
+            self.join_room(RoomRef::Code(msg.0), None, ctx);
+        }
+    }
+}
+
+/// Sent during a handover to the next process, which the client should reconnect to right away
+/// and resume its session on with the token
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Migrate(pub String);
+
+impl Handler<Migrate> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Migrate, _: &mut Self::Context) -> Self::Result {
+        self.send(OutgoingMessage::Reconnect(msg.0));
+    }
+}
+
+/// Logs every frame to and from the client until the given time, or stops doing so, see
+/// [super::TraceUser]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Trace(pub Option<Instant>);
+
+impl Handler<Trace> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Trace, _: &mut Self::Context) -> Self::Result {
+        let user = self.id.as_deref().unwrap_or_default();
+        match msg.0 {
+            Some(until) => {
+                let secs = until.saturating_duration_since(Instant::now()).as_secs();
+                log::info!(target: TRACE_TARGET, "tracing {user} for {secs}s");
+            }
+            None => log::info!(target: TRACE_TARGET, "stopped tracing {user}"),
+        }
+        self.traced_until = msg.0;
+    }
+}
+
+/// Sent by a full room that put the client in line for a seat
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Queued {
+    pub room: Addr<Room>,
+    pub position: usize,
+}
+
+impl Handler<Queued> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: Queued, _: &mut Self::Context) -> Self::Result {
+        if self.room.is_some() {
+            // The client got into another room in the meantime
+            if let Some(transient_id) = self.transient_id {
+                msg.room.do_send(LeaveQueue(transient_id));
+            }
+            return;
+        }
+        self.leave_queue();
+        self.queued = Some(msg.room);
+        self.send(OutgoingMessage::QueuePosition(msg.position));
     }
 }
 
-#[derive(Message, serde::Serialize)]
+/// Sent by the room the client was queued for once they got a seat or were turned away
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QueueOutcome(pub Result<(RoomCode, Addr<Room>), JoinRoomError>);
+
+impl Handler<QueueOutcome> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: QueueOutcome, _: &mut Self::Context) -> Self::Result {
+        self.queued = None;
+        let result = match msg.0 {
+            Ok((code, addr)) => {
+                self.room = Some(addr.clone());
+                self.moved(Some((addr, &code)));
+                message::Result::Success(code_to_string(&code).unwrap().to_string())
+            }
+            Err(err) => message::Result::Error(err),
+        };
+        self.reply_to(None, OutgoingMessage::JoinRoomResult(result));
+    }
+}
+
+/// Sent by the room to a reconnecting client so that it can pick up where it left off.
+/// `game` is empty if no game is running in the room.
+#[derive(Message)]
 #[rtype(result = "()")]
 pub struct RestoreState {
     pub code: RoomCode,
-    pub game: String
+    pub room: Addr<Room>,
+    pub game: Option<serde_json::Value>,
+    pub state: RoomState,
+    pub metadata: RoomMetadata,
+    /// Number of the latest event broadcast to the room
+    pub seq: u64,
 }
 
 impl Handler<RestoreState> for Session {
     type Result = ();
-    fn handle(&mut self, msg: RestoreState, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.game)
+    fn handle(&mut self, msg: RestoreState, _: &mut Self::Context) -> Self::Result {
+        self.rejoined(msg.room, &msg.code);
+        let code = code_to_string(&msg.code).unwrap().to_string();
+        self.send(OutgoingMessage::RestoreState {
+            code,
+            game: msg.game,
+            state: msg.state,
+            metadata: msg.metadata,
+            seq: msg.seq,
+        })
+    }
+}
+
+/// Sent by the room in place of [RestoreState] to a reconnecting client that only missed a few
+/// events, which are replayed as they were broadcast
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReplayEvents {
+    pub code: RoomCode,
+    pub room: Addr<Room>,
+    pub events: Vec<ByteString>,
+}
+
+impl Handler<ReplayEvents> for Session {
+    type Result = ();
+    fn handle(&mut self, msg: ReplayEvents, _: &mut Self::Context) -> Self::Result {
+        self.rejoined(msg.room, &msg.code);
+        let code = code_to_string(&msg.code).unwrap().to_string();
+        let events = msg
+            .events
+            .into_iter()
+            .filter_map(|frame| match RawValue::from_string(frame.to_string()) {
+                Ok(event) => Some(event),
+                Err(err) => {
+                    log::error!("dropping malformed event replayed to client: {err}");
+                    None
+                }
+            })
+            .collect();
+        self.send(OutgoingMessage::MissedEvents { code, events })
     }
 }
 
+/// The frame with its secrets replaced, see [SECRET_KINDS] and [SECRET_FIELDS]. Frames that are
+/// not JSON could hold anything and are left out altogether.
+fn redacted(frame: &str) -> String {
+    fn redact(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (key, value) in fields {
+                    if SECRET_FIELDS.contains(&key.as_str()) {
+                        *value = "(redacted)".into();
+                    } else {
+                        redact(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+            _ => {}
+        }
+    }
+    let Ok(mut message) = serde_json::from_str::<serde_json::Value>(frame) else {
+        return format!("({} bytes that are not JSON)", frame.len());
+    };
+    let secret = message["kind"]
+        .as_str()
+        .is_some_and(|kind| SECRET_KINDS.contains(&kind));
+    if let Some(data) = message.get_mut("data") {
+        if secret {
+            // Either the secret itself, or a result holding it
+            if data.is_string() {
+                *data = "(redacted)".into();
+            } else if data["status"] == "Success" {
+                if let Some(result) = data.get_mut("data") {
+                    *result = "(redacted)".into();
+                }
+            }
+        }
+        redact(data);
+    }
+    message.to_string()
+}
+
 fn code_to_string<'a>(code: &'a [u8]) -> Result<std::borrow::Cow<'a, str>, ()> {
-    if code.len() != ROOM_CODE_LENGTH {
+    if !(MIN_ROOM_CODE_LENGTH..=MAX_ROOM_CODE_LENGTH).contains(&code.len()) {
         Err(())
     } else {
         Ok(String::from_utf8_lossy(code))
     }
 }
 
+/// Only strings no longer than the codes this server generates are taken for codes, which takes
+/// in the shorter codes of rooms handed over by a process generating those. Longer ones might be
+/// aliases, or the longer codes of handed over rooms, which the room manager tells apart.
 fn string_to_code(str: &str) -> Result<RoomCode, ()> {
-    if str.len() != ROOM_CODE_LENGTH {
+    if str.len() > room_code_length() {
         Err(())
     } else {
-        let mut buf = [0; ROOM_CODE_LENGTH];
-        for (i, char) in str.as_bytes().iter().enumerate() {
-            buf[i] = *char;
+        RoomCode::try_from(str.as_bytes())
+    }
+}
+
+/// The room a client refers to by a code or an alias
+fn room_ref(code: &str) -> Result<RoomRef, ()> {
+    string_to_code(code)
+        .map(RoomRef::Code)
+        .or_else(|_| normalize_alias(code).map(RoomRef::Alias).ok_or(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_leave_out_tokens_and_passwords() {
+        for frame in [
+            r#"{"kind":"LoginResult","data":{"status":"Success","data":"s3cret"}}"#,
+            r#"{"kind":"Reconnect","data":{"user":"ann","token":"s3cret"}}"#,
+            r#"{"kind":"Resume","data":"s3cret"}"#,
+            r#"{"kind":"JoinRoom","data":{"code":"ABCD","password":"s3cret"}}"#,
+            r#"{"kind":"CreateRoom","data":{"public":false,"password":"s3cret"}}"#,
+            r#"{"kind":"Chat","data":"s3cret"#,
+        ] {
+            assert!(!redacted(frame).contains("s3cret"), "{frame}");
         }
-        Ok(buf)
+        let refused = r#"{"kind":"ResumeResult","data":{"status":"Error","data":"InvalidToken"}}"#;
+        assert!(redacted(refused).contains("InvalidToken"));
+        assert!(redacted(r#"{"kind":"Chat","data":"hello"}"#).contains("hello"));
+    }
+
+    #[test]
+    fn codes_of_other_lengths_are_taken_in() {
+        let length = room_code_length();
+        let code = "A".repeat(length);
+        assert!(matches!(room_ref(&code), Ok(RoomRef::Code(_))));
+        let shorter = "A".repeat(MIN_ROOM_CODE_LENGTH);
+        assert!(matches!(room_ref(&shorter), Ok(RoomRef::Code(_))));
+        // Left to the room manager to tell apart from an alias
+        let longer = "A".repeat(length + 1);
+        assert!(matches!(room_ref(&longer), Ok(RoomRef::Alias(_))));
+        assert!(room_ref("AB").is_err());
     }
 }
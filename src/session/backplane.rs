@@ -0,0 +1,692 @@
+//! Redis backplane tying together the session managers of a deployment spread over several
+//! nodes, so that whispers and presence work whichever node the users are connected to. Every
+//! node lists its users in a Redis set of its own, announces them coming and going on a shared
+//! channel and listens for whispers relayed to it on a channel of its own. Nodes send out a
+//! heartbeat every [HEARTBEAT_INTERVAL], which keeps their set from expiring. The users of a node
+//! that has not been heard from for [NODE_TTL] count as offline, so a node that crashes does not
+//! leave its users online for good. Announcements made on any node go out to the users of every
+//! other one on a channel of their own. Without Redis, all of these only cover the users of the
+//! node itself.
+
+use actix::prelude::*;
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender, WeakSender};
+
+use super::actor::SerializedMessage;
+use super::message::{Announcement, OutgoingMessage};
+use super::{SessionManager, UserId};
+use crate::room::invite::unix_time;
+
+pub type NodeId = Arc<str>;
+
+/// Sorted set of the nodes, scored by the unix time of their latest heartbeat
+const NODES_KEY: &str = "zgm:nodes";
+/// Channel every node sends its heartbeats on
+const HEARTBEAT_CHANNEL: &str = "zgm:heartbeats";
+/// Channel every node announces its users coming and going on
+const PRESENCE_CHANNEL: &str = "zgm:presence";
+/// Channel operator announcements are passed on to the other nodes on
+const ANNOUNCEMENT_CHANNEL: &str = "zgm:announcements";
+/// Commands waiting for the connection to Redis, newer ones are dropped past this
+const MAX_QUEUED_COMMANDS: usize = 4096;
+/// Wait between two attempts to connect to Redis
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Wait between two heartbeats of a node
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// A node not heard from for this long is taken to be gone, along with its users
+const NODE_TTL: Duration = Duration::from_secs(30);
+
+/// Channel the node with the id listens for relayed whispers on
+fn node_channel(node: &str) -> String {
+    format!("zgm:node:{node}")
+}
+
+/// Set of the users on the node with the id, expiring unless the node keeps sending heartbeats
+fn users_key(node: &str) -> String {
+    format!("zgm:users:{node}")
+}
+
+pub struct BackplaneConfig {
+    /// `host:port` of the Redis server
+    addr: String,
+    /// Identifies this node to the others, unique within the deployment
+    node: NodeId,
+}
+
+impl BackplaneConfig {
+    /// Reads `REDIS_URL`, a `redis://host:port` address, and `NODE_ID`, which defaults to a
+    /// random id. There is no backplane without the former.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("REDIS_URL").ok().filter(|x| !x.is_empty())?;
+        let Some(addr) = url.strip_prefix("redis://") else {
+            log::error!("unsupported redis url {url}, whispers and presence stay on this node");
+            return None;
+        };
+        let node = std::env::var("NODE_ID")
+            .ok()
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| {
+                let mut rng = fastrand::Rng::new();
+                std::iter::repeat_with(|| rng.alphanumeric())
+                    .take(12)
+                    .collect()
+            });
+        Some(Self {
+            addr: addr.trim_end_matches('/').into(),
+            node: node.into(),
+        })
+    }
+}
+
+/// A user connecting to or leaving a node, as announced on [PRESENCE_CHANNEL]
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct PresenceChange {
+    user: UserId,
+    node: NodeId,
+    online: bool,
+}
+
+/// A whisper on its way to the node its recipient is on, already through the profanity filter of
+/// the node it was sent on
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct RelayedWhisper {
+    pub to: UserId,
+    pub from: UserId,
+    pub text: String,
+}
+
+/// An announcement made on a node, as published on [ANNOUNCEMENT_CHANNEL]
+#[derive(Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct RelayedAnnouncement {
+    node: NodeId,
+    announcement: Announcement,
+}
+
+/// Every user on another node, sent whenever the backplane (re)connects
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Directory(Vec<(UserId, NodeId)>);
+
+/// A node, this one included, sending its heartbeat on [HEARTBEAT_CHANNEL]
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Heartbeat(NodeId);
+
+enum Command {
+    Online(UserId),
+    Offline(UserId),
+    Relay(NodeId, RelayedWhisper),
+    Announce(Announcement),
+    Heartbeat,
+}
+
+/// The session manager's end of the backplane
+pub struct Backplane {
+    commands: Sender<Command>,
+    /// When each node that is still around was last heard from
+    heard: HashMap<NodeId, Instant>,
+}
+
+impl Backplane {
+    /// Connects to Redis in the background, reconnecting whenever the connection drops
+    pub fn connect(config: BackplaneConfig, session_manager: Addr<SessionManager>) -> Self {
+        let (commands, queued) = channel(MAX_QUEUED_COMMANDS);
+        let BackplaneConfig { addr, node } = config;
+        actix::spawn(subscribe(
+            addr.clone(),
+            node.clone(),
+            session_manager.clone(),
+        ));
+        actix::spawn(publish(addr, node, queued, session_manager));
+        actix::spawn(beat(commands.downgrade()));
+        Self {
+            commands,
+            heard: HashMap::new(),
+        }
+    }
+    /// Lists the user in the directory as being on this node and lets the others know
+    pub fn online(&self, user: UserId) {
+        self.queue(Command::Online(user));
+    }
+    pub fn offline(&self, user: UserId) {
+        self.queue(Command::Offline(user));
+    }
+    /// Hands the whisper to the node its recipient is on
+    pub fn relay(&self, node: NodeId, whisper: RelayedWhisper) {
+        self.queue(Command::Relay(node, whisper));
+    }
+    /// Has the other nodes pass the announcement on to their users
+    pub fn announce(&self, announcement: Announcement) {
+        self.queue(Command::Announce(announcement));
+    }
+    fn queue(&self, command: Command) {
+        if self.commands.try_send(command).is_err() {
+            log::warn!("redis is falling behind, dropping backplane command");
+        }
+    }
+    /// Notes that the node was heard from, forgetting the nodes gone quiet for [NODE_TTL]
+    fn heard(&mut self, node: NodeId, now: Instant) {
+        self.heard.insert(node, now);
+        self.heard
+            .retain(|_, at| now.duration_since(*at) < NODE_TTL);
+    }
+    fn alive(&self, node: &NodeId) -> bool {
+        self.heard.contains_key(node)
+    }
+}
+
+/// Queues a heartbeat every [HEARTBEAT_INTERVAL] for as long as the session manager is around
+async fn beat(commands: WeakSender<Command>) {
+    let mut interval = actix::clock::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(commands) = commands.upgrade() else {
+            return;
+        };
+        if commands.try_send(Command::Heartbeat).is_err() {
+            log::warn!("redis is falling behind, dropping heartbeat");
+        }
+    }
+}
+
+impl Handler<PresenceChange> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: PresenceChange, _: &mut Self::Context) -> Self::Result {
+        if msg.online {
+            if let Some(backplane) = &mut self.backplane {
+                backplane
+                    .heard
+                    .entry(msg.node.clone())
+                    .or_insert_with(Instant::now);
+            }
+            self.remote.insert(msg.user.clone(), msg.node);
+        } else if self.remote.get(&msg.user) == Some(&msg.node) {
+            self.remote.remove(&msg.user);
+        }
+        // The user may still be logged in elsewhere
+        self.announce(&msg.user, self.is_online(&msg.user));
+    }
+}
+
+impl Handler<RelayedWhisper> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: RelayedWhisper, _: &mut Self::Context) -> Self::Result {
+        if let Some(session) = self.local_session(&msg.to) {
+            session.do_send(SerializedMessage(OutgoingMessage::Whisper {
+                from: msg.from,
+                text: msg.text,
+            }));
+        }
+    }
+}
+
+impl Handler<RelayedAnnouncement> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: RelayedAnnouncement, _: &mut Self::Context) -> Self::Result {
+        self.announce_locally(msg.announcement);
+    }
+}
+
+impl Handler<Directory> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: Directory, _: &mut Self::Context) -> Self::Result {
+        if let Some(backplane) = &mut self.backplane {
+            let now = Instant::now();
+            for (_, node) in &msg.0 {
+                backplane.heard(node.clone(), now);
+            }
+        }
+        self.remote = msg.0.into_iter().collect();
+    }
+}
+
+impl Handler<Heartbeat> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: Heartbeat, _: &mut Self::Context) -> Self::Result {
+        let Some(backplane) = &mut self.backplane else {
+            return;
+        };
+        backplane.heard(msg.0, Instant::now());
+        let gone: Vec<UserId> = self
+            .remote
+            .iter()
+            .filter(|(_, node)| !backplane.alive(node))
+            .map(|(user, _)| user.clone())
+            .collect();
+        for user in gone {
+            log::info!("{user} is on a node gone quiet, taking them offline");
+            self.remote.remove(&user);
+            self.announce(&user, self.is_online(&user));
+        }
+    }
+}
+
+/// Keeps a connection to Redis and runs the commands queued by the session manager on it
+async fn publish(
+    addr: String,
+    node: NodeId,
+    mut queued: Receiver<Command>,
+    session_manager: Addr<SessionManager>,
+) {
+    // Users of this node, listed in the directory again after reconnecting
+    let mut local = HashSet::new();
+    loop {
+        match TcpStream::connect(&addr).await {
+            Ok(stream) => {
+                log::info!("backplane connected to redis at {addr} as node {node}");
+                let (reader, mut writer) = stream.into_split();
+                let mut reader = BufReader::new(reader);
+                let result = async {
+                    let directory = load_directory(&mut writer, &mut reader, &node, &local).await?;
+                    session_manager.do_send(Directory(directory));
+                    writer.write_all(&heartbeat(&node, unix_time())).await?;
+                    for user in &local {
+                        writer.write_all(&announce(&node, user, true)).await?;
+                    }
+                    let reading = actix::spawn(check_replies(reader));
+                    let result = run(&mut writer, &node, &mut queued, &mut local).await;
+                    reading.abort();
+                    result
+                }
+                .await;
+                match result {
+                    // The session manager is gone
+                    Ok(()) => return,
+                    Err(err) => log::error!("lost connection to redis at {addr}: {err}"),
+                }
+            }
+            Err(err) => log::error!("cannot connect to redis at {addr}: {err}"),
+        }
+        actix::clock::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Reads the users of every node that sent a heartbeat within [NODE_TTL], leaving out those of
+/// this node. Users the node lost track of, say because it went down before they left, are taken
+/// out of its set.
+async fn load_directory(
+    writer: &mut (impl AsyncWrite + Unpin),
+    reader: &mut (impl AsyncBufRead + Unpin + Send),
+    node: &NodeId,
+    local: &HashSet<UserId>,
+) -> io::Result<Vec<(UserId, NodeId)>> {
+    let quiet_since = unix_time().saturating_sub(NODE_TTL.as_secs()).to_string();
+    writer
+        .write_all(&command(&[
+            b"ZREMRANGEBYSCORE",
+            NODES_KEY.as_bytes(),
+            b"-inf",
+            quiet_since.as_bytes(),
+        ]))
+        .await?;
+    read_reply(reader).await?;
+    writer
+        .write_all(&command(&[b"ZRANGE", NODES_KEY.as_bytes(), b"0", b"-1"]))
+        .await?;
+    let nodes = read_members(reader).await?;
+    let mut directory = Vec::new();
+    for other in nodes {
+        let other: NodeId = other.into();
+        if other == *node {
+            continue;
+        }
+        writer
+            .write_all(&command(&[b"SMEMBERS", users_key(&other).as_bytes()]))
+            .await?;
+        for user in read_members(reader).await? {
+            directory.push((user.into(), other.clone()));
+        }
+    }
+    writer
+        .write_all(&command(&[b"SMEMBERS", users_key(node).as_bytes()]))
+        .await?;
+    for user in read_members(reader).await? {
+        if !local.contains(user.as_str()) {
+            writer.write_all(&announce(node, &user, false)).await?;
+            read_reply(reader).await?;
+            read_reply(reader).await?;
+        }
+    }
+    Ok(directory)
+}
+
+/// Reads a reply listing the members of a set
+async fn read_members(reader: &mut (impl AsyncBufRead + Unpin + Send)) -> io::Result<Vec<String>> {
+    let Reply::Array(Some(members)) = read_reply(reader).await? else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected reply",
+        ));
+    };
+    Ok(members
+        .into_iter()
+        .filter_map(|x| match x {
+            Reply::Bulk(Some(member)) => Some(String::from_utf8_lossy(&member).into()),
+            _ => None,
+        })
+        .collect())
+}
+
+async fn run(
+    writer: &mut OwnedWriteHalf,
+    node: &NodeId,
+    queued: &mut Receiver<Command>,
+    local: &mut HashSet<UserId>,
+) -> io::Result<()> {
+    while let Some(queued) = queued.recv().await {
+        let frame = match queued {
+            Command::Online(user) => {
+                let frame = announce(node, &user, true);
+                local.insert(user);
+                frame
+            }
+            Command::Offline(user) => {
+                let frame = announce(node, &user, false);
+                local.remove(&user);
+                frame
+            }
+            Command::Relay(to, whisper) => {
+                let payload = serde_json::to_vec(&whisper)?;
+                command(&[b"PUBLISH", node_channel(&to).as_bytes(), &payload])
+            }
+            Command::Announce(announcement) => {
+                let relayed = RelayedAnnouncement {
+                    node: node.clone(),
+                    announcement,
+                };
+                let payload = serde_json::to_vec(&relayed)?;
+                command(&[b"PUBLISH", ANNOUNCEMENT_CHANNEL.as_bytes(), &payload])
+            }
+            Command::Heartbeat => heartbeat(node, unix_time()),
+        };
+        writer.write_all(&frame).await?;
+    }
+    Ok(())
+}
+
+/// Commands updating the directory and telling the other nodes about it
+fn announce(node: &str, user: &str, online: bool) -> Vec<u8> {
+    let key = users_key(node);
+    let mut frame = if online {
+        let mut frame = command(&[b"SADD", key.as_bytes(), user.as_bytes()]);
+        frame.extend(expire(&key));
+        frame
+    } else {
+        command(&[b"SREM", key.as_bytes(), user.as_bytes()])
+    };
+    let change = PresenceChange {
+        user: user.into(),
+        node: node.into(),
+        online,
+    };
+    let payload = serde_json::to_vec(&change).unwrap_or_default();
+    frame.extend(command(&[
+        b"PUBLISH",
+        PRESENCE_CHANNEL.as_bytes(),
+        &payload,
+    ]));
+    frame
+}
+
+/// Commands keeping the users of the node from expiring and telling the other nodes it is still
+/// around, at the unix time given
+fn heartbeat(node: &str, now: u64) -> Vec<u8> {
+    let mut frame = expire(&users_key(node));
+    frame.extend(command(&[
+        b"ZADD",
+        NODES_KEY.as_bytes(),
+        now.to_string().as_bytes(),
+        node.as_bytes(),
+    ]));
+    frame.extend(command(&[
+        b"PUBLISH",
+        HEARTBEAT_CHANNEL.as_bytes(),
+        node.as_bytes(),
+    ]));
+    frame
+}
+
+fn expire(key: &str) -> Vec<u8> {
+    let ttl = NODE_TTL.as_secs().to_string();
+    command(&[b"EXPIRE", key.as_bytes(), ttl.as_bytes()])
+}
+
+/// Nobody waits for the replies to the commands, errors are only logged
+async fn check_replies(mut reader: BufReader<OwnedReadHalf>) {
+    loop {
+        match read_reply(&mut reader).await {
+            Ok(Reply::Error(err)) => log::error!("redis: {err}"),
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Listens for presence changes, announcements and whispers relayed to this node, and passes
+/// them on to the session manager
+async fn subscribe(addr: String, node: NodeId, session_manager: Addr<SessionManager>) {
+    let whispers = node_channel(&node);
+    loop {
+        let result = async {
+            let stream = TcpStream::connect(&addr).await?;
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            writer
+                .write_all(&command(&[
+                    b"SUBSCRIBE",
+                    PRESENCE_CHANNEL.as_bytes(),
+                    ANNOUNCEMENT_CHANNEL.as_bytes(),
+                    HEARTBEAT_CHANNEL.as_bytes(),
+                    whispers.as_bytes(),
+                ]))
+                .await?;
+            loop {
+                let Reply::Array(Some(reply)) = read_reply(&mut reader).await? else {
+                    continue;
+                };
+                let [kind, Reply::Bulk(Some(channel)), Reply::Bulk(Some(payload))] =
+                    reply.as_slice()
+                else {
+                    continue;
+                };
+                if *kind != Reply::Bulk(Some(b"message".to_vec())) {
+                    continue;
+                }
+                if channel == PRESENCE_CHANNEL.as_bytes() {
+                    match serde_json::from_slice::<PresenceChange>(payload) {
+                        Ok(change) if change.node != node => session_manager.do_send(change),
+                        Ok(_) => {}
+                        Err(err) => log::error!("malformed presence change: {err}"),
+                    }
+                } else if channel == ANNOUNCEMENT_CHANNEL.as_bytes() {
+                    match serde_json::from_slice::<RelayedAnnouncement>(payload) {
+                        Ok(relayed) if relayed.node != node => session_manager.do_send(relayed),
+                        Ok(_) => {}
+                        Err(err) => log::error!("malformed relayed announcement: {err}"),
+                    }
+                } else if channel == HEARTBEAT_CHANNEL.as_bytes() {
+                    let beating = String::from_utf8_lossy(payload);
+                    session_manager.do_send(Heartbeat(beating.into()));
+                } else if channel == whispers.as_bytes() {
+                    match serde_json::from_slice::<RelayedWhisper>(payload) {
+                        Ok(whisper) => session_manager.do_send(whisper),
+                        Err(err) => log::error!("malformed relayed whisper: {err}"),
+                    }
+                }
+            }
+        }
+        .await;
+        let err: io::Error = match result {
+            Ok(never) => never,
+            Err(err) => err,
+        };
+        log::error!("lost subscription to redis at {addr}: {err}");
+        actix::clock::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Encodes a command in the Redis protocol, as an array of bulk strings
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut frame = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        frame.extend(format!("${}\r\n", arg.len()).as_bytes());
+        frame.extend(*arg);
+        frame.extend(b"\r\n");
+    }
+    frame
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    /// [None] for the null bulk string
+    Bulk(Option<Vec<u8>>),
+    /// [None] for the null array
+    Array(Option<Vec<Reply>>),
+}
+
+type ReplyFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Reply>> + Send + 'a>>;
+
+/// Reads a single reply in the Redis protocol
+fn read_reply<R: AsyncBufRead + Unpin + Send>(reader: &mut R) -> ReplyFuture<'_> {
+    Box::pin(async move {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed redis reply");
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end_matches("\r\n");
+        let (kind, rest) = line.split_at_checked(1).ok_or_else(invalid)?;
+        let length = || rest.parse::<i64>().map_err(|_| invalid());
+        Ok(match kind {
+            "+" => Reply::Simple(rest.into()),
+            "-" => Reply::Error(rest.into()),
+            ":" => Reply::Integer(length()?),
+            "$" => match usize::try_from(length()?) {
+                Ok(length) => {
+                    let mut data = vec![0; length + 2];
+                    reader.read_exact(&mut data).await?;
+                    data.truncate(length);
+                    Reply::Bulk(Some(data))
+                }
+                Err(_) => Reply::Bulk(None),
+            },
+            "*" => match usize::try_from(length()?) {
+                Ok(length) => {
+                    let mut items = Vec::with_capacity(length);
+                    for _ in 0..length {
+                        items.push(read_reply(reader).await?);
+                    }
+                    Reply::Array(Some(items))
+                }
+                Err(_) => Reply::Array(None),
+            },
+            _ => return Err(invalid()),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_arrays_of_bulk_strings() {
+        assert_eq!(
+            command(&[b"HSET", b"key", b"user", b""]),
+            b"*4\r\n$4\r\nHSET\r\n$3\r\nkey\r\n$4\r\nuser\r\n$0\r\n\r\n"
+        );
+    }
+
+    #[actix::test]
+    async fn replies_are_parsed() {
+        let mut input: &[u8] = b"*3\r\n$7\r\nmessage\r\n$-1\r\n*2\r\n:5\r\n-ERR no\r\n+OK\r\n";
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Array(Some(vec![
+                Reply::Bulk(Some(b"message".to_vec())),
+                Reply::Bulk(None),
+                Reply::Array(Some(vec![Reply::Integer(5), Reply::Error("ERR no".into())])),
+            ]))
+        );
+        assert_eq!(
+            read_reply(&mut input).await.unwrap(),
+            Reply::Simple("OK".into())
+        );
+        assert!(read_reply(&mut input).await.is_err());
+    }
+
+    #[test]
+    fn users_go_into_a_set_that_expires_with_the_node() {
+        let online = [
+            command(&[b"SADD", b"zgm:users:a", b"ann"]),
+            command(&[b"EXPIRE", b"zgm:users:a", b"30"]),
+        ]
+        .concat();
+        assert!(announce("a", "ann", true).starts_with(&online));
+        let offline = command(&[b"SREM", b"zgm:users:a", b"ann"]);
+        assert!(announce("a", "ann", false).starts_with(&offline));
+        let beat = [
+            command(&[b"EXPIRE", b"zgm:users:a", b"30"]),
+            command(&[b"ZADD", b"zgm:nodes", b"100", b"a"]),
+            command(&[b"PUBLISH", b"zgm:heartbeats", b"a"]),
+        ]
+        .concat();
+        assert_eq!(heartbeat("a", 100), beat);
+    }
+
+    #[actix::test]
+    async fn directory_covers_live_nodes_and_drops_users_this_node_lost() {
+        let mut replies: &[u8] = b":1\r\n\
+            *2\r\n$1\r\na\r\n$1\r\nb\r\n\
+            *2\r\n$3\r\nbob\r\n$3\r\ncat\r\n\
+            *2\r\n$3\r\nann\r\n$3\r\ndan\r\n\
+            :1\r\n:0\r\n";
+        let mut sent = Vec::new();
+        let local = HashSet::from_iter(["ann".into()]);
+        let node = "a".into();
+        let mut directory = load_directory(&mut sent, &mut replies, &node, &local)
+            .await
+            .unwrap();
+        directory.sort();
+        let on_b = |user: &str| (user.into(), "b".into());
+        assert_eq!(directory, vec![on_b("bob"), on_b("cat")]);
+        assert!(replies.is_empty());
+        let sent = String::from_utf8(sent).unwrap();
+        assert!(sent.contains("SMEMBERS\r\n$11\r\nzgm:users:b"));
+        assert!(sent.contains("SREM\r\n$11\r\nzgm:users:a\r\n$3\r\ndan"));
+        assert!(!sent.contains("SREM\r\n$11\r\nzgm:users:a\r\n$3\r\nann"));
+    }
+
+    #[test]
+    fn nodes_gone_quiet_are_no_longer_alive() {
+        let (commands, _) = channel(1);
+        let mut backplane = Backplane {
+            commands,
+            heard: HashMap::new(),
+        };
+        let start = Instant::now();
+        backplane.heard("a".into(), start);
+        backplane.heard("b".into(), start + NODE_TTL / 2);
+        assert!(backplane.alive(&"a".into()));
+        backplane.heard("b".into(), start + NODE_TTL);
+        assert!(!backplane.alive(&"a".into()));
+        assert!(backplane.alive(&"b".into()));
+    }
+}
@@ -0,0 +1,164 @@
+//! Keeps clients that read slower than the server writes from piling up messages on the server
+//! without bound. Websocket connections stop writing as soon as their socket does, so the bytes
+//! sent to them are counted until the socket takes them. Sessions shed frames a later one
+//! supersedes once too many are queued and hang up on clients that fall too far behind to ever
+//! catch up.
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::web::Bytes;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+const DEFAULT_SHED_THRESHOLD: usize = 64 * 1024;
+const DEFAULT_DISCONNECT_THRESHOLD: usize = 1024 * 1024;
+
+/// How much an outgoing message matters to the client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    /// Reactions, hints and other messages that are either cosmetic or superseded by the next
+    /// one of their kind, the first to go once the client falls behind
+    Low,
+}
+
+/// What becomes of an outgoing message, see [BackpressureConfig::check]
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Send,
+    /// The message is dropped, the client is behind but may still catch up
+    Shed,
+    /// The client is too far behind and is disconnected
+    Disconnect,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BackpressureConfig {
+    /// Low priority messages are dropped while more bytes than this are queued for the client
+    pub shed_threshold: usize,
+    /// Clients with more bytes than this queued are disconnected
+    pub disconnect_threshold: usize,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            shed_threshold: DEFAULT_SHED_THRESHOLD,
+            disconnect_threshold: DEFAULT_DISCONNECT_THRESHOLD,
+        }
+    }
+}
+
+impl BackpressureConfig {
+    /// Reads `OUTGOING_SHED_THRESHOLD` and `OUTGOING_DISCONNECT_THRESHOLD`, both in bytes
+    pub fn from_env() -> Self {
+        fn read<T: FromStr>(name: &str, default: T) -> T {
+            match std::env::var(name).map(|x| x.parse()) {
+                Ok(Ok(value)) => value,
+                Ok(Err(_)) => {
+                    log::error!("ignoring malformed {name}");
+                    default
+                }
+                Err(_) => default,
+            }
+        }
+        let shed_threshold = read("OUTGOING_SHED_THRESHOLD", DEFAULT_SHED_THRESHOLD);
+        let disconnect_threshold = read(
+            "OUTGOING_DISCONNECT_THRESHOLD",
+            DEFAULT_DISCONNECT_THRESHOLD,
+        );
+        Self {
+            shed_threshold,
+            // Shedding has to kick in before clients get disconnected to be of any use
+            disconnect_threshold: disconnect_threshold.max(shed_threshold),
+        }
+    }
+    /// Decides on a message of the given priority for a client with `queued` bytes still
+    /// waiting to be written to it
+    pub fn check(&self, queued: usize, priority: Priority) -> Verdict {
+        if queued > self.disconnect_threshold {
+            Verdict::Disconnect
+        } else if queued > self.shed_threshold && priority == Priority::Low {
+            Verdict::Shed
+        } else {
+            Verdict::Send
+        }
+    }
+}
+
+/// Bytes sent to a connection that have not been handed to its socket yet, shared between the
+/// session sending them and the body of the connection's response
+#[derive(Clone, Default)]
+pub struct Backlog(Arc<AtomicUsize>);
+
+impl Backlog {
+    pub fn queued(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+    pub fn push(&self, len: usize) {
+        self.0.fetch_add(len, Ordering::Relaxed);
+    }
+    /// Chunks handed to the socket carry framing on top of the payloads that were pushed, so
+    /// the count never goes below zero
+    pub fn pop(&self, len: usize) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                Some(queued.saturating_sub(len))
+            });
+    }
+}
+
+/// Body of a websocket response, takes whatever the socket is handed off the connection's
+/// [Backlog]
+pub struct Drained {
+    body: BoxBody,
+    backlog: Backlog,
+}
+
+impl Drained {
+    pub fn new(body: BoxBody, backlog: Backlog) -> Self {
+        Self { body, backlog }
+    }
+}
+
+impl MessageBody for Drained {
+    type Error = <BoxBody as MessageBody>::Error;
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.backlog.pop(chunk.len());
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_priority_messages_go_first() {
+        let config = BackpressureConfig {
+            shed_threshold: 100,
+            disconnect_threshold: 1000,
+        };
+        assert_eq!(config.check(100, Priority::Low), Verdict::Send);
+        assert_eq!(config.check(101, Priority::Low), Verdict::Shed);
+        assert_eq!(config.check(1000, Priority::Normal), Verdict::Send);
+        assert_eq!(config.check(1001, Priority::Normal), Verdict::Disconnect);
+        assert_eq!(config.check(1001, Priority::Low), Verdict::Disconnect);
+        let backlog = Backlog::default();
+        backlog.push(10);
+        backlog.pop(14);
+        assert_eq!(backlog.queued(), 0);
+    }
+}
@@ -0,0 +1,51 @@
+//! Bots run by communities, such as quiz masters or moderators, log in with an API key instead
+//! of as a user. They get a user id of their own namespace, so that rooms can tell them apart
+//! from players: bots are labeled in rosters, are not rated and have a larger chat budget.
+
+use ahash::{HashMap, HashMapExt};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+
+use super::UserId;
+
+/// Prefix of the user ids of bots, players cannot log in with an id starting with it
+pub const BOT_PREFIX: &str = "bot:";
+
+pub fn is_bot(user: &str) -> bool {
+    user.starts_with(BOT_PREFIX)
+}
+
+#[derive(Serialize, Clone)]
+pub enum BotLoginError {
+    /// The key does not belong to a registered bot
+    InvalidKey,
+    AlreadyLoggedIn,
+    InternalServerError,
+}
+
+/// API keys of the registered bots, keyed by their SHA-1 digest like the admin tokens
+pub struct BotKeys(HashMap<[u8; 20], UserId>);
+
+impl BotKeys {
+    /// Reads `BOT_KEYS`, a comma separated list of `name:key` entries. Nobody can log in as a
+    /// bot without it.
+    pub fn from_env() -> Self {
+        let mut keys = HashMap::new();
+        let entries = std::env::var("BOT_KEYS").unwrap_or_default();
+        for entry in entries.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let Some((name, key)) = entry.split_once(':') else {
+                log::error!("skipping malformed bot key entry");
+                continue;
+            };
+            keys.insert(
+                Sha1::digest(key).into(),
+                format!("{BOT_PREFIX}{name}").into(),
+            );
+        }
+        Self(keys)
+    }
+    /// User id of the bot the key was issued to
+    pub fn authenticate(&self, key: &str) -> Option<UserId> {
+        self.0.get(&<[u8; 20]>::from(Sha1::digest(key))).cloned()
+    }
+}
@@ -0,0 +1,487 @@
+//! Server side translations of the text clients may show their players as is. Clients opt in by
+//! declaring a locale when logging in, see [super::message::IncomingMessage::Login]. Clients that
+//! did not keep getting English text and translate the stable keys and codes sent next to it on
+//! their own.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::errors::ErrorKind;
+use super::message::RemoveReason;
+
+/// Languages the catalog has text in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+    Fr,
+    De,
+}
+
+/// Countdowns clients are warned about in their own language, see
+/// [super::message::OutgoingMessage::Notice]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Countdown {
+    /// See [super::message::OutgoingMessage::RoomExpiring]
+    RoomExpiring,
+    /// See [super::message::OutgoingMessage::RoomEnding]
+    RoomEnding,
+    /// See [super::message::OutgoingMessage::StartingIn]
+    StartingIn,
+}
+
+impl Countdown {
+    /// Stable key of the notice, see [RemoveReason::localization_key]
+    pub fn localization_key(self) -> &'static str {
+        match self {
+            Countdown::RoomExpiring => "room.countdown.expiring",
+            Countdown::RoomEnding => "room.countdown.ending",
+            Countdown::StartingIn => "room.countdown.starting",
+        }
+    }
+}
+
+impl Locale {
+    /// Picks the language of a locale tag such as `es-MX` by its primary subtag, languages the
+    /// catalog lacks fall back to English
+    pub fn parse(tag: &str) -> Self {
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "es" => Locale::Es,
+            "fr" => Locale::Fr,
+            "de" => Locale::De,
+            _ => Locale::En,
+        }
+    }
+    /// Explanation of an error, see [ErrorKind::message]
+    pub fn error(self, kind: ErrorKind) -> &'static str {
+        match self {
+            Locale::En => kind.message(),
+            Locale::Es => es::error(kind),
+            Locale::Fr => fr::error(kind),
+            Locale::De => de::error(kind),
+        }
+    }
+    /// Explanation of why the client was removed from its room, naming whoever `by` removed it
+    /// if anyone did
+    pub fn removed(self, reason: RemoveReason, by: Option<&str>) -> String {
+        match (reason, by) {
+            (RemoveReason::Kicked, Some(by)) => {
+                return match self {
+                    Locale::En => format!("Removed by {by}"),
+                    Locale::Es => format!("{by} te ha expulsado"),
+                    Locale::Fr => format!("{by} vous a exclu"),
+                    Locale::De => format!("Von {by} entfernt"),
+                }
+            }
+            (RemoveReason::Banned, Some(by)) => {
+                return match self {
+                    Locale::En => format!("Removed and banned by {by}"),
+                    Locale::Es => format!("{by} te ha expulsado y vetado"),
+                    Locale::Fr => format!("{by} vous a exclu et banni"),
+                    Locale::De => format!("Von {by} entfernt und gesperrt"),
+                }
+            }
+            _ => {}
+        }
+        match self {
+            Locale::En => en::removed(reason),
+            Locale::Es => es::removed(reason),
+            Locale::Fr => fr::removed(reason),
+            Locale::De => de::removed(reason),
+        }
+        .into()
+    }
+    /// Warning that `remaining` is left on a countdown, rounded up to whole seconds
+    pub fn countdown(self, countdown: Countdown, remaining: Duration) -> String {
+        let secs = remaining.as_millis().div_ceil(1000);
+        match (self, countdown) {
+            (Locale::En, Countdown::RoomExpiring) => {
+                format!("The room closes in {secs} seconds unless something happens")
+            }
+            (Locale::En, Countdown::RoomEnding) => format!("The room closes in {secs} seconds"),
+            (Locale::En, Countdown::StartingIn) => format!("The game starts in {secs} seconds"),
+            (Locale::Es, Countdown::RoomExpiring) => {
+                format!("La sala se cierra en {secs} segundos si nadie hace nada")
+            }
+            (Locale::Es, Countdown::RoomEnding) => format!("La sala se cierra en {secs} segundos"),
+            (Locale::Es, Countdown::StartingIn) => {
+                format!("La partida empieza en {secs} segundos")
+            }
+            (Locale::Fr, Countdown::RoomExpiring) => {
+                format!("Le salon ferme dans {secs} secondes si personne ne fait rien")
+            }
+            (Locale::Fr, Countdown::RoomEnding) => format!("Le salon ferme dans {secs} secondes"),
+            (Locale::Fr, Countdown::StartingIn) => {
+                format!("La partie commence dans {secs} secondes")
+            }
+            (Locale::De, Countdown::RoomExpiring) => {
+                format!("Der Raum schließt in {secs} Sekunden, wenn nichts passiert")
+            }
+            (Locale::De, Countdown::RoomEnding) => {
+                format!("Der Raum schließt in {secs} Sekunden")
+            }
+            (Locale::De, Countdown::StartingIn) => {
+                format!("Das Spiel beginnt in {secs} Sekunden")
+            }
+        }
+    }
+}
+
+mod en {
+    use super::*;
+
+    pub fn removed(reason: RemoveReason) -> &'static str {
+        match reason {
+            RemoveReason::RoomClosed => "The room was closed",
+            RemoveReason::RoomExpired => "The room closed after sitting idle",
+            RemoveReason::Logout => "You logged out",
+            RemoveReason::Disconnected => "You lost your connection",
+            RemoveReason::Idle => "You were idle for too long",
+            RemoveReason::LeaveRequested => "You left the room",
+            RemoveReason::IdMismatch => "Your session did not match the room",
+            RemoveReason::Kicked => "You were kicked from the room",
+            RemoveReason::Banned => "You were kicked from the room and cannot rejoin it",
+            RemoveReason::Merged => "The room was merged into another one",
+            RemoveReason::RateLimited => "You sent too many messages",
+            RemoveReason::UnsupportedProtocol => "Your app is too old for this server, update it",
+            RemoveReason::SlowConnection => "Your connection is too slow to keep up",
+            RemoveReason::LoggedInElsewhere => "You logged in somewhere else",
+            RemoveReason::MalformedFrame => "Your app sent something the server cannot read",
+        }
+    }
+}
+
+mod es {
+    use super::*;
+
+    pub fn error(kind: ErrorKind) -> &'static str {
+        match kind {
+            ErrorKind::InternalServerError => "Algo ha fallado en el servidor",
+            ErrorKind::ServerBusy => "El servidor está muy ocupado, inténtalo más tarde",
+            ErrorKind::RateLimited => "Demasiadas peticiones, ve más despacio",
+            ErrorKind::NotAllowed => "Esto no está permitido aquí",
+            ErrorKind::NotLoggedIn => "Inicia sesión primero",
+            ErrorKind::NotInRoom => "No estás en ninguna sala",
+            ErrorKind::NotLeader => "Solo el líder de la sala puede hacer esto",
+            ErrorKind::AlreadyLoggedIn => "Ya has iniciado sesión",
+            ErrorKind::AlreadyConnected => "Ya estás conectado desde otro sitio",
+            ErrorKind::InvalidToken => "El token no es válido",
+            ErrorKind::InvalidKey => "La clave no es válida",
+            ErrorKind::Reserved => "Este nombre está reservado",
+            ErrorKind::RoomNotFound => "Esa sala no existe",
+            ErrorKind::RoomFull => "La sala está llena",
+            ErrorKind::RoomLocked => "La sala está cerrada",
+            ErrorKind::GameInProgress => "Hay una partida en curso en la sala",
+            ErrorKind::AlreadyInRoom => "Ya estás en una sala",
+            ErrorKind::InvalidCode => "El código de la sala no es válido",
+            ErrorKind::NoMatch => "Ninguna sala se ajusta a tus preferencias",
+            ErrorKind::NameTaken => "Alguien en la sala ya usa este nombre",
+            ErrorKind::InappropriateName => "El nombre no es apropiado",
+            ErrorKind::Banned => "Te han vetado en la sala",
+            ErrorKind::Queued => "La sala está llena, estás en la cola para un asiento",
+            ErrorKind::InvalidInvite => "La invitación no es válida",
+            ErrorKind::InviteExpired => "La invitación ha caducado",
+            ErrorKind::NoCodeAvailable => {
+                "Ahora no se puede abrir ninguna sala, inténtalo más tarde"
+            }
+            ErrorKind::SpectatorsFull => "La sala no admite más espectadores",
+            ErrorKind::WrongPassword => "La contraseña es incorrecta",
+            ErrorKind::InvalidPassword => "La contraseña es demasiado larga",
+            ErrorKind::GameAlreadyRunning => "Ya hay una partida en curso",
+            ErrorKind::NoGameRunning => "No hay ninguna partida en curso",
+            ErrorKind::NotEnoughPlayers => "No hay suficientes jugadores para empezar",
+            ErrorKind::VotingRematch => "La sala está votando la revancha",
+            ErrorKind::ModeAtCapacity => "Hay demasiadas partidas de este modo ahora mismo",
+            ErrorKind::OutOfTurn => "No es tu turno",
+            ErrorKind::TooFast => "Demasiado rápido, espera un momento",
+            ErrorKind::Duplicate => "Esto ya se ha jugado",
+            ErrorKind::NothingToReplay => "No ha pasado nada lo bastante reciente para repetir",
+            ErrorKind::NoSuchPlayer => "Ese jugador no está en la sala",
+            ErrorKind::CannotKickSelf => "No puedes expulsarte a ti mismo",
+            ErrorKind::NotBanned => "El jugador no está vetado",
+            ErrorKind::InvalidAlias => "El alias no es válido",
+            ErrorKind::Blocked => "Esto no está permitido",
+            ErrorKind::Taken => "Esto ya está ocupado",
+            ErrorKind::NotOnline => "El usuario no está conectado",
+            ErrorKind::Empty => "El mensaje está vacío",
+            ErrorKind::TooLong => "El mensaje es demasiado largo",
+            ErrorKind::NotFriend => "El usuario no te tiene como amigo",
+            ErrorKind::ListenOnly => "Solo el anfitrión puede hablar en esta sala",
+            ErrorKind::RevealsWord => "El mensaje desvela la palabra",
+            ErrorKind::InvalidPlayerLimit => "El límite de jugadores está fuera de rango",
+            ErrorKind::InvalidMinPlayers => "El mínimo de jugadores está fuera de rango",
+            ErrorKind::InvalidSpectatorLimit => "El límite de espectadores está fuera de rango",
+            ErrorKind::InvalidTurnDuration => "La duración del turno está fuera de rango",
+            ErrorKind::InvalidTurnHandoff => "La pausa entre turnos está fuera de rango",
+            ErrorKind::InvalidRoundDuration => "La duración de la ronda está fuera de rango",
+            ErrorKind::InvalidHintInterval => "El intervalo de pistas está fuera de rango",
+            ErrorKind::InvalidUpcomingTurns => "El número de próximos turnos está fuera de rango",
+            ErrorKind::InvalidReconnectGrace => "El margen para reconectar está fuera de rango",
+            ErrorKind::UnsupportedLanguage => "El idioma no está disponible",
+            ErrorKind::ValueTooLong => "El valor es demasiado largo",
+            ErrorKind::TooManyEntries => "Hay demasiadas entradas",
+            ErrorKind::Inappropriate => "El texto no es apropiado",
+            ErrorKind::InvalidName => "El nombre no es válido",
+            ErrorKind::InvalidAvatar => "El avatar no es válido",
+            ErrorKind::InvalidColor => "El color no es válido",
+            ErrorKind::InvalidWords => "La lista de palabras no es válida",
+        }
+    }
+
+    pub fn removed(reason: RemoveReason) -> &'static str {
+        match reason {
+            RemoveReason::RoomClosed => "La sala se ha cerrado",
+            RemoveReason::RoomExpired => "La sala se ha cerrado por inactividad",
+            RemoveReason::Logout => "Has cerrado sesión",
+            RemoveReason::Disconnected => "Has perdido la conexión",
+            RemoveReason::Idle => "Has estado inactivo demasiado tiempo",
+            RemoveReason::LeaveRequested => "Has salido de la sala",
+            RemoveReason::IdMismatch => "Tu sesión no coincide con la sala",
+            RemoveReason::Kicked => "Te han expulsado de la sala",
+            RemoveReason::Banned => "Te han expulsado de la sala y no puedes volver",
+            RemoveReason::Merged => "La sala se ha unido a otra",
+            RemoveReason::RateLimited => "Has enviado demasiados mensajes",
+            RemoveReason::UnsupportedProtocol => {
+                "Tu aplicación es demasiado antigua para este servidor, actualízala"
+            }
+            RemoveReason::SlowConnection => "Tu conexión es demasiado lenta",
+            RemoveReason::LoggedInElsewhere => "Has iniciado sesión en otro sitio",
+            RemoveReason::MalformedFrame => {
+                "Tu aplicación envió algo que el servidor no puede leer"
+            }
+        }
+    }
+}
+
+mod fr {
+    use super::*;
+
+    pub fn error(kind: ErrorKind) -> &'static str {
+        match kind {
+            ErrorKind::InternalServerError => "Une erreur s'est produite sur le serveur",
+            ErrorKind::ServerBusy => "Le serveur est trop occupé, réessayez plus tard",
+            ErrorKind::RateLimited => "Trop de requêtes, ralentissez",
+            ErrorKind::NotAllowed => "Ce n'est pas autorisé ici",
+            ErrorKind::NotLoggedIn => "Connectez-vous d'abord",
+            ErrorKind::NotInRoom => "Vous n'êtes dans aucun salon",
+            ErrorKind::NotLeader => "Seul le chef du salon peut faire cela",
+            ErrorKind::AlreadyLoggedIn => "Vous êtes déjà connecté",
+            ErrorKind::AlreadyConnected => "Vous êtes déjà connecté depuis un autre endroit",
+            ErrorKind::InvalidToken => "Le jeton n'est pas valide",
+            ErrorKind::InvalidKey => "La clé n'est pas valide",
+            ErrorKind::Reserved => "Ce nom est réservé",
+            ErrorKind::RoomNotFound => "Ce salon n'existe pas",
+            ErrorKind::RoomFull => "Le salon est plein",
+            ErrorKind::RoomLocked => "Le salon est verrouillé",
+            ErrorKind::GameInProgress => "Une partie est en cours dans le salon",
+            ErrorKind::AlreadyInRoom => "Vous êtes déjà dans un salon",
+            ErrorKind::InvalidCode => "Le code du salon n'est pas valide",
+            ErrorKind::NoMatch => "Aucun salon ne correspond à vos préférences",
+            ErrorKind::NameTaken => "Quelqu'un dans le salon porte déjà ce nom",
+            ErrorKind::InappropriateName => "Le nom n'est pas approprié",
+            ErrorKind::Banned => "Vous avez été banni du salon",
+            ErrorKind::Queued => "Le salon est plein, vous êtes dans la file d'attente",
+            ErrorKind::InvalidInvite => "L'invitation n'est pas valide",
+            ErrorKind::InviteExpired => "L'invitation a expiré",
+            ErrorKind::NoCodeAvailable => "Aucun salon ne peut être ouvert pour l'instant",
+            ErrorKind::SpectatorsFull => "Le salon n'accepte plus de spectateurs",
+            ErrorKind::WrongPassword => "Le mot de passe est incorrect",
+            ErrorKind::InvalidPassword => "Le mot de passe est trop long",
+            ErrorKind::GameAlreadyRunning => "Une partie est déjà en cours",
+            ErrorKind::NoGameRunning => "Aucune partie n'est en cours",
+            ErrorKind::NotEnoughPlayers => "Pas assez de joueurs pour commencer",
+            ErrorKind::VotingRematch => "Le salon vote pour une revanche",
+            ErrorKind::ModeAtCapacity => "Trop de parties de ce mode sont en cours",
+            ErrorKind::OutOfTurn => "Ce n'est pas votre tour",
+            ErrorKind::TooFast => "Trop rapide, attendez un instant",
+            ErrorKind::Duplicate => "Cela a déjà été joué",
+            ErrorKind::NothingToReplay => "Rien d'assez récent à rejouer",
+            ErrorKind::NoSuchPlayer => "Ce joueur n'est pas dans le salon",
+            ErrorKind::CannotKickSelf => "Vous ne pouvez pas vous exclure vous-même",
+            ErrorKind::NotBanned => "Le joueur n'est pas banni",
+            ErrorKind::InvalidAlias => "L'alias n'est pas valide",
+            ErrorKind::Blocked => "Ce n'est pas autorisé",
+            ErrorKind::Taken => "C'est déjà pris",
+            ErrorKind::NotOnline => "L'utilisateur n'est pas en ligne",
+            ErrorKind::Empty => "Le message est vide",
+            ErrorKind::TooLong => "Le message est trop long",
+            ErrorKind::NotFriend => "L'utilisateur ne vous compte pas parmi ses amis",
+            ErrorKind::ListenOnly => "Seul l'hôte peut parler dans ce salon",
+            ErrorKind::RevealsWord => "Le message dévoile le mot",
+            ErrorKind::InvalidPlayerLimit => "La limite de joueurs est hors limites",
+            ErrorKind::InvalidMinPlayers => "Le nombre minimum de joueurs est hors limites",
+            ErrorKind::InvalidSpectatorLimit => "La limite de spectateurs est hors limites",
+            ErrorKind::InvalidTurnDuration => "La durée du tour est hors limites",
+            ErrorKind::InvalidTurnHandoff => "La pause entre les tours est hors limites",
+            ErrorKind::InvalidRoundDuration => "La durée de la manche est hors limites",
+            ErrorKind::InvalidHintInterval => "L'intervalle des indices est hors limites",
+            ErrorKind::InvalidUpcomingTurns => "Le nombre de tours à venir est hors limites",
+            ErrorKind::InvalidReconnectGrace => "Le délai de reconnexion est hors limites",
+            ErrorKind::UnsupportedLanguage => "La langue n'est pas prise en charge",
+            ErrorKind::ValueTooLong => "La valeur est trop longue",
+            ErrorKind::TooManyEntries => "Il y a trop d'entrées",
+            ErrorKind::Inappropriate => "Le texte n'est pas approprié",
+            ErrorKind::InvalidName => "Le nom n'est pas valide",
+            ErrorKind::InvalidAvatar => "L'avatar n'est pas valide",
+            ErrorKind::InvalidColor => "La couleur n'est pas valide",
+            ErrorKind::InvalidWords => "La liste de mots n'est pas valide",
+        }
+    }
+
+    pub fn removed(reason: RemoveReason) -> &'static str {
+        match reason {
+            RemoveReason::RoomClosed => "Le salon a été fermé",
+            RemoveReason::RoomExpired => "Le salon a fermé faute d'activité",
+            RemoveReason::Logout => "Vous vous êtes déconnecté",
+            RemoveReason::Disconnected => "Vous avez perdu la connexion",
+            RemoveReason::Idle => "Vous êtes resté inactif trop longtemps",
+            RemoveReason::LeaveRequested => "Vous avez quitté le salon",
+            RemoveReason::IdMismatch => "Votre session ne correspond pas au salon",
+            RemoveReason::Kicked => "Vous avez été exclu du salon",
+            RemoveReason::Banned => "Vous avez été exclu du salon et ne pouvez plus y revenir",
+            RemoveReason::Merged => "Le salon a été fusionné avec un autre",
+            RemoveReason::RateLimited => "Vous avez envoyé trop de messages",
+            RemoveReason::UnsupportedProtocol => {
+                "Votre application est trop ancienne pour ce serveur, mettez-la à jour"
+            }
+            RemoveReason::SlowConnection => "Votre connexion est trop lente",
+            RemoveReason::LoggedInElsewhere => "Vous vous êtes connecté ailleurs",
+            RemoveReason::MalformedFrame => {
+                "Votre application a envoyé quelque chose que le serveur ne peut pas lire"
+            }
+        }
+    }
+}
+
+mod de {
+    use super::*;
+
+    pub fn error(kind: ErrorKind) -> &'static str {
+        match kind {
+            ErrorKind::InternalServerError => "Auf dem Server ist etwas schiefgelaufen",
+            ErrorKind::ServerBusy => "Der Server ist ausgelastet, versuch es später noch einmal",
+            ErrorKind::RateLimited => "Zu viele Anfragen, mach langsamer",
+            ErrorKind::NotAllowed => "Das ist hier nicht erlaubt",
+            ErrorKind::NotLoggedIn => "Melde dich zuerst an",
+            ErrorKind::NotInRoom => "Du bist in keinem Raum",
+            ErrorKind::NotLeader => "Nur der Leiter des Raums kann das tun",
+            ErrorKind::AlreadyLoggedIn => "Du bist bereits angemeldet",
+            ErrorKind::AlreadyConnected => "Du bist bereits von woanders verbunden",
+            ErrorKind::InvalidToken => "Das Token ist ungültig",
+            ErrorKind::InvalidKey => "Der Schlüssel ist ungültig",
+            ErrorKind::Reserved => "Dieser Name ist reserviert",
+            ErrorKind::RoomNotFound => "Diesen Raum gibt es nicht",
+            ErrorKind::RoomFull => "Der Raum ist voll",
+            ErrorKind::RoomLocked => "Der Raum ist gesperrt",
+            ErrorKind::GameInProgress => "Im Raum läuft ein Spiel",
+            ErrorKind::AlreadyInRoom => "Du bist bereits in einem Raum",
+            ErrorKind::InvalidCode => "Der Raumcode ist ungültig",
+            ErrorKind::NoMatch => "Kein Raum passt zu deinen Einstellungen",
+            ErrorKind::NameTaken => "Jemand im Raum trägt diesen Namen bereits",
+            ErrorKind::InappropriateName => "Der Name ist unangemessen",
+            ErrorKind::Banned => "Du wurdest aus dem Raum verbannt",
+            ErrorKind::Queued => "Der Raum ist voll, du stehst in der Warteschlange",
+            ErrorKind::InvalidInvite => "Die Einladung ist ungültig",
+            ErrorKind::InviteExpired => "Die Einladung ist abgelaufen",
+            ErrorKind::NoCodeAvailable => "Gerade kann kein Raum geöffnet werden",
+            ErrorKind::SpectatorsFull => "Der Raum hat keinen Platz für weitere Zuschauer",
+            ErrorKind::WrongPassword => "Das Passwort ist falsch",
+            ErrorKind::InvalidPassword => "Das Passwort ist zu lang",
+            ErrorKind::GameAlreadyRunning => "Es läuft bereits ein Spiel",
+            ErrorKind::NoGameRunning => "Es läuft kein Spiel",
+            ErrorKind::NotEnoughPlayers => "Nicht genug Spieler für ein Spiel",
+            ErrorKind::VotingRematch => "Der Raum stimmt über eine Revanche ab",
+            ErrorKind::ModeAtCapacity => "Gerade laufen zu viele Spiele dieses Modus",
+            ErrorKind::OutOfTurn => "Du bist nicht am Zug",
+            ErrorKind::TooFast => "Zu schnell, warte einen Moment",
+            ErrorKind::Duplicate => "Das wurde bereits gespielt",
+            ErrorKind::NothingToReplay => "Nichts ist kürzlich genug passiert, um es zu zeigen",
+            ErrorKind::NoSuchPlayer => "Dieser Spieler ist nicht im Raum",
+            ErrorKind::CannotKickSelf => "Du kannst dich nicht selbst entfernen",
+            ErrorKind::NotBanned => "Der Spieler ist nicht verbannt",
+            ErrorKind::InvalidAlias => "Der Alias ist ungültig",
+            ErrorKind::Blocked => "Das ist nicht erlaubt",
+            ErrorKind::Taken => "Das ist bereits vergeben",
+            ErrorKind::NotOnline => "Der Nutzer ist nicht online",
+            ErrorKind::Empty => "Die Nachricht ist leer",
+            ErrorKind::TooLong => "Die Nachricht ist zu lang",
+            ErrorKind::NotFriend => "Der Nutzer hat dich nicht als Freund",
+            ErrorKind::ListenOnly => "Nur der Gastgeber kann in diesem Raum sprechen",
+            ErrorKind::RevealsWord => "Die Nachricht verrät das Wort",
+            ErrorKind::InvalidPlayerLimit => "Das Spielerlimit liegt außerhalb des Bereichs",
+            ErrorKind::InvalidMinPlayers => "Die Mindestspielerzahl liegt außerhalb des Bereichs",
+            ErrorKind::InvalidSpectatorLimit => "Das Zuschauerlimit liegt außerhalb des Bereichs",
+            ErrorKind::InvalidTurnDuration => "Die Zugdauer liegt außerhalb des Bereichs",
+            ErrorKind::InvalidTurnHandoff => {
+                "Die Pause zwischen Zügen liegt außerhalb des Bereichs"
+            }
+            ErrorKind::InvalidRoundDuration => "Die Rundendauer liegt außerhalb des Bereichs",
+            ErrorKind::InvalidHintInterval => "Der Hinweisabstand liegt außerhalb des Bereichs",
+            ErrorKind::InvalidUpcomingTurns => {
+                "Die Zahl der kommenden Züge liegt außerhalb des Bereichs"
+            }
+            ErrorKind::InvalidReconnectGrace => {
+                "Die Frist zum Wiederverbinden liegt außerhalb des Bereichs"
+            }
+            ErrorKind::UnsupportedLanguage => "Die Sprache wird nicht unterstützt",
+            ErrorKind::ValueTooLong => "Der Wert ist zu lang",
+            ErrorKind::TooManyEntries => "Es gibt zu viele Einträge",
+            ErrorKind::Inappropriate => "Der Text ist unangemessen",
+            ErrorKind::InvalidName => "Der Name ist ungültig",
+            ErrorKind::InvalidAvatar => "Der Avatar ist ungültig",
+            ErrorKind::InvalidColor => "Die Farbe ist ungültig",
+            ErrorKind::InvalidWords => "Die Wortliste ist ungültig",
+        }
+    }
+
+    pub fn removed(reason: RemoveReason) -> &'static str {
+        match reason {
+            RemoveReason::RoomClosed => "Der Raum wurde geschlossen",
+            RemoveReason::RoomExpired => "Der Raum wurde wegen Inaktivität geschlossen",
+            RemoveReason::Logout => "Du hast dich abgemeldet",
+            RemoveReason::Disconnected => "Deine Verbindung ist abgebrochen",
+            RemoveReason::Idle => "Du warst zu lange inaktiv",
+            RemoveReason::LeaveRequested => "Du hast den Raum verlassen",
+            RemoveReason::IdMismatch => "Deine Sitzung passt nicht zum Raum",
+            RemoveReason::Kicked => "Du wurdest aus dem Raum entfernt",
+            RemoveReason::Banned => "Du wurdest aus dem Raum entfernt und kannst nicht zurück",
+            RemoveReason::Merged => "Der Raum wurde mit einem anderen zusammengelegt",
+            RemoveReason::RateLimited => "Du hast zu viele Nachrichten gesendet",
+            RemoveReason::UnsupportedProtocol => {
+                "Deine App ist zu alt für diesen Server, aktualisiere sie"
+            }
+            RemoveReason::SlowConnection => "Deine Verbindung ist zu langsam",
+            RemoveReason::LoggedInElsewhere => "Du hast dich woanders angemeldet",
+            RemoveReason::MalformedFrame => {
+                "Deine App hat etwas gesendet, das der Server nicht lesen kann"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_fall_back_to_english() {
+        assert_eq!(Locale::parse("es-MX"), Locale::Es);
+        assert_eq!(Locale::parse("DE_at"), Locale::De);
+        assert_eq!(Locale::parse("xx"), Locale::En);
+        assert_eq!(
+            Locale::Fr.removed(RemoveReason::Kicked, Some("ana")),
+            "ana vous a exclu"
+        );
+        assert_eq!(
+            Locale::En.removed(RemoveReason::Banned, Some("ana")),
+            "Removed and banned by ana"
+        );
+        assert_eq!(
+            Locale::En.countdown(Countdown::StartingIn, Duration::from_millis(4200)),
+            "The game starts in 5 seconds"
+        );
+        assert_eq!(Locale::Es.error(ErrorKind::RoomFull), "La sala está llena");
+    }
+}
@@ -0,0 +1,411 @@
+//! Compression of websocket messages, the `permessage-deflate` extension (RFC 7692). The
+//! websocket codec knows nothing about extensions, so compressed frames are rewritten on their
+//! way in and out: frames from the client are inflated before the codec parses them, and frames
+//! to the client above [DeflateConfig::threshold] are deflated after the codec wrote them.
+//! Clients that don't offer the extension are served uncompressed frames.
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::error::PayloadError;
+use actix_web::http::header::{HeaderValue, SEC_WEBSOCKET_EXTENSIONS};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::HttpRequest;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const DEFAULT_THRESHOLD: usize = 512;
+/// Largest message a client can send, compressed or not, same as the websocket codec's limit
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// Parameters the server answers every accepted offer with. Neither side keeps its window
+/// between messages, so that connections don't hold on to compression state.
+const RESPONSE: &str = concat!(
+    "permessage-deflate; ",
+    "server_no_context_takeover; client_no_context_takeover"
+);
+/// Every compressed message ends in these bytes, which are left out on the wire
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const MASKED: u8 = 0x80;
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DeflateConfig {
+    pub enabled: bool,
+    /// Messages to the client shorter than this many bytes are not worth compressing
+    pub threshold: usize,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+impl DeflateConfig {
+    /// Compression is switched on with `WS_DEFLATE=on`, `WS_DEFLATE_THRESHOLD` is in bytes
+    pub fn from_env() -> Self {
+        let enabled =
+            std::env::var("WS_DEFLATE").is_ok_and(|x| matches!(x.as_str(), "1" | "true" | "on"));
+        let threshold = match std::env::var("WS_DEFLATE_THRESHOLD").map(|x| x.parse()) {
+            Ok(Ok(threshold)) => threshold,
+            Ok(Err(_)) => {
+                log::error!("ignoring malformed WS_DEFLATE_THRESHOLD");
+                DEFAULT_THRESHOLD
+            }
+            Err(_) => DEFAULT_THRESHOLD,
+        };
+        Self { enabled, threshold }
+    }
+    /// Whether the client offered the extension with parameters the server can honor. Offers
+    /// asking the server for a smaller window than the default one are passed on.
+    pub fn negotiate(&self, req: &HttpRequest) -> bool {
+        self.enabled
+            && req
+                .headers()
+                .get_all(SEC_WEBSOCKET_EXTENSIONS)
+                .filter_map(|x| x.to_str().ok())
+                .flat_map(|x| x.split(','))
+                .any(acceptable)
+    }
+    /// Value of the `Sec-WebSocket-Extensions` header of the handshake response
+    pub fn response(&self) -> HeaderValue {
+        HeaderValue::from_static(RESPONSE)
+    }
+}
+
+fn acceptable(offer: &str) -> bool {
+    let mut params = offer.split(';').map(str::trim);
+    params.next() == Some("permessage-deflate")
+        && params.all(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match name.trim() {
+                "server_no_context_takeover" | "client_no_context_takeover" => true,
+                "client_max_window_bits" => true,
+                "server_max_window_bits" => value.trim_matches('"') == "15",
+                _ => false,
+            }
+        })
+}
+
+struct Header {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    /// Length of the header itself
+    len: usize,
+    payload: usize,
+}
+
+impl Header {
+    /// Header of the frame at the start of `buf`, if all of it arrived
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let (first, second) = (*buf.first()?, *buf.get(1)?);
+        let (mut len, payload) = match second & 0x7f {
+            126 => (
+                4,
+                u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as usize,
+            ),
+            127 => (
+                10,
+                u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?) as usize,
+            ),
+            payload => (2, payload as usize),
+        };
+        let mask = if second & MASKED != 0 {
+            let mask = buf.get(len..len + 4)?.try_into().ok()?;
+            len += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        Some(Self {
+            fin: first & FIN != 0,
+            rsv1: first & RSV1 != 0,
+            opcode: first & 0x0f,
+            mask,
+            len,
+            payload,
+        })
+    }
+    fn is_data(&self) -> bool {
+        matches!(self.opcode, TEXT | BINARY | CONTINUATION)
+    }
+}
+
+/// Writes a whole frame. Frames to the codec have to be masked, which they are with a mask that
+/// leaves the payload as it is.
+fn write_frame(dst: &mut BytesMut, first: u8, payload: &[u8], masked: bool) {
+    dst.extend_from_slice(&[first]);
+    let mask = if masked { MASKED } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => dst.extend_from_slice(&[mask | len as u8]),
+        len @ 126..=0xffff => {
+            dst.extend_from_slice(&[mask | 126]);
+            dst.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            dst.extend_from_slice(&[mask | 127]);
+            dst.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if masked {
+        dst.extend_from_slice(&[0; 4]);
+    }
+    dst.extend_from_slice(payload);
+}
+
+/// Payload stream of a websocket request, inflates compressed messages from the client
+pub struct Inflated<S> {
+    payload: S,
+    /// `None` unless the extension was negotiated, in which case the payload passes through
+    inflater: Option<Decompress>,
+    /// Bytes of frames that did not arrive whole yet
+    buf: BytesMut,
+    /// Opcode and unmasked payload of the compressed message whose frames are being collected
+    message: Option<(u8, Vec<u8>)>,
+}
+
+impl<S> Inflated<S> {
+    pub fn new(payload: S, negotiated: bool) -> Self {
+        Self {
+            payload,
+            inflater: negotiated.then(|| Decompress::new(false)),
+            buf: BytesMut::new(),
+            message: None,
+        }
+    }
+    /// Rewrites the frames that arrived whole so far, compressed messages are passed on once
+    /// their last frame arrived
+    fn rewrite(&mut self) -> Result<BytesMut, PayloadError> {
+        let mut out = BytesMut::new();
+        while let Some(header) = Header::parse(&self.buf) {
+            if header.payload > MAX_MESSAGE_SIZE {
+                return Err(PayloadError::Overflow);
+            }
+            if self.buf.len() < header.len + header.payload {
+                break;
+            }
+            let frame = self.buf.split_to(header.len + header.payload);
+            let continues = header.opcode == CONTINUATION && self.message.is_some();
+            if !(header.is_data() && (header.rsv1 || continues)) {
+                out.extend_from_slice(&frame);
+                continue;
+            }
+            let (opcode, message) = self
+                .message
+                .get_or_insert_with(|| (header.opcode, Vec::new()));
+            let mask = header.mask.unwrap_or_default();
+            let payload = frame[header.len..].iter().enumerate();
+            message.extend(payload.map(|(i, byte)| byte ^ mask[i % 4]));
+            if message.len() > MAX_MESSAGE_SIZE {
+                return Err(PayloadError::Overflow);
+            }
+            if header.fin {
+                let opcode = *opcode;
+                let (_, message) = self.message.take().expect("collected above");
+                let message = self.inflate(message)?;
+                write_frame(&mut out, FIN | opcode, &message, true);
+            }
+        }
+        Ok(out)
+    }
+    /// Inflates a whole message. Clients don't keep their window between messages, see
+    /// [RESPONSE], so neither does the inflater.
+    fn inflate(&mut self, input: Vec<u8>) -> Result<Vec<u8>, PayloadError> {
+        let inflater = self.inflater.as_mut().expect("only called once negotiated");
+        let inflated = inflate(inflater, input);
+        inflater.reset(false);
+        inflated
+    }
+}
+
+fn inflate(inflater: &mut Decompress, mut input: Vec<u8>) -> Result<Vec<u8>, PayloadError> {
+    input.extend_from_slice(&TRAILER);
+    let start = inflater.total_in();
+    let mut output = Vec::with_capacity(input.len() * 2);
+    loop {
+        if output.len() == output.capacity() {
+            output.reserve(output.len());
+        }
+        let before = (inflater.total_in(), inflater.total_out());
+        let read = (inflater.total_in() - start) as usize;
+        inflater
+            .decompress_vec(&input[read..], &mut output, FlushDecompress::Sync)
+            .map_err(|_| PayloadError::EncodingCorrupted)?;
+        if output.len() > MAX_MESSAGE_SIZE {
+            return Err(PayloadError::Overflow);
+        }
+        let read = (inflater.total_in() - start) as usize;
+        let stuck = (inflater.total_in(), inflater.total_out()) == before;
+        if read == input.len() && (output.len() < output.capacity() || stuck) {
+            return Ok(output);
+        }
+        if stuck {
+            return Err(PayloadError::EncodingCorrupted);
+        }
+    }
+}
+
+impl<S> Stream for Inflated<S>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, PayloadError>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.inflater.is_none() {
+            return Pin::new(&mut self.payload).poll_next(cx);
+        }
+        loop {
+            match Pin::new(&mut self.payload).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.buf.extend_from_slice(&chunk);
+                    match self.rewrite() {
+                        Ok(out) if out.is_empty() => continue,
+                        Ok(out) => return Poll::Ready(Some(Ok(out.freeze()))),
+                        Err(err) => return Poll::Ready(Some(Err(err))),
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+/// Body of a websocket response, deflates messages to the client above the threshold
+pub struct Deflated {
+    body: BoxBody,
+    threshold: usize,
+    /// Reset after every message, the server doesn't keep its window between them
+    deflater: Compress,
+    /// Bytes of frames the body did not yield whole yet
+    buf: BytesMut,
+}
+
+impl Deflated {
+    pub fn new(body: BoxBody, threshold: usize) -> Self {
+        Self {
+            body,
+            threshold,
+            deflater: Compress::new(Compression::default(), false),
+            buf: BytesMut::new(),
+        }
+    }
+    /// Frames that can't be compressed or aren't worth it go out as they are
+    fn rewrite(&mut self) -> BytesMut {
+        let mut out = BytesMut::new();
+        while let Some(header) = Header::parse(&self.buf) {
+            if self.buf.len() < header.len + header.payload {
+                break;
+            }
+            let frame = self.buf.split_to(header.len + header.payload);
+            let payload = &frame[header.len..];
+            let whole = header.fin && matches!(header.opcode, TEXT | BINARY);
+            let deflated = (whole && payload.len() >= self.threshold)
+                .then(|| self.deflate(payload))
+                .flatten();
+            match deflated {
+                Some(deflated) => {
+                    write_frame(&mut out, FIN | RSV1 | header.opcode, &deflated, false)
+                }
+                None => out.extend_from_slice(&frame),
+            }
+        }
+        out
+    }
+    /// Compressed payload of a message, if it came out any shorter
+    fn deflate(&mut self, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut output = Vec::with_capacity(payload.len() + TRAILER.len());
+        let status = self
+            .deflater
+            .compress_vec(payload, &mut output, FlushCompress::Sync);
+        let whole =
+            self.deflater.total_in() as usize == payload.len() && output.ends_with(&TRAILER);
+        self.deflater.reset();
+        if status.is_err() || !whole {
+            return None;
+        }
+        output.truncate(output.len() - TRAILER.len());
+        (output.len() < payload.len()).then_some(output)
+    }
+}
+
+impl MessageBody for Deflated {
+    type Error = <BoxBody as MessageBody>::Error;
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        loop {
+            match Pin::new(&mut self.body).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.buf.extend_from_slice(&chunk);
+                    let out = self.rewrite();
+                    if !out.is_empty() {
+                        return Poll::Ready(Some(Ok(out.freeze())));
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let text = r#"{"kind":"Chat","data":"hello hello hello hello hello hello hello"}"#;
+        let mut body = BytesMut::new();
+        write_frame(&mut body, FIN | TEXT, text.as_bytes(), false);
+        write_frame(&mut body, FIN | TEXT, text.as_bytes(), false);
+        write_frame(&mut body, FIN | TEXT, b"short", false);
+        let mut deflated = Deflated::new(BoxBody::new(()), 32);
+        deflated.buf = body;
+        let mut out = deflated.rewrite();
+        let header = Header::parse(&out).unwrap();
+        assert!(header.rsv1 && header.payload < text.len());
+        // Neither message refers back to the other one
+        let first = out.split_to(header.len + header.payload);
+        assert!(out.starts_with(&first));
+        out.extend_from_slice(&first);
+        // Frames from the client are masked
+        let mut client = BytesMut::new();
+        client.extend_from_slice(&[FIN | RSV1 | TEXT, MASKED | header.payload as u8, 1, 2, 3, 4]);
+        let payload = &out[header.len..header.len + header.payload];
+        client.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, x)| x ^ [1, 2, 3, 4][i % 4]),
+        );
+        client.extend_from_slice(&out[header.len + header.payload..]);
+        let mut inflated = Inflated::new((), true);
+        inflated.buf = client;
+        let mut out = inflated.rewrite().unwrap();
+        assert_eq!(Header::parse(&out).unwrap().mask, Some([0; 4]));
+        // The short frame was never compressed and passes through, the deflated one that was
+        // sent again after it inflates the same
+        for payload in [text.as_bytes(), b"short", text.as_bytes()] {
+            let header = Header::parse(&out).unwrap();
+            assert!(!header.rsv1);
+            let frame = out.split_to(header.len + header.payload);
+            assert_eq!(&frame[header.len..], payload);
+        }
+        assert!(acceptable("permessage-deflate; client_max_window_bits"));
+        assert!(!acceptable("permessage-deflate; server_max_window_bits=10"));
+    }
+}
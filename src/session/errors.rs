@@ -0,0 +1,429 @@
+//! Errors of every result sent to clients, see [super::message::Result]. Each error enum maps
+//! its variants onto [ErrorKind], which gives every way a request can fail a stable numeric code
+//! and a message, in English unless the client declared a locale, see [super::catalog], so that
+//! the same failure looks the same whichever request it answers and clients can branch on it
+//! without knowing every enum.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::game::validation::InputError;
+use crate::room::actor::{
+    BanError, GameInputError, InviteError, JoinRoomError, KickError, LockError, PauseError,
+    PromoteError, StartGameError,
+};
+use crate::room::browser::ListRoomsError;
+use crate::room::chat::ChatError;
+use crate::room::instant_replay::ReplayError;
+use crate::room::metadata::MetadataError;
+use crate::room::settings::SettingsError;
+use crate::room::AliasError;
+
+use super::bot::BotLoginError;
+use super::catalog::Locale;
+use super::message::LeaveRoomError;
+use super::presence::{FriendInviteError, FriendsError};
+use super::profile::ProfileError;
+use super::{LoginError, ResumeError, WhisperError};
+
+/// First version of the protocol errors are sent as an [ErrorPayload] in, older clients get the
+/// name of the variant of the error enum instead
+pub const TYPED_ERRORS_SINCE: u32 = 3;
+/// First version of the protocol rejected chat messages and failed matches carry an
+/// [ErrorPayload] in, like results do
+pub const TYPED_REJECTIONS_SINCE: u32 = 10;
+
+/// Every way a request can fail. Codes are grouped by what the request was about and must never
+/// change once released, new kinds get a code of their own.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    // Any request
+    InternalServerError = 100,
+    ServerBusy = 101,
+    RateLimited = 102,
+    NotAllowed = 103,
+    NotLoggedIn = 104,
+    NotInRoom = 105,
+    NotLeader = 106,
+    // Logging in
+    AlreadyLoggedIn = 200,
+    AlreadyConnected = 201,
+    InvalidToken = 202,
+    InvalidKey = 203,
+    Reserved = 204,
+    // Joining rooms
+    RoomNotFound = 300,
+    RoomFull = 301,
+    RoomLocked = 302,
+    GameInProgress = 303,
+    AlreadyInRoom = 304,
+    InvalidCode = 305,
+    NoMatch = 306,
+    NameTaken = 307,
+    InappropriateName = 308,
+    Banned = 309,
+    Queued = 310,
+    InvalidInvite = 311,
+    InviteExpired = 312,
+    NoCodeAvailable = 313,
+    SpectatorsFull = 314,
+    WrongPassword = 315,
+    InvalidPassword = 316,
+    // Games
+    GameAlreadyRunning = 400,
+    NoGameRunning = 401,
+    NotEnoughPlayers = 402,
+    VotingRematch = 403,
+    ModeAtCapacity = 404,
+    OutOfTurn = 405,
+    TooFast = 406,
+    Duplicate = 407,
+    NothingToReplay = 408,
+    // Running rooms
+    NoSuchPlayer = 500,
+    CannotKickSelf = 501,
+    NotBanned = 502,
+    InvalidAlias = 503,
+    Blocked = 504,
+    Taken = 505,
+    // Talking to other players
+    NotOnline = 600,
+    Empty = 601,
+    TooLong = 602,
+    NotFriend = 603,
+    ListenOnly = 604,
+    RevealsWord = 605,
+    // Settings, metadata and profiles
+    InvalidPlayerLimit = 700,
+    InvalidMinPlayers = 701,
+    InvalidSpectatorLimit = 702,
+    InvalidTurnDuration = 703,
+    InvalidTurnHandoff = 704,
+    InvalidRoundDuration = 705,
+    InvalidHintInterval = 706,
+    InvalidUpcomingTurns = 707,
+    InvalidReconnectGrace = 708,
+    UnsupportedLanguage = 709,
+    ValueTooLong = 710,
+    TooManyEntries = 711,
+    Inappropriate = 712,
+    InvalidName = 713,
+    InvalidAvatar = 714,
+    InvalidColor = 715,
+    InvalidWords = 716,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+    /// Explanation in English, for clients that lack a translation of their own
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorKind::InternalServerError => "Something went wrong on the server",
+            ErrorKind::ServerBusy => "The server is too busy right now, try again later",
+            ErrorKind::RateLimited => "Too many requests, slow down",
+            ErrorKind::NotAllowed => "This is not allowed here",
+            ErrorKind::NotLoggedIn => "Log in first",
+            ErrorKind::NotInRoom => "You are not in a room",
+            ErrorKind::NotLeader => "Only the leader of the room can do this",
+            ErrorKind::AlreadyLoggedIn => "You are already logged in",
+            ErrorKind::AlreadyConnected => "You are already connected from somewhere else",
+            ErrorKind::InvalidToken => "The token is not valid",
+            ErrorKind::InvalidKey => "The key is not valid",
+            ErrorKind::Reserved => "This name is reserved",
+            ErrorKind::RoomNotFound => "There is no such room",
+            ErrorKind::RoomFull => "The room is full",
+            ErrorKind::RoomLocked => "The room is locked",
+            ErrorKind::GameInProgress => "A game is running in the room",
+            ErrorKind::AlreadyInRoom => "You are already in a room",
+            ErrorKind::InvalidCode => "The room code is not valid",
+            ErrorKind::NoMatch => "No room fits your preferences",
+            ErrorKind::NameTaken => "Someone in the room already goes by this name",
+            ErrorKind::InappropriateName => "The name is not appropriate",
+            ErrorKind::Banned => "You were banned from the room",
+            ErrorKind::Queued => "The room is full, you are in line for a seat",
+            ErrorKind::InvalidInvite => "The invite is not valid",
+            ErrorKind::InviteExpired => "The invite expired",
+            ErrorKind::NoCodeAvailable => "No room can be opened right now, try again later",
+            ErrorKind::SpectatorsFull => "The room has no room for more spectators",
+            ErrorKind::WrongPassword => "The password is wrong",
+            ErrorKind::InvalidPassword => "The password is too long",
+            ErrorKind::GameAlreadyRunning => "A game is already running",
+            ErrorKind::NoGameRunning => "No game is running",
+            ErrorKind::NotEnoughPlayers => "Not enough players to start a game",
+            ErrorKind::VotingRematch => "The room is voting on a rematch",
+            ErrorKind::ModeAtCapacity => "Too many games of this mode are running right now",
+            ErrorKind::OutOfTurn => "It is not your turn",
+            ErrorKind::TooFast => "Too fast, wait a moment",
+            ErrorKind::Duplicate => "This was already played",
+            ErrorKind::NothingToReplay => "Nothing happened recently enough to replay",
+            ErrorKind::NoSuchPlayer => "There is no such player in the room",
+            ErrorKind::CannotKickSelf => "You cannot kick yourself",
+            ErrorKind::NotBanned => "The player is not banned",
+            ErrorKind::InvalidAlias => "The alias is not valid",
+            ErrorKind::Blocked => "This is not allowed",
+            ErrorKind::Taken => "This is already taken",
+            ErrorKind::NotOnline => "The user is not online",
+            ErrorKind::Empty => "The message is empty",
+            ErrorKind::TooLong => "The message is too long",
+            ErrorKind::NotFriend => "The user does not have you as a friend",
+            ErrorKind::ListenOnly => "Only the host can talk in this room",
+            ErrorKind::RevealsWord => "The message gives away the word",
+            ErrorKind::InvalidPlayerLimit => "The player limit is out of range",
+            ErrorKind::InvalidMinPlayers => "The minimum number of players is out of range",
+            ErrorKind::InvalidSpectatorLimit => "The spectator limit is out of range",
+            ErrorKind::InvalidTurnDuration => "The turn duration is out of range",
+            ErrorKind::InvalidTurnHandoff => "The pause between turns is out of range",
+            ErrorKind::InvalidRoundDuration => "The round duration is out of range",
+            ErrorKind::InvalidHintInterval => "The hint interval is out of range",
+            ErrorKind::InvalidUpcomingTurns => "The number of upcoming turns is out of range",
+            ErrorKind::InvalidReconnectGrace => "The reconnect grace period is out of range",
+            ErrorKind::UnsupportedLanguage => "The language is not supported",
+            ErrorKind::ValueTooLong => "The value is too long",
+            ErrorKind::TooManyEntries => "There are too many entries",
+            ErrorKind::Inappropriate => "The text is not appropriate",
+            ErrorKind::InvalidName => "The name is not valid",
+            ErrorKind::InvalidAvatar => "The avatar is not valid",
+            ErrorKind::InvalidColor => "The color is not valid",
+            ErrorKind::InvalidWords => "The word list is not valid",
+        }
+    }
+}
+
+/// Errors that can be sent to clients in a result
+pub trait ErrorCode {
+    fn kind(&self) -> ErrorKind;
+    /// Anything else the client needs to know about the error
+    fn detail(&self) -> Option<Value> {
+        None
+    }
+}
+
+/// What clients get for an error, see [super::message::Result]
+#[derive(Serialize)]
+pub struct ErrorPayload {
+    pub code: u16,
+    pub kind: ErrorKind,
+    pub message: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Value>,
+}
+
+impl ErrorPayload {
+    /// Payload with the message in the locale, see [super::catalog]
+    pub fn new(err: &impl ErrorCode, locale: Locale) -> Self {
+        let kind = err.kind();
+        Self {
+            code: kind.code(),
+            kind,
+            message: locale.error(kind),
+            detail: err.detail(),
+        }
+    }
+}
+
+/// An error on its way to a client, sent in whichever form the client expects, see
+/// [super::message::Reply::render]
+pub trait ClientError {
+    /// The error as an [ErrorPayload] in the locale if `typed`, or as the variant of the error
+    /// enum for clients that predate the payload
+    fn render(&self, typed: bool, locale: Locale) -> Value;
+}
+
+impl<E: ErrorCode + Serialize> ClientError for E {
+    fn render(&self, typed: bool, locale: Locale) -> Value {
+        let value = if typed {
+            serde_json::to_value(ErrorPayload::new(self, locale))
+        } else {
+            serde_json::to_value(self)
+        };
+        value.unwrap_or_default()
+    }
+}
+
+/// Implements [ErrorCode] for error enums whose variants are named after their kind
+macro_rules! error_kinds {
+    ($($error:ident { $($variant:ident),* $(,)? })*) => {$(
+        impl ErrorCode for $error {
+            fn kind(&self) -> ErrorKind {
+                match self {
+                    $($error::$variant => ErrorKind::$variant,)*
+                }
+            }
+        }
+    )*};
+}
+
+error_kinds! {
+    ReplayError { NotInRoom, NothingToReplay, InternalServerError }
+    MetadataError {
+        NotInRoom, NotLeader, InvalidKey, ValueTooLong, TooManyEntries, Inappropriate,
+        InternalServerError,
+    }
+    ListRoomsError { InternalServerError }
+    StartGameError {
+        NotInRoom, GameAlreadyRunning, NotLeader, NotAllowed, NotEnoughPlayers, VotingRematch,
+        ModeAtCapacity, InternalServerError,
+    }
+    KickError { NotInRoom, NotLeader, NoSuchPlayer, CannotKickSelf, InternalServerError }
+    BanError { NotInRoom, NotLeader, NotBanned, InternalServerError }
+    InviteError { NotInRoom, NotLeader, NotAllowed, InternalServerError }
+    PromoteError { NotInRoom, NotLeader, NoSuchPlayer, InternalServerError }
+    LockError { NotInRoom, NotLeader, InternalServerError }
+    PauseError { NotInRoom, NotLeader, NoGameRunning, InternalServerError }
+    AliasError { NotInRoom, NotLeader, InvalidAlias, Blocked, Taken, InternalServerError }
+    SettingsError {
+        NotInRoom, NotLeader, GameInProgress, NotAllowed, InvalidPlayerLimit, InvalidMinPlayers,
+        InvalidSpectatorLimit, InvalidTurnDuration, InvalidTurnHandoff, InvalidRoundDuration,
+        InvalidHintInterval, InvalidUpcomingTurns, InvalidReconnectGrace, UnsupportedLanguage,
+        InvalidWords, Inappropriate, InternalServerError,
+    }
+    InputError { OutOfTurn, TooFast, Duplicate }
+    ProfileError { InvalidName, InvalidAvatar, InvalidColor }
+    LeaveRoomError { NotInRoom }
+    BotLoginError { InvalidKey, AlreadyLoggedIn, InternalServerError }
+    LoginError { AlreadyConnected, InvalidToken, Reserved, AlreadyLoggedIn, InternalServerError }
+    ResumeError { InvalidToken, AlreadyLoggedIn, InternalServerError }
+    WhisperError { NotLoggedIn, NotOnline, Empty, TooLong, Inappropriate, InternalServerError }
+    FriendsError { NotLoggedIn, TooManyEntries, InternalServerError }
+    FriendInviteError {
+        NotLoggedIn, NotInRoom, NotOnline, NotFriend, NotAllowed, TooManyEntries, InvalidInvite,
+        InviteExpired, InternalServerError,
+    }
+}
+
+impl ErrorCode for JoinRoomError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            JoinRoomError::RoomFull => ErrorKind::RoomFull,
+            JoinRoomError::GameInProgress => ErrorKind::GameInProgress,
+            JoinRoomError::AlreadyInRoom => ErrorKind::AlreadyInRoom,
+            JoinRoomError::RoomNotFound => ErrorKind::RoomNotFound,
+            JoinRoomError::InvalidCode => ErrorKind::InvalidCode,
+            JoinRoomError::NoMatch => ErrorKind::NoMatch,
+            JoinRoomError::NameTaken => ErrorKind::NameTaken,
+            JoinRoomError::InappropriateName => ErrorKind::InappropriateName,
+            JoinRoomError::Banned => ErrorKind::Banned,
+            JoinRoomError::RoomLocked => ErrorKind::RoomLocked,
+            JoinRoomError::Queued(_) => ErrorKind::Queued,
+            JoinRoomError::InvalidInvite => ErrorKind::InvalidInvite,
+            JoinRoomError::InviteExpired => ErrorKind::InviteExpired,
+            JoinRoomError::ServerBusy => ErrorKind::ServerBusy,
+            JoinRoomError::NoCodeAvailable => ErrorKind::NoCodeAvailable,
+            JoinRoomError::SpectatorsFull => ErrorKind::SpectatorsFull,
+            JoinRoomError::WrongPassword => ErrorKind::WrongPassword,
+            JoinRoomError::InvalidPassword => ErrorKind::InvalidPassword,
+            JoinRoomError::InvalidPlayerLimit => ErrorKind::InvalidPlayerLimit,
+            JoinRoomError::UnsupportedLanguage => ErrorKind::UnsupportedLanguage,
+            JoinRoomError::InternalServerError => ErrorKind::InternalServerError,
+        }
+    }
+    fn detail(&self) -> Option<Value> {
+        match self {
+            // Position in line for a seat
+            JoinRoomError::Queued(position) => Some((*position).into()),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorCode for ChatError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            ChatError::NotInRoom => ErrorKind::NotInRoom,
+            ChatError::Empty => ErrorKind::Empty,
+            ChatError::TooLong => ErrorKind::TooLong,
+            ChatError::RateLimited => ErrorKind::RateLimited,
+            ChatError::ListenOnly => ErrorKind::ListenOnly,
+            ChatError::RevealsWord => ErrorKind::RevealsWord,
+            ChatError::Profanity => ErrorKind::Inappropriate,
+            ChatError::ServerBusy => ErrorKind::ServerBusy,
+            ChatError::InternalServerError => ErrorKind::InternalServerError,
+        }
+    }
+}
+
+impl ErrorCode for GameInputError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            GameInputError::NotInRoom => ErrorKind::NotInRoom,
+            GameInputError::NoGameRunning => ErrorKind::NoGameRunning,
+            // The game turned the input down, which is what the client cares about
+            GameInputError::Rejected(err) => err.kind(),
+            GameInputError::InternalServerError => ErrorKind::InternalServerError,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::message::{OutgoingMessage, Reply, Result, ResultOf};
+    use serde_json::json;
+
+    fn render(msg: OutgoingMessage, protocol: u32, locale: Locale) -> Value {
+        let reply = Reply {
+            msg,
+            request_id: None,
+        };
+        serde_json::from_str(&reply.render(protocol, locale)).unwrap()
+    }
+
+    #[test]
+    fn errors_are_typed_unless_the_client_is_too_old() {
+        let result = Result::<String, _>::Error(JoinRoomError::Queued(3));
+        let typed = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            typed,
+            json!({
+                "status": "Error",
+                "data": {
+                    "code": 310,
+                    "kind": "Queued",
+                    "message": ErrorKind::Queued.message(),
+                    "detail": 3,
+                },
+            })
+        );
+        let joined = || OutgoingMessage::JoinRoomResult(result.clone());
+        let legacy = render(joined(), TYPED_ERRORS_SINCE - 1, Locale::Es);
+        assert_eq!(
+            legacy["data"],
+            json!({ "status": "Error", "data": { "Queued": 3 } })
+        );
+        let localized = render(joined(), TYPED_ERRORS_SINCE, Locale::Es);
+        assert_eq!(
+            localized["data"]["data"]["message"],
+            Locale::Es.error(ErrorKind::Queued)
+        );
+        let created = OutgoingMessage::Result(ResultOf::CreateRoom(Result::Error(
+            JoinRoomError::ServerBusy,
+        )));
+        let legacy = render(created, TYPED_ERRORS_SINCE - 1, Locale::En);
+        assert_eq!(legacy["data"]["data"]["data"], "ServerBusy");
+        let success = Result::<_, LockError>::Success(());
+        assert_eq!(
+            serde_json::to_value(&success).unwrap(),
+            json!({ "status": "Success", "data": null })
+        );
+    }
+
+    #[test]
+    fn rejections_are_typed_since_they_were_given_codes() {
+        let rejected = || OutgoingMessage::ChatRejected(ChatError::Profanity);
+        let bare = render(rejected(), TYPED_REJECTIONS_SINCE - 1, Locale::De);
+        assert_eq!(bare["data"], "Profanity");
+        let typed = render(rejected(), TYPED_REJECTIONS_SINCE, Locale::De);
+        assert_eq!(typed["data"]["kind"], "Inappropriate");
+        assert_eq!(
+            typed["data"]["message"],
+            Locale::De.error(ErrorKind::Inappropriate)
+        );
+        let failed = OutgoingMessage::MatchFailed {
+            reason: JoinRoomError::NoMatch,
+        };
+        let typed = render(failed, TYPED_REJECTIONS_SINCE, Locale::En);
+        assert_eq!(typed["data"]["reason"]["code"], 306);
+    }
+}
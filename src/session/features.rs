@@ -0,0 +1,70 @@
+use serde::Serialize;
+
+/// Environment variable holding a comma separated list of features to switch off
+const DISABLED_FEATURES_VAR: &str = "DISABLED_FEATURES";
+
+/// Optional features that can be switched off per deployment
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    Chat,
+    Spectating,
+    CustomWords,
+    /// Lets room leaders pick a vanity alias for their room
+    VanityCodes,
+}
+
+impl Feature {
+    const ALL: [Feature; 4] = [
+        Feature::Chat,
+        Feature::Spectating,
+        Feature::CustomWords,
+        Feature::VanityCodes,
+    ];
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chat" => Some(Feature::Chat),
+            "spectating" => Some(Feature::Spectating),
+            "custom_words" => Some(Feature::CustomWords),
+            "vanity_codes" => Some(Feature::VanityCodes),
+            _ => None,
+        }
+    }
+}
+
+/// The set of features disabled on this server. Checked centrally by the session before any
+/// incoming message is dispatched, and advertised to clients in the `Welcome` message.
+#[derive(Default)]
+pub struct FeatureFlags {
+    disabled: Vec<Feature>,
+}
+
+impl FeatureFlags {
+    pub fn from_env() -> Self {
+        let disabled = std::env::var(DISABLED_FEATURES_VAR)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| {
+                let feature = Feature::from_name(name);
+                if feature.is_none() {
+                    log::warn!("ignoring unknown feature `{name}` in {DISABLED_FEATURES_VAR}");
+                }
+                feature
+            })
+            .collect();
+        Self { disabled }
+    }
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        !self.disabled.contains(&feature)
+    }
+    pub fn disabled(&self) -> &[Feature] {
+        &self.disabled
+    }
+    pub fn enabled(&self) -> Vec<Feature> {
+        Feature::ALL
+            .into_iter()
+            .filter(|x| self.is_enabled(*x))
+            .collect()
+    }
+}
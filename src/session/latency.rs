@@ -0,0 +1,109 @@
+//! Round trip times of clients, measured with pings the server sends every
+//! [super::timings::SessionTimings::ping_interval] over transports that have them. Pings carry
+//! the time they were sent at, so that the session can tell the round trip time from the pong
+//! alone. Only a pong echoing the latest ping counts, so that clients cannot make up a round
+//! trip time of their choosing to get matched with players they would otherwise not be.
+
+use std::time::{Duration, Instant};
+
+/// First version of the protocol clients are told their round trip time in, see
+/// [super::message::OutgoingMessage::LatencyReport]
+pub const LATENCY_REPORTS_SINCE: u32 = 4;
+/// Random joins prefer rooms whose players' round trip times are within this of their own
+pub const LATENCY_BAND: Duration = Duration::from_millis(100);
+/// Weight of a new sample in the smoothed round trip time, as in TCP
+const SMOOTHING: f64 = 0.125;
+
+pub struct Latency {
+    /// Pings carry the time since then
+    epoch: Instant,
+    /// Smoothed round trip time, unknown until the first pong
+    rtt: Option<Duration>,
+    /// Payload of the latest ping, until it is answered
+    pending: Option<[u8; 8]>,
+}
+
+impl Latency {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            epoch: now,
+            rtt: None,
+            pending: None,
+        }
+    }
+    /// Payload of a ping sent at `now`, any earlier ping goes unanswered from now on
+    pub fn ping(&mut self, now: Instant) -> [u8; 8] {
+        let since = now.saturating_duration_since(self.epoch);
+        let payload = (since.as_micros() as u64).to_be_bytes();
+        self.pending = Some(payload);
+        payload
+    }
+    /// Records the round trip of the latest ping, pongs that don't answer it are ignored.
+    /// Returns the smoothed round trip time.
+    pub fn pong(&mut self, payload: &[u8], now: Instant) -> Option<Duration> {
+        if self.pending.is_none_or(|pending| pending != payload) {
+            return None;
+        }
+        let sent = Duration::from_micros(u64::from_be_bytes(self.pending.take()?));
+        let sample = now
+            .saturating_duration_since(self.epoch)
+            .checked_sub(sent)?;
+        let rtt = match self.rtt {
+            Some(rtt) => rtt.mul_f64(1.0 - SMOOTHING) + sample.mul_f64(SMOOTHING),
+            None => sample,
+        };
+        self.rtt = Some(rtt);
+        Some(rtt)
+    }
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+}
+
+/// Average round trip time of the players of a room, if any of them was measured
+pub fn average(rtts: impl Iterator<Item = Duration>) -> Option<Duration> {
+    let (sum, count) = rtts.fold((Duration::ZERO, 0u32), |(sum, count), rtt| {
+        (sum + rtt, count + 1)
+    });
+    (count > 0).then(|| sum / count)
+}
+
+/// Whether two round trip times are within [LATENCY_BAND] of each other. Unknown ones are close
+/// to anything.
+pub fn similar(a: Option<Duration>, b: Option<Duration>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.abs_diff(b) <= LATENCY_BAND,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pongs_are_smoothed_into_the_round_trip_time() {
+        let start = Instant::now();
+        let mut latency = Latency::new(start);
+        let ping = latency.ping(start + Duration::from_secs(1));
+        let at = start + Duration::from_millis(1080);
+        assert_eq!(latency.pong(&ping, at), Some(Duration::from_millis(80)));
+        let ping = latency.ping(start + Duration::from_secs(2));
+        let at = start + Duration::from_millis(2160);
+        assert_eq!(latency.pong(&ping, at), Some(Duration::from_millis(90)));
+        // Pongs from the future, ones the client made up or answering an earlier ping, and
+        // repeated ones are ignored
+        let ping = latency.ping(start + Duration::from_secs(5));
+        assert_eq!(latency.pong(&ping, at), None);
+        assert_eq!(latency.pong(b"hello", at), None);
+        let made_up = (4_990_000u64).to_be_bytes();
+        assert_eq!(latency.pong(&made_up, start + Duration::from_secs(5)), None);
+        let earlier = latency.ping(start + Duration::from_secs(6));
+        let ping = latency.ping(start + Duration::from_secs(7));
+        let at = start + Duration::from_millis(7090);
+        assert_eq!(latency.pong(&earlier, at), None);
+        assert_eq!(latency.pong(&ping, at), Some(Duration::from_millis(90)));
+        assert_eq!(latency.pong(&ping, at), None);
+        assert_eq!(latency.rtt(), Some(Duration::from_millis(90)));
+    }
+}
@@ -1,50 +1,880 @@
 use bytestring::ByteString;
-use serde::{Deserialize, Serialize};
-use crate::{session::TransientId, room::actor::JoinRoomError};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::{
+    game::{summary::GameSummary, GameMode, Input},
+    room::{
+        actor::{
+            BanError, GameInputError, InviteError, JoinRoomError, KickError, LockError,
+            PauseError, PromoteError, StartGameError,
+        },
+        browser::{ListRoomsError, RoomPage, RoomQuery},
+        chat::ChatError,
+        instant_replay::{ReplayClip, ReplayError},
+        lobby::LobbyAction,
+        matching::MatchPreferences,
+        metadata::{MetadataError, RoomMetadata},
+        practice::{PracticeBoard, PracticeSeed},
+        settings::{RoomSettings, SettingsError, SettingsUpdate},
+        state::RoomState,
+        AliasError,
+    },
+    session::{
+        bot::BotLoginError, LoginError, PresenceEntry, ResumeError, TransientId, UserId,
+        WhisperError,
+    },
+    version::BuildInfo,
+};
+use super::backpressure::Priority;
+use super::catalog::Locale;
+use super::errors::{
+    ClientError, ErrorCode, ErrorPayload, TYPED_ERRORS_SINCE, TYPED_REJECTIONS_SINCE,
+};
+use super::features::Feature;
+use super::presence::{FriendInviteError, FriendsError};
+use super::profile::{Profile, ProfileError};
+use super::ratelimit::MessageClass;
+
+/// Either the code or alias of a specific room, an invite to one, or what the player wants from a
+/// random one
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum JoinTarget {
+    Code(String),
+    /// Code of a room behind a password, see [IncomingMessage::CreateRoom]
+    Protected {
+        code: String,
+        password: String,
+    },
+    Invite { invite: String },
+    Match(MatchPreferences),
+}
+
+/// Who a client logs in as, along with the locale of the text it shows its players as is, if
+/// it wants the server to translate that text, see [crate::session::catalog]
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum LoginAs<'a> {
+    User(&'a str),
+    Localized { user: &'a str, locale: &'a str },
+}
+
+/// Id a client gives a request to tell its result apart from those of the other requests it
+/// has in flight, see [Reply]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(u64),
+    Text(Box<str>),
+}
+
+/// What clients actually send, a message along with the id of the request it makes
+#[derive(Deserialize)]
+pub struct IncomingEnvelope<'a> {
+    #[serde(flatten, borrow)]
+    pub message: IncomingMessage<'a>,
+    /// Echoed in the result of the request, if the client gave one
+    #[serde(default)]
+    pub request_id: Option<RequestId>,
+}
 
 #[derive(Deserialize)]
 #[serde(tag = "kind", content = "data")]
 pub enum IncomingMessage<'a> {
-    Login(&'a str),
-    JoinRoom(Option<&'a str>),
+    /// States the version of the protocol the client speaks, unless it did so when connecting.
+    /// Clients that state none are taken to speak the first one, see
+    /// [crate::version::PROTOCOL_VERSION].
+    Hello(u32),
+    Login(#[serde(borrow)] LoginAs<'a>),
+    /// Logs in again after losing the connection, with the resume token from
+    /// [OutgoingMessage::LoginResult] and the `seq` of the last room event the client got, or
+    /// the last one it acknowledged if unset. The room only sends the events the client missed
+    /// if it still has them, see [OutgoingMessage::MissedEvents].
+    Reconnect {
+        user: &'a str,
+        token: &'a str,
+        #[serde(default)]
+        last_seq: Option<u64>,
+        /// See [LoginAs::Localized]
+        #[serde(default, borrow)]
+        locale: Option<&'a str>,
+    },
+    /// Acknowledges every room event up to the one with this `seq`, see [Sequenced]. Lets the
+    /// server tell clients that fall behind, and pick up where the client left off when it
+    /// reconnects without a `seq` of its own.
+    Ack(u64),
+    /// Logs in as a registered bot with its API key, see [crate::session::bot]
+    BotLogin(&'a str),
+    /// Logs back in after the server was replaced, with the token from
+    /// [OutgoingMessage::Reconnect]
+    Resume(&'a str),
+    JoinRoom(Option<JoinTarget>),
+    /// Watches the room with the code or alias without taking a seat in it
+    Spectate(&'a str),
+    /// Opens a solo room playing the practice puzzle of the seed, or of a new seed if unset
+    Practice(Option<PracticeSeed>),
+    /// Opens a new room led by the client, answered with [ResultOf::CreateRoom]. Rooms are
+    /// private unless asked otherwise, and always are if they have a password.
+    CreateRoom {
+        #[serde(default)]
+        public: bool,
+        /// Opens a broadcast-only room for an event, see [crate::room::RoomKind::Announcement]
+        #[serde(default)]
+        announcement: bool,
+        max_players: Option<u8>,
+        mode: Option<GameMode>,
+        language: Option<String>,
+        password: Option<String>,
+    },
+    /// Leaves the room the client plays in or watches, also giving up its place in line for a
+    /// seat or in matchmaking
+    LeaveRoom,
     Logout,
+    GameInput(Input),
+    Lobby(LobbyAction),
+    SetRoomAlias(&'a str),
+    Chat(String),
+    /// Private message to a user, wherever they are connected, see [crate::session::backplane]
+    Whisper {
+        to: &'a str,
+        text: String,
+    },
+    /// Asks whether the users are logged in, see [crate::session::MAX_PRESENCE_QUERY]
+    Presence(Vec<String>),
+    /// Adds the users to the client's friend list, answered with [OutgoingMessage::Friends]. The
+    /// client is told about them coming and going from then on, see [crate::session::presence].
+    AddFriends(Vec<String>),
+    RemoveFriends(Vec<String>),
+    /// Invites a friend who lists the client as a friend too to the room the client is in,
+    /// answered with [OutgoingMessage::InviteFriendResult]
+    InviteFriend(&'a str),
+    /// Accepts or declines a [OutgoingMessage::FriendInvite]. Accepting only lets the friend who
+    /// sent it know, the client joins the room on its own.
+    AnswerInvite {
+        id: u64,
+        accept: bool,
+    },
+    SetProfile(Profile),
+    /// Removes the player from the room and keeps them from rejoining until they are unbanned
+    KickPlayer {
+        target: TransientId,
+    },
+    /// Lifts the ban on a kicked player, identified by the transient id they had when kicked
+    Unban(TransientId),
+    ListBans,
+    PromoteLeader(TransientId),
+    /// Starts the countdown to the next game, answered with [ResultOf::StartGame]
+    StartGame,
+    /// Asks for the game events of the last moments, see [crate::room::instant_replay]
+    InstantReplay,
+    UpdateRoomSettings(SettingsUpdate),
+    SetLocked(bool),
+    /// Pauses or resumes the running game, leaders only
+    SetPaused(bool),
+    /// Sets an entry of the room's metadata, or removes it if the value is empty
+    SetRoomMetadata {
+        key: String,
+        value: String,
+    },
+    /// Asks for an invite token to hand out to a friend, see [crate::room::actor::CreateInvite]
+    CreateInvite {
+        #[serde(default)]
+        single_use: bool,
+    },
+    /// Public rooms for the room browser
+    ListRooms(RoomQuery),
     // Add more types here
 }
 
+impl IncomingMessage<'_> {
+    /// The optional feature a message belongs to, if any. Messages belonging to a disabled
+    /// feature are rejected before being dispatched.
+    pub fn required_feature(&self) -> Option<Feature> {
+        match self {
+            IncomingMessage::Hello(_)
+            | IncomingMessage::Ack(_)
+            | IncomingMessage::Login(_)
+            | IncomingMessage::Reconnect { .. }
+            | IncomingMessage::BotLogin(_)
+            | IncomingMessage::Resume(_)
+            | IncomingMessage::JoinRoom(_)
+            | IncomingMessage::Practice(_)
+            | IncomingMessage::CreateRoom { .. }
+            | IncomingMessage::LeaveRoom
+            | IncomingMessage::Logout
+            | IncomingMessage::GameInput(_)
+            | IncomingMessage::Lobby(_)
+            | IncomingMessage::SetProfile(_)
+            | IncomingMessage::KickPlayer { .. }
+            | IncomingMessage::Unban(_)
+            | IncomingMessage::ListBans
+            | IncomingMessage::PromoteLeader(_)
+            | IncomingMessage::StartGame
+            | IncomingMessage::InstantReplay
+            | IncomingMessage::UpdateRoomSettings(_)
+            | IncomingMessage::SetLocked(_)
+            | IncomingMessage::SetPaused(_)
+            | IncomingMessage::SetRoomMetadata { .. }
+            | IncomingMessage::CreateInvite { .. }
+            | IncomingMessage::ListRooms(_)
+            | IncomingMessage::Presence(_)
+            | IncomingMessage::AddFriends(_)
+            | IncomingMessage::RemoveFriends(_)
+            | IncomingMessage::InviteFriend(_)
+            | IncomingMessage::AnswerInvite { .. } => None,
+            IncomingMessage::SetRoomAlias(_) => Some(Feature::VanityCodes),
+            IncomingMessage::Chat(_) | IncomingMessage::Whisper { .. } => Some(Feature::Chat),
+            IncomingMessage::Spectate(_) => Some(Feature::Spectating),
+        }
+    }
+    /// The budget the message is counted against, see [crate::session::ratelimit]
+    pub fn rate_class(&self) -> MessageClass {
+        match self {
+            IncomingMessage::Chat(_)
+            | IncomingMessage::Whisper { .. }
+            | IncomingMessage::InviteFriend(_)
+            | IncomingMessage::Lobby(_) => MessageClass::Chat,
+            IncomingMessage::GameInput(_) => MessageClass::Guess,
+            IncomingMessage::Login(_)
+            | IncomingMessage::Reconnect { .. }
+            | IncomingMessage::BotLogin(_)
+            | IncomingMessage::Resume(_)
+            | IncomingMessage::JoinRoom(_)
+            | IncomingMessage::Spectate(_)
+            | IncomingMessage::Practice(_)
+            | IncomingMessage::CreateRoom { .. } => MessageClass::Join,
+            _ => MessageClass::Other,
+        }
+    }
+    /// Whether the message shows the client is still around, see
+    /// [crate::session::timings::SessionTimings::idle_timeout]. Protocol housekeeping doesn't.
+    pub fn is_activity(&self) -> bool {
+        !matches!(self, IncomingMessage::Hello(_) | IncomingMessage::Ack(_))
+    }
+    /// Version of the protocol the message was introduced in, or the shape it was sent in.
+    /// Clients speaking an older one are told it is unsupported rather than having it
+    /// dispatched. Every message lists its own so that new ones have to pick theirs.
+    pub fn since(&self) -> u32 {
+        match self {
+            IncomingMessage::Login(LoginAs::Localized { .. })
+            | IncomingMessage::Reconnect {
+                locale: Some(_), ..
+            } => 6,
+            IncomingMessage::Reconnect { .. } | IncomingMessage::InstantReplay => 2,
+            IncomingMessage::Ack(_) => 5,
+            IncomingMessage::AddFriends(_) | IncomingMessage::RemoveFriends(_) => 7,
+            IncomingMessage::InviteFriend(_) | IncomingMessage::AnswerInvite { .. } => 8,
+            IncomingMessage::SetPaused(_) => 9,
+            IncomingMessage::Hello(_)
+            | IncomingMessage::Login(LoginAs::User(_))
+            | IncomingMessage::BotLogin(_)
+            | IncomingMessage::Resume(_)
+            | IncomingMessage::JoinRoom(_)
+            | IncomingMessage::Spectate(_)
+            | IncomingMessage::Practice(_)
+            | IncomingMessage::CreateRoom { .. }
+            | IncomingMessage::LeaveRoom
+            | IncomingMessage::Logout
+            | IncomingMessage::GameInput(_)
+            | IncomingMessage::Lobby(_)
+            | IncomingMessage::SetRoomAlias(_)
+            | IncomingMessage::Chat(_)
+            | IncomingMessage::Whisper { .. }
+            | IncomingMessage::Presence(_)
+            | IncomingMessage::SetProfile(_)
+            | IncomingMessage::KickPlayer { .. }
+            | IncomingMessage::Unban(_)
+            | IncomingMessage::ListBans
+            | IncomingMessage::PromoteLeader(_)
+            | IncomingMessage::StartGame
+            | IncomingMessage::UpdateRoomSettings(_)
+            | IncomingMessage::SetLocked(_)
+            | IncomingMessage::SetRoomMetadata { .. }
+            | IncomingMessage::CreateInvite { .. }
+            | IncomingMessage::ListRooms(_) => 1,
+        }
+    }
+}
+
 #[derive(Serialize, Clone)]
+pub enum LeaveRoomError {
+    NotInRoom,
+}
+
+/// What came of a user logging in while connected already, see
+/// [crate::session::ConnectionPolicy]
+#[derive(Serialize, Clone, Copy)]
+pub enum OtherConnection {
+    /// Sent to the new connection, which replaced the older one with a session of its own. The
+    /// older one was told it was [RemoveReason::LoggedInElsewhere] and gave up its seat.
+    ReplacedOlder,
+    /// Sent to the connection the user is logged in over, a newer one was turned away
+    RefusedNewer,
+    /// Sent to every connection of the user, all of which stay open
+    Allowed { connections: usize },
+}
+
+#[derive(Serialize, Clone, Copy)]
 pub enum RemoveReason {
+    /// The room was shut down while the player was still in it
     RoomClosed,
+    /// The room reached the end of its lifetime
+    RoomExpired,
     Logout,
+    /// The player lost connection and didn't come back in time
     Disconnected,
+    /// The player stayed inactive for too long
+    Idle,
     LeaveRequested,
     IdMismatch,
+    /// The room's leader removed the player
+    Kicked,
+    /// The room's leader removed the player and barred them from rejoining until they are
+    /// unbanned
+    Banned,
+    /// The room was short on players and merged into another one, which the player is moved
+    /// into right away
+    Merged,
+    /// The client kept sending messages faster than allowed and was disconnected, see
+    /// [crate::session::ratelimit]
+    RateLimited,
+    /// The client speaks a version of the protocol the server does not, see
+    /// [crate::version::MIN_PROTOCOL_VERSION]
+    UnsupportedProtocol,
+    /// The client fell too far behind reading the messages sent to it and was disconnected, see
+    /// [crate::session::backpressure]
+    SlowConnection,
+    /// The user logged in over another connection, which took over for this one, see
+    /// [crate::session::ConnectionPolicy::KickOldest]
+    LoggedInElsewhere,
+    /// The client sent a frame too large or nesting too deep to be parsed, see
+    /// [crate::session::sink::check_frame]
+    MalformedFrame,
+}
+
+impl RemoveReason {
+    /// Stable key clients look the localized explanation up with. Keys must never change once
+    /// released, even if the variant gets renamed.
+    pub fn localization_key(self) -> &'static str {
+        match self {
+            RemoveReason::RoomClosed => "room.removed.room_closed",
+            RemoveReason::RoomExpired => "room.removed.room_expired",
+            RemoveReason::Logout => "room.removed.logout",
+            RemoveReason::Disconnected => "room.removed.disconnected",
+            RemoveReason::Idle => "room.removed.idle",
+            RemoveReason::LeaveRequested => "room.removed.left",
+            RemoveReason::IdMismatch => "room.removed.id_mismatch",
+            RemoveReason::Kicked => "room.removed.kicked",
+            RemoveReason::Banned => "room.removed.banned",
+            RemoveReason::Merged => "room.removed.merged",
+            RemoveReason::RateLimited => "room.removed.rate_limited",
+            RemoveReason::UnsupportedProtocol => "room.removed.unsupported_protocol",
+            RemoveReason::SlowConnection => "room.removed.slow_connection",
+            RemoveReason::LoggedInElsewhere => "room.removed.logged_in_elsewhere",
+            RemoveReason::MalformedFrame => "room.removed.malformed_frame",
+        }
+    }
 }
 
 #[derive(Serialize, Clone)]
+#[serde(tag = "kind", content = "data")]
 pub enum ResultOf {
     JoinRoom,
+    /// Carries the code of the new room
+    CreateRoom(Result<String, JoinRoomError>),
+    StartGame(Result<(), StartGameError>),
 }
 
-#[derive(Serialize, Clone)]
-#[serde(tag = "status", content = "data")]
+/// Outcome of a request. Errors are sent as an [ErrorPayload] in English, replies to a client
+/// carry theirs in the form the client expects, see [Reply::render]
+#[derive(Clone)]
 pub enum Result<T, E> {
     Success(T),
     Error(E)
 }
 
+impl<T, E: ErrorCode + Serialize> Result<T, E> {
+    fn error(&self) -> Option<&dyn ClientError> {
+        match self {
+            Result::Success(_) => None,
+            Result::Error(err) => Some(err),
+        }
+    }
+}
+
+impl<T: Serialize, E: Serialize + ErrorCode> Serialize for Result<T, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Result", 2)?;
+        match self {
+            Result::Success(data) => {
+                state.serialize_field("status", "Success")?;
+                state.serialize_field("data", data)?;
+            }
+            Result::Error(err) => {
+                state.serialize_field("status", "Error")?;
+                state.serialize_field("data", &ErrorPayload::new(err, Locale::En))?;
+            }
+        }
+        state.end()
+    }
+}
+
+/// Practice room opened for the player, along with the seed of its puzzle to share
+#[derive(Serialize, Clone)]
+pub struct PracticeRoom {
+    pub code: String,
+    pub seed: PracticeSeed,
+}
+
+/// Public information about a member of a room
+#[derive(Serialize, Clone)]
+pub struct RosterEntry {
+    pub id: TransientId,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub color: Option<u32>,
+    pub leader: bool,
+    /// Logged in as a bot rather than a player
+    pub bot: bool,
+}
+
+/// A player the leader kicked from the room
+#[derive(Serialize, Clone)]
+pub struct BannedPlayer {
+    /// Transient id the player had when they were kicked, used to unban them
+    pub id: TransientId,
+    pub name: String,
+}
+
+/// When a timer runs out, both as the server's wall clock time and as the time that was left when
+/// the message went out. Clients whose clock is off from the server's count down from
+/// `remaining` instead of relying on `at`.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Deadline {
+    /// Milliseconds since the unix epoch
+    pub at: u64,
+    /// Milliseconds left
+    pub remaining: u64,
+}
+
+impl Deadline {
+    pub fn after(remaining: Duration) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            at: (now + remaining).as_millis() as u64,
+            remaining: remaining.as_millis() as u64,
+        }
+    }
+    /// Deadline of a timer set to fire at the given instant
+    pub fn at(instant: Instant) -> Self {
+        Self::after(instant.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// What an [Announcement] is about, for clients that show them differently
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnnouncementKind {
+    #[default]
+    General,
+    /// The server is going down for maintenance soon
+    Maintenance,
+    /// Message of the day
+    Motd,
+}
+
+/// Message from the operators to every connected client, see [crate::session::BroadcastToAll]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Announcement {
+    #[serde(default)]
+    pub kind: AnnouncementKind,
+    pub text: String,
+}
+
+/// Final standing of a single player, part of the [GameSummary] sent when a game ends
+#[derive(Serialize, Clone)]
+pub struct PlayerResult {
+    pub id: TransientId,
+    /// Empty if the player left the room before the game ended
+    pub name: Option<String>,
+    pub score: usize,
+    /// Players with the same score share a rank
+    pub rank: usize,
+}
+
 #[derive(Serialize, Clone)]
 #[serde(tag = "kind", content = "data")]
 pub enum OutgoingMessage {
-    RemoveFromRoom(RemoveReason),
+    /// First message sent on every new connection
+    Welcome {
+        disabled_features: Vec<Feature>,
+        /// Build the server is running, to be attached to client side bug reports
+        server: BuildInfo,
+    },
+    /// The incoming message belongs to a feature that is switched off on this server
+    FeatureDisabled(Feature),
+    /// The incoming message is not part of the version of the protocol the client speaks, it
+    /// was introduced in the given one, see [IncomingMessage::Hello]
+    UnsupportedMessage {
+        since: u32,
+    },
+    RemoveFromRoom {
+        reason: RemoveReason,
+        /// See [RemoveReason::localization_key]
+        key: &'static str,
+        /// Human readable explanation, in the locale the client declared when logging in. Only
+        /// kicks are explained to clients that declared none, in English.
+        detail: Option<String>,
+    },
     ForceDisconnect(RemoveReason),
+    /// The user logged in over another connection too, see [crate::session::ConnectionPolicy]
+    OtherConnection(OtherConnection),
+    /// The message was dropped for going over the budget of its class, the client can send
+    /// another one after `retry_in` milliseconds
+    RateLimited {
+        class: MessageClass,
+        retry_in: u64,
+    },
+    /// Round trip time of the client's connection in milliseconds, smoothed over the latest
+    /// pings. Sent whenever the server hears back from a ping.
+    LatencyReport {
+        rtt: u64,
+    },
+    /// The server is being replaced. The client should reconnect right away and resume its
+    /// session with this token.
+    Reconnect(String),
+    Announcement(Announcement),
+    /// Holds the resume token of the new session, see [IncomingMessage::Reconnect]
+    ResumeResult(Result<String, ResumeError>),
+    /// Holds the resume token of the session, to be shown when reconnecting with
+    /// [IncomingMessage::Reconnect]
+    LoginResult(Result<String, LoginError>),
+    BotLoginResult(Result<(), BotLoginError>),
+    /// The room has been idle for a while and closes at the deadline unless a game starts or one
+    /// of the players does something
+    RoomExpiring(Deadline),
+    /// The room reaches the end of its lifetime and closes at the deadline, cutting short any
+    /// game still running then
+    RoomEnding(Deadline),
+    /// A game starts at the deadline unless players leave in the meantime
+    StartingIn(Deadline),
+    /// Text to show the player as is, in the locale the client declared when logging in. Only
+    /// sent to clients that declared one, see [crate::session::catalog]
+    Notice {
+        /// See [crate::session::catalog::Countdown::localization_key]
+        key: &'static str,
+        text: String,
+    },
+    /// The countdown to the next game was called off because too few players are left
+    StartCancelled,
+    /// The countdown ran out but the game could not start
+    StartFailed(StartGameError),
     GameStarted,
-    GameEnd,
+    /// The room moved on to another state, also sent to members as they join
+    RoomState(RoomState),
+    GameEnd(GameSummary),
     JoinRoomResult(Result<String, JoinRoomError>),
-    TurnUpdate(TransientId)
+    /// Answer to [IncomingMessage::Spectate], carrying the code of the room being watched
+    SpectateResult(Result<String, JoinRoomError>),
+    PracticeResult(Result<PracticeRoom, JoinRoomError>),
+    LeaveRoomResult(Result<(), LeaveRoomError>),
+    Whisper {
+        from: UserId,
+        text: String,
+    },
+    WhisperResult(Result<(), WhisperError>),
+    Presence(Vec<PresenceEntry>),
+    /// What everyone on the client's friend list is up to, answers changes to the list
+    Friends(Result<Vec<PresenceEntry>, FriendsError>),
+    /// A friend came online, went offline, or started or finished a game
+    FriendPresence(PresenceEntry),
+    /// Holds the id of the invite, see [OutgoingMessage::InviteAnswered]
+    InviteFriendResult(Result<u64, FriendInviteError>),
+    /// A friend invites the client to the room with the code, see [IncomingMessage::AnswerInvite]
+    FriendInvite {
+        id: u64,
+        from: UserId,
+        /// Name the friend goes by
+        name: String,
+        room: String,
+    },
+    AnswerInviteResult(Result<(), FriendInviteError>),
+    /// The friend the client invited answered the invite with the id
+    InviteAnswered {
+        id: u64,
+        user: UserId,
+        accepted: bool,
+    },
+    /// Leaderboard of the puzzle, sent once a practice game ends
+    PracticeBoard(PracticeBoard),
+    /// The client waits in line for a seat in a full room at this position, see
+    /// [JoinRoomError::Queued]
+    QueuePosition(usize),
+    RoomList(Result<RoomPage, ListRoomsError>),
+    /// Answer to a random join, carrying the code of the room the player was placed in
+    MatchFound(String),
+    MatchFailed { reason: JoinRoomError },
+    /// Everyone currently in the room, sent to players when they join or reconnect
+    Roster(Vec<RosterEntry>),
+    PlayerJoined(RosterEntry),
+    PlayerLeft(TransientId),
+    /// The player lost connection, their seat is kept for them until the deadline. Followed by
+    /// [OutgoingMessage::PlayerRejoined] if they make it back in time, and by
+    /// [OutgoingMessage::PlayerLeft] otherwise.
+    PlayerDisconnected {
+        player: TransientId,
+        deadline: Deadline,
+    },
+    /// A member reconnected on a new stream and was given a new transient id
+    PlayerRejoined {
+        previous: TransientId,
+        player: RosterEntry,
+    },
+    /// The turn is over and goes to the next player at the deadline, see
+    /// [crate::room::settings::RoomSettings::turn_handoff]
+    TurnEnding(Deadline),
+    /// The player got the turn and has until the deadline to play
+    TurnUpdate {
+        player: TransientId,
+        deadline: Deadline,
+    },
+    /// Players who get the turn after the current turn holder, in order. Only sent if the room
+    /// asked for it and subject to change if someone goes AFK or leaves.
+    UpcomingTurns(Vec<TransientId>),
+    /// The player missed too many turns in a row and will have their turns skipped
+    PlayerAfk(TransientId),
+    /// A previously AFK player submitted an input and is back in the turn rotation
+    PlayerReturned(TransientId),
+    /// The secret word of the current round, with its letters masked out except for those
+    /// revealed as hints
+    WordUpdate(String),
+    /// The current round ends at the deadline, only sent if the room limits its rounds
+    RoundDeadline(Deadline),
+    /// Nobody guessed the secret word before the round ran out of time
+    WordExpired(String),
+    WordGuessed { player: TransientId, word: String },
+    GameInputResult(Result<(), GameInputError>),
+    SetRoomAliasResult(Result<String, AliasError>),
+    ChatMessage { from: TransientId, text: String },
+    /// Sent only to the sender of a chat message that was not relayed
+    ChatRejected(ChatError),
+    SetProfileResult(Result<(), ProfileError>),
+    KickPlayerResult(Result<(), KickError>),
+    UnbanResult(Result<(), BanError>),
+    BanList(Result<Vec<BannedPlayer>, BanError>),
+    PromoteLeaderResult(Result<(), PromoteError>),
+    UpdateRoomSettingsResult(Result<(), SettingsError>),
+    SetLockedResult(Result<(), LockError>),
+    SetPausedResult(Result<(), PauseError>),
+    /// Answer to a request of the client, see [ResultOf]
+    Result(ResultOf),
+    SetRoomMetadataResult(Result<(), MetadataError>),
+    /// The invite token, to be passed to [IncomingMessage::JoinRoom] by the invited player
+    CreateInviteResult(Result<String, InviteError>),
+    /// The leader locked or unlocked the room
+    RoomLocked(bool),
+    /// The leader paused or resumed the game. Turn and round deadlines are sent again on resume.
+    GamePaused(bool),
+    /// The leader changed the room's settings
+    RoomSettings(RoomSettings),
+    /// Everything the leader attached to the room, sent whenever it changes and to members as
+    /// they join if there is any
+    RoomMetadata(RoomMetadata),
+    /// The room has a new leader, either handed over by the previous one or picked by the server
+    /// after the previous one left
+    LeaderChanged(TransientId),
+    EmojiPing { player: TransientId, emoji: String },
+    PollStarted { question: String, options: Vec<String> },
+    PollResults { question: String, votes: Vec<usize> },
+    /// A game just ended and the players have until the deadline to vote for a rematch with
+    /// [LobbyAction::Rematch]
+    RematchVoteStarted(Deadline),
+    /// Votes cast so far in the running rematch vote
+    RematchVotes { agree: usize, refuse: usize },
+    /// Whether the game is restarted, otherwise the room is open to new players again
+    RematchDecided(bool),
+    /// Room and game state for a client that reconnected on a new stream
+    RestoreState {
+        code: String,
+        game: Option<serde_json::Value>,
+        state: RoomState,
+        metadata: RoomMetadata,
+        /// Number of the latest room event, see [Sequenced]
+        seq: u64,
+    },
+    /// Room events the client missed while reconnecting, oldest first, sent in place of
+    /// [OutgoingMessage::RestoreState] when the room still has all of them
+    MissedEvents {
+        code: String,
+        events: Vec<Box<RawValue>>,
+    },
+    /// Game events of the last moments, asked for with [IncomingMessage::InstantReplay] or
+    /// played back to everyone when the game flags a highlight
+    InstantReplay(Result<ReplayClip, ReplayError>),
 }
 
-impl Into<ByteString> for OutgoingMessage {
-    fn into(self) -> ByteString {
-        ByteString::from(serde_json::to_string(&self).unwrap())
+impl OutgoingMessage {
+    pub fn removed(reason: RemoveReason, detail: Option<String>) -> Self {
+        OutgoingMessage::RemoveFromRoom {
+            reason,
+            key: reason.localization_key(),
+            detail,
+        }
+    }
+    /// Messages the client can do without are dropped first when it falls behind, see
+    /// [crate::session::backpressure]
+    pub fn priority(&self) -> Priority {
+        match self {
+            OutgoingMessage::EmojiPing { .. }
+            | OutgoingMessage::WordUpdate(_)
+            | OutgoingMessage::LatencyReport { .. }
+            | OutgoingMessage::PollResults { .. }
+            | OutgoingMessage::RematchVotes { .. } => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// Error a message carries, along with where it is in the serialized message
+pub struct CarriedError<'a> {
+    pub error: &'a dyn ClientError,
+    /// JSON pointer to the error
+    pub pointer: &'static str,
+    /// Version of the protocol the error is sent as an [ErrorPayload] since
+    pub typed_since: u32,
+}
+
+impl OutgoingMessage {
+    /// The error the message carries, if any
+    pub fn error(&self) -> Option<CarriedError<'_>> {
+        let typed = |error, pointer| CarriedError {
+            error,
+            pointer,
+            typed_since: TYPED_ERRORS_SINCE,
+        };
+        let rejection = |error, pointer| CarriedError {
+            error,
+            pointer,
+            typed_since: TYPED_REJECTIONS_SINCE,
+        };
+        let result = match self {
+            OutgoingMessage::ResumeResult(x) => x.error(),
+            OutgoingMessage::LoginResult(x) => x.error(),
+            OutgoingMessage::BotLoginResult(x) => x.error(),
+            OutgoingMessage::JoinRoomResult(x) => x.error(),
+            OutgoingMessage::SpectateResult(x) => x.error(),
+            OutgoingMessage::PracticeResult(x) => x.error(),
+            OutgoingMessage::LeaveRoomResult(x) => x.error(),
+            OutgoingMessage::WhisperResult(x) => x.error(),
+            OutgoingMessage::Friends(x) => x.error(),
+            OutgoingMessage::InviteFriendResult(x) => x.error(),
+            OutgoingMessage::AnswerInviteResult(x) => x.error(),
+            OutgoingMessage::RoomList(x) => x.error(),
+            OutgoingMessage::GameInputResult(x) => x.error(),
+            OutgoingMessage::SetRoomAliasResult(x) => x.error(),
+            OutgoingMessage::SetProfileResult(x) => x.error(),
+            OutgoingMessage::KickPlayerResult(x) => x.error(),
+            OutgoingMessage::UnbanResult(x) => x.error(),
+            OutgoingMessage::BanList(x) => x.error(),
+            OutgoingMessage::PromoteLeaderResult(x) => x.error(),
+            OutgoingMessage::UpdateRoomSettingsResult(x) => x.error(),
+            OutgoingMessage::SetLockedResult(x) => x.error(),
+            OutgoingMessage::SetPausedResult(x) => x.error(),
+            OutgoingMessage::SetRoomMetadataResult(x) => x.error(),
+            OutgoingMessage::CreateInviteResult(x) => x.error(),
+            OutgoingMessage::InstantReplay(x) => x.error(),
+            OutgoingMessage::Result(ResultOf::CreateRoom(x)) => {
+                return x.error().map(|x| typed(x, "/data/data/data"));
+            }
+            OutgoingMessage::Result(ResultOf::StartGame(x)) => {
+                return x.error().map(|x| typed(x, "/data/data/data"));
+            }
+            OutgoingMessage::ChatRejected(x) => return Some(rejection(x, "/data")),
+            OutgoingMessage::MatchFailed { reason } => {
+                return Some(rejection(reason, "/data/reason"));
+            }
+            _ => None,
+        };
+        result.map(|x| typed(x, "/data/data"))
+    }
+}
+
+impl From<OutgoingMessage> for ByteString {
+    fn from(msg: OutgoingMessage) -> Self {
+        ByteString::from(serde_json::to_string(&msg).unwrap())
+    }
+}
+
+/// A message broadcast to a whole room, numbered in the order the room sent it out. Clients
+/// hand the number of the last one they got back when they reconnect, see
+/// [IncomingMessage::Reconnect].
+#[derive(Serialize)]
+pub struct Sequenced {
+    #[serde(flatten)]
+    pub msg: OutgoingMessage,
+    pub seq: u64,
+}
+
+impl From<Sequenced> for ByteString {
+    fn from(msg: Sequenced) -> Self {
+        ByteString::from(serde_json::to_string(&msg).unwrap())
+    }
+}
+
+/// The answer to a request, along with the id the client gave the request, if any. Answers
+/// include results as well as the client being told the request was rate limited or belongs to
+/// a switched off feature.
+#[derive(Serialize)]
+pub struct Reply {
+    #[serde(flatten)]
+    pub msg: OutgoingMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<RequestId>,
+}
+
+impl Reply {
+    /// Serializes the reply with the error it carries, if any, in the form the client expects:
+    /// as an [ErrorPayload] in the client's locale, or as the variant of the error enum if the
+    /// protocol it speaks predates the payload
+    pub fn render(&self, protocol: u32, locale: Locale) -> ByteString {
+        let Some(carried) = self.msg.error() else {
+            return ByteString::from(serde_json::to_string(self).unwrap());
+        };
+        let mut reply = serde_json::to_value(self).unwrap();
+        if let Some(error) = reply.pointer_mut(carried.pointer) {
+            *error = carried.error.render(protocol >= carried.typed_since, locale);
+        }
+        ByteString::from(reply.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_may_come_with_an_id() {
+        let plain = r#"{"kind":"Chat","data":"hi"}"#;
+        let envelope: IncomingEnvelope = serde_json::from_str(plain).unwrap();
+        assert!(matches!(envelope.message, IncomingMessage::Chat(text) if text == "hi"));
+        assert!(envelope.request_id.is_none());
+        let numbered = r#"{"kind":"LeaveRoom","request_id":42}"#;
+        let envelope: IncomingEnvelope = serde_json::from_str(numbered).unwrap();
+        assert!(matches!(envelope.message, IncomingMessage::LeaveRoom));
+        assert!(matches!(envelope.request_id, Some(RequestId::Number(42))));
+        let named = r#"{"request_id":"join-1","kind":"JoinRoom","data":"ABCD"}"#;
+        let envelope: IncomingEnvelope = serde_json::from_str(named).unwrap();
+        assert!(matches!(envelope.request_id, Some(RequestId::Text(id)) if &*id == "join-1"));
+
+        let reply = Reply {
+            msg: OutgoingMessage::MatchFound("ABCD".into()),
+            request_id: Some(RequestId::Number(42)),
+        };
+        let reply = serde_json::to_value(&reply).unwrap();
+        assert_eq!(reply["kind"], "MatchFound");
+        assert_eq!(reply["request_id"], 42);
     }
 }
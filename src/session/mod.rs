@@ -1,19 +1,51 @@
 use crate::{
+    events::{EventBus, Publish, ServerEvent, Subscribe},
+    profanity::{ProfanityFilter, Verdict},
     room::{
-        actor::{ClientReconnection, RemovePlayer, Room},
+        actor::{ClientReconnection, HeldSeat, RemovePlayer, Room},
+        chat::MAX_CHAT_LENGTH,
         RoomCode,
     },
-    session::{actor::Session, message::RemoveReason},
+    server::handover::RESUME_WINDOW,
+    session::{
+        actor::{Frame, SerializedMessage, Session, Stop, Trace},
+        backplane::{Backplane, BackplaneConfig, NodeId, RelayedWhisper},
+        bot::{is_bot, BotKeys},
+        catalog::Locale,
+        message::{Announcement, OtherConnection, OutgoingMessage, RemoveReason},
+        presence::Presence,
+        profile::Profile,
+        sink::SharedFrame,
+        transient::TransientIds,
+    },
 };
 use actix::prelude::*;
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use bytestring::ByteString;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub mod actor;
+pub mod backplane;
+pub mod backpressure;
+pub mod bot;
+pub mod catalog;
+pub mod deflate;
+pub mod errors;
+pub mod features;
+pub mod latency;
 pub mod message;
+pub mod msgpack;
+pub mod presence;
+pub mod profile;
+pub mod ratelimit;
+pub mod sink;
+pub mod timings;
+pub mod transient;
 
 pub type UserId = Arc<str>;
-pub type TransientId = u64;
+pub use transient::TransientId;
 
 struct SessionData {
     /// The actor [Addr] of a [Session]
@@ -23,6 +55,54 @@ struct SessionData {
     transient_id: TransientId,
     /// The [Addr] of the [Room] the session is currently in, if in one
     room_addr: Option<Addr<Room>>,
+    /// Handed to the client on login, only a client showing it can take over the session after
+    /// losing its connection, see [message::IncomingMessage::Reconnect]
+    resume_token: Box<str>,
+    /// Further connections of the user, only kept under [ConnectionPolicy::Allow]. One of them
+    /// carries on as the session when it stops.
+    others: Vec<Connection>,
+    /// Locale the user declared when logging in, see [catalog]
+    locale: Option<Locale>,
+}
+
+/// A further connection of a user, see [ConnectionPolicy::Allow]
+struct Connection {
+    transient_id: TransientId,
+    addr: Addr<Session>,
+    /// Handed to the client of the connection only, it becomes the resume token of the session
+    /// once the connection carries on as the session
+    resume_token: Box<str>,
+    /// The [Addr] of the [Room] the connection is in, if in one
+    room_addr: Option<Addr<Room>>,
+}
+
+/// What happens when a user logs in while they are connected already. Clients reconnecting
+/// with their resume token always take the session over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionPolicy {
+    /// The older connection is disconnected and gives up its seat, the new one starts a session
+    /// of its own
+    KickOldest,
+    /// The new connection is turned away with [LoginError::AlreadyConnected]
+    #[default]
+    RejectNewest,
+    /// Both connections stay open, each with a transient id of its own
+    Allow,
+}
+
+impl ConnectionPolicy {
+    /// Reads `MULTI_CONNECTION`, one of `kick-oldest`, `reject-newest` or `allow`
+    pub fn from_env() -> Self {
+        match std::env::var("MULTI_CONNECTION").as_deref() {
+            Ok("kick-oldest") => ConnectionPolicy::KickOldest,
+            Ok("reject-newest") | Err(_) => ConnectionPolicy::RejectNewest,
+            Ok("allow") => ConnectionPolicy::Allow,
+            Ok(_) => {
+                log::error!("ignoring malformed MULTI_CONNECTION");
+                ConnectionPolicy::default()
+            }
+        }
+    }
 }
 
 /// Atomic session manager
@@ -30,94 +110,335 @@ struct SessionData {
 /// interaction
 pub struct SessionManager {
     sessions: HashMap<UserId, SessionData>,
+    /// Every session on this node, logged in or not, see [Connect]
+    connections: HashSet<Addr<Session>>,
     transient_id_map: HashMap<TransientId, UserId>,
-    temp_id_counter: TransientId,
+    transient_ids: TransientIds,
+    /// Sessions handed over by the previous process, keyed by their resume token, see
+    /// [crate::server::handover]
+    migrated: HashMap<String, SessionSummary>,
+    bots: BotKeys,
+    /// Told about every sign in, tells the manager about games starting and ending in turn
+    events: Addr<EventBus>,
+    /// Connects to Redis once the manager has started, if configured
+    backplane_config: Option<BackplaneConfig>,
+    backplane: Option<Backplane>,
+    /// Users logged in on other nodes and the node each of them is on, see [backplane]
+    remote: HashMap<UserId, NodeId>,
+    /// Users whose frames are logged until the given time, including over new connections, see
+    /// [TraceUser]
+    traced: HashMap<UserId, Instant>,
+    policy: ConnectionPolicy,
+    /// Whispers go through it before they reach their recipient, on this node or any other
+    profanity: Arc<ProfanityFilter>,
+    /// Friend lists and rooms of the users, see [presence]
+    presence: Presence,
+}
+
+/// A client of the process that handed it over, who is expected to reconnect with its token
+#[derive(Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub token: String,
+    pub user: UserId,
+    /// Code of the room the client was in
+    pub room: Option<String>,
+    pub profile: Option<Profile>,
+    /// Locale the user declared when logging in, see [catalog]
+    #[serde(default)]
+    pub locale: Option<Locale>,
 }
 
 impl SessionManager {
-    pub fn new() -> Self {
+    pub fn new(
+        bots: BotKeys,
+        events: Addr<EventBus>,
+        backplane_config: Option<BackplaneConfig>,
+        policy: ConnectionPolicy,
+        profanity: Arc<ProfanityFilter>,
+    ) -> Self {
         Self {
             sessions: HashMap::with_capacity(1 << 12),
-            temp_id_counter: 0,
+            connections: HashSet::with_capacity(1 << 12),
+            transient_ids: TransientIds::default(),
             transient_id_map: HashMap::with_capacity(1 << 12),
+            migrated: HashMap::new(),
+            bots,
+            events,
+            backplane_config,
+            backplane: None,
+            remote: HashMap::new(),
+            traced: HashMap::new(),
+            policy,
+            profanity,
+            presence: Presence::default(),
         }
     }
 
+    /// A transient id no session of any user holds, see [transient]
     pub fn new_id(&mut self) -> TransientId {
-        if self.temp_id_counter >= 10_000_000_000 {
-            self.temp_id_counter = 0;
-        }
-        self.temp_id_counter += 1;
-        self.temp_id_counter
+        let held = &self.transient_id_map;
+        self.transient_ids.issue(|id| held.contains_key(&id))
     }
 
+    /// Takes over the previous session of the user if there is one, answering with the resume
+    /// token of the new session. Callers make sure the client is entitled to take it over.
+    /// `last_seq` is the last room event a reconnecting client got, see [ClientReconnection].
+    /// Unless the client is `resuming` its session, a session whose client is still connected is
+    /// left to the [ConnectionPolicy], and both connections are told what came of it.
     pub fn add_session(
         &mut self,
         client_id: UserId,
         session_addr: Addr<Session>,
         transient_id: TransientId,
-    ) {
+        last_seq: Option<u64>,
+        resuming: bool,
+        locale: Option<Locale>,
+    ) -> Result<Box<str>, LoginError> {
+        let mut kicked = None;
+        if let Some(old) = self
+            .sessions
+            .get_mut(&client_id)
+            .filter(|old| !resuming && old.session_addr.connected())
+        {
+            let notify = |addr: &Addr<Session>, msg| {
+                addr.do_send(SerializedMessage(OutgoingMessage::OtherConnection(msg)));
+            };
+            match self.policy {
+                ConnectionPolicy::RejectNewest => {
+                    notify(&old.session_addr, OtherConnection::RefusedNewer);
+                    return Err(LoginError::AlreadyConnected);
+                }
+                ConnectionPolicy::KickOldest => {
+                    old.session_addr.do_send(SerializedMessage(OutgoingMessage::ForceDisconnect(
+                        RemoveReason::LoggedInElsewhere,
+                    )));
+                    old.session_addr.do_send(Stop);
+                    notify(&session_addr, OtherConnection::ReplacedOlder);
+                    // Only clients showing the resume token take over the session and its seat
+                    kicked = Some(old.transient_id);
+                }
+                ConnectionPolicy::Allow => {
+                    self.transient_id_map.insert(transient_id, client_id.clone());
+                    let resume_token = resume_token();
+                    old.others.push(Connection {
+                        transient_id,
+                        addr: session_addr,
+                        resume_token: resume_token.clone(),
+                        room_addr: None,
+                    });
+                    let connections = old.others.len() + 1;
+                    let msg = OtherConnection::Allowed { connections };
+                    notify(&old.session_addr, msg);
+                    for other in &old.others {
+                        notify(&other.addr, msg);
+                    }
+                    return Ok(resume_token);
+                }
+            }
+        }
+        if let Some(kicked) = kicked {
+            self.remove_session(kicked, RemoveReason::LoggedInElsewhere);
+        }
+        if let Some(backplane) = &self.backplane {
+            backplane.online(client_id.clone());
+        }
+        let user = client_id.clone();
+        let resume_token = resume_token();
+        self.transient_id_map.insert(transient_id, client_id.clone());
+        if let Some(&until) = self.traced.get(&client_id) {
+            session_addr.do_send(Trace(Some(until)));
+        }
         if let Some(old) = self.sessions.get_mut(&client_id) {
             if let Some(room) = &old.room_addr {
                 room.do_send(ClientReconnection {
                     replacee: old.transient_id,
                     replacer: (transient_id, session_addr.clone()),
+                    last_seq,
                 });
             }
+            self.transient_id_map.remove(&old.transient_id);
             old.transient_id = transient_id;
             old.session_addr = session_addr;
+            old.resume_token = resume_token.clone();
+            old.locale = locale;
         } else {
+            self.events.do_send(Publish(ServerEvent::SignedIn {
+                user: client_id.clone(),
+                bot: is_bot(&client_id),
+            }));
             self.sessions.insert(
                 client_id,
                 SessionData {
                     room_addr: None,
                     session_addr,
                     transient_id,
+                    resume_token: resume_token.clone(),
+                    others: Vec::new(),
+                    locale,
                 },
             );
         }
+        self.announce(&user, true);
+        Ok(resume_token)
     }
 
+    /// Removes the session the transient id belongs to, taking the user out of their room. Ids of
+    /// further connections of a user only go themselves.
     pub fn remove_session(&mut self, transient_id: TransientId, reason: RemoveReason) {
-        if let Some(client_id) = self.transient_id_map.remove(&transient_id) {
-            if let Some(SessionData {
+        let Some(client_id) = self.transient_id_map.remove(&transient_id) else {
+            return;
+        };
+        let owned = self.sessions.get(&client_id);
+        if owned.is_none_or(|data| data.transient_id != transient_id) {
+            return;
+        }
+        if let Some(room) = self.forget_session(&client_id).and_then(|data| data.room_addr) {
+            room.do_send(RemovePlayer {
                 transient_id,
-                room_addr,
-                ..
-            }) = self.sessions.remove(&client_id)
-            {
-                if let Some(room) = room_addr {
-                    room.do_send(RemovePlayer {
-                        transient_id,
-                        reason,
-                    });
-                }
-            }
+                reason,
+            });
+        }
+    }
+
+    /// Drops the session of the user along with the transient ids of all of its connections
+    fn forget_session(&mut self, user: &UserId) -> Option<SessionData> {
+        let data = self.sessions.remove(user)?;
+        self.transient_id_map.remove(&data.transient_id);
+        for other in &data.others {
+            self.transient_id_map.remove(&other.transient_id);
         }
+        Some(data)
+    }
+
+    /// Keeps the session of a client that lost connection for as long as its room holds the
+    /// client's seat, so that the client can reconnect to both, see [ClientReconnection]
+    fn hold_session(
+        &mut self,
+        room: Addr<Room>,
+        transient_id: TransientId,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        room.send(HeldSeat(transient_id))
+            .into_actor(self)
+            .map(move |grace, act, ctx| {
+                let reason = RemoveReason::Disconnected;
+                match grace {
+                    Ok(Some(grace)) => {
+                        ctx.run_later(grace, move |act, _| {
+                            act.remove_session(transient_id, reason)
+                        });
+                    }
+                    _ => act.remove_session(transient_id, reason),
+                }
+            })
+            .spawn(ctx);
     }
 
-    pub fn get_user_by_transient_id(&self, transient_id: TransientId) -> Option<UserId> {
-        self.transient_id_map.get(&transient_id).cloned()
+    /// The session of the user if they are connected to this node
+    fn local_session(&self, user: &UserId) -> Option<&Addr<Session>> {
+        self.sessions
+            .get(user)
+            .map(|data| &data.session_addr)
+            .filter(|addr| addr.connected())
     }
 }
 
 impl Actor for SessionManager {
     type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.events.do_send(Subscribe(ctx.address().recipient()));
+        if let Some(config) = self.backplane_config.take() {
+            self.backplane = Some(Backplane::connect(config, ctx.address()));
+        }
+    }
+}
+
+/// A random token that cannot be guessed
+fn resume_token() -> Box<str> {
+    format!("{:032x}", rand::random::<u128>()).into()
 }
 
+#[derive(Serialize, Clone)]
+pub enum LoginError {
+    /// Someone is logged in as the user already, only they can take the session over with their
+    /// resume token
+    AlreadyConnected,
+    /// The resume token is not the one of the session being taken over
+    InvalidToken,
+    /// The user id is reserved for bots, see [message::IncomingMessage::BotLogin]
+    Reserved,
+    AlreadyLoggedIn,
+    InternalServerError,
+}
+
+/// Logs a client in, answering with the resume token of its session
 #[derive(Message)]
-#[rtype(result = "TransientId")]
+#[rtype(result = "Result<(TransientId, Box<str>), LoginError>")]
 struct Register {
     session_addr: Addr<Session>,
     user_id: UserId,
+    /// Set by clients reconnecting after losing their connection, along with the last room event
+    /// they got, see [message::IncomingMessage::Reconnect]
+    reconnect: Option<(Box<str>, Option<u64>)>,
+    locale: Option<Locale>,
 }
 
 impl Handler<Register> for SessionManager {
-    type Result = TransientId;
+    type Result = Result<(TransientId, Box<str>), LoginError>;
     fn handle(&mut self, msg: Register, _: &mut Self::Context) -> Self::Result {
+        let (token, last_seq) = msg.reconnect.unzip();
+        let resuming = token.is_some();
+        if let Some(old) = self.sessions.get(&msg.user_id) {
+            match token {
+                Some(token) => {
+                    // Compared in constant time so that the token cannot be guessed byte by byte
+                    let expected = old.resume_token.as_bytes();
+                    let diff = expected
+                        .iter()
+                        .zip(token.as_bytes())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b));
+                    if diff != 0 || expected.len() != token.len() {
+                        return Err(LoginError::InvalidToken);
+                    }
+                }
+                // Left to the policy, see [SessionManager::add_session]
+                None if old.session_addr.connected() => {}
+                None if old.room_addr.is_some() => return Err(LoginError::AlreadyConnected),
+                // Nothing is left of the previous session to take over
+                None => {
+                    self.forget_session(&msg.user_id);
+                }
+            }
+        }
         let transient_id = self.new_id();
-        self.add_session(msg.user_id, msg.session_addr, transient_id);
-        transient_id
+        let token = self.add_session(
+            msg.user_id,
+            msg.session_addr,
+            transient_id,
+            last_seq.flatten(),
+            resuming,
+            msg.locale,
+        )?;
+        Ok((transient_id, token))
+    }
+}
+
+/// Registers a bot by its API key, answering with the user id it was issued to
+#[derive(Message)]
+#[rtype(result = "Option<(TransientId, UserId)>")]
+struct RegisterBot {
+    session_addr: Addr<Session>,
+    key: Box<str>,
+}
+
+impl Handler<RegisterBot> for SessionManager {
+    type Result = Option<(TransientId, UserId)>;
+    fn handle(&mut self, msg: RegisterBot, _: &mut Self::Context) -> Self::Result {
+        let user_id = self.bots.authenticate(&msg.key)?;
+        let transient_id = self.new_id();
+        self.add_session(user_id.clone(), msg.session_addr, transient_id, None, false, None)
+            .ok()?;
+        Some((transient_id, user_id))
     }
 }
 
@@ -125,12 +446,65 @@ impl Handler<Register> for SessionManager {
 #[rtype(result = "()")]
 struct Unregister {
     transient_id: TransientId,
+    /// The user the session was logged in as, unless another session took over for them
+    user: Option<UserId>,
     reason: RemoveReason,
 }
 
 impl Handler<Unregister> for SessionManager {
     type Result = ();
-    fn handle(&mut self, msg: Unregister, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: Unregister, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(data) = msg.user.as_ref().and_then(|user| self.sessions.get_mut(user)) {
+            if data.transient_id == msg.transient_id {
+                // Another connection of the user carries on as the session. The seat of the one
+                // that went away is given up, its resume token went to the other connection.
+                if let Some(other) = data.others.pop() {
+                    self.transient_id_map.remove(&msg.transient_id);
+                    data.transient_id = other.transient_id;
+                    data.session_addr = other.addr;
+                    data.resume_token = other.resume_token;
+                    let room = std::mem::replace(&mut data.room_addr, other.room_addr);
+                    if let Some(room) = room {
+                        room.do_send(RemovePlayer {
+                            transient_id: msg.transient_id,
+                            reason: msg.reason,
+                        });
+                    }
+                    return;
+                }
+            } else {
+                let idx = data
+                    .others
+                    .iter()
+                    .position(|other| other.transient_id == msg.transient_id);
+                let room = idx.and_then(|idx| data.others.remove(idx).room_addr);
+                self.transient_id_map.remove(&msg.transient_id);
+                if let Some(room) = room {
+                    room.do_send(RemovePlayer {
+                        transient_id: msg.transient_id,
+                        reason: msg.reason,
+                    });
+                }
+                return;
+            }
+        }
+        // The user may have logged in again on a new session already
+        if let Some(user) = msg.user.filter(|user| {
+            self.sessions
+                .get(user)
+                .is_some_and(|data| data.transient_id == msg.transient_id)
+        }) {
+            self.announce(&user, false);
+            self.presence.forget(&user);
+            let room = self.sessions.get(&user).and_then(|data| data.room_addr.clone());
+            if let Some(backplane) = &self.backplane {
+                backplane.offline(user);
+            }
+            if let (RemoveReason::Disconnected, Some(room)) = (msg.reason, room) {
+                self.hold_session(room, msg.transient_id, ctx);
+                return;
+            }
+        }
         self.remove_session(msg.transient_id, msg.reason);
     }
 }
@@ -154,12 +528,657 @@ pub struct UpdateSessionRoomInfo(pub TransientId, pub Option<Addr<Room>>);
 impl Handler<UpdateSessionRoomInfo> for SessionManager {
     type Result = ();
     fn handle(&mut self, msg: UpdateSessionRoomInfo, _: &mut Self::Context) -> Self::Result {
-        if let Some(session_info) = self
+        let Some(data) = self
             .transient_id_map
             .get(&msg.0)
             .and_then(|x| self.sessions.get_mut(x))
-        {
-            session_info.room_addr = msg.1;
+        else {
+            return;
+        };
+        if data.transient_id == msg.0 {
+            data.room_addr = msg.1;
+        } else if let Some(other) = data.others.iter_mut().find(|x| x.transient_id == msg.0) {
+            other.room_addr = msg.1;
+        }
+    }
+}
+
+/// Logs every frame to and from a user for a while, to look into an issue they reported without
+/// turning up the log level of the whole server. Tracing stops after the given duration, or
+/// right away if it is zero. Returns whether the user is online on this node.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct TraceUser {
+    pub user: UserId,
+    pub duration: Duration,
+}
+
+impl Handler<TraceUser> for SessionManager {
+    type Result = bool;
+    fn handle(&mut self, msg: TraceUser, _: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+        self.traced.retain(|_, until| *until > now);
+        let until = (!msg.duration.is_zero()).then(|| now + msg.duration);
+        match until {
+            Some(until) => self.traced.insert(msg.user.clone(), until),
+            None => self.traced.remove(&msg.user),
+        };
+        let Some(session) = self.sessions.get(&msg.user) else {
+            return false;
+        };
+        session.session_addr.do_send(Trace(until));
+        true
+    }
+}
+
+/// Longest announcement operators can make, in bytes
+pub const MAX_ANNOUNCEMENT_LENGTH: usize = 1000;
+
+/// Sends the announcement to every session connected to this node, and has the other nodes do
+/// the same through the [backplane]. Returns the number of sessions on this node it went to.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct BroadcastToAll(pub Announcement);
+
+impl Handler<BroadcastToAll> for SessionManager {
+    type Result = usize;
+    fn handle(&mut self, msg: BroadcastToAll, _: &mut Self::Context) -> Self::Result {
+        if let Some(backplane) = &self.backplane {
+            backplane.announce(msg.0.clone());
+        }
+        self.announce_locally(msg.0)
+    }
+}
+
+impl SessionManager {
+    /// Sends the announcement to every session on this node, including those that have yet to
+    /// log in. It is serialized only once for all of them.
+    pub(super) fn announce_locally(&self, announcement: Announcement) -> usize {
+        let msg = OutgoingMessage::Announcement(announcement);
+        let priority = msg.priority();
+        let json: ByteString = msg.into();
+        let frame = SharedFrame::from(json);
+        let mut delivered = 0;
+        for session in self.connections.iter().filter(|addr| addr.connected()) {
+            session.do_send(Frame(frame.clone(), priority));
+            delivered += 1;
+        }
+        delivered
+    }
+}
+
+/// Sent by every session once it has started, so that it gets announcements before the client
+/// logs in
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Connect(Addr<Session>);
+
+impl Handler<Connect> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
+        self.connections.insert(msg.0);
+    }
+}
+
+/// Sent by every session once it has stopped, see [Connect]
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Disconnect(Addr<Session>);
+
+impl Handler<Disconnect> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: Disconnect, _: &mut Self::Context) -> Self::Result {
+        self.connections.remove(&msg.0);
+    }
+}
+
+/// Every registered user and their session, for the handover to the next process
+#[derive(Message)]
+#[rtype(result = "Vec<(UserId, Addr<Session>, Option<Locale>)>")]
+pub struct ExportSessions;
+
+impl Handler<ExportSessions> for SessionManager {
+    type Result = MessageResult<ExportSessions>;
+    fn handle(&mut self, _: ExportSessions, _: &mut Self::Context) -> Self::Result {
+        let sessions = self
+            .sessions
+            .iter()
+            .map(|(user, data)| (user.clone(), data.session_addr.clone(), data.locale))
+            .collect();
+        MessageResult(sessions)
+    }
+}
+
+/// Number of users logged in, without exporting their sessions
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct CountSessions;
+
+impl Handler<CountSessions> for SessionManager {
+    type Result = usize;
+    fn handle(&mut self, _: CountSessions, _: &mut Self::Context) -> Self::Result {
+        self.sessions.len()
+    }
+}
+
+/// Sessions handed over by the previous process, their tokens stop working after
+/// [RESUME_WINDOW]
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ImportSessions(pub Vec<SessionSummary>);
+
+impl Handler<ImportSessions> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: ImportSessions, ctx: &mut Self::Context) -> Self::Result {
+        self.migrated.extend(
+            msg.0
+                .into_iter()
+                .map(|session| (session.token.clone(), session)),
+        );
+        ctx.run_later(RESUME_WINDOW, |act, _| act.migrated.clear());
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub enum ResumeError {
+    /// The token is unknown, was already used or has expired
+    InvalidToken,
+    AlreadyLoggedIn,
+    InternalServerError,
+}
+
+/// Logs a client of the previous process back in as the user its resume token was issued to
+#[derive(Message)]
+#[rtype(result = "Option<(TransientId, SessionSummary)>")]
+struct Resume {
+    token: String,
+    session_addr: Addr<Session>,
+}
+
+impl Handler<Resume> for SessionManager {
+    type Result = Option<(TransientId, SessionSummary)>;
+    fn handle(&mut self, msg: Resume, _: &mut Self::Context) -> Self::Result {
+        let mut summary = self.migrated.remove(&msg.token)?;
+        let transient_id = self.new_id();
+        let token = self
+            .add_session(
+                summary.user.clone(),
+                msg.session_addr,
+                transient_id,
+                None,
+                true,
+                summary.locale,
+            )
+            .ok()?;
+        // The client reconnects to this process with the token of its new session from now on
+        summary.token = token.into();
+        Some((transient_id, summary))
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub enum WhisperError {
+    NotLoggedIn,
+    /// Nobody is logged in as the recipient, on this node or any other
+    NotOnline,
+    Empty,
+    TooLong,
+    /// The text contains profanity and the server is configured to refuse such messages
+    Inappropriate,
+    InternalServerError,
+}
+
+/// Hands a private message to its recipient, on whichever node they are logged in
+#[derive(Message)]
+#[rtype(result = "Result<(), WhisperError>")]
+pub struct Whisper {
+    pub from: UserId,
+    pub to: UserId,
+    pub text: String,
+}
+
+impl Handler<Whisper> for SessionManager {
+    type Result = Result<(), WhisperError>;
+    fn handle(&mut self, msg: Whisper, _: &mut Self::Context) -> Self::Result {
+        if msg.text.is_empty() {
+            return Err(WhisperError::Empty);
+        }
+        if msg.text.len() > MAX_CHAT_LENGTH {
+            return Err(WhisperError::TooLong);
+        }
+        let text = match self.profanity.check(&msg.text) {
+            Verdict::Clean => msg.text,
+            Verdict::Masked(masked) => masked,
+            Verdict::Escalated(masked) => {
+                log::warn!(
+                    target: "moderation",
+                    "profanity in whisper from {} to {}: {:?}",
+                    msg.from,
+                    msg.to,
+                    msg.text
+                );
+                masked
+            }
+            Verdict::Rejected => return Err(WhisperError::Inappropriate),
+        };
+        if let Some(session) = self.local_session(&msg.to) {
+            session.do_send(SerializedMessage(OutgoingMessage::Whisper {
+                from: msg.from,
+                text,
+            }));
+            return Ok(());
+        }
+        match (self.remote.get(&msg.to), &self.backplane) {
+            (Some(node), Some(backplane)) => {
+                backplane.relay(
+                    node.clone(),
+                    RelayedWhisper {
+                        to: msg.to,
+                        from: msg.from,
+                        text,
+                    },
+                );
+                Ok(())
+            }
+            _ => Err(WhisperError::NotOnline),
+        }
+    }
+}
+
+/// Most users a client can ask about at once
+pub const MAX_PRESENCE_QUERY: usize = 64;
+
+#[derive(Serialize, Clone)]
+pub struct PresenceEntry {
+    pub user: UserId,
+    pub online: bool,
+    /// Code of the room the user is in, only shared with the users on their friend list, see
+    /// [presence]
+    pub room: Option<String>,
+    /// Whether a game is running in that room
+    pub playing: bool,
+}
+
+/// Whether the users are logged in, on this node or any other
+#[derive(Message)]
+#[rtype(result = "Vec<PresenceEntry>")]
+pub struct QueryPresence {
+    /// The user asking, if logged in
+    pub from: Option<UserId>,
+    pub users: Vec<UserId>,
+}
+
+impl Handler<QueryPresence> for SessionManager {
+    type Result = MessageResult<QueryPresence>;
+    fn handle(&mut self, msg: QueryPresence, _: &mut Self::Context) -> Self::Result {
+        let entries = msg
+            .users
+            .into_iter()
+            .take(MAX_PRESENCE_QUERY)
+            .map(|user| {
+                let online = self.is_online(&user);
+                self.presence.entry(user, online, msg.from.as_ref())
+            })
+            .collect();
+        MessageResult(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BroadcastToAll, ConnectionPolicy};
+    use crate::room::actor::START_COUNTDOWN;
+    use crate::session::message::{Announcement, AnnouncementKind};
+    use crate::testing::Server;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[actix::test]
+    async fn disconnected_players_reclaim_their_seat() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        let token = ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        assert_eq!(ben.expect("JoinRoomResult").await["status"], "Success");
+
+        ann.drop_connection().await;
+        ben.expect("PlayerDisconnected").await;
+        // Only the client holding the resume token gets the seat back
+        let stranger = server.connect().await;
+        stranger.send(json!({ "kind": "Login", "data": "ann" })).await;
+        let refused = stranger.expect("LoginResult").await;
+        assert_eq!(refused["status"], "Error");
+
+        let ann = server.connect().await;
+        ann.hello().await;
+        let reconnect = json!({ "user": "ann", "token": token, "last_seq": null });
+        ann.send(json!({ "kind": "Reconnect", "data": reconnect })).await;
+        assert_eq!(ann.expect("LoginResult").await["status"], "Success");
+        ann.expect("RestoreState").await;
+        ben.expect("PlayerRejoined").await;
+        // The reconnected session is back in the room, and can leave it
+        ann.send(json!({ "kind": "LeaveRoom" })).await;
+        ben.expect("PlayerLeft").await;
+    }
+
+    #[actix::test]
+    async fn players_reconnecting_mid_game_keep_their_standing() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        let token = ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        let ann_id = ben.expect("Roster").await[0]["id"].clone();
+        ann.expect("PlayerJoined").await;
+        let words = json!({ "words": ["cat"] });
+        ann.send(json!({ "kind": "UpdateRoomSettings", "data": words })).await;
+        ann.expect("UpdateRoomSettingsResult").await;
+        ann.send(json!({ "kind": "StartGame" })).await;
+        actix::clock::sleep(Duration::from_secs(START_COUNTDOWN)).await;
+        // Players can't repeat their last guess, but any spelling of the word will do
+        let mut spellings = ["cat", "Cat", "CAT"].into_iter().cycle();
+        let mut guess = || {
+            let word = json!({ "kind": "Word", "data": spellings.next() });
+            json!({ "kind": "GameInput", "data": word })
+        };
+        // Guesses coming in right as the turn starts are refused as automated
+        let thinking = Duration::from_millis(300);
+        let mut turn = ann.expect("TurnUpdate").await["player"].clone();
+        // Ben drops out while it's ann's turn
+        if turn != ann_id {
+            actix::clock::sleep(thinking).await;
+            ben.send(guess()).await;
+            turn = ann.expect("TurnUpdate").await["player"].clone();
+        }
+        ben.drop_connection().await;
+        ann.expect("PlayerDisconnected").await;
+        let ben = server.connect().await;
+        ben.hello().await;
+        let reconnect = json!({ "user": "ben", "token": token, "last_seq": null });
+        ben.send(json!({ "kind": "Reconnect", "data": reconnect })).await;
+        let rejoined = ann.expect("PlayerRejoined").await;
+        let ben_id = rejoined["player"]["id"].clone();
+
+        // Every guess is right, so there are no more turns once the last round is over
+        loop {
+            let player = if turn == ann_id { &ann } else { &ben };
+            actix::clock::sleep(thinking).await;
+            player.send(guess()).await;
+            let Some(update) = ann.next("TurnUpdate").await else {
+                break;
+            };
+            turn = update["player"].clone();
+            assert!(turn == ann_id || turn == ben_id, "turn went to {turn}");
+        }
+        let summary = ben.expect("GameEnd").await;
+        let standing = summary["standings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["id"] == ben_id)
+            .expect("ben is in the standings");
+        assert_eq!(standing["name"], rejoined["player"]["name"]);
+        assert!(standing["score"].as_u64().unwrap() > 0);
+    }
+
+    #[actix::test]
+    async fn announcements_reach_clients_yet_to_log_in() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        let stranger = server.connect().await;
+        let announcement = Announcement {
+            kind: AnnouncementKind::Maintenance,
+            text: "Back in five".to_owned(),
+        };
+        let delivered = server.session_manager.send(BroadcastToAll(announcement)).await;
+        assert_eq!(delivered.unwrap(), 2);
+        for client in [ann, stranger] {
+            let announcement = client.expect("Announcement").await;
+            assert_eq!(announcement["text"], "Back in five");
+        }
+    }
+
+    #[actix::test]
+    async fn rooms_are_opened_as_asked_or_not_at_all() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        for (request, error) in [
+            (json!({ "language": "xx" }), "UnsupportedLanguage"),
+            (json!({ "max_players": 1 }), "InvalidPlayerLimit"),
+            (json!({ "password": "p".repeat(65) }), "InvalidPassword"),
+        ] {
+            ann.send(json!({ "kind": "CreateRoom", "data": request })).await;
+            let refused = ann.expect("Result").await;
+            assert_eq!(refused["data"]["data"]["kind"], error);
+        }
+        let request = json!({ "max_players": 3, "language": "de", "password": " secret " });
+        ann.send(json!({ "kind": "CreateRoom", "data": request })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        let wrong = json!({ "code": code, "password": "secrets" });
+        ben.send(json!({ "kind": "JoinRoom", "data": wrong })).await;
+        let refused = ben.expect("JoinRoomResult").await;
+        assert_eq!(refused["data"]["kind"], "WrongPassword");
+        // Trimmed the same way as when the room was opened
+        let right = json!({ "code": code, "password": "secret\n" });
+        ben.send(json!({ "kind": "JoinRoom", "data": right })).await;
+        assert_eq!(ben.expect("JoinRoomResult").await["status"], "Success");
+    }
+
+    #[actix::test]
+    async fn results_carry_the_id_of_their_request() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        let search = json!({ "kind": "JoinRoom", "data": {}, "request_id": "search" });
+        ann.send(search).await;
+        // Answered once the matchmaker got to it, long after the request was handled
+        let found = ann.expect_reply("MatchFound").await;
+        assert_eq!(found["request_id"], "search");
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let refused = ann.expect_reply("Result").await;
+        assert!(refused.get("request_id").is_none());
+
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        let create = json!({ "kind": "CreateRoom", "data": {}, "request_id": 7 });
+        ben.send(create).await;
+        let created = ben.expect_reply("Result").await;
+        assert_eq!(created["request_id"], 7);
+        assert_eq!(created["data"]["data"]["status"], "Success");
+    }
+
+    #[actix::test]
+    async fn only_the_leader_of_an_announcement_room_is_heard() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        let request = json!({ "public": true, "announcement": true });
+        ann.send(json!({ "kind": "CreateRoom", "data": request })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        assert_eq!(ben.expect("JoinRoomResult").await["status"], "Success");
+
+        ben.send(json!({ "kind": "Chat", "data": "hi" })).await;
+        let rejected = ben.expect("ChatRejected").await;
+        assert_eq!(rejected["kind"], "ListenOnly");
+        ann.send(json!({ "kind": "Chat", "data": "welcome" })).await;
+        assert_eq!(ben.expect("ChatMessage").await["text"], "welcome");
+        ann.send(json!({ "kind": "StartGame" })).await;
+        let refused = ann.expect("Result").await;
+        assert_eq!(refused["data"]["data"]["kind"], "NotAllowed");
+    }
+
+    #[actix::test]
+    async fn clients_only_send_what_their_protocol_has() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        // Speaks the first version, having never said otherwise
+        let localized = json!({ "user": "ann", "locale": "de" });
+        ann.send(json!({ "kind": "Login", "data": localized })).await;
+        assert_eq!(ann.expect("UnsupportedMessage").await["since"], 6);
+        ann.send(json!({ "kind": "Login", "data": "ann" })).await;
+        assert_eq!(ann.expect("LoginResult").await["status"], "Success");
+        ann.send(json!({ "kind": "Hello", "data": 8 })).await;
+        ann.send(json!({ "kind": "SetPaused", "data": true })).await;
+        assert_eq!(ann.expect("UnsupportedMessage").await["since"], 9);
+        ann.hello().await;
+        ann.send(json!({ "kind": "SetPaused", "data": true })).await;
+        let refused = ann.expect("SetPausedResult").await;
+        assert_eq!(refused["data"]["kind"], "NotInRoom");
+    }
+
+    #[actix::test]
+    async fn frames_nesting_too_deep_are_refused_whatever_the_transport() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        let nested = "[".repeat(64) + &"]".repeat(64);
+        let nested: serde_json::Value = serde_json::from_str(&nested).unwrap();
+        ann.send(json!({ "kind": "Chat", "data": nested })).await;
+        assert_eq!(ann.expect("ForceDisconnect").await, "MalformedFrame");
+    }
+
+    #[actix::test]
+    async fn quiet_players_are_only_idle_once_out_of_the_room_for_long_enough() {
+        let mut server = Server::start();
+        server.timings.heartbeat_interval = Duration::from_millis(20);
+        server.timings.idle_timeout = Duration::from_millis(200);
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        assert_eq!(ben.expect("JoinRoomResult").await["status"], "Success");
+        let target = ann.expect("PlayerJoined").await["id"].clone();
+        actix::clock::sleep(Duration::from_millis(300)).await;
+        let kick = json!({ "target": target });
+        ann.send(json!({ "kind": "KickPlayer", "data": kick })).await;
+        ben.expect("RemoveFromRoom").await;
+        actix::clock::sleep(Duration::from_millis(100)).await;
+        assert!(ben.session.connected());
+        assert_eq!(ben.expect("ForceDisconnect").await, "Idle");
+    }
+
+    #[actix::test]
+    async fn leadership_moves_on_while_the_leader_is_away() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        let joined = ann.expect("PlayerJoined").await;
+        ann.drop_connection().await;
+        // Ann's seat is still held for her, but ben leads in the meantime
+        ben.expect("PlayerDisconnected").await;
+        assert_eq!(ben.expect("LeaderChanged").await, joined["id"]);
+    }
+
+    #[actix::test]
+    async fn placeholder_names_are_numbered_when_taken() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        let created = ann.expect("Result").await;
+        let code = created["data"]["data"].as_str().unwrap().to_owned();
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        let placeholder = ann.expect("PlayerJoined").await["name"].clone();
+        ben.send(json!({ "kind": "LeaveRoom" })).await;
+        ann.expect("PlayerLeft").await;
+        // Someone else picks the name ben was given
+        let carl = server.connect().await;
+        carl.login("carl").await;
+        let profile = json!({ "name": placeholder });
+        carl.send(json!({ "kind": "SetProfile", "data": profile })).await;
+        assert_eq!(carl.expect("SetProfileResult").await["status"], "Success");
+        carl.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        assert_eq!(ann.expect("PlayerJoined").await["name"], placeholder);
+        ben.send(json!({ "kind": "JoinRoom", "data": code })).await;
+        let numbered = format!("{} (2)", placeholder.as_str().unwrap());
+        assert_eq!(ann.expect("PlayerJoined").await["name"], numbered);
+    }
+
+    #[actix::test]
+    async fn whispers_are_filtered_for_profanity() {
+        let server = Server::start();
+        let ann = server.connect().await;
+        ann.login("ann").await;
+        let ben = server.connect().await;
+        ben.login("ben").await;
+        let whisper = json!({ "to": "ben", "text": "you bastard" });
+        ann.send(json!({ "kind": "Whisper", "data": whisper })).await;
+        assert_eq!(ann.expect("WhisperResult").await["status"], "Success");
+        assert_eq!(ben.expect("Whisper").await["text"], "you *******");
+    }
+
+    #[actix::test]
+    async fn newer_logins_never_take_over_the_older_session() {
+        let server = Server::with_policy(ConnectionPolicy::KickOldest);
+        let ann = server.connect().await;
+        let token = ann.login("ann").await;
+        ann.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+        ann.expect("Result").await;
+        let newer = server.connect().await;
+        assert_ne!(newer.login("ann").await, token);
+        assert_eq!(ann.expect("ForceDisconnect").await, "LoggedInElsewhere");
+        // The seat went with the older connection
+        assert!(newer.next("RestoreState").await.is_none());
+
+        let server = Server::with_policy(ConnectionPolicy::Allow);
+        let ann = server.connect().await;
+        let token = ann.login("ann").await;
+        let other = server.connect().await;
+        assert_ne!(other.login("ann").await, token);
+        ann.expect("OtherConnection").await;
+    }
+
+    #[actix::test]
+    async fn every_connection_gives_up_its_own_seat() {
+        let server = Server::with_policy(ConnectionPolicy::Allow);
+        let mut rooms = Vec::new();
+        for (user, guest) in [("ann", "ben"), ("ann", "cat")] {
+            let host = server.connect().await;
+            host.login(user).await;
+            host.send(json!({ "kind": "CreateRoom", "data": {} })).await;
+            let created = host.expect("Result").await;
+            let code = created["data"]["data"].as_str().unwrap().to_owned();
+            let guest_client = server.connect().await;
+            guest_client.login(guest).await;
+            guest_client.send(json!({ "kind": "JoinRoom", "data": code })).await;
+            let host_id = guest_client.expect("Roster").await[0]["id"].clone();
+            rooms.push((host, guest_client, host_id));
+        }
+        // The first connection logs out and the second one carries on as ann's session, in a
+        // room of its own
+        for (host, guest, host_id) in rooms {
+            host.send(json!({ "kind": "Logout" })).await;
+            assert_eq!(guest.expect("PlayerLeft").await, host_id);
         }
     }
 }
@@ -0,0 +1,75 @@
+//! MessagePack encoding of the protocol, for clients that would rather spend less bandwidth than
+//! read their frames. Messages are the same as over JSON: outgoing frames are serialized once as
+//! JSON and transcoded once for all the clients that asked for MessagePack, see
+//! [super::sink::SharedFrame], and incoming frames are transcoded to JSON before the session
+//! parses them, see [super::sink::Encoding].
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::Cursor;
+
+use super::sink::{FrameViolation, MAX_FRAME_SIZE, MAX_JSON_DEPTH};
+
+/// Transcodes a JSON frame to MessagePack
+pub fn from_json(frame: &str) -> Result<Vec<u8>, String> {
+    let value = serde_json::from_str::<Value>(frame).map_err(|x| x.to_string())?;
+    rmp_serde::to_vec_named(&value).map_err(|x| x.to_string())
+}
+
+/// Transcodes a MessagePack frame from a client to JSON, holding it to the same limits as JSON
+/// frames
+pub fn to_json(frame: &[u8]) -> Result<String, FrameViolation> {
+    if frame.len() > MAX_FRAME_SIZE {
+        return Err(FrameViolation::TooLarge(frame.len()));
+    }
+    let mut decoder = rmp_serde::Deserializer::new(Cursor::new(frame));
+    // The decoder counts the top level too
+    decoder.set_max_depth(MAX_JSON_DEPTH + 1);
+    let value = Value::deserialize(&mut decoder).map_err(|err| match err {
+        rmp_serde::decode::Error::DepthLimitExceeded => FrameViolation::TooDeep,
+        _ => FrameViolation::Malformed("not a MessagePack message"),
+    })?;
+    if decoder.position() != frame.len() as u64 {
+        return Err(FrameViolation::Malformed("trailing bytes"));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_survive_the_round_trip() {
+        let frame = r#"{"kind":"Chat","data":["héllo",0,200,70000,-5,-40000,1.5,null,true]}"#;
+        let packed = from_json(frame).unwrap();
+        // fixmap of 2
+        assert_eq!(packed[0], 0x82);
+        assert!(packed.len() < frame.len());
+        let json = to_json(&packed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&json).unwrap(),
+            serde_json::from_str::<Value>(frame).unwrap()
+        );
+    }
+
+    #[test]
+    fn hostile_frames_are_refused() {
+        let nested = vec![0x91; MAX_JSON_DEPTH + 1];
+        assert_eq!(to_json(&nested), Err(FrameViolation::TooDeep));
+        let mut deepest = vec![0x91; MAX_JSON_DEPTH - 1];
+        deepest.push(0x90);
+        assert!(to_json(&deepest).is_ok());
+        // An array claiming four billion items
+        let huge = [0xdd, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(to_json(&huge), Err(FrameViolation::Malformed(_))));
+        assert!(matches!(
+            to_json(&[0x81, 0x01, 0x02]),
+            Err(FrameViolation::Malformed(_))
+        ));
+        assert!(matches!(
+            to_json(&[0xc0, 0xc0]),
+            Err(FrameViolation::Malformed(_))
+        ));
+    }
+}
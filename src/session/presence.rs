@@ -0,0 +1,431 @@
+//! Friend lists and what friends are up to. Clients register the users they are friends with
+//! for as long as they stay logged in, and are told whenever one of those comes online, goes
+//! offline, or starts or finishes a game. Users only let on which room they are in to the users
+//! on their own friend list, so that the codes of private rooms do not leak to anyone asking.
+//! Rooms and games are only known for the users of this node, see [super::backplane].
+//!
+//! Users in a room can invite the friends who list them in turn to it, see [InviteFriend]. The
+//! invite reaches the friend's session with the code of the room, and their answer is relayed
+//! back to the user who sent it.
+
+use actix::prelude::*;
+use ahash::{HashMap, HashSet};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use super::actor::SerializedMessage;
+use super::message::OutgoingMessage;
+use super::{PresenceEntry, SessionManager, UserId};
+use crate::events::ServerEvent;
+
+/// Most friends a user can register
+pub const MAX_FRIENDS: usize = 200;
+/// How long friends have to answer an invite
+pub const INVITE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Most invites a user can have waiting for an answer at once
+pub const MAX_PENDING_INVITES: usize = 10;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum FriendsError {
+    NotLoggedIn,
+    /// The friend list would grow past [MAX_FRIENDS]
+    TooManyEntries,
+    InternalServerError,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum FriendInviteError {
+    NotLoggedIn,
+    NotInRoom,
+    /// The friend is not logged in on this node
+    NotOnline,
+    /// The friend does not list the user inviting them as a friend
+    NotFriend,
+    /// Users cannot invite themselves
+    NotAllowed,
+    /// The user has [MAX_PENDING_INVITES] waiting for an answer already
+    TooManyEntries,
+    /// There is no such invite to answer
+    InvalidInvite,
+    /// Nobody answered the invite within [INVITE_TIMEOUT]
+    InviteExpired,
+    InternalServerError,
+}
+
+/// Invite to a room waiting for the friend to answer it
+struct PendingInvite {
+    from: UserId,
+    to: UserId,
+    expires: Instant,
+}
+
+#[derive(Default)]
+pub struct Presence {
+    /// Friend list of every user of this node who registered one
+    friends: HashMap<UserId, HashSet<UserId>>,
+    /// Users who list the user as a friend, told whenever they come and go
+    watchers: HashMap<UserId, HashSet<UserId>>,
+    /// Code of the room every user of this node is in, if in one
+    rooms: HashMap<UserId, String>,
+    /// Users in every room, by its code
+    members: HashMap<String, HashSet<UserId>>,
+    /// Codes of the rooms running a game
+    playing: HashSet<String>,
+    /// Invites waiting for an answer, by their id
+    invites: HashMap<u64, PendingInvite>,
+    /// Id of the latest invite
+    last_invite: u64,
+}
+
+impl Presence {
+    /// Adds the users to the friends of `user`, either all of them or none if there would be
+    /// too many
+    pub fn add_friends(&mut self, user: &UserId, friends: Vec<UserId>) -> Result<(), FriendsError> {
+        let list = self.friends.entry(user.clone()).or_default();
+        let new: HashSet<_> = friends
+            .into_iter()
+            .filter(|friend| friend != user && !list.contains(friend))
+            .collect();
+        if list.len() + new.len() > MAX_FRIENDS {
+            return Err(FriendsError::TooManyEntries);
+        }
+        for friend in new {
+            let watchers = self.watchers.entry(friend.clone()).or_default();
+            watchers.insert(user.clone());
+            list.insert(friend);
+        }
+        Ok(())
+    }
+    pub fn remove_friends(&mut self, user: &UserId, friends: &[UserId]) {
+        let Some(list) = self.friends.get_mut(user) else {
+            return;
+        };
+        for friend in friends {
+            if list.remove(friend) {
+                unwatch(&mut self.watchers, friend, user);
+            }
+        }
+        if list.is_empty() {
+            self.friends.remove(user);
+        }
+    }
+    /// Drops the friend list, room and invites of a user who went offline. Others keep them on
+    /// their friend lists.
+    pub fn forget(&mut self, user: &UserId) {
+        self.entered(user, None);
+        self.invites
+            .retain(|_, invite| invite.from != *user && invite.to != *user);
+        for friend in self.friends.remove(user).unwrap_or_default() {
+            unwatch(&mut self.watchers, &friend, user);
+        }
+    }
+    pub fn friends(&self, user: &UserId) -> Vec<UserId> {
+        self.friends
+            .get(user)
+            .map(|list| list.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    /// Users to tell when `user` comes and goes
+    pub fn watchers(&self, user: &UserId) -> Vec<UserId> {
+        self.watchers
+            .get(user)
+            .map(|watchers| watchers.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    /// The user moved into the room with the code, or left the one they were in
+    pub fn entered(&mut self, user: &UserId, room: Option<String>) {
+        if let Some(old) = self.rooms.remove(user) {
+            if let Some(members) = self.members.get_mut(&old) {
+                members.remove(user);
+                if members.is_empty() {
+                    self.members.remove(&old);
+                }
+            }
+        }
+        if let Some(room) = room {
+            let members = self.members.entry(room.clone()).or_default();
+            members.insert(user.clone());
+            self.rooms.insert(user.clone(), room);
+        }
+    }
+    /// Marks a game as started or over in the room, answering with its members if that changed
+    /// anything
+    pub fn set_playing(&mut self, room: String, playing: bool) -> Vec<UserId> {
+        let changed = if playing {
+            self.playing.insert(room.clone())
+        } else {
+            self.playing.remove(&room)
+        };
+        match self.members.get(&room) {
+            Some(members) if changed => members.iter().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+    /// Invites the friend to the room `from` is in, answering with the id of the invite and the
+    /// code of the room
+    pub fn invite(
+        &mut self,
+        from: &UserId,
+        to: &UserId,
+        now: Instant,
+    ) -> Result<(u64, String), FriendInviteError> {
+        if from == to {
+            return Err(FriendInviteError::NotAllowed);
+        }
+        let room = self.rooms.get(from).ok_or(FriendInviteError::NotInRoom)?;
+        if !self.friends.get(to).is_some_and(|list| list.contains(from)) {
+            return Err(FriendInviteError::NotFriend);
+        }
+        self.invites.retain(|_, invite| invite.expires > now);
+        let pending = self.invites.values().filter(|x| x.from == *from).count();
+        if pending >= MAX_PENDING_INVITES {
+            return Err(FriendInviteError::TooManyEntries);
+        }
+        self.last_invite += 1;
+        self.invites.insert(
+            self.last_invite,
+            PendingInvite {
+                from: from.clone(),
+                to: to.clone(),
+                expires: now + INVITE_TIMEOUT,
+            },
+        );
+        Ok((self.last_invite, room.clone()))
+    }
+    /// Takes the invite with the id sent to `user` out of the pending ones, answering with the
+    /// user who sent it
+    pub fn answer(
+        &mut self,
+        id: u64,
+        user: &UserId,
+        now: Instant,
+    ) -> Result<UserId, FriendInviteError> {
+        if self.invites.get(&id).is_none_or(|x| x.to != *user) {
+            return Err(FriendInviteError::InvalidInvite);
+        }
+        match self.invites.remove(&id) {
+            Some(invite) if invite.expires > now => Ok(invite.from),
+            _ => Err(FriendInviteError::InviteExpired),
+        }
+    }
+    /// What `asker` gets to know about the user. Only the users on their friend list learn
+    /// which room they are in.
+    pub fn entry(&self, user: UserId, online: bool, asker: Option<&UserId>) -> PresenceEntry {
+        let shared = asker.is_some_and(|asker| {
+            self.friends
+                .get(&user)
+                .is_some_and(|list| list.contains(asker))
+        });
+        let room = self.rooms.get(&user).filter(|_| shared && online).cloned();
+        PresenceEntry {
+            playing: room
+                .as_ref()
+                .is_some_and(|room| self.playing.contains(room)),
+            room,
+            online,
+            user,
+        }
+    }
+}
+
+/// Stops telling `user` about `friend`
+fn unwatch(watchers: &mut HashMap<UserId, HashSet<UserId>>, friend: &UserId, user: &UserId) {
+    if let Some(users) = watchers.get_mut(friend) {
+        users.remove(user);
+        if users.is_empty() {
+            watchers.remove(friend);
+        }
+    }
+}
+
+impl SessionManager {
+    /// Whether the user is logged in, on this node or any other
+    pub(super) fn is_online(&self, user: &UserId) -> bool {
+        self.local_session(user).is_some() || self.remote.contains_key(user)
+    }
+    /// Tells the users who list the user as a friend what they are up to now
+    pub(super) fn announce(&self, user: &UserId, online: bool) {
+        for watcher in self.presence.watchers(user) {
+            if let Some(session) = self.local_session(&watcher) {
+                let entry = self.presence.entry(user.clone(), online, Some(&watcher));
+                session.do_send(SerializedMessage(OutgoingMessage::FriendPresence(entry)));
+            }
+        }
+    }
+}
+
+/// Changes the friend list of the user, answering with what every friend on it is up to
+#[derive(Message)]
+#[rtype(result = "Result<Vec<PresenceEntry>, FriendsError>")]
+pub struct UpdateFriends {
+    pub user: UserId,
+    pub add: Vec<UserId>,
+    pub remove: Vec<UserId>,
+}
+
+impl Handler<UpdateFriends> for SessionManager {
+    type Result = Result<Vec<PresenceEntry>, FriendsError>;
+    fn handle(&mut self, msg: UpdateFriends, _: &mut Self::Context) -> Self::Result {
+        self.presence.remove_friends(&msg.user, &msg.remove);
+        self.presence.add_friends(&msg.user, msg.add)?;
+        let entries = self
+            .presence
+            .friends(&msg.user)
+            .into_iter()
+            .map(|friend| {
+                let online = self.is_online(&friend);
+                self.presence.entry(friend, online, Some(&msg.user))
+            })
+            .collect();
+        Ok(entries)
+    }
+}
+
+/// Invites a friend to the room the user is in, answering with the id of the invite. The friend
+/// gets an [OutgoingMessage::FriendInvite].
+#[derive(Message)]
+#[rtype(result = "Result<u64, FriendInviteError>")]
+pub struct InviteFriend {
+    pub from: UserId,
+    /// Name the user goes by, shown to the friend
+    pub name: String,
+    pub to: UserId,
+}
+
+impl Handler<InviteFriend> for SessionManager {
+    type Result = Result<u64, FriendInviteError>;
+    fn handle(&mut self, msg: InviteFriend, _: &mut Self::Context) -> Self::Result {
+        let Some(session) = self.local_session(&msg.to).cloned() else {
+            return Err(FriendInviteError::NotOnline);
+        };
+        let (id, room) = self.presence.invite(&msg.from, &msg.to, Instant::now())?;
+        session.do_send(SerializedMessage(OutgoingMessage::FriendInvite {
+            id,
+            from: msg.from,
+            name: msg.name,
+            room,
+        }));
+        Ok(id)
+    }
+}
+
+/// Accepts or declines an invite sent to the user, the user who sent it is told either way
+#[derive(Message)]
+#[rtype(result = "Result<(), FriendInviteError>")]
+pub struct AnswerInvite {
+    pub user: UserId,
+    pub id: u64,
+    pub accept: bool,
+}
+
+impl Handler<AnswerInvite> for SessionManager {
+    type Result = Result<(), FriendInviteError>;
+    fn handle(&mut self, msg: AnswerInvite, _: &mut Self::Context) -> Self::Result {
+        let from = self.presence.answer(msg.id, &msg.user, Instant::now())?;
+        if let Some(session) = self.local_session(&from) {
+            session.do_send(SerializedMessage(OutgoingMessage::InviteAnswered {
+                id: msg.id,
+                user: msg.user,
+                accepted: msg.accept,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Sent by sessions whenever their user moves into a room or leaves one
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct InRoom {
+    pub user: UserId,
+    /// Code of the room
+    pub room: Option<String>,
+}
+
+impl Handler<InRoom> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: InRoom, _: &mut Self::Context) -> Self::Result {
+        self.presence.entered(&msg.user, msg.room);
+    }
+}
+
+impl Handler<ServerEvent> for SessionManager {
+    type Result = ();
+    fn handle(&mut self, msg: ServerEvent, _: &mut Self::Context) -> Self::Result {
+        let (room, playing) = match msg {
+            ServerEvent::GameStarted { room, .. } => (room, true),
+            ServerEvent::GameFinished { room, .. } | ServerEvent::RoomClosed { room, .. } => {
+                (room, false)
+            }
+            _ => return,
+        };
+        for user in self.presence.set_playing(room, playing) {
+            self.announce(&user, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rooms_are_only_shared_with_friends() {
+        let (ann, ben, cat): (UserId, UserId, UserId) = ("ann".into(), "ben".into(), "cat".into());
+        let mut presence = Presence::default();
+        presence
+            .add_friends(&ann, vec![ben.clone(), cat.clone(), ann.clone()])
+            .unwrap();
+        assert_eq!(presence.friends(&ann).len(), 2);
+        assert_eq!(presence.watchers(&ben), vec![ann.clone()]);
+        presence.entered(&ann, Some("ABCD".into()));
+        assert_eq!(presence.set_playing("ABCD".into(), true), vec![ann.clone()]);
+        assert!(presence.set_playing("ABCD".into(), true).is_empty());
+        let entry = presence.entry(ann.clone(), true, Some(&ben));
+        assert_eq!(entry.room.as_deref(), Some("ABCD"));
+        assert!(entry.playing);
+        let entry = presence.entry(ann.clone(), true, None);
+        assert_eq!(entry.room, None);
+        presence.remove_friends(&ann, std::slice::from_ref(&ben));
+        assert!(presence.watchers(&ben).is_empty());
+        let many = (0..MAX_FRIENDS)
+            .map(|x| UserId::from(x.to_string()))
+            .collect();
+        assert_eq!(
+            presence.add_friends(&ann, many),
+            Err(FriendsError::TooManyEntries)
+        );
+        presence.forget(&ann);
+        assert!(presence.watchers(&cat).is_empty());
+        assert_eq!(presence.entry(ann, true, Some(&cat)).room, None);
+    }
+
+    #[test]
+    fn invites_go_to_friends_who_list_the_inviter() {
+        let (ann, ben): (UserId, UserId) = ("ann".into(), "ben".into());
+        let mut presence = Presence::default();
+        let now = Instant::now();
+        presence.entered(&ann, Some("ABCD".into()));
+        assert_eq!(
+            presence.invite(&ann, &ben, now),
+            Err(FriendInviteError::NotFriend)
+        );
+        presence.add_friends(&ben, vec![ann.clone()]).unwrap();
+        let (id, room) = presence.invite(&ann, &ben, now).unwrap();
+        assert_eq!(room, "ABCD");
+        assert_eq!(
+            presence.answer(id, &ann, now),
+            Err(FriendInviteError::InvalidInvite)
+        );
+        assert_eq!(presence.answer(id, &ben, now), Ok(ann.clone()));
+        assert_eq!(
+            presence.answer(id, &ben, now),
+            Err(FriendInviteError::InvalidInvite)
+        );
+        let (id, _) = presence.invite(&ann, &ben, now).unwrap();
+        assert_eq!(
+            presence.answer(id, &ben, now + INVITE_TIMEOUT),
+            Err(FriendInviteError::InviteExpired)
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use super::TransientId;
+use serde::{Deserialize, Serialize};
+
+/// Longest display name (in characters) a player can pick
+const MAX_NAME_LENGTH: usize = 24;
+/// Avatars are identifiers of assets bundled with the client, never arbitrary content
+const MAX_AVATAR_LENGTH: usize = 64;
+/// Colors are plain 24 bit RGB values
+const MAX_COLOR: u32 = 0xFF_FF_FF;
+
+/// How a player presents themselves to the other members of a room
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub avatar: Option<String>,
+    pub color: Option<u32>,
+}
+
+/// Named after the [super::errors::ErrorKind] each of them is reported as
+#[derive(Serialize, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum ProfileError {
+    InvalidName,
+    InvalidAvatar,
+    InvalidColor,
+}
+
+impl Profile {
+    /// Profile given to players that join a room without having picked one
+    pub fn placeholder(id: TransientId) -> Self {
+        Self {
+            name: format!("Player {id}"),
+            avatar: None,
+            color: None,
+        }
+    }
+    /// Checks that the profile is well formed. Whether the name is appropriate and unique is up
+    /// to the room the player joins.
+    pub fn validate(mut self) -> Result<Self, ProfileError> {
+        self.name = self.name.trim().to_string();
+        let length = self.name.chars().count();
+        if length == 0 || length > MAX_NAME_LENGTH || self.name.chars().any(char::is_control) {
+            return Err(ProfileError::InvalidName);
+        }
+        if let Some(avatar) = &self.avatar {
+            if avatar.is_empty()
+                || avatar.len() > MAX_AVATAR_LENGTH
+                || !avatar
+                    .bytes()
+                    .all(|x| x.is_ascii_alphanumeric() || x == b'_' || x == b'-')
+            {
+                return Err(ProfileError::InvalidAvatar);
+            }
+        }
+        if self.color.is_some_and(|color| color > MAX_COLOR) {
+            return Err(ProfileError::InvalidColor);
+        }
+        Ok(self)
+    }
+}
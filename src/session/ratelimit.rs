@@ -0,0 +1,158 @@
+//! Token buckets for the messages a client sends, one per kind of message so that a flood of
+//! guesses doesn't eat into the budget for chat and the other way around. Messages over budget
+//! are dropped with a warning, and connections that keep going over it are closed.
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Messages a session can go over budget with within [STRIKE_WINDOW] before it is closed
+const MAX_STRIKES: u32 = 20;
+const STRIKE_WINDOW: Duration = Duration::from_secs(60);
+/// Bots talk and play a lot faster than people, their budgets are this many times larger
+pub const BOT_FACTOR: f64 = 6.0;
+
+/// Kinds of messages with a budget of their own
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageClass {
+    /// Chat, whispers and other messages relayed to other players
+    Chat,
+    /// Gameplay inputs
+    Guess,
+    /// Logging in, joining and creating rooms
+    Join,
+    Other,
+}
+
+impl MessageClass {
+    /// Messages a client can send at once, and how many more it can send every second
+    fn budget(self) -> (f64, f64) {
+        match self {
+            MessageClass::Chat => (10.0, 1.0),
+            MessageClass::Guess => (10.0, 4.0),
+            MessageClass::Join => (5.0, 0.5),
+            MessageClass::Other => (30.0, 10.0),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    refill: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(class: MessageClass, factor: f64, now: Instant) -> Self {
+        let (capacity, refill) = class.budget();
+        Self {
+            tokens: capacity * factor,
+            capacity: capacity * factor,
+            refill: refill * factor,
+            last: now,
+        }
+    }
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill).min(self.capacity);
+        self.last = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+    /// How long until the bucket holds a token again
+    fn retry_in(&self) -> Duration {
+        Duration::from_secs_f64(((1.0 - self.tokens) / self.refill).max(0.0))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allowed,
+    /// The message is dropped, the client may try again after the given time
+    Limited(Duration),
+    /// The client kept going over budget and is disconnected
+    Abusive,
+}
+
+pub struct RateLimiter {
+    buckets: [Bucket; 4],
+    /// Messages over budget since the start of the strike window
+    strikes: u32,
+    strikes_since: Instant,
+}
+
+impl RateLimiter {
+    /// `factor` scales every budget, see [BOT_FACTOR]
+    pub fn new(factor: f64) -> Self {
+        let now = Instant::now();
+        Self {
+            buckets: [
+                Bucket::new(MessageClass::Chat, factor, now),
+                Bucket::new(MessageClass::Guess, factor, now),
+                Bucket::new(MessageClass::Join, factor, now),
+                Bucket::new(MessageClass::Other, factor, now),
+            ],
+            strikes: 0,
+            strikes_since: now,
+        }
+    }
+    pub fn check(&mut self, class: MessageClass, now: Instant) -> Verdict {
+        let bucket = &mut self.buckets[class as usize];
+        if bucket.take(now) {
+            return Verdict::Allowed;
+        }
+        if now.saturating_duration_since(self.strikes_since) >= STRIKE_WINDOW {
+            self.strikes = 0;
+            self.strikes_since = now;
+        }
+        self.strikes += 1;
+        if self.strikes > MAX_STRIKES {
+            Verdict::Abusive
+        } else {
+            Verdict::Limited(bucket.retry_in())
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes_have_budgets_of_their_own() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(limiter.check(MessageClass::Join, now), Verdict::Allowed);
+        }
+        assert_eq!(
+            limiter.check(MessageClass::Join, now),
+            Verdict::Limited(Duration::from_secs(2))
+        );
+        assert_eq!(limiter.check(MessageClass::Chat, now), Verdict::Allowed);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(limiter.check(MessageClass::Join, later), Verdict::Allowed);
+    }
+
+    #[test]
+    fn floods_end_up_disconnected() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        let verdicts = (0..40)
+            .map(|_| limiter.check(MessageClass::Guess, now))
+            .collect::<Vec<_>>();
+        assert_eq!(verdicts[9], Verdict::Allowed);
+        assert!(matches!(verdicts[10], Verdict::Limited(_)));
+        assert!(matches!(verdicts[29], Verdict::Limited(_)));
+        assert_eq!(verdicts[30], Verdict::Abusive);
+    }
+}
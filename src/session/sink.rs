@@ -0,0 +1,361 @@
+use actix::prelude::*;
+use actix_web::web::Bytes;
+use actix_web_actors::ws::{self, CloseCode, ProtocolError, WebsocketContext};
+use bytestring::ByteString;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, OnceLock};
+
+use super::actor::{Disconnected, Incoming, Pong, Session};
+use super::backpressure::Backlog;
+use super::msgpack;
+
+/// Longest text frame a client can send over a websocket, in bytes
+pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+/// Deepest a client message can nest arrays and objects. No message needs more than a few levels,
+/// and deeply nested ones are costly to parse and drop.
+pub const MAX_JSON_DEPTH: usize = 16;
+
+/// Why a frame was refused before being parsed
+#[derive(Debug, PartialEq)]
+pub enum FrameViolation {
+    /// Size of the frame in bytes
+    TooLarge(usize),
+    TooDeep,
+    /// Binary frames that are not valid MessagePack, see [super::msgpack]
+    Malformed(&'static str),
+}
+
+impl std::fmt::Display for FrameViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameViolation::TooLarge(size) => write!(f, "frame of {size} bytes is too large"),
+            FrameViolation::TooDeep => write!(f, "frame nests too deep"),
+            FrameViolation::Malformed(reason) => write!(f, "malformed frame: {reason}"),
+        }
+    }
+}
+
+/// Checks a frame against [MAX_FRAME_SIZE] and [MAX_JSON_DEPTH] without parsing it. Brackets
+/// within strings are skipped, anything else that is malformed is left to the parser.
+pub fn check_frame(text: &str) -> Result<(), FrameViolation> {
+    if text.len() > MAX_FRAME_SIZE {
+        return Err(FrameViolation::TooLarge(text.len()));
+    }
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for byte in text.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_JSON_DEPTH {
+                    return Err(FrameViolation::TooDeep);
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Outgoing frame, serialized as JSON. Clones share the frame's MessagePack encoding, so a frame
+/// sent to many clients is only transcoded once however many of them asked for MessagePack.
+#[derive(Clone)]
+pub struct SharedFrame {
+    json: ByteString,
+    /// [None] if the frame could not be transcoded
+    msgpack: Arc<OnceLock<Option<Bytes>>>,
+}
+
+impl SharedFrame {
+    pub fn json(&self) -> &ByteString {
+        &self.json
+    }
+    pub fn into_json(self) -> ByteString {
+        self.json
+    }
+    /// The frame in MessagePack, transcoded by whichever clone asks first
+    pub fn msgpack(&self) -> Option<&Bytes> {
+        let msgpack = self.msgpack.get_or_init(|| match msgpack::from_json(&self.json) {
+            Ok(frame) => Some(frame.into()),
+            Err(err) => {
+                log::error!("cannot transcode outgoing frame: {err}");
+                None
+            }
+        });
+        msgpack.as_ref()
+    }
+}
+
+impl From<ByteString> for SharedFrame {
+    fn from(json: ByteString) -> Self {
+        Self {
+            json,
+            msgpack: Arc::default(),
+        }
+    }
+}
+
+/// Transport a [Session] writes its outgoing messages to. Keeps the session logic independent of
+/// websockets, so that it can run over in-memory channels in tests and bots, or over other
+/// transports.
+pub trait ClientSink: 'static {
+    /// Delivers a serialized message to the client
+    fn send(&mut self, frame: SharedFrame);
+    /// Called once when the session stops, the transport should hang up on the client
+    fn close(&mut self);
+    /// Pings the client, which should answer with a [super::actor::Pong] carrying the same
+    /// payload. Transports without pings of their own leave their clients' latency unknown.
+    fn ping(&mut self, _payload: [u8; 8]) {}
+    /// Bytes sent to the client that the transport is still holding on to, see
+    /// [super::backpressure]. Transports that don't buffer writes of their own have none.
+    fn backlog(&self) -> usize {
+        0
+    }
+}
+
+/// Collects outgoing messages in a channel. The receiving end sees the channel disconnect once the
+/// session is gone.
+impl ClientSink for Sender<ByteString> {
+    fn send(&mut self, frame: SharedFrame) {
+        // Nobody is listening anymore, which is up to the embedder
+        let _ = Sender::send(self, frame.into_json());
+    }
+    fn close(&mut self) {}
+}
+
+/// Wire encoding of a websocket connection, picked by the client when it connects
+#[derive(serde::Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Text frames holding JSON
+    #[default]
+    Json,
+    /// Binary frames holding MessagePack, see [super::msgpack]
+    MsgPack,
+}
+
+/// Websocket transport, forwards frames from the client to its [Session] and writes the
+/// session's messages back to the socket
+pub struct WsConnection {
+    session: Addr<Session>,
+    encoding: Encoding,
+    /// Bytes the session sent that the socket has not taken yet, see [WsSink]
+    backlog: Backlog,
+}
+
+impl WsConnection {
+    pub fn new(session: Addr<Session>, encoding: Encoding, backlog: Backlog) -> Self {
+        Self {
+            session,
+            encoding,
+            backlog,
+        }
+    }
+    /// Hangs up on a client that sent something it shouldn't have
+    fn refuse(&self, violation: FrameViolation, ctx: &mut WebsocketContext<Self>) {
+        log::warn!("closing connection: {violation}");
+        ctx.close(Some((CloseCode::Protocol, violation.to_string()).into()));
+        ctx.stop();
+    }
+}
+
+impl Actor for WsConnection {
+    type Context = WebsocketContext<Self>;
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.session.do_send(Disconnected);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ProtocolError>> for WsConnection {
+    fn handle(&mut self, item: Result<ws::Message, ProtocolError>, ctx: &mut Self::Context) {
+        match item {
+            Ok(msg) => match msg {
+                // Checked by the session, see [check_frame]
+                ws::Message::Text(text) => self.session.do_send(Incoming(text)),
+                ws::Message::Binary(bytes) => match msgpack::to_json(&bytes) {
+                    Ok(text) => self.session.do_send(Incoming(text.into())),
+                    Err(violation) => self.refuse(violation, ctx),
+                },
+                ws::Message::Ping(bytes) => ctx.pong(&bytes),
+                ws::Message::Pong(bytes) => self.session.do_send(Pong(bytes.to_vec())),
+                ws::Message::Close(reason) => {
+                    ctx.close(reason);
+                    ctx.stop();
+                }
+                _ => {}
+            },
+            Err(err) => {
+                // Frames over the codec's own limit end up here, as does anything else that
+                // leaves the stream unusable
+                log::error!("{err}");
+                ctx.close(Some(CloseCode::Protocol.into()));
+                ctx.stop();
+            }
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Outgoing(SharedFrame);
+
+impl Handler<Outgoing> for WsConnection {
+    type Result = ();
+    fn handle(&mut self, msg: Outgoing, ctx: &mut Self::Context) -> Self::Result {
+        let json = msg.0.json().len();
+        match self.encoding {
+            Encoding::Json => ctx.text(msg.0.into_json()),
+            // Frames were counted as the JSON they are transcoded from
+            Encoding::MsgPack => match msg.0.msgpack() {
+                Some(frame) => {
+                    self.backlog.pop(json.saturating_sub(frame.len()));
+                    ctx.binary(frame.clone());
+                }
+                None => self.backlog.pop(json),
+            },
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Close;
+
+impl Handler<Close> for WsConnection {
+    type Result = ();
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) -> Self::Result {
+        ctx.close(None);
+        ctx.stop();
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Ping([u8; 8]);
+
+impl Handler<Ping> for WsConnection {
+    type Result = ();
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        ctx.ping(&msg.0);
+    }
+}
+
+/// Session's end of a [WsConnection]. Frames are counted as soon as they are sent, the connection
+/// only gets to write them while the socket keeps up.
+pub struct WsSink {
+    connection: Addr<WsConnection>,
+    backlog: Backlog,
+}
+
+impl WsSink {
+    pub fn new(connection: Addr<WsConnection>, backlog: Backlog) -> Self {
+        Self {
+            connection,
+            backlog,
+        }
+    }
+}
+
+impl ClientSink for WsSink {
+    fn send(&mut self, frame: SharedFrame) {
+        self.backlog.push(frame.json().len());
+        self.connection.do_send(Outgoing(frame));
+    }
+    fn close(&mut self) {
+        self.connection.do_send(Close);
+    }
+    fn ping(&mut self, payload: [u8; 8]) {
+        self.connection.do_send(Ping(payload));
+    }
+    fn backlog(&self) -> usize {
+        self.backlog.queued()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Server;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// What a [Recorder] was handed by its session
+    #[derive(Default)]
+    struct Recorded {
+        frames: Vec<ByteString>,
+        pings: usize,
+        closed: bool,
+    }
+
+    /// Transport holding on to everything it is sent, claiming to be [Recorder::backlog] bytes
+    /// behind
+    #[derive(Clone, Default)]
+    struct Recorder {
+        recorded: Arc<Mutex<Recorded>>,
+        backlog: Arc<AtomicUsize>,
+    }
+
+    impl ClientSink for Recorder {
+        fn send(&mut self, frame: SharedFrame) {
+            self.recorded.lock().unwrap().frames.push(frame.into_json());
+        }
+        fn close(&mut self) {
+            self.recorded.lock().unwrap().closed = true;
+        }
+        fn ping(&mut self, _: [u8; 8]) {
+            self.recorded.lock().unwrap().pings += 1;
+        }
+        fn backlog(&self) -> usize {
+            self.backlog.load(Ordering::Relaxed)
+        }
+    }
+
+    #[actix::test]
+    async fn sessions_only_talk_to_their_transport() {
+        let mut server = Server::start();
+        server.timings.ping_interval = Duration::from_millis(10);
+        let recorder = Recorder::default();
+        let session = server.session(recorder.clone());
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        {
+            let recorded = recorder.recorded.lock().unwrap();
+            assert!(recorded.frames[0].contains("Welcome"));
+            assert!(recorded.pings > 0);
+            assert!(!recorded.closed);
+        }
+        // Far enough behind to be let go on the next frame
+        recorder.backlog.store(usize::MAX, Ordering::Relaxed);
+        let login = r#"{"kind":"Login","data":"ann"}"#;
+        session.send(Incoming(login.into())).await.unwrap();
+        let recorded = recorder.recorded.lock().unwrap();
+        assert!(recorded.frames.last().unwrap().contains("SlowConnection"));
+        assert!(recorded.closed);
+    }
+
+    #[test]
+    fn hostile_frames_are_refused() {
+        assert_eq!(check_frame(r#"{"kind":"Chat","data":"[[[{{{"}"#), Ok(()));
+        let nested = "[".repeat(MAX_JSON_DEPTH + 1);
+        assert_eq!(check_frame(&nested), Err(FrameViolation::TooDeep));
+        let escaped = format!(r#"{{"kind":"Chat","data":"\\\"{}"}}"#, "[".repeat(40));
+        assert_eq!(check_frame(&escaped), Ok(()));
+        let large = "a".repeat(MAX_FRAME_SIZE + 1);
+        assert_eq!(
+            check_frame(&large),
+            Err(FrameViolation::TooLarge(MAX_FRAME_SIZE + 1))
+        );
+    }
+}
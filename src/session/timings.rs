@@ -0,0 +1,103 @@
+//! How long sessions wait on their clients, configurable per deployment so that servers behind
+//! slow or lossy networks can be more patient without a rebuild
+
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 5;
+const DEFAULT_RECONNECTION_TIME_LIMIT: u64 = 15;
+const DEFAULT_PING_INTERVAL: u64 = 10;
+/// Pings in a row a connection can go without answering before it is stale
+const MISSED_PINGS: u32 = 3;
+const DEFAULT_HEARTBEAT_TIMEOUT: u64 = DEFAULT_PING_INTERVAL * MISSED_PINGS as u64;
+const DEFAULT_IDLE_TIMEOUT: u64 = 5 * 60;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SessionTimings {
+    /// How often the session checks whether its connection went stale
+    pub heartbeat_interval: Duration,
+    /// Connections the session has not heard back from for this long are stale. Quiet clients
+    /// are only heard from when they answer a ping, so this is always longer than
+    /// [SessionTimings::ping_interval].
+    pub heartbeat_timeout: Duration,
+    /// How long a stale connection is given to pick back up before the session is stopped. The
+    /// room the client is in keeps their seat for a while longer, see
+    /// [crate::room::RoomConfig::reconnect_grace_secs]
+    pub reconnection_time_limit: Duration,
+    /// How often the client is pinged, see [super::latency]
+    pub ping_interval: Duration,
+    /// Clients that are not in a room or looking for one and didn't send anything for this long
+    /// are disconnected, however well their connection keeps up
+    pub idle_timeout: Duration,
+}
+
+impl Default for SessionTimings {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL),
+            heartbeat_timeout: Duration::from_secs(DEFAULT_HEARTBEAT_TIMEOUT),
+            reconnection_time_limit: Duration::from_secs(DEFAULT_RECONNECTION_TIME_LIMIT),
+            ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL),
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT),
+        }
+    }
+}
+
+impl SessionTimings {
+    /// Reads `HEARTBEAT_INTERVAL`, `HEARTBEAT_TIMEOUT`, `RECONNECTION_TIME_LIMIT`,
+    /// `PING_INTERVAL` and `IDLE_TIMEOUT`, all in seconds
+    pub fn from_env() -> Self {
+        fn read<T: FromStr>(name: &str, default: T) -> T {
+            match std::env::var(name).map(|x| x.parse()) {
+                Ok(Ok(value)) => value,
+                Ok(Err(_)) => {
+                    log::error!("ignoring malformed {name}");
+                    default
+                }
+                Err(_) => default,
+            }
+        }
+        let secs = |name, default: u64| Duration::from_secs(read(name, default).max(1));
+        let timings = Self {
+            heartbeat_interval: secs("HEARTBEAT_INTERVAL", DEFAULT_HEARTBEAT_INTERVAL),
+            heartbeat_timeout: secs("HEARTBEAT_TIMEOUT", DEFAULT_HEARTBEAT_TIMEOUT),
+            reconnection_time_limit: secs(
+                "RECONNECTION_TIME_LIMIT",
+                DEFAULT_RECONNECTION_TIME_LIMIT,
+            ),
+            ping_interval: secs("PING_INTERVAL", DEFAULT_PING_INTERVAL),
+            idle_timeout: secs("IDLE_TIMEOUT", DEFAULT_IDLE_TIMEOUT),
+        };
+        timings.checked()
+    }
+    /// Gives connections a few pings to answer if the heartbeat timeout would run out before
+    /// the next ping
+    fn checked(self) -> Self {
+        if self.heartbeat_timeout > self.ping_interval {
+            return self;
+        }
+        log::error!("HEARTBEAT_TIMEOUT must be longer than PING_INTERVAL, ignoring it");
+        Self {
+            heartbeat_timeout: self.ping_interval * MISSED_PINGS,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_get_to_answer_pings_before_going_stale() {
+        let defaults = SessionTimings::default();
+        assert!(defaults.heartbeat_timeout > defaults.ping_interval);
+        let timings = SessionTimings {
+            heartbeat_timeout: Duration::from_secs(2),
+            ping_interval: Duration::from_secs(10),
+            ..defaults
+        }
+        .checked();
+        assert_eq!(timings.heartbeat_timeout, Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,82 @@
+//! Transient ids, the handles rooms, games and clients refer to sessions by. An id is a counter
+//! tagged with a generation that starts out random for every process and moves on whenever the
+//! counter wraps, so that ids never repeat while a process runs long enough to wrap it once.
+//! Ids still held by a session are skipped regardless. Ids stay within the 53 bits a JavaScript
+//! number holds exactly, since clients get them as plain JSON numbers.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const COUNTER_BITS: u32 = 32;
+const GENERATION_BITS: u32 = 21;
+const COUNTER_MASK: u64 = (1 << COUNTER_BITS) - 1;
+const GENERATION_MASK: u64 = (1 << GENERATION_BITS) - 1;
+
+/// Refers to a single connection of a user, see [TransientIds]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[serde(transparent)]
+pub struct TransientId(u64);
+
+impl fmt::Display for TransientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+impl From<u64> for TransientId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// Hands out the transient ids of a process
+pub struct TransientIds {
+    generation: u64,
+    counter: u64,
+}
+
+impl Default for TransientIds {
+    fn default() -> Self {
+        Self {
+            generation: rand::random::<u64>() & GENERATION_MASK,
+            counter: 0,
+        }
+    }
+}
+
+impl TransientIds {
+    /// The next id for which `in_use` is false
+    pub fn issue(&mut self, in_use: impl Fn(TransientId) -> bool) -> TransientId {
+        loop {
+            if self.counter == COUNTER_MASK {
+                self.counter = 0;
+                self.generation = (self.generation + 1) & GENERATION_MASK;
+            }
+            self.counter += 1;
+            let id = TransientId(self.generation << COUNTER_BITS | self.counter);
+            if !in_use(id) {
+                return id;
+            }
+            log::warn!("transient id {id} is still in use, skipping it");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_on_to_the_next_generation_and_skips_ids_in_use() {
+        let mut ids = TransientIds {
+            generation: GENERATION_MASK,
+            counter: COUNTER_MASK - 1,
+        };
+        let last = ids.issue(|_| false);
+        assert_eq!(last.0, (1 << 53) - 1);
+        assert_eq!(ids.issue(|_| false), TransientId(1));
+        let held = TransientId(2);
+        assert_eq!(ids.issue(|id| id == held), TransientId(3));
+    }
+}
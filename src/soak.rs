@@ -0,0 +1,176 @@
+//! Internal load generator for soak testing a deployment without any external traffic
+//! generator. Synthetic sessions are spawned inside the process at a fixed rate, log in, join a
+//! random room and leave again after a while, so that memory usage can be watched over a long
+//! run. Only compiled with the `soak` feature and only started when `SOAK_RATE` is set.
+
+use crate::capacity::{Capacity, CapacityClass};
+use crate::deadletter::DeadLetters;
+use crate::room::RoomManager;
+use crate::session::actor::{Disconnected, Incoming, Session};
+use crate::session::timings::SessionTimings;
+use crate::session::{
+    features::FeatureFlags,
+    sink::{ClientSink, SharedFrame},
+    SessionManager,
+};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix_web::rt::time::sleep;
+use bytestring::ByteString;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Seconds between two reports of the generator's counters in the logs
+const REPORT_INTERVAL: u64 = 60;
+const DEFAULT_LIFETIME: u64 = 120;
+const DEFAULT_MAX_SESSIONS: usize = 1000;
+
+pub struct SoakConfig {
+    /// Synthetic sessions spawned every second
+    rate: usize,
+    /// How long each synthetic session stays in its room before logging out
+    lifetime: Duration,
+    /// Upper bound on synthetic sessions alive at the same time
+    max_sessions: usize,
+}
+
+impl SoakConfig {
+    /// Reads `SOAK_RATE`, `SOAK_SESSION_LIFETIME` (in seconds) and `SOAK_MAX_SESSIONS`. Returns
+    /// nothing unless a non zero rate is set.
+    pub fn from_env() -> Option<Self> {
+        let rate = std::env::var("SOAK_RATE").ok()?.parse().ok()?;
+        if rate == 0 {
+            return None;
+        }
+        let lifetime = std::env::var("SOAK_SESSION_LIFETIME")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_LIFETIME);
+        let max_sessions = std::env::var("SOAK_MAX_SESSIONS")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_MAX_SESSIONS);
+        Some(Self {
+            rate,
+            lifetime: Duration::from_secs(lifetime),
+            max_sessions,
+        })
+    }
+}
+
+pub struct LoadGenerator {
+    config: SoakConfig,
+    session_manager: Addr<SessionManager>,
+    room_manager: Addr<RoomManager>,
+    features: Arc<FeatureFlags>,
+    dead_letters: Addr<DeadLetters>,
+    timings: SessionTimings,
+    /// Synthetic sessions are admitted like any other, see [crate::capacity]
+    capacity: Arc<Capacity>,
+    spawned: u64,
+    finished: u64,
+    live: usize,
+}
+
+impl LoadGenerator {
+    pub fn new(
+        config: SoakConfig,
+        session_manager: Addr<SessionManager>,
+        room_manager: Addr<RoomManager>,
+        features: Arc<FeatureFlags>,
+        dead_letters: Addr<DeadLetters>,
+        timings: SessionTimings,
+        capacity: Arc<Capacity>,
+    ) -> Self {
+        Self {
+            config,
+            session_manager,
+            room_manager,
+            features,
+            dead_letters,
+            timings,
+            capacity,
+            spawned: 0,
+            finished: 0,
+            live: 0,
+        }
+    }
+    fn spawn_session(&mut self, ctx: &mut Context<Self>) {
+        let Some(permit) = self.capacity.admit(CapacityClass::Public) else {
+            return log::warn!("soak session turned away, the server is full");
+        };
+        let session = Session::new(
+            self.session_manager.clone(),
+            self.room_manager.clone(),
+            Arc::clone(&self.features),
+            self.dead_letters.clone(),
+            self.timings,
+            permit,
+            Discard,
+        )
+        .start();
+        let generator = ctx.address();
+        let script = script(session, self.spawned, self.config.lifetime);
+        self.spawned += 1;
+        self.live += 1;
+        actix::spawn(async move {
+            script.await;
+            generator.do_send(SessionFinished);
+        });
+    }
+}
+
+impl Actor for LoadGenerator {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::warn!(
+            "soak test running: {} synthetic sessions per second, at most {} at once",
+            self.config.rate,
+            self.config.max_sessions
+        );
+        ctx.run_interval(Duration::from_secs(1), |act, ctx| {
+            let room = act.config.max_sessions.saturating_sub(act.live);
+            for _ in 0..act.config.rate.min(room) {
+                act.spawn_session(ctx);
+            }
+        });
+        ctx.run_interval(Duration::from_secs(REPORT_INTERVAL), |act, _| {
+            log::info!(
+                "soak test: {} live sessions, {} spawned, {} finished",
+                act.live,
+                act.spawned,
+                act.finished
+            );
+        });
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct SessionFinished;
+
+impl Handler<SessionFinished> for LoadGenerator {
+    type Result = ();
+    fn handle(&mut self, _: SessionFinished, _: &mut Self::Context) -> Self::Result {
+        self.live -= 1;
+        self.finished += 1;
+    }
+}
+
+/// Synthetic clients have no use for what the server sends them
+struct Discard;
+
+impl ClientSink for Discard {
+    fn send(&mut self, _: SharedFrame) {}
+    fn close(&mut self) {}
+}
+
+/// What a synthetic client sends, ending with its connection going away
+async fn script(session: Addr<Session>, n: u64, lifetime: Duration) {
+    let send = |text: String| session.send(Incoming(text.into()));
+    // The session may have stopped on its own already, leaving nothing more to do
+    let _ = send(format!(r#"{{"kind":"Login","data":"soak-{n}"}}"#)).await;
+    let _ = send(r#"{"kind":"JoinRoom","data":null}"#.to_owned()).await;
+    sleep(lifetime).await;
+    let _ = send(r#"{"kind":"Logout"}"#.to_owned()).await;
+    let _ = session.send(Disconnected).await;
+}
@@ -0,0 +1,247 @@
+//! Runs the session and room managers together in tests, with clients talking to their sessions
+//! over in-memory channels the way transports do
+
+use crate::capacity::{Capacity, CapacityClass, CapacityConfig};
+use crate::deadletter::DeadLetters;
+use crate::events::{EventBus, ServerEvent};
+use crate::game::limits::ModeLimits;
+use crate::jobs::JobPool;
+use crate::load::Load;
+use crate::profanity::ProfanityFilter;
+use crate::rating::Ratings;
+use crate::room::{
+    denylist::Denylist,
+    fanout::FanoutPool,
+    invite::InviteSigner,
+    placement::{ArbiterPool, PlacementStrategy},
+    practice::PracticeBoards,
+    InactivityConfig, RoomLifetime, RoomManager, RoomServices,
+};
+use crate::session::actor::{Disconnected, Incoming, Session};
+use crate::session::bot::BotKeys;
+use crate::session::sink::ClientSink;
+use crate::session::timings::SessionTimings;
+use crate::session::{features::FeatureFlags, ConnectionPolicy, SessionManager};
+use actix::prelude::*;
+use bytestring::ByteString;
+use serde_json::{json, Value};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// How long a client waits for a message before the test fails
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Server {
+    pub session_manager: Addr<SessionManager>,
+    pub room_manager: Addr<RoomManager>,
+    pub services: RoomServices,
+    pub timings: SessionTimings,
+    features: Arc<FeatureFlags>,
+}
+
+impl Server {
+    pub fn start() -> Self {
+        Self::with_policy(ConnectionPolicy::default())
+    }
+    pub fn with_policy(policy: ConnectionPolicy) -> Self {
+        Self::new(policy, None)
+    }
+    /// Rooms opened without a lifetime of their own get this one
+    pub fn with_lifetime(lifetime: RoomLifetime) -> Self {
+        Self::new(ConnectionPolicy::default(), Some(lifetime))
+    }
+    fn new(policy: ConnectionPolicy, lifetime: Option<RoomLifetime>) -> Self {
+        let events = EventBus::default().start();
+        let profanity = Arc::new(ProfanityFilter::load());
+        let session_manager = SessionManager::new(
+            BotKeys::from_env(),
+            events.clone(),
+            None,
+            policy,
+            Arc::clone(&profanity),
+        )
+        .start();
+        let dead_letters = DeadLetters::from_env().start();
+        let services = RoomServices {
+            profanity,
+            fanout: FanoutPool::new(1, dead_letters.clone()),
+            jobs: JobPool::new(1),
+            dead_letters,
+            inactivity: InactivityConfig::from_env(),
+            lifetime,
+            invites: Arc::new(InviteSigner::from_env()),
+            ratings: Arc::new(Ratings::from_env()),
+            load: Arc::new(Load::default()),
+            practice: Arc::new(PracticeBoards::default()),
+            events,
+            capacity: Arc::new(Capacity::new(CapacityConfig::from_env())),
+            modes: Arc::new(ModeLimits::from_env()),
+            archive: Arc::default(),
+        };
+        let placement = ArbiterPool::new(1, PlacementStrategy::RoundRobin);
+        let room_manager =
+            RoomManager::new(Denylist::from_env(), services.clone(), placement).start();
+        Self {
+            session_manager,
+            room_manager,
+            services,
+            timings: SessionTimings::default(),
+            features: Arc::new(FeatureFlags::default()),
+        }
+    }
+    /// A new client, which has read the welcome message already
+    pub async fn connect(&self) -> Client {
+        let (sender, frames) = channel();
+        let session = self.session(sender);
+        let client = Client { session, frames };
+        client.expect("Welcome").await;
+        client
+    }
+    /// A new session writing to the transport
+    pub fn session(&self, sink: impl ClientSink) -> Addr<Session> {
+        Session::new(
+            self.session_manager.clone(),
+            self.room_manager.clone(),
+            Arc::clone(&self.features),
+            self.services.dead_letters.clone(),
+            self.timings,
+            self.services.capacity.admit(CapacityClass::Public).unwrap(),
+            sink,
+        )
+        .start()
+    }
+}
+
+/// Timings under which a client that sends nothing is dropped within half a second
+pub fn impatient() -> SessionTimings {
+    SessionTimings {
+        heartbeat_interval: Duration::from_millis(20),
+        heartbeat_timeout: Duration::from_millis(150),
+        reconnection_time_limit: Duration::from_millis(100),
+        ..SessionTimings::default()
+    }
+}
+
+pub struct Client {
+    pub session: Addr<Session>,
+    frames: Receiver<ByteString>,
+}
+
+impl Client {
+    pub async fn send(&self, message: Value) {
+        let text = message.to_string();
+        self.session.send(Incoming(text.into())).await.unwrap();
+    }
+    /// Data of the next message of the kind, skipping any other
+    pub async fn expect(&self, kind: &str) -> Value {
+        self.next(kind)
+            .await
+            .unwrap_or_else(|| panic!("no {kind} within {TIMEOUT:?}"))
+    }
+    /// Data of the next message of the kind, if one comes within [TIMEOUT]
+    pub async fn next(&self, kind: &str) -> Option<Value> {
+        let mut message = self.next_message(kind).await?;
+        Some(message["data"].take())
+    }
+    /// The next message of the kind as a whole, along with the id of the request it answers
+    pub async fn expect_reply(&self, kind: &str) -> Value {
+        self.next_message(kind)
+            .await
+            .unwrap_or_else(|| panic!("no {kind} within {TIMEOUT:?}"))
+    }
+    async fn next_message(&self, kind: &str) -> Option<Value> {
+        let deadline = actix::clock::Instant::now() + TIMEOUT;
+        while actix::clock::Instant::now() < deadline {
+            while let Ok(frame) = self.frames.try_recv() {
+                let message: Value = serde_json::from_str(&frame).unwrap();
+                if message["kind"] == kind {
+                    return Some(message);
+                }
+            }
+            actix::clock::sleep(Duration::from_millis(5)).await;
+        }
+        None
+    }
+    /// Speaks the latest protocol from now on
+    pub async fn hello(&self) {
+        let version = crate::version::PROTOCOL_VERSION;
+        self.send(json!({ "kind": "Hello", "data": version })).await;
+    }
+    /// Logs in on the latest protocol, answering with the resume token
+    pub async fn login(&self, user: &str) -> String {
+        self.hello().await;
+        self.send(json!({ "kind": "Login", "data": user })).await;
+        let result = self.expect("LoginResult").await;
+        result["data"].as_str().expect("logged in").to_owned()
+    }
+    /// The connection goes away without the client logging out
+    pub async fn drop_connection(self) {
+        let _ = self.session.send(Disconnected).await;
+    }
+}
+
+/// A player, not a bot, signing in
+pub fn signed_in(user: &str) -> ServerEvent {
+    ServerEvent::SignedIn {
+        user: user.into(),
+        bot: false,
+    }
+}
+
+/// Request received by an [http_endpoint]
+pub struct Request {
+    pub head: String,
+    pub body: String,
+}
+
+/// Local HTTP endpoint answering every request with 204 No Content, along with the requests it
+/// receives
+pub async fn http_endpoint() -> (String, UnboundedReceiver<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, requests) = unbounded_channel();
+    actix::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let sender = sender.clone();
+            actix::spawn(async move {
+                let mut data = Vec::new();
+                let mut buf = [0; 4096];
+                let (head, length) = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    assert!(read > 0, "request cut short");
+                    data.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&data);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let head = text[..end].to_owned();
+                        let length = head
+                            .lines()
+                            .find_map(|x| {
+                                x.to_lowercase()
+                                    .strip_prefix("content-length:")?
+                                    .trim()
+                                    .parse()
+                                    .ok()
+                            })
+                            .unwrap_or(0);
+                        data.drain(..end + 4);
+                        break (head, length);
+                    }
+                };
+                while data.len() < length {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    assert!(read > 0, "request body cut short");
+                    data.extend_from_slice(&buf[..read]);
+                }
+                let body = String::from_utf8(data).unwrap();
+                let _ = sender.send(Request { head, body });
+                let answer = b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n";
+                let _ = stream.write_all(answer).await;
+            });
+        }
+    });
+    (url, requests)
+}
@@ -0,0 +1,64 @@
+use crate::session::features::{Feature, FeatureFlags};
+use serde::Serialize;
+
+/// Version of the client protocol spoken by this server. Bumped on every breaking change to
+/// the incoming or outgoing messages.
+///
+/// 1. The protocol as it was before clients stated their version
+/// 2. Logging back in with a resume token and instant replays
+/// 3. Errors come with a numeric code, a kind and a message
+/// 4. Reports of the round trip time of the connection
+/// 5. Acknowledging room events
+/// 6. Declaring a locale when logging in or reconnecting
+/// 7. Friend lists
+/// 8. Inviting friends to the room
+/// 9. Pausing the game
+/// 10. Rejected chat messages and failed matches come with a code, a kind and a message
+pub const PROTOCOL_VERSION: u32 = 10;
+/// Version clients that never state one are taken to speak
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// Oldest version of the client protocol still spoken, clients on older ones are turned away
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Whether the server speaks the version of the protocol a client asked for
+pub fn protocol_supported(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// Identifies the exact build a server is running, for `/version`, the startup banner and the
+/// `Welcome` message so that client side bug reports can be matched to a build.
+#[derive(Serialize, Clone)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    /// Seconds since the unix epoch
+    pub built_at: u64,
+    pub protocol_version: u32,
+    /// Clients can speak any version from this one up to [BuildInfo::protocol_version]
+    pub min_protocol_version: u32,
+    /// Features enabled on this deployment
+    pub features: Vec<Feature>,
+}
+
+impl BuildInfo {
+    pub fn new(features: &FeatureFlags) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("GIT_HASH"),
+            built_at: env!("BUILD_TIMESTAMP").parse().unwrap_or_default(),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: features.enabled(),
+        }
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "zgm-srv {} ({}, built at {}), protocol v{}, features: {:?}",
+            self.version, self.git_hash, self.built_at, self.protocol_version, self.features
+        )
+    }
+}
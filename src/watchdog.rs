@@ -0,0 +1,236 @@
+use actix::prelude::*;
+use ahash::{HashSet, HashSetExt};
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::events::{EventBus, Publish, ServerEvent};
+use crate::room::{room_code_space, GetRoomStats, RoomManager, RoomStats};
+use crate::webhook::{is_http_url, Outbound, PostJson, JSON};
+
+const DEFAULT_INTERVAL: u64 = 60;
+const DEFAULT_ROOMS_PER_MINUTE: f64 = 600.0;
+const DEFAULT_JOIN_FAILURE_RATIO: f64 = 0.5;
+const DEFAULT_CODE_USAGE: f64 = 0.8;
+/// Join failure ratios over fewer joins than this are too noisy to alert on
+const MIN_JOIN_SAMPLE: u64 = 20;
+
+/// Thresholds the [Watchdog] alerts on, set well below the point where the server starts turning
+/// players away
+pub struct WatchdogConfig {
+    /// How often the room manager's counters are sampled
+    pub interval: Duration,
+    pub rooms_per_minute: f64,
+    /// Share of joins that end in an error
+    pub join_failure_ratio: f64,
+    /// Share of all possible room codes held by live rooms
+    pub code_usage: f64,
+    /// `http://` or `https://` endpoint every warning is POSTed to, if any
+    pub webhook: Option<String>,
+}
+
+impl WatchdogConfig {
+    /// Reads `WATCHDOG_INTERVAL` (in seconds), `WATCHDOG_ROOMS_PER_MINUTE`,
+    /// `WATCHDOG_JOIN_FAILURE_RATIO`, `WATCHDOG_CODE_USAGE` and `ALERT_WEBHOOK`
+    pub fn from_env() -> Self {
+        fn read<T: FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(default)
+        }
+        Self {
+            interval: Duration::from_secs(read("WATCHDOG_INTERVAL", DEFAULT_INTERVAL).max(1)),
+            rooms_per_minute: read("WATCHDOG_ROOMS_PER_MINUTE", DEFAULT_ROOMS_PER_MINUTE),
+            join_failure_ratio: read("WATCHDOG_JOIN_FAILURE_RATIO", DEFAULT_JOIN_FAILURE_RATIO),
+            code_usage: read("WATCHDOG_CODE_USAGE", DEFAULT_CODE_USAGE),
+            webhook: std::env::var("ALERT_WEBHOOK")
+                .ok()
+                .filter(|x| !x.is_empty())
+                .filter(|url| {
+                    let valid = is_http_url(url);
+                    if !valid {
+                        log::error!("ignoring alert webhook {url}, it is not an http(s) url");
+                    }
+                    valid
+                }),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CapacityAlert {
+    /// Rooms are being created faster than expected
+    RoomCreationRate,
+    /// An unusual share of players fail to get into a room
+    JoinFailureRate,
+    /// Live rooms hold most of the available room codes
+    CodeExhaustion,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CapacityWarning {
+    pub alert: CapacityAlert,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// Rates measured over the last sampling interval
+#[derive(Serialize, Clone, Default)]
+pub struct ScalingReport {
+    pub rooms_per_minute: f64,
+    pub join_failure_ratio: f64,
+    pub code_usage: f64,
+    /// Codes thrown away per created room because they contained a denylisted string
+    pub code_rerolls_per_room: f64,
+    pub live_rooms: usize,
+    /// Stopped rooms kept around for reuse
+    pub pooled_rooms: usize,
+    /// Alerts currently raised
+    pub alerts: Vec<CapacityAlert>,
+}
+
+/// Periodically samples the room manager's counters, and warns through the [EventBus] and the
+/// alert webhook when the server gets close to its limits. Every alert is raised once when its
+/// threshold is crossed and cleared once the value drops back below it.
+pub struct Watchdog {
+    config: WatchdogConfig,
+    room_manager: Addr<RoomManager>,
+    events: Addr<EventBus>,
+    outbound: Outbound,
+    previous: Option<(RoomStats, Instant)>,
+    raised: HashSet<CapacityAlert>,
+    report: ScalingReport,
+}
+
+impl Watchdog {
+    pub fn new(
+        config: WatchdogConfig,
+        room_manager: Addr<RoomManager>,
+        events: Addr<EventBus>,
+        outbound: Outbound,
+    ) -> Self {
+        Self {
+            config,
+            room_manager,
+            events,
+            outbound,
+            previous: None,
+            raised: HashSet::new(),
+            report: Default::default(),
+        }
+    }
+    fn sample(&mut self, ctx: &mut Context<Self>) {
+        self.room_manager
+            .send(GetRoomStats)
+            .into_actor(self)
+            .then(|res, act, _| {
+                match res {
+                    Ok(stats) => act.update(stats),
+                    Err(err) => log::error!("cannot sample room stats: {err}"),
+                }
+                actix::fut::ready(())
+            })
+            .wait(ctx);
+    }
+    fn update(&mut self, stats: RoomStats) {
+        let now = Instant::now();
+        let Some((previous, at)) = self.previous.replace((stats, now)) else {
+            return;
+        };
+        let minutes = now.duration_since(at).as_secs_f64() / 60.0;
+        let created = stats.created - previous.created;
+        let joins = stats.joins - previous.joins;
+        let failures = stats.join_failures - previous.join_failures;
+        let rerolls = stats.code_rerolls - previous.code_rerolls;
+        let mut report = ScalingReport {
+            rooms_per_minute: created as f64 / minutes.max(f64::EPSILON),
+            join_failure_ratio: failures as f64 / joins.max(1) as f64,
+            code_usage: stats.live_rooms as f64 / room_code_space() as f64,
+            code_rerolls_per_room: rerolls as f64 / created.max(1) as f64,
+            live_rooms: stats.live_rooms,
+            pooled_rooms: stats.pooled_rooms,
+            alerts: Vec::new(),
+        };
+        let checks = [
+            (
+                CapacityAlert::RoomCreationRate,
+                report.rooms_per_minute,
+                self.config.rooms_per_minute,
+                true,
+            ),
+            (
+                CapacityAlert::JoinFailureRate,
+                report.join_failure_ratio,
+                self.config.join_failure_ratio,
+                joins >= MIN_JOIN_SAMPLE,
+            ),
+            (
+                CapacityAlert::CodeExhaustion,
+                report.code_usage,
+                self.config.code_usage,
+                true,
+            ),
+        ];
+        for (alert, value, threshold, significant) in checks {
+            let warning = CapacityWarning {
+                alert,
+                value,
+                threshold,
+            };
+            if significant && value >= threshold {
+                if self.raised.insert(alert) {
+                    log::warn!("{alert:?} at {value:.3}, alerting above {threshold:.3}");
+                    self.alert(ServerEvent::CapacityWarning(warning));
+                }
+            } else if value < threshold && self.raised.remove(&alert) {
+                log::info!("{alert:?} back to {value:.3}");
+                self.alert(ServerEvent::CapacityRecovered(warning));
+            }
+        }
+        report.alerts = self.raised.iter().copied().collect();
+        self.report = report;
+    }
+    fn alert(&self, event: ServerEvent) {
+        if let Some(url) = &self.config.webhook {
+            match serde_json::to_string(&event) {
+                Ok(body) => {
+                    let sent = self.outbound.post(PostJson {
+                        url: url.clone(),
+                        content_type: JSON,
+                        body,
+                    });
+                    actix::spawn(async move {
+                        match sent.await {
+                            Ok(status) if (200..300).contains(&status) => {}
+                            Ok(status) => log::error!("alert webhook answered {status}"),
+                            Err(err) => log::error!("cannot reach alert webhook: {err}"),
+                        }
+                    });
+                }
+                Err(err) => log::error!("cannot serialize alert: {err}"),
+            }
+        }
+        self.events.do_send(Publish(event));
+    }
+}
+
+impl Actor for Watchdog {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.sample(ctx);
+        ctx.run_interval(self.config.interval, |act, ctx| act.sample(ctx));
+    }
+}
+
+/// Rates and alerts as of the last sample
+#[derive(Message)]
+#[rtype(result = "ScalingReport")]
+pub struct GetScalingReport;
+
+impl Handler<GetScalingReport> for Watchdog {
+    type Result = MessageResult<GetScalingReport>;
+    fn handle(&mut self, _: GetScalingReport, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.report.clone())
+    }
+}
@@ -0,0 +1,241 @@
+use actix::{Actor, Addr, Arbiter, ArbiterHandle, AsyncContext, Context, Handler};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, Semaphore};
+
+use crate::events::{EventBus, ServerEvent, Subscribe};
+
+/// How long an endpoint gets to answer a request, connecting included
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests under way at once, further ones are turned away until some of them complete
+const MAX_PENDING: usize = 256;
+/// Content type of plain JSON bodies
+pub const JSON: &str = "application/json";
+/// Content type of the records produced through a Kafka REST proxy
+pub const KAFKA_REST_JSON: &str = "application/vnd.kafka.json.v2+json";
+
+/// Whether [Outbound] can reach the URL
+pub fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// A JSON body to POST through [Outbound]
+pub struct PostJson {
+    pub url: String,
+    /// Usually [JSON], some endpoints want a more specific one
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum PostError {
+    /// Too many requests are under way already
+    Overloaded,
+    /// The endpoint could not be reached or did not answer in time
+    Failed(reqwest::Error),
+    /// The outbound thread went away before the endpoint answered
+    Cancelled,
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostError::Overloaded => write!(f, "too many requests under way"),
+            PostError::Failed(err) => err.fmt(f),
+            PostError::Cancelled => write!(f, "request cancelled"),
+        }
+    }
+}
+
+/// Sends the server's outgoing HTTP requests from a thread of its own, so that slow webhooks,
+/// alert endpoints or analytics sinks never hold up the actors or the CPU heavy work on the
+/// [crate::jobs::JobPool]. Requests are made asynchronously, at most [MAX_PENDING] at once, and
+/// `https://` endpoints are reached over TLS.
+#[derive(Clone)]
+pub struct Outbound {
+    arbiter: ArbiterHandle,
+    client: reqwest::Client,
+    slots: Arc<Semaphore>,
+}
+
+impl Outbound {
+    /// Starts the thread the requests are sent from
+    pub fn start() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .expect("cannot set up the http client");
+        Self {
+            arbiter: Arbiter::new().handle(),
+            client,
+            slots: Arc::new(Semaphore::new(MAX_PENDING)),
+        }
+    }
+    /// Starts sending the request, the returned future resolves with the status code the
+    /// endpoint answered with
+    pub fn post(&self, request: PostJson) -> impl Future<Output = Result<u16, PostError>> {
+        let (sender, receiver) = oneshot::channel();
+        match Arc::clone(&self.slots).try_acquire_owned() {
+            Ok(slot) => {
+                let sent = self
+                    .client
+                    .post(request.url)
+                    .header(reqwest::header::CONTENT_TYPE, request.content_type)
+                    .body(request.body)
+                    .send();
+                self.arbiter.spawn(async move {
+                    let result = sent.await;
+                    drop(slot);
+                    let _ = sender.send(
+                        result
+                            .map(|x| x.status().as_u16())
+                            .map_err(PostError::Failed),
+                    );
+                });
+            }
+            Err(_) => {
+                let _ = sender.send(Err(PostError::Overloaded));
+            }
+        }
+        async move { receiver.await.unwrap_or(Err(PostError::Cancelled)) }
+    }
+}
+
+/// POSTs the lifecycle events of every room, rooms opening and closing and games starting and
+/// ending, to the URLs listed in `ROOM_WEBHOOKS`, so that bots and other services can react to
+/// them. The body is the event as published on the [EventBus].
+pub struct RoomWebhooks {
+    urls: Vec<String>,
+    events: Addr<EventBus>,
+    outbound: Outbound,
+}
+
+impl RoomWebhooks {
+    /// [None] unless `ROOM_WEBHOOKS` lists at least one URL
+    pub fn from_env(events: Addr<EventBus>, outbound: Outbound) -> Option<Self> {
+        let urls: Vec<String> = std::env::var("ROOM_WEBHOOKS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .filter(|url| {
+                let valid = is_http_url(url);
+                if !valid {
+                    log::error!("skipping room webhook {url}, it is not an http(s) url");
+                }
+                valid
+            })
+            .map(String::from)
+            .collect();
+        (!urls.is_empty()).then_some(Self {
+            urls,
+            events,
+            outbound,
+        })
+    }
+}
+
+impl Actor for RoomWebhooks {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.events.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<ServerEvent> for RoomWebhooks {
+    type Result = ();
+    fn handle(&mut self, msg: ServerEvent, _: &mut Self::Context) -> Self::Result {
+        let lifecycle = matches!(
+            msg,
+            ServerEvent::RoomCreated { .. }
+                | ServerEvent::RoomClosed { .. }
+                | ServerEvent::GameStarted { .. }
+                | ServerEvent::GameFinished { .. }
+        );
+        if !lifecycle {
+            return;
+        }
+        let body = match serde_json::to_string(&msg) {
+            Ok(body) => body,
+            Err(err) => return log::error!("cannot serialize room event: {err}"),
+        };
+        for url in &self.urls {
+            let sent = self.outbound.post(PostJson {
+                url: url.clone(),
+                content_type: JSON,
+                body: body.clone(),
+            });
+            let topic = msg.topic();
+            actix::spawn(async move {
+                match sent.await {
+                    Ok(status) if (200..300).contains(&status) => {}
+                    Ok(status) => log::error!("room webhook answered {status} to {topic}"),
+                    Err(err) => log::error!("cannot reach room webhook: {err}"),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameMode;
+    use crate::testing::http_endpoint;
+    use serde_json::Value;
+
+    #[actix::test]
+    async fn lifecycle_events_reach_every_webhook() {
+        let (first, mut first_requests) = http_endpoint().await;
+        let (second, mut second_requests) = http_endpoint().await;
+        let webhooks = RoomWebhooks {
+            urls: vec![first, second],
+            events: EventBus::default().start(),
+            outbound: Outbound::start(),
+        }
+        .start();
+        webhooks
+            .send(ServerEvent::SignedIn {
+                user: "ann".into(),
+                bot: false,
+            })
+            .await
+            .unwrap();
+        webhooks
+            .send(ServerEvent::GameStarted {
+                room: "ABCD".into(),
+                mode: GameMode::Standard,
+                players: 2,
+            })
+            .await
+            .unwrap();
+        for requests in [&mut first_requests, &mut second_requests] {
+            let request = actix::clock::timeout(Duration::from_secs(2), requests.recv())
+                .await
+                .expect("no webhook call in time")
+                .unwrap();
+            assert!(request.head.contains(JSON));
+            let event: Value = serde_json::from_str(&request.body).unwrap();
+            assert_eq!(event["kind"], "GameStarted");
+            assert_eq!(event["data"]["room"], "ABCD");
+        }
+        actix::clock::sleep(Duration::from_millis(100)).await;
+        assert!(first_requests.try_recv().is_err());
+    }
+
+    #[actix::test]
+    async fn requests_past_the_limit_are_turned_away() {
+        let outbound = Outbound::start();
+        let _taken = Arc::clone(&outbound.slots)
+            .try_acquire_many_owned(MAX_PENDING as u32)
+            .unwrap();
+        let sent = outbound.post(PostJson {
+            url: "http://127.0.0.1:9".into(),
+            content_type: JSON,
+            body: String::new(),
+        });
+        assert!(matches!(sent.await, Err(PostError::Overloaded)));
+    }
+}